@@ -0,0 +1,407 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    io,
+    path::{Component, Path, PathBuf},
+};
+
+use crate::{
+    Lockfile, ProcessResult, archive::split_member_path, calculate_hash, config::Config,
+    redact::MARKER_PREFIX, vfs::{FileKind, FileSystem},
+};
+
+/// A file's content as written out in a model's response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FileBlock {
+    /// The path the block is labeled with, as written in the response.
+    pub path: String,
+    /// The block's content.
+    pub content: String,
+}
+
+/// Most words a line labeling the next code block may have besides the path,
+/// e.g. `Updated file: `src/lib.rs``.
+const MAX_LABEL_WORDS: usize = 3;
+
+/// Extracts the files from a model's `response`, in the formats clamp embeds them in:
+///
+/// *   Fenced code blocks labeled with a path: in the fence's info string
+///     (`` ```rust src/lib.rs ``, `` ```rust:src/lib.rs ``, `` ```path=src/lib.rs ``) or
+///     on a short line in front of it, as in clamp's own `` ### `src/lib.rs` `` headers
+///     and `Contents of src/lib.rs:` lines.
+/// *   `<file path="src/lib.rs">` ... `</file>` tags, each on a line of its own.
+///
+/// Code blocks without a path are taken to be examples and skipped. As clamp's
+/// wrappers end a file's content with an extra newline, a single trailing blank line
+/// is dropped. If a path occurs more than once, its last block wins.
+pub fn parse_response(response: &str) -> Vec<FileBlock> {
+    let lines: Vec<&str> = response.split_inclusive('\n').collect();
+    let mut blocks: Vec<FileBlock> = Vec::new();
+    // The path named by the last non-blank line outside of blocks
+    let mut label: Option<String> = None;
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index].trim();
+        let block = if let Some((fence, info)) = opening_fence(line) {
+            let path = info_path(info).or_else(|| label.take());
+            let end = (index + 1..lines.len()).find(|&i| is_closing_fence(lines[i].trim(), fence));
+            Some((path, end))
+        } else if let Some(path) = xml_file_path(line) {
+            let end = (index + 1..lines.len()).find(|&i| lines[i].trim() == "</file>");
+            Some((Some(path), end))
+        } else {
+            None
+        };
+        let Some((path, end)) = block else {
+            if !line.is_empty() {
+                label = label_path(line);
+            }
+            index += 1;
+            continue;
+        };
+
+        // An unterminated block runs to the end of the response
+        let body_end = end.unwrap_or(lines.len());
+        if let Some(path) = path {
+            let mut content = lines[index + 1..body_end].concat();
+            if content.ends_with("\n\n") {
+                content.pop();
+            } else if content.ends_with("\r\n\r\n") {
+                content.truncate(content.len() - 2);
+            }
+            blocks.retain(|block| block.path != path);
+            blocks.push(FileBlock { path, content });
+        }
+        label = None;
+        index = body_end + 1;
+    }
+    blocks
+}
+
+/// The fence (e.g. `` ``` ``) and info string of a line opening a code block.
+fn opening_fence(line: &str) -> Option<(&str, &str)> {
+    let marker = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let length = line.len() - line.trim_start_matches(marker).len();
+    let (fence, info) = line.split_at(length);
+    // Backticks in a backtick fence's info string make it inline code instead
+    (length >= 3 && !(marker == '`' && info.contains('`'))).then(|| (fence, info.trim()))
+}
+
+/// Whether `line` closes a code block opened with `fence`.
+fn is_closing_fence(line: &str, fence: &str) -> bool {
+    let marker = fence.as_bytes()[0] as char;
+    line.len() >= fence.len() && line.chars().all(|c| c == marker)
+}
+
+/// The path named in a fence's info string, if any.
+fn info_path(info: &str) -> Option<String> {
+    info.split_whitespace().find_map(|word| {
+        let word = ["path=", "file="]
+            .iter()
+            .find_map(|key| word.strip_prefix(key))
+            .or_else(|| word.split_once(':').map(|(_, path)| path))
+            .unwrap_or(word);
+        let word = word.trim_matches('"');
+        is_path_like(word).then(|| word.to_string())
+    })
+}
+
+/// The path of a `<file path="...">` line.
+fn xml_file_path(line: &str) -> Option<String> {
+    let rest = line.strip_prefix("<file path=\"")?;
+    let (path, rest) = rest.split_once('"')?;
+    (rest.trim() == ">" && !path.is_empty()).then(|| unescape_xml(path))
+}
+
+/// The path named by a line labeling the next code block, if it is one.
+fn label_path(line: &str) -> Option<String> {
+    if let Some(path) = line.strip_prefix("Contents of ").and_then(|rest| rest.strip_suffix(':')) {
+        return Some(path.to_string());
+    }
+    let mut spans = line.split('`');
+    let (before, path, after) = (spans.next()?, spans.next()?, spans.next()?);
+    if spans.next().is_some() || !is_path_like(path) {
+        return None; // No backticks, or more than one span
+    }
+    let words = format!("{before} {after}")
+        .split_whitespace()
+        .filter(|word| word.chars().any(char::is_alphanumeric))
+        .count();
+    (words <= MAX_LABEL_WORDS).then(|| path.to_string())
+}
+
+/// Whether `text` looks like a file path rather than a language name or other word.
+fn is_path_like(text: &str) -> bool {
+    !text.is_empty()
+        && text.contains(['/', '.'])
+        && !text.contains(char::is_whitespace)
+        && !text.ends_with(['/', '.', ':'])
+}
+
+/// The file that the response's `block` is written to: the include the template
+/// embedded as `result` under the block's path, or a new file below `base_dir`.
+/// Returns it with its current content in `fs` (`None` for a new file).
+///
+/// Returns an error for blocks that cannot be applied: blocks still containing
+/// redaction markers, linked files, files embedded only in part or transformed
+/// (e.g. by `head=` or line numbers), and files whose content no longer matches
+/// their `lockfile` entry, as the response was based on their earlier content.
+/// Includes without an entry are refused unless `force` is set. New files must
+/// be below `base_dir` without passing through a symlink, and existing files the
+/// template does not include are refused, as the model has not seen them.
+pub fn resolve_target(
+    fs: &dyn FileSystem,
+    block: &FileBlock,
+    base_dir: &Path,
+    result: &ProcessResult,
+    lockfile: &Lockfile,
+    config: &Config,
+    force: bool,
+) -> Result<(PathBuf, Option<Vec<u8>>)> {
+    if block.content.contains(MARKER_PREFIX) {
+        bail!("The response contains {MARKER_PREFIX}...] markers, which would replace the secrets they stand for");
+    }
+    let is_remote = |path: &str| path.starts_with("https://") || split_member_path(path).is_some();
+    if is_remote(&block.path) {
+        bail!("Linked files and files inside archives cannot be written");
+    }
+    let Some(resolution) = result.resolutions.iter().find(|resolution| resolution.path == block.path) else {
+        return Ok((new_file_target(fs, &block.path, base_dir)?, None));
+    };
+
+    let canonical_path = &resolution.canonical_path;
+    if is_remote(&canonical_path.to_string_lossy()) {
+        bail!("Linked files and files inside archives cannot be written");
+    }
+    let current = fs
+        .read(canonical_path)
+        .with_context(|| format!("Failed to read file '{}'", canonical_path.display()))?;
+    // The lockfile hashes only what was embedded, so check that it was the whole file first
+    if current.len() != resolution.size {
+        bail!("Only part of the file was embedded, its content was transformed, or it changed since the prompt was built");
+    }
+    match lockfile.hash(canonical_path) {
+        Some(locked) if calculate_hash(&config.hashed_content(&current)) != *locked => bail!(
+            "The file changed since lockfile '{}' was written, so the response may be based on its earlier content",
+            lockfile.path().display()
+        ),
+        Some(_) => {}
+        None if !force => bail!(
+            "Lockfile '{}' has no entry for the file, so it cannot be checked for changes since the prompt was built; pass --force to write it anyway",
+            lockfile.path().display()
+        ),
+        None => {}
+    }
+    Ok((canonical_path.clone(), Some(current)))
+}
+
+/// The path of a new file at `path`, as written in a response, below `base_dir`.
+/// Refuses paths that leave `base_dir`, directly or through a symlink (even one
+/// that points nowhere yet), and paths where something exists already.
+fn new_file_target(fs: &dyn FileSystem, path: &str, base_dir: &Path) -> Result<PathBuf> {
+    let relative = Path::new(path);
+    if !relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_) | Component::CurDir))
+    {
+        bail!("New files must be below the template's directory");
+    }
+    let base = fs
+        .canonicalize(base_dir)
+        .with_context(|| format!("Failed to resolve directory '{}'", base_dir.display()))?;
+
+    let target = base.join(relative);
+
+    // Every existing ancestor below `base` must be a real directory; below the
+    // deepest one, nothing exists that could lead elsewhere
+    let mut existing = base.clone();
+    let mut ancestor = base.clone();
+    for component in relative.components() {
+        ancestor.push(component);
+        match fs.symlink_metadata(&ancestor) {
+            Ok(metadata) if metadata.kind == FileKind::Symlink => bail!(
+                "'{}' is a symlink, and new files are not written through symlinks",
+                ancestor.display()
+            ),
+            Ok(_) if ancestor == target => bail!("The file exists, but the template does not include it"),
+            Ok(_) => existing.clone_from(&ancestor),
+            Err(error) if error.kind() == io::ErrorKind::NotFound => break,
+            Err(error) => {
+                return Err(error).with_context(|| format!("Failed to inspect '{}'", ancestor.display()));
+            }
+        }
+    }
+    let existing = fs
+        .canonicalize(&existing)
+        .with_context(|| format!("Failed to resolve directory '{}'", existing.display()))?;
+    if !existing.starts_with(&base) {
+        bail!("New files must be below the template's directory");
+    }
+    Ok(target)
+}
+
+/// Reverses [`escape_xml_attribute`](crate::wrapper::escape_xml_attribute).
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ProcessOptions, process_template_content, vfs::MemoryFileSystem};
+    use std::sync::Arc;
+
+    const NOTES: &str = "first\nsecond\n";
+
+    fn block(path: &str, content: &str) -> FileBlock {
+        FileBlock {
+            path: path.to_string(),
+            content: content.to_string(),
+        }
+    }
+
+    /// Renders `template` below `/project` of `fs`, with a lockfile of that render.
+    fn render(fs: MemoryFileSystem, template: &str) -> (Arc<MemoryFileSystem>, ProcessResult, Lockfile) {
+        let fs = Arc::new(fs);
+        let options = ProcessOptions {
+            file_system: Some(fs.clone()),
+            ..Default::default()
+        };
+        let result = process_template_content(template, Path::new("/project"), "t.clamp", &options).unwrap();
+        let mut lockfile = Lockfile::new("/project/t.clamp.lock");
+        lockfile.update_from(&result);
+        (fs, result, lockfile)
+    }
+
+    fn project() -> MemoryFileSystem {
+        let mut fs = MemoryFileSystem::new();
+        fs.insert("/project/notes.txt", NOTES);
+        fs.insert("/project/src/other.rs", "");
+        fs.insert("/secret.txt", "");
+        fs
+    }
+
+    fn target(block: &FileBlock, template: &str, lockfile: Option<Lockfile>, force: bool) -> Result<(PathBuf, Option<Vec<u8>>)> {
+        let (fs, result, locked) = render(project(), template);
+        let lockfile = lockfile.unwrap_or(locked);
+        resolve_target(&*fs, block, Path::new("/project"), &result, &lockfile, &Config::default(), force)
+    }
+
+    #[test]
+    fn parses_labeled_code_blocks_and_file_tags() {
+        let response = "\
+Here you go.
+
+```rust src/lib.rs
+fn main() {}
+```
+
+### `notes.txt`
+
+```
+new notes
+```
+
+```
+an unlabeled example
+```
+
+<file path=\"docs/a&amp;b.md\">
+# Docs
+</file>
+
+```rust:src/lib.rs
+fn main() { println!(); }
+```
+";
+        assert_eq!(
+            parse_response(response),
+            vec![
+                block("notes.txt", "new notes\n"),
+                block("docs/a&b.md", "# Docs\n"),
+                block("src/lib.rs", "fn main() { println!(); }\n"),
+            ]
+        );
+    }
+
+    #[test]
+    fn drops_the_trailing_blank_line_of_clamps_wrappers() {
+        let response = "### `notes.txt`\n```\nalpha\n\n```\n";
+        assert_eq!(parse_response(response), vec![block("notes.txt", "alpha\n")]);
+    }
+
+    #[test]
+    fn writes_locked_includes_and_new_files_below_the_template() {
+        let (path, current) = target(&block("notes.txt", "new\n"), "[[include: notes.txt]]\n", None, false).unwrap();
+        assert_eq!((path, current), (PathBuf::from("/project/notes.txt"), Some(NOTES.as_bytes().to_vec())));
+
+        let (path, current) = target(&block("docs/new.md", "new\n"), "[[include: notes.txt]]\n", None, false).unwrap();
+        assert_eq!((path, current), (PathBuf::from("/project/docs/new.md"), None));
+    }
+
+    #[test]
+    fn refuses_paths_outside_the_template_directory() {
+        for path in ["../secret.txt", "docs/../../secret.txt", "/secret.txt", "/project/new.txt"] {
+            let error = target(&block(path, "x\n"), "", None, false).unwrap_err();
+            assert!(error.to_string().contains("below the template's directory"), "{path}: {error}");
+        }
+    }
+
+    #[test]
+    fn refuses_existing_files_the_template_does_not_include() {
+        let error = target(&block("src/other.rs", "x\n"), "", None, false).unwrap_err();
+        assert!(error.to_string().contains("does not include it"), "{error}");
+    }
+
+    #[test]
+    fn refuses_unlocked_changed_partial_and_redacted_blocks() {
+        let template = "[[include: notes.txt]]\n";
+        let unlocked = || Some(Lockfile::new("/project/t.clamp.lock"));
+        let error = target(&block("notes.txt", "x\n"), template, unlocked(), false).unwrap_err();
+        assert!(error.to_string().contains("--force"), "{error}");
+        assert!(target(&block("notes.txt", "x\n"), template, unlocked(), true).is_ok());
+
+        let mut stale = Lockfile::new("/project/t.clamp.lock");
+        stale.update_entry(Path::new("/project/notes.txt"), Some(calculate_hash(b"older\n")));
+        let error = target(&block("notes.txt", "x\n"), template, Some(stale), true).unwrap_err();
+        assert!(error.to_string().contains("changed since lockfile"), "{error}");
+
+        let error = target(&block("notes.txt", "x\n"), "[[include: notes.txt head=1]]\n", None, false).unwrap_err();
+        assert!(error.to_string().contains("Only part of the file"), "{error}");
+
+        let error = target(&block("notes.txt", "token [REDACTED:bearer-token]\n"), template, None, true).unwrap_err();
+        assert!(error.to_string().contains("markers"), "{error}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_new_files_through_symlinks() {
+        use crate::vfs::OsFileSystem;
+        use std::{fs, os::unix::fs::symlink};
+
+        let dir = std::env::temp_dir().join(format!("clamp-apply-{}", std::process::id()));
+        let (project, outside) = (dir.join("project"), dir.join("outside"));
+        fs::create_dir_all(&project).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        symlink(&outside, project.join("linked")).unwrap();
+        symlink(outside.join("missing.txt"), project.join("dangling.txt")).unwrap();
+
+        let result = process_template_content("", &project, "t.clamp", &ProcessOptions::default()).unwrap();
+        let lockfile = Lockfile::new(project.join("t.clamp.lock"));
+        let resolve = |path: &str| {
+            resolve_target(&OsFileSystem, &block(path, "x\n"), &project, &result, &lockfile, &Config::default(), true)
+        };
+        let outcomes = [resolve("linked/new.txt"), resolve("dangling.txt"), resolve("fresh/new.txt")];
+        fs::remove_dir_all(&dir).unwrap();
+
+        let [through_dir, dangling, fresh] = outcomes;
+        for error in [through_dir.unwrap_err(), dangling.unwrap_err()] {
+            assert!(error.to_string().contains("is a symlink"), "{error}");
+        }
+        assert_eq!(fresh.unwrap().1, None);
+    }
+}
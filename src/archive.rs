@@ -0,0 +1,658 @@
+use anyhow::{Context, Result, bail};
+
+/// File name endings of the archives whose files can be included.
+const ARCHIVE_EXTENSIONS: &[&str] = &[".zip", ".tar", ".tar.gz", ".tgz"];
+
+/// Most bytes a zip member or gzip stream may decompress to, so that a corrupt or
+/// crafted archive cannot exhaust memory.
+pub const MAX_INFLATED_SIZE: usize = 256 * 1024 * 1024;
+
+/// Splits an include path naming a file inside an archive, such as
+/// `vendor/deps.tar.gz!lib/parser.rs`, into the archive's path and the member's.
+/// Returns `None` for any other path.
+pub fn split_member_path(path: &str) -> Option<(&str, &str)> {
+    path.match_indices('!').find_map(|(index, _)| {
+        let (archive, member) = (&path[..index], &path[index + 1..]);
+        let lower = archive.to_ascii_lowercase();
+        let is_archive = ARCHIVE_EXTENSIONS.iter().any(|extension| lower.ends_with(extension));
+        (is_archive && !member.is_empty()).then_some((archive, member))
+    })
+}
+
+/// Reads the file `member` (a `/`-separated path) out of `archive`, the content of a
+/// zip, tar or gzip-compressed tar file, without extracting anything else. Returns
+/// `None` if the archive has no such file.
+///
+/// Zip members may be stored or deflated; encrypted members and ZIP64 archives are
+/// not supported. Compressed content may not inflate to more than [`MAX_INFLATED_SIZE`].
+pub fn read_member(archive: &[u8], member: &str) -> Result<Option<Vec<u8>>> {
+    let member = member.trim_start_matches("./");
+    if archive.starts_with(b"PK\x03\x04") || archive.starts_with(b"PK\x05\x06") {
+        zip_member(archive, member)
+    } else if archive.starts_with(&[0x1f, 0x8b]) {
+        let tar = gunzip(archive).context("Invalid gzip data")?;
+        tar_member(&tar, member)
+    } else {
+        tar_member(archive, member)
+    }
+}
+
+fn u16_at(data: &[u8], offset: usize) -> usize {
+    u16::from_le_bytes([data[offset], data[offset + 1]]) as usize
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+/// Looks `member` up in the central directory of a zip archive and decompresses it.
+fn zip_member(zip: &[u8], member: &str) -> Result<Option<Vec<u8>>> {
+    // The end of central directory record: 22 bytes and a comment of up to 64 KiB
+    if zip.len() < 22 {
+        bail!("Not a zip archive (missing end of central directory record)");
+    }
+    let last = zip.len() - 22;
+    let end = (last.saturating_sub(0xFFFF)..=last)
+        .rev()
+        .find(|&offset| zip[offset..].starts_with(b"PK\x05\x06"))
+        .context("Not a zip archive (missing end of central directory record)")?;
+    let count = u16_at(zip, end + 10);
+    let directory = u32_at(zip, end + 16);
+    if count == 0xFFFF || directory == 0xFFFF_FFFF {
+        bail!("ZIP64 archives are not supported");
+    }
+
+    let mut offset = directory as usize;
+    for _ in 0..count {
+        let header = zip
+            .get(offset..offset + 46)
+            .filter(|header| header.starts_with(b"PK\x01\x02"))
+            .context("Invalid central directory entry")?;
+        let name_end = offset + 46 + u16_at(header, 28);
+        let name = zip.get(offset + 46..name_end).context("Truncated central directory")?;
+        offset = name_end + u16_at(header, 30) + u16_at(header, 32);
+        if name != member.as_bytes() {
+            continue;
+        }
+
+        if u16_at(header, 8) & 1 != 0 {
+            bail!("'{member}' is encrypted");
+        }
+        let local = u32_at(header, 42) as usize;
+        let local_header = zip
+            .get(local..local + 30)
+            .filter(|header| header.starts_with(b"PK\x03\x04"))
+            .with_context(|| format!("Invalid local header of '{member}'"))?;
+        let start = local + 30 + u16_at(local_header, 26) + u16_at(local_header, 28);
+        let compressed_size = u32_at(header, 20) as usize;
+        let data = zip
+            .get(start..start + compressed_size)
+            .with_context(|| format!("Truncated data of '{member}'"))?;
+        // The sizes and checksum in the central directory are checked, not trusted
+        let size = u32_at(header, 24) as usize;
+        let content = match u16_at(header, 10) {
+            0 => data.to_vec(),
+            8 => {
+                let mut content = Vec::with_capacity(size.min(MAX_INFLATED_SIZE));
+                inflate(data, &mut content, MAX_INFLATED_SIZE)
+                    .with_context(|| format!("Invalid compressed data of '{member}'"))?;
+                content
+            }
+            method => bail!("'{member}' uses unsupported compression method {method}"),
+        };
+        if content.len() != size {
+            bail!(
+                "Size mismatch in '{member}' ({} bytes, {size} recorded in the archive)",
+                content.len()
+            );
+        }
+        if crc32(&content) != u32_at(header, 16) {
+            bail!("Checksum mismatch in '{member}'");
+        }
+        return Ok(Some(content));
+    }
+    Ok(None)
+}
+
+/// Finds `member` among the entries of a tar archive (ustar, GNU or pax).
+fn tar_member(tar: &[u8], member: &str) -> Result<Option<Vec<u8>>> {
+    if !tar.len().is_multiple_of(512) {
+        bail!("Not a tar archive (its size is not a multiple of 512 bytes)");
+    }
+    let mut offset = 0;
+    // Set by a GNU long name or pax header for the entry that follows it
+    let mut long_name: Option<String> = None;
+    while let Some(header) = tar.get(offset..offset + 512) {
+        if header.iter().all(|&byte| byte == 0) {
+            break; // End of archive
+        }
+        let checksum: u64 = header
+            .iter()
+            .enumerate()
+            .map(|(index, &byte)| if (148..156).contains(&index) { u64::from(b' ') } else { u64::from(byte) })
+            .sum();
+        if tar_number(&header[148..156])? != checksum {
+            bail!("Invalid tar header at offset {offset} (checksum mismatch)");
+        }
+        let size = usize::try_from(tar_number(&header[124..136])?)?;
+        let start = offset + 512;
+        let data = tar
+            .get(start..start.saturating_add(size))
+            .with_context(|| format!("Entry at offset {offset} extends past the end of the archive"))?;
+        offset = start + size.div_ceil(512) * 512;
+
+        let type_flag = header[156];
+        match type_flag {
+            b'L' => {
+                long_name = Some(field_text(data));
+                continue;
+            }
+            b'x' => {
+                long_name = pax_path(data).or(long_name);
+                continue;
+            }
+            _ => {}
+        }
+        let name = long_name.take().unwrap_or_else(|| {
+            let name = field_text(&header[0..100]);
+            let prefix = field_text(&header[345..500]);
+            if header[257..].starts_with(b"ustar") && !prefix.is_empty() {
+                format!("{prefix}/{name}")
+            } else {
+                name
+            }
+        });
+        if name.trim_start_matches("./") != member {
+            continue;
+        }
+        match type_flag {
+            b'0' | b'7' | 0 => return Ok(Some(data.to_vec())),
+            b'1' | b'2' => bail!("'{member}' is a link, which cannot be included"),
+            _ => bail!("'{member}' is not a file"),
+        }
+    }
+    Ok(None)
+}
+
+/// A NUL-terminated header field as text.
+fn field_text(field: &[u8]) -> String {
+    let end = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..end]).into_owned()
+}
+
+/// A numeric header field: octal digits, or big-endian binary if the high bit is set.
+fn tar_number(field: &[u8]) -> Result<u64> {
+    if field[0] & 0x80 != 0 {
+        return field[1..]
+            .iter()
+            .try_fold(u64::from(field[0] & 0x7F), |value, &byte| {
+                value.checked_mul(256).map(|value| value | u64::from(byte))
+            })
+            .context("Header number out of range");
+    }
+    let text = field_text(field);
+    let digits = text.trim_matches([' ', '\0']);
+    if digits.is_empty() {
+        return Ok(0);
+    }
+    u64::from_str_radix(digits, 8).with_context(|| format!("Invalid header number '{digits}'"))
+}
+
+/// The `path` record of a pax extended header (`<length> path=<value>\n`).
+fn pax_path(data: &[u8]) -> Option<String> {
+    let mut rest = data;
+    while !rest.is_empty() {
+        let space = rest.iter().position(|&byte| byte == b' ')?;
+        let length: usize = std::str::from_utf8(&rest[..space]).ok()?.parse().ok()?;
+        let record = rest.get(space + 1..length)?;
+        if let Some(value) = record.strip_prefix(b"path=") {
+            let value = value.strip_suffix(b"\n").unwrap_or(value);
+            return Some(String::from_utf8_lossy(value).into_owned());
+        }
+        rest = &rest[length..];
+    }
+    None
+}
+
+/// Decompresses gzip data, which may consist of several members, to at most
+/// [`MAX_INFLATED_SIZE`] bytes.
+fn gunzip(data: &[u8]) -> Result<Vec<u8>> {
+    let mut content = Vec::new();
+    let mut rest = data;
+    // Some tools pad the file with zeros
+    while rest.iter().any(|&byte| byte != 0) {
+        if rest.len() < 18 || !rest.starts_with(&[0x1f, 0x8b]) {
+            bail!("Missing gzip header");
+        }
+        if rest[2] != 8 {
+            bail!("Unsupported compression method {}", rest[2]);
+        }
+        let flags = rest[3];
+        let mut offset = 10;
+        if flags & 0x04 != 0 {
+            offset += 2 + u16_at(rest, offset); // Extra field
+        }
+        for flag in [0x08, 0x10] {
+            // File name and comment, NUL-terminated
+            if flags & flag != 0 {
+                let length = rest
+                    .get(offset..)
+                    .and_then(|field| field.iter().position(|&byte| byte == 0))
+                    .context("Truncated gzip header")?;
+                offset += length + 1;
+            }
+        }
+        if flags & 0x02 != 0 {
+            offset += 2; // Header checksum
+        }
+
+        let body = rest.get(offset..).context("Truncated gzip header")?;
+        let start = content.len();
+        let consumed = inflate(body, &mut content, MAX_INFLATED_SIZE)?;
+        let trailer = body.get(consumed..consumed + 8).context("Truncated gzip trailer")?;
+        if crc32(&content[start..]) != u32_at(trailer, 0)
+            || (content.len() - start) as u32 != u32_at(trailer, 4)
+        {
+            bail!("Checksum mismatch");
+        }
+        rest = &body[consumed + 8..];
+    }
+    Ok(content)
+}
+
+/// Decompresses zlib data (RFC 1950), the format git stores objects in, to at most
+/// `limit` bytes.
+pub(crate) fn zlib_decompress(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let header = data.get(..2).context("Missing zlib header")?;
+    // Deflate, a valid header checksum and no preset dictionary
+    if header[0] & 0x0F != 8 || u16::from_be_bytes([header[0], header[1]]) % 31 != 0 || header[1] & 0x20 != 0 {
+        bail!("Invalid zlib header");
+    }
+    let mut content = Vec::new();
+    let consumed = inflate(&data[2..], &mut content, limit)?;
+    let trailer = data.get(2 + consumed..2 + consumed + 4).context("Truncated zlib trailer")?;
+    if adler32(&content) != u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]) {
+        bail!("Checksum mismatch");
+    }
+    Ok(content)
+}
+
+/// The Adler-32 checksum used by zlib.
+pub(crate) fn adler32(data: &[u8]) -> u32 {
+    const MODULUS: u32 = 65521;
+    let (mut a, mut b) = (1u32, 0u32);
+    // The largest run of bytes that cannot overflow `b` before the modulo
+    for chunk in data.chunks(5552) {
+        for &byte in chunk {
+            a += u32::from(byte);
+            b += a;
+        }
+        a %= MODULUS;
+        b %= MODULUS;
+    }
+    (b << 16) | a
+}
+
+/// The CRC-32 checksum used by zip and gzip.
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in data {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+/// Reads the bits of a DEFLATE stream, least significant first.
+struct Bits<'a> {
+    data: &'a [u8],
+    /// Offset of the next byte to load
+    offset: usize,
+    buffer: u32,
+    count: u32,
+}
+
+impl Bits<'_> {
+    fn take(&mut self, count: u32) -> Result<u32> {
+        while self.count < count {
+            let byte = *self.data.get(self.offset).context("Unexpected end of compressed data")?;
+            self.offset += 1;
+            self.buffer |= u32::from(byte) << self.count;
+            self.count += 8;
+        }
+        let value = self.buffer & ((1 << count) - 1);
+        self.buffer >>= count;
+        self.count -= count;
+        Ok(value)
+    }
+
+    /// Drops the bits left of the current byte, returning whole loaded bytes.
+    fn align(&mut self) {
+        self.offset -= (self.count / 8) as usize;
+        self.buffer = 0;
+        self.count = 0;
+    }
+}
+
+/// A canonical Huffman code, as the number of codes of each length and the symbols
+/// in code order.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    /// The code for symbols with the given code `lengths` (0 for unused symbols).
+    fn new(lengths: &[u8]) -> Result<Self> {
+        let mut counts = [0u16; 16];
+        for &length in lengths {
+            counts[usize::from(length)] += 1;
+        }
+        counts[0] = 0;
+        let mut left: i32 = 1;
+        for &count in &counts[1..] {
+            left = (left << 1) - i32::from(count);
+            if left < 0 {
+                bail!("Invalid Huffman code lengths");
+            }
+        }
+
+        let mut offsets = [0u16; 16];
+        for length in 1..15 {
+            offsets[length + 1] = offsets[length] + counts[length];
+        }
+        let mut symbols = vec![0; lengths.len()];
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                let offset = &mut offsets[usize::from(length)];
+                symbols[usize::from(*offset)] = symbol as u16;
+                *offset += 1;
+            }
+        }
+        Ok(Self { counts, symbols })
+    }
+
+    fn decode(&self, bits: &mut Bits<'_>) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for &count in &self.counts[1..] {
+            code |= bits.take(1)? as i32;
+            let count = i32::from(count);
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        bail!("Invalid Huffman code")
+    }
+}
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195,
+    227, 258,
+];
+const LENGTH_EXTRA_BITS: [u8; 29] = [0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537, 2049, 3073,
+    4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA_BITS: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+/// The order in which the code lengths of the code length code are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Decompresses a raw DEFLATE stream (RFC 1951) from the start of `data`, appending
+/// to `out` until it holds `limit` bytes. Returns the number of bytes the stream took up.
+fn inflate(data: &[u8], out: &mut Vec<u8>, limit: usize) -> Result<usize> {
+    let mut bits = Bits { data, offset: 0, buffer: 0, count: 0 };
+    loop {
+        let last = bits.take(1)? == 1;
+        match bits.take(2)? {
+            0 => {
+                bits.align();
+                let header = data.get(bits.offset..bits.offset + 4).context("Truncated stored block")?;
+                let length = u16_at(header, 0);
+                if length != !u16_at(header, 2) & 0xFFFF {
+                    bail!("Invalid stored block length");
+                }
+                let start = bits.offset + 4;
+                check_limit(out.len() + length, limit)?;
+                out.extend_from_slice(data.get(start..start + length).context("Truncated stored block")?);
+                bits.offset = start + length;
+            }
+            1 => {
+                let mut lengths = [8u8; 288];
+                lengths[144..256].fill(9);
+                lengths[256..280].fill(7);
+                inflate_block(&mut bits, out, limit, &Huffman::new(&lengths)?, &Huffman::new(&[5; 30])?)?;
+            }
+            2 => {
+                let (literals, distances) = dynamic_codes(&mut bits)?;
+                inflate_block(&mut bits, out, limit, &literals, &distances)?;
+            }
+            _ => bail!("Invalid block type"),
+        }
+        if last {
+            break;
+        }
+    }
+    bits.align();
+    Ok(bits.offset)
+}
+
+fn check_limit(size: usize, limit: usize) -> Result<()> {
+    if size > limit {
+        bail!("Decompressed data exceeds {limit} bytes");
+    }
+    Ok(())
+}
+
+/// Reads the literal/length and distance codes of a dynamic Huffman block.
+fn dynamic_codes(bits: &mut Bits<'_>) -> Result<(Huffman, Huffman)> {
+    let literal_count = bits.take(5)? as usize + 257;
+    let distance_count = bits.take(5)? as usize + 1;
+    let code_length_count = bits.take(4)? as usize + 4;
+    if literal_count > 286 || distance_count > 30 {
+        bail!("Invalid code counts");
+    }
+    let mut code_lengths = [0u8; 19];
+    for &symbol in &CODE_LENGTH_ORDER[..code_length_count] {
+        code_lengths[symbol] = bits.take(3)? as u8;
+    }
+    let code_length_code = Huffman::new(&code_lengths)?;
+
+    let total = literal_count + distance_count;
+    let mut lengths = vec![0u8; total];
+    let mut index = 0;
+    while index < total {
+        let symbol = code_length_code.decode(bits)?;
+        if symbol < 16 {
+            lengths[index] = symbol as u8;
+            index += 1;
+            continue;
+        }
+        let (length, repeat) = match symbol {
+            16 if index > 0 => (lengths[index - 1], 3 + bits.take(2)?),
+            16 => bail!("Repeated code length without a previous one"),
+            17 => (0, 3 + bits.take(3)?),
+            _ => (0, 11 + bits.take(7)?),
+        };
+        let end = index + repeat as usize;
+        if end > total {
+            bail!("Too many code lengths");
+        }
+        lengths[index..end].fill(length);
+        index = end;
+    }
+    if lengths[256] == 0 {
+        bail!("Missing end-of-block code");
+    }
+    Ok((Huffman::new(&lengths[..literal_count])?, Huffman::new(&lengths[literal_count..])?))
+}
+
+/// Decodes the symbols of a compressed block up to its end-of-block code.
+fn inflate_block(
+    bits: &mut Bits<'_>,
+    out: &mut Vec<u8>,
+    limit: usize,
+    literals: &Huffman,
+    distances: &Huffman,
+) -> Result<()> {
+    loop {
+        let symbol = usize::from(literals.decode(bits)?);
+        if symbol < 256 {
+            check_limit(out.len() + 1, limit)?;
+            out.push(symbol as u8);
+            continue;
+        }
+        if symbol == 256 {
+            return Ok(());
+        }
+        let index = symbol - 257;
+        if index >= LENGTH_BASES.len() {
+            bail!("Invalid length code");
+        }
+        let length = usize::from(LENGTH_BASES[index]) + bits.take(u32::from(LENGTH_EXTRA_BITS[index]))? as usize;
+        let index = usize::from(distances.decode(bits)?);
+        if index >= DISTANCE_BASES.len() {
+            bail!("Invalid distance code");
+        }
+        let distance =
+            usize::from(DISTANCE_BASES[index]) + bits.take(u32::from(DISTANCE_EXTRA_BITS[index]))? as usize;
+        if distance > out.len() {
+            bail!("Distance too far back");
+        }
+        check_limit(out.len() + length, limit)?;
+        let start = out.len() - distance;
+        for offset in 0..length {
+            out.push(out[start + offset]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Made with `zip -X` (`-0` for stored.zip, `-9` for deflated.zip) and GNU tar
+    // (`--format=gnu`, `--format=pax`, `--format=ustar` piped through `gzip -9n`)
+    const STORED_ZIP: &[u8] = include_bytes!("../tests/fixtures/archive/stored.zip");
+    const DEFLATED_ZIP: &[u8] = include_bytes!("../tests/fixtures/archive/deflated.zip");
+    const GNU_TAR: &[u8] = include_bytes!("../tests/fixtures/archive/gnu.tar");
+    const PAX_TAR: &[u8] = include_bytes!("../tests/fixtures/archive/pax.tar");
+    const USTAR_TAR_GZ: &[u8] = include_bytes!("../tests/fixtures/archive/ustar.tar.gz");
+
+    /// Directory of the fixtures' member whose path is longer than 100 bytes
+    const LONG_DIR: &str = "a-rather-long-directory-name/a-rather-long-directory-name/a-rather-long-directory-name/a-rather-long-directory-name/a-rather-long-directory-name";
+
+    fn lines() -> Vec<u8> {
+        (0..2000).map(|index| format!("line {index}\n")).collect::<String>().into_bytes()
+    }
+
+    fn long_name() -> String {
+        format!("{LONG_DIR}/long.txt")
+    }
+
+    /// Offset of the central directory entry of `name` in `zip`.
+    fn central_entry(zip: &[u8], name: &str) -> usize {
+        (0..zip.len() - 46)
+            .find(|&offset| zip[offset..].starts_with(b"PK\x01\x02") && zip[offset + 46..].starts_with(name.as_bytes()))
+            .expect("entry exists")
+    }
+
+    #[test]
+    fn splits_member_paths() {
+        assert_eq!(
+            split_member_path("vendor/deps.tar.gz!lib/parser.rs"),
+            Some(("vendor/deps.tar.gz", "lib/parser.rs"))
+        );
+        assert_eq!(split_member_path("docs/A.ZIP!README.md"), Some(("docs/A.ZIP", "README.md")));
+        assert_eq!(split_member_path("notes!.txt"), None);
+        assert_eq!(split_member_path("deps.zip!"), None);
+    }
+
+    #[test]
+    fn reads_stored_zip_members() {
+        assert_eq!(read_member(STORED_ZIP, "lines.txt").unwrap(), Some(lines()));
+        assert_eq!(read_member(STORED_ZIP, "./hello.txt").unwrap(), Some(b"hello\n".to_vec()));
+        assert_eq!(read_member(STORED_ZIP, "missing.txt").unwrap(), None);
+    }
+
+    #[test]
+    fn reads_deflated_zip_members() {
+        assert_eq!(read_member(DEFLATED_ZIP, "lines.txt").unwrap(), Some(lines()));
+        assert_eq!(read_member(DEFLATED_ZIP, "hello.txt").unwrap(), Some(b"hello\n".to_vec()));
+    }
+
+    #[test]
+    fn reads_gnu_long_names() {
+        assert_eq!(read_member(GNU_TAR, "hello.txt").unwrap(), Some(b"hello\n".to_vec()));
+        assert_eq!(read_member(GNU_TAR, &long_name()).unwrap(), Some(b"hello\n".to_vec()));
+    }
+
+    #[test]
+    fn reads_pax_paths() {
+        assert_eq!(read_member(PAX_TAR, &long_name()).unwrap(), Some(b"hello\n".to_vec()));
+        assert_eq!(read_member(PAX_TAR, "long.txt").unwrap(), None);
+    }
+
+    #[test]
+    fn reads_gzipped_ustar_members() {
+        assert_eq!(read_member(USTAR_TAR_GZ, "lines.txt").unwrap(), Some(lines()));
+        assert_eq!(read_member(USTAR_TAR_GZ, &long_name()).unwrap(), Some(b"hello\n".to_vec()));
+    }
+
+    #[test]
+    fn rejects_wrong_zip_sizes_and_checksums() {
+        let entry = central_entry(DEFLATED_ZIP, "lines.txt");
+
+        let mut understated = DEFLATED_ZIP.to_vec();
+        understated[entry + 24..entry + 28].copy_from_slice(&100u32.to_le_bytes());
+        let error = read_member(&understated, "lines.txt").unwrap_err();
+        assert!(format!("{error:#}").contains("Size mismatch"), "{error:#}");
+
+        // A huge recorded size is not allocated up front
+        let mut overstated = DEFLATED_ZIP.to_vec();
+        overstated[entry + 24..entry + 28].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(read_member(&overstated, "lines.txt").is_err());
+
+        let mut corrupt = DEFLATED_ZIP.to_vec();
+        corrupt[entry + 16] ^= 0xFF;
+        let error = read_member(&corrupt, "lines.txt").unwrap_err();
+        assert!(format!("{error:#}").contains("Checksum mismatch"), "{error:#}");
+    }
+
+    #[test]
+    fn limits_inflated_size() {
+        let entry = central_entry(DEFLATED_ZIP, "lines.txt");
+        let compressed_size = u32_at(DEFLATED_ZIP, entry + 20) as usize;
+        let local = u32_at(DEFLATED_ZIP, entry + 42) as usize;
+        let start = local + 30 + u16_at(DEFLATED_ZIP, local + 26) + u16_at(DEFLATED_ZIP, local + 28);
+        let data = &DEFLATED_ZIP[start..start + compressed_size];
+
+        let mut out = Vec::new();
+        assert_eq!(inflate(data, &mut out, usize::MAX).unwrap(), compressed_size);
+        assert_eq!(out, lines());
+        let error = inflate(data, &mut Vec::new(), 1000).unwrap_err();
+        assert!(error.to_string().contains("exceeds 1000 bytes"), "{error}");
+    }
+
+    #[test]
+    fn rejects_corrupt_tar_headers() {
+        let mut corrupt = GNU_TAR.to_vec();
+        corrupt[0] ^= 0xFF;
+        let error = read_member(&corrupt, "hello.txt").unwrap_err();
+        assert!(error.to_string().contains("checksum mismatch"), "{error}");
+        assert!(read_member(&GNU_TAR[..1000], "hello.txt").is_err());
+    }
+
+    #[test]
+    fn computes_crc32() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
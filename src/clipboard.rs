@@ -0,0 +1,71 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+};
+
+/// Candidate clipboard programs for the current platform, in order of preference.
+/// Each entry is the program name followed by its arguments.
+fn clipboard_commands() -> Vec<(&'static str, &'static [&'static str])> {
+    if cfg!(target_os = "macos") {
+        vec![("pbcopy", &[])]
+    } else if cfg!(target_os = "windows") {
+        vec![("clip", &[])]
+    } else {
+        let mut commands: Vec<(&'static str, &'static [&'static str])> = Vec::new();
+        // Prefer the Wayland tool when running under a Wayland session
+        if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+            commands.push(("wl-copy", &[]));
+        }
+        commands.push(("xclip", &["-selection", "clipboard"]));
+        commands.push(("xsel", &["--clipboard", "--input"]));
+        commands
+    }
+}
+
+/// Places `content` on the system clipboard.
+///
+/// Uses the platform's clipboard utility (`pbcopy` on macOS, `clip` on Windows,
+/// `wl-copy`/`xclip`/`xsel` elsewhere), trying each candidate until one succeeds.
+///
+/// Returns an error if no clipboard utility is available or all of them fail.
+pub fn copy_to_clipboard(content: &str) -> Result<()> {
+    let mut tried = Vec::new();
+
+    for (program, args) in clipboard_commands() {
+        let child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+
+        let mut child = match child {
+            Ok(child) => child,
+            Err(_) => {
+                tried.push(program);
+                continue; // Not installed, try the next one
+            }
+        };
+
+        child
+            .stdin
+            .take()
+            .context("Failed to open clipboard program stdin")?
+            .write_all(content.as_bytes())
+            .with_context(|| format!("Failed to write output to '{program}'"))?;
+
+        let status = child
+            .wait()
+            .with_context(|| format!("Failed to wait for '{program}'"))?;
+        if status.success() {
+            return Ok(());
+        }
+        tried.push(program);
+    }
+
+    bail!(
+        "No working clipboard utility found (tried: {})",
+        tried.join(", ")
+    )
+}
@@ -0,0 +1,492 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    env,
+    ffi::OsString,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ignore::IgnoreRules,
+    redact::Redactor,
+    vfs::FileSystem,
+    wrapper::{WrapStyle, WrapperTemplate},
+};
+
+/// Name of the project configuration file.
+pub const CONFIG_FILE_NAME: &str = "clamp.toml";
+
+/// Project-wide settings read from `clamp.toml`:
+///
+/// ```toml
+/// wrap = "review"
+/// snapshots = true
+/// normalize = "lf"
+/// include_roots = ["./", "../shared-snippets/", "~/.config/clamp/snippets/"]
+/// pin = ["vendor/", "docs/spec.md"]
+///
+/// [wrappers.review]
+/// prefix = "--- {path} ({size} bytes) ---\n"
+/// suffix = "--- end of {path} ---\n"
+///
+/// [languages]
+/// "Dockerfile" = "docker"
+/// "yml.j2" = "yaml"
+///
+/// [lint]
+/// max_include_size = 262144
+/// allow = ["outside-repo"]
+///
+/// [redact]
+/// disable = ["bearer-token"]
+///
+/// [redact.rules]
+/// internal-token = "itk_[a-z0-9]{32}"
+/// ```
+///
+/// Template frontmatter and command-line flags take precedence over these settings,
+/// which in turn take precedence over the `CLAMP_*` environment variables listed in
+/// [`ENV_VARS`].
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    /// Default embedding format for all templates.
+    pub wrap: Option<WrapStyle>,
+    /// Store a copy of every locked include under `.clamp/objects/` at `update-lock`
+    /// time, so `clamp diff` can show what changed since.
+    #[serde(default)]
+    pub snapshots: bool,
+    /// Line endings included files are converted to before they are hashed and
+    /// embedded.
+    #[serde(default)]
+    pub normalize: LineEndings,
+    /// Hash included files as they are on disk even when `normalize` converts them,
+    /// so that the lockfile still tracks changes to line endings.
+    #[serde(default)]
+    pub hash_raw: bool,
+    /// Directories searched in order for a relative include that is not found next
+    /// to the template. Relative roots are resolved against the directory of
+    /// `clamp.toml`, and a leading `~/` against the home directory, when loading.
+    #[serde(default)]
+    pub include_roots: Vec<PathBuf>,
+    /// Gitignore-style patterns, relative to the directory of `clamp.toml`, of the
+    /// includes locked with [`LockPolicy::Pin`](crate::LockPolicy::Pin) unless their
+    /// directive sets a `policy=`.
+    #[serde(default)]
+    pub pin: Vec<String>,
+    /// [`pin`](Self::pin) compiled against the canonical directory of `clamp.toml`
+    #[serde(skip)]
+    pin_rules: Option<(PathBuf, IgnoreRules)>,
+    /// User-defined wrapper templates, selectable by name as a wrap style.
+    #[serde(default)]
+    pub wrappers: BTreeMap<String, WrapperTemplate>,
+    /// Language hint overrides, keyed by file name or extension (without the dot).
+    #[serde(default)]
+    pub languages: BTreeMap<String, String>,
+    /// Settings for `clamp lint`.
+    #[serde(default)]
+    pub lint: LintConfig,
+    /// Secrets redaction applied to included content.
+    #[serde(default)]
+    pub redact: RedactConfig,
+}
+
+/// Line-ending conversions for the `normalize` setting of `clamp.toml`.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEndings {
+    /// Keep files byte for byte.
+    #[default]
+    None,
+    /// Convert CRLF line endings to LF.
+    Lf,
+}
+
+impl LineEndings {
+    /// `content` with its line endings converted.
+    pub fn apply(self, content: &str) -> Cow<'_, str> {
+        match self {
+            LineEndings::Lf if content.contains("\r\n") => Cow::Owned(content.replace("\r\n", "\n")),
+            _ => Cow::Borrowed(content),
+        }
+    }
+}
+
+/// The `[lint]` table of `clamp.toml`.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct LintConfig {
+    /// Includes larger than this many bytes are flagged by `large-include`.
+    pub max_include_size: Option<u64>,
+    /// Lint IDs disabled for the whole project.
+    #[serde(default)]
+    pub allow: Vec<String>,
+}
+
+/// The `[redact]` table of `clamp.toml` (see [`Redactor`]).
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RedactConfig {
+    /// Whether secrets are redacted at all (default: on).
+    #[serde(default = "enabled_by_default")]
+    pub enabled: bool,
+    /// Built-in rules that are turned off.
+    #[serde(default)]
+    pub disable: Vec<String>,
+    /// User-defined rules: name -> regex. A `secret` group limits the replacement to
+    /// that part of the match.
+    #[serde(default)]
+    pub rules: BTreeMap<String, String>,
+}
+
+impl Default for RedactConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            disable: Vec::new(),
+            rules: BTreeMap::new(),
+        }
+    }
+}
+
+fn enabled_by_default() -> bool {
+    true
+}
+
+/// Kinds of values of [`ENV_VARS`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvValue {
+    String,
+    Bool,
+    Integer,
+    /// Separated like `PATH` (`:` on Unix, `;` on Windows)
+    Paths,
+    /// Comma-separated
+    List,
+}
+
+/// What a variable of [`ENV_VARS`] provides the default for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvTarget {
+    /// A `clamp.toml` setting, by its key (with its table)
+    Setting(&'static [&'static str]),
+    /// A command-line option without a `clamp.toml` equivalent, by its long name
+    Option(&'static str),
+}
+
+/// Every `CLAMP_*` environment variable: the variable, what it is the default for and
+/// the kind of value. They are the lowest layer of settings, below `clamp.toml`,
+/// template frontmatter and command-line flags.
+pub const ENV_VARS: &[(&str, EnvTarget, EnvValue)] = &[
+    ("CLAMP_WRAP", EnvTarget::Setting(&["wrap"]), EnvValue::String),
+    ("CLAMP_SNAPSHOTS", EnvTarget::Setting(&["snapshots"]), EnvValue::Bool),
+    ("CLAMP_NORMALIZE", EnvTarget::Setting(&["normalize"]), EnvValue::String),
+    ("CLAMP_HASH_RAW", EnvTarget::Setting(&["hash_raw"]), EnvValue::Bool),
+    ("CLAMP_INCLUDE_ROOTS", EnvTarget::Setting(&["include_roots"]), EnvValue::Paths),
+    ("CLAMP_LINT_MAX_INCLUDE_SIZE", EnvTarget::Setting(&["lint", "max_include_size"]), EnvValue::Integer),
+    ("CLAMP_LINT_ALLOW", EnvTarget::Setting(&["lint", "allow"]), EnvValue::List),
+    ("CLAMP_REDACT", EnvTarget::Setting(&["redact", "enabled"]), EnvValue::Bool),
+    ("CLAMP_BASE_DIR", EnvTarget::Option("base-dir"), EnvValue::String),
+    ("CLAMP_LOCKFILE", EnvTarget::Option("lockfile"), EnvValue::String),
+    ("CLAMP_STRICT", EnvTarget::Option("strict"), EnvValue::Bool),
+    ("CLAMP_SYMLINKS", EnvTarget::Option("symlinks"), EnvValue::String),
+    ("CLAMP_NO_IGNORE", EnvTarget::Option("no-ignore"), EnvValue::Bool),
+    ("CLAMP_KEEP_GOING", EnvTarget::Option("keep-going"), EnvValue::Bool),
+    ("CLAMP_COLOR", EnvTarget::Option("color"), EnvValue::String),
+    ("CLAMP_LOCKED", EnvTarget::Option("locked"), EnvValue::Bool),
+    ("CLAMP_LOG_FORMAT", EnvTarget::Option("log-format"), EnvValue::String),
+];
+
+impl Config {
+    /// Reads and parses the configuration file at `path` in `fs`, layered over the
+    /// settings of the `CLAMP_*` environment variables.
+    pub fn load(fs: &dyn FileSystem, path: &Path) -> Result<Self> {
+        Self::load_with_env(fs, path, &os_env)
+    }
+
+    fn load_with_env(fs: &dyn FileSystem, path: &Path, lookup: EnvLookup) -> Result<Self> {
+        let content = fs
+            .read_to_string(path)
+            .with_context(|| format!("Failed to read config file '{}'", path.display()))?;
+        let file: toml::Table = toml::from_str(&content)
+            .with_context(|| format!("Failed to parse config file '{}'", path.display()))?;
+        let mut settings = env_settings(lookup)?;
+        merge_tables(&mut settings, file);
+        let mut config: Self = toml::Value::Table(settings)
+            .try_into()
+            .with_context(|| format!("Invalid settings in config file '{}' or CLAMP_* variables", path.display()))?;
+        let config_dir = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        config.include_roots = config
+            .include_roots
+            .iter()
+            .map(|root| resolve_root(config_dir, root))
+            .collect::<Result<_>>()
+            .with_context(|| format!("Invalid include_roots in config file '{}'", path.display()))?;
+        if !config.pin.is_empty() {
+            let root = fs
+                .canonicalize(config_dir)
+                .with_context(|| format!("Failed to resolve directory '{}'", config_dir.display()))?;
+            let mut rules = IgnoreRules::new();
+            rules.add_patterns(&root, &config.pin.join("\n"));
+            config.pin_rules = Some((root, rules));
+        }
+        Redactor::new(&config.redact)
+            .with_context(|| format!("Invalid [redact] settings in config file '{}'", path.display()))?;
+        Ok(config)
+    }
+
+    /// Loads the nearest `clamp.toml` in `start` or any of its ancestors in `fs`.
+    /// Returns the settings of the `CLAMP_*` environment variables if there is none.
+    pub fn discover(fs: &dyn FileSystem, start: &Path) -> Result<Self> {
+        Self::discover_with_env(fs, start, &os_env)
+    }
+
+    fn discover_with_env(fs: &dyn FileSystem, start: &Path, lookup: EnvLookup) -> Result<Self> {
+        match find_config_file(fs, start) {
+            Some(path) => Self::load_with_env(fs, &path, lookup),
+            None => Self::from_env_with(lookup),
+        }
+    }
+
+    /// The part of a file's `content` that the lockfile hash covers: the content with
+    /// line endings converted by [`normalize`](Self::normalize), or as-is with
+    /// [`hash_raw`](Self::hash_raw) or if it is not UTF-8. [Redaction](Self::redact)
+    /// is never applied, so a changed secret changes the hash.
+    pub fn hashed_content<'a>(&self, content: &'a [u8]) -> Cow<'a, [u8]> {
+        match std::str::from_utf8(content) {
+            Ok(text) if !self.hash_raw => match self.normalize.apply(text) {
+                Cow::Borrowed(_) => Cow::Borrowed(content),
+                Cow::Owned(converted) => Cow::Owned(converted.into_bytes()),
+            },
+            _ => Cow::Borrowed(content),
+        }
+    }
+
+    /// Whether the include at the canonical `path` matches a [`pin`](Self::pin) pattern.
+    pub fn is_pinned(&self, fs: &dyn FileSystem, path: &Path) -> bool {
+        self.pin_rules
+            .as_ref()
+            .is_some_and(|(root, rules)| rules.is_ignored_within(fs, root, path))
+    }
+
+    /// The settings of the `CLAMP_*` environment variables, with defaults for the rest.
+    pub fn from_env() -> Result<Self> {
+        Self::from_env_with(&os_env)
+    }
+
+    fn from_env_with(lookup: EnvLookup) -> Result<Self> {
+        toml::Value::Table(env_settings(lookup)?)
+            .try_into()
+            .context("Invalid settings in CLAMP_* environment variables")
+    }
+}
+
+/// Looks up an environment variable by name; [`os_env`] outside of tests.
+type EnvLookup<'a> = &'a dyn Fn(&str) -> Option<OsString>;
+
+fn os_env(name: &str) -> Option<OsString> {
+    env::var_os(name)
+}
+
+/// The [`ENV_VARS`] for settings that are set, as a table shaped like `clamp.toml`.
+/// Relative include roots are resolved against the current directory.
+fn env_settings(lookup: EnvLookup) -> Result<toml::Table> {
+    let mut settings = toml::Table::new();
+    for (name, target, kind) in ENV_VARS {
+        let EnvTarget::Setting(key) = target else {
+            continue;
+        };
+        let Some(value) = env_value(lookup, name, *kind)? else {
+            continue;
+        };
+        let (last, tables) = key.split_last().expect("Setting keys are not empty");
+        let mut table = &mut settings;
+        for name in tables {
+            table = table
+                .entry(*name)
+                .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+                .as_table_mut()
+                .expect("Setting tables are tables");
+        }
+        table.insert((*last).to_string(), value);
+    }
+    Ok(settings)
+}
+
+/// The value of the [`ENV_VARS`] variable for the command-line `option` (its long
+/// name, e.g. `lockfile`), if it is set: a string, or a boolean for flags.
+pub fn env_option(option: &str) -> Result<Option<toml::Value>> {
+    let (name, _, kind) = ENV_VARS
+        .iter()
+        .find(|(_, target, _)| matches!(target, EnvTarget::Option(name) if *name == option))
+        .with_context(|| format!("No environment variable for option '--{option}'"))?;
+    env_value(&os_env, name, *kind)
+}
+
+/// The variable `name` parsed as `kind`, if it is set.
+fn env_value(lookup: EnvLookup, name: &str, kind: EnvValue) -> Result<Option<toml::Value>> {
+    let Some(raw) = lookup(name) else {
+        return Ok(None);
+    };
+    let text = raw.to_string_lossy();
+    let value = match kind {
+        EnvValue::String => toml::Value::String(text.into_owned()),
+        EnvValue::Bool => match text.as_ref() {
+            "1" | "true" | "yes" | "on" => toml::Value::Boolean(true),
+            "0" | "false" | "no" | "off" | "" => toml::Value::Boolean(false),
+            _ => bail!("Invalid value '{text}' for {name}: expected true or false"),
+        },
+        EnvValue::Integer => toml::Value::Integer(
+            text.parse()
+                .with_context(|| format!("Invalid value '{text}' for {name}: expected a number"))?,
+        ),
+        EnvValue::Paths => {
+            let cwd = env::current_dir().context("Failed to determine current directory")?;
+            let roots = env::split_paths(&raw)
+                .filter(|root| !root.as_os_str().is_empty())
+                .map(|root| {
+                    resolve_root(&cwd, &root)
+                        .map(|root| toml::Value::String(root.to_string_lossy().into_owned()))
+                })
+                .collect::<Result<_>>()
+                .with_context(|| format!("Invalid value for {name}"))?;
+            toml::Value::Array(roots)
+        }
+        EnvValue::List => toml::Value::Array(
+            text.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        ),
+    };
+    Ok(Some(value))
+}
+
+/// Merges `overlay` into `base`, key by key within nested tables.
+fn merge_tables(base: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => merge_tables(base, overlay),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Resolves an include root as written in `clamp.toml` located in `config_dir`.
+fn resolve_root(config_dir: &Path, root: &Path) -> Result<PathBuf> {
+    if let Ok(rest) = root.strip_prefix("~") {
+        let home = env::var_os("HOME")
+            .with_context(|| format!("Cannot expand '{}': HOME is not set", root.display()))?;
+        return Ok(PathBuf::from(home).join(rest));
+    }
+    Ok(config_dir.join(root))
+}
+
+/// Finds the nearest `clamp.toml` in `start` or any of its ancestors in `fs`.
+pub fn find_config_file(fs: &dyn FileSystem, start: &Path) -> Option<PathBuf> {
+    let start = fs.canonicalize(start).ok()?;
+    start
+        .ancestors()
+        .map(|dir| dir.join(CONFIG_FILE_NAME))
+        .find(|path| fs.is_file(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{ProcessOptions, process_template, vfs::MemoryFileSystem};
+
+    /// A project at `/project` with `clamp.toml` holding `config`, and `page.clamp`
+    /// including `a.txt` below `frontmatter`.
+    fn project(config: Option<&str>, frontmatter: &str) -> MemoryFileSystem {
+        let mut files = MemoryFileSystem::new();
+        if let Some(config) = config {
+            files.insert("/project/clamp.toml", config);
+        }
+        files.insert("/project/page.clamp", format!("{frontmatter}[[include: a.txt]]\n"));
+        files.insert("/project/a.txt", "hi\n");
+        files
+    }
+
+    fn discover(files: &MemoryFileSystem, vars: &[(&str, &str)]) -> Result<Config> {
+        let lookup = |name: &str| {
+            vars.iter().find(|(var, _)| *var == name).map(|(_, value)| OsString::from(value))
+        };
+        Config::discover_with_env(files, Path::new("/project"), &lookup)
+    }
+
+    /// Renders `page.clamp` with the configuration discovered under `vars` and the
+    /// `--wrap` flag `wrap`, and returns the wrap style the output was rendered in.
+    fn rendered_style(files: MemoryFileSystem, vars: &[(&str, &str)], wrap: Option<WrapStyle>) -> &'static str {
+        let options = ProcessOptions {
+            config: discover(&files, vars).unwrap(),
+            wrap,
+            file_system: Some(Arc::new(files)),
+            ..Default::default()
+        };
+        let output = process_template(Path::new("/project/page.clamp"), &options).unwrap().output_content;
+        if output.contains("<file path=\"a.txt\">") {
+            "xml"
+        } else if output.contains("==> a.txt <==") {
+            "none"
+        } else if output.contains("```") {
+            "markdown"
+        } else {
+            panic!("unexpected output {output:?}")
+        }
+    }
+
+    #[test]
+    fn wrap_layers_env_config_frontmatter_and_flags() {
+        let env = [("CLAMP_WRAP", "xml")];
+        let config = Some("wrap = \"none\"\n");
+        let frontmatter = "+++\nwrap = \"markdown\"\n+++\n";
+
+        assert_eq!(rendered_style(project(None, ""), &[], None), "markdown");
+        assert_eq!(rendered_style(project(None, ""), &env, None), "xml");
+        assert_eq!(rendered_style(project(config, ""), &env, None), "none");
+        assert_eq!(rendered_style(project(config, frontmatter), &env, None), "markdown");
+        assert_eq!(rendered_style(project(config, frontmatter), &env, Some(WrapStyle::Xml)), "xml");
+    }
+
+    #[test]
+    fn env_settings_merge_below_nested_tables() {
+        let env = [
+            ("CLAMP_LINT_MAX_INCLUDE_SIZE", "10"),
+            ("CLAMP_LINT_ALLOW", "large-include, outside-repo"),
+            ("CLAMP_SNAPSHOTS", "yes"),
+            ("CLAMP_HASH_RAW", "off"),
+        ];
+        let config = discover(&project(Some("hash_raw = true\n[lint]\nallow = [\"duplicate-include\"]\n"), ""), &env).unwrap();
+        assert_eq!(config.lint.max_include_size, Some(10));
+        assert_eq!(config.lint.allow, ["duplicate-include"]);
+        assert!(config.snapshots);
+        assert!(config.hash_raw);
+
+        let config = discover(&project(None, ""), &env).unwrap();
+        assert_eq!(config.lint.allow, ["large-include", "outside-repo"]);
+        assert!(!config.hash_raw);
+    }
+
+    #[test]
+    fn rejects_invalid_env_values() {
+        let error = discover(&project(None, ""), &[("CLAMP_SNAPSHOTS", "maybe")]).unwrap_err();
+        assert_eq!(format!("{error:#}"), "Invalid value 'maybe' for CLAMP_SNAPSHOTS: expected true or false");
+
+        let error = discover(&project(Some(""), ""), &[("CLAMP_LINT_MAX_INCLUDE_SIZE", "big")]).unwrap_err();
+        assert_eq!(format!("{error:#}"), "Invalid value 'big' for CLAMP_LINT_MAX_INCLUDE_SIZE: expected a number: invalid digit found in string");
+
+        let error = discover(&project(Some(""), ""), &[("CLAMP_NORMALIZE", "sideways")]).unwrap_err();
+        assert!(format!("{error:#}").starts_with("Invalid settings in config file '/project/clamp.toml' or CLAMP_* variables"), "{error:#}");
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt::Write;
+
+/// Lines of unchanged context around each change in [`unified_diff`] output.
+const CONTEXT_LINES: usize = 3;
+
+/// Edit distance beyond which the diff gives up on a minimal result and replaces
+/// the differing region as a whole, bounding time and memory on unrelated files.
+const MAX_EDIT_DISTANCE: usize = 2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Edit {
+    Keep,
+    Delete,
+    Insert,
+}
+
+/// Line-level diff of `old` and `new` in unified format (`---`/`+++` headers with
+/// the given labels, then `@@` hunks). Empty if the texts have the same lines.
+pub fn unified_diff(old: &str, new: &str, old_label: &str, new_label: &str) -> String {
+    let old_lines: Vec<&str> = old.lines().collect();
+    let new_lines: Vec<&str> = new.lines().collect();
+    let edits = diff_lines(&old_lines, &new_lines);
+
+    // (edit, line) in output order
+    let (mut old_index, mut new_index) = (0, 0);
+    let ops: Vec<(Edit, &str)> = edits
+        .iter()
+        .map(|&edit| match edit {
+            Edit::Keep => {
+                old_index += 1;
+                new_index += 1;
+                (edit, old_lines[old_index - 1])
+            }
+            Edit::Delete => {
+                old_index += 1;
+                (edit, old_lines[old_index - 1])
+            }
+            Edit::Insert => {
+                new_index += 1;
+                (edit, new_lines[new_index - 1])
+            }
+        })
+        .collect();
+
+    let mut hunks: Vec<(usize, usize)> = Vec::new();
+    for (index, (edit, _)) in ops.iter().enumerate() {
+        if *edit == Edit::Keep {
+            continue;
+        }
+        let start = index.saturating_sub(CONTEXT_LINES);
+        let end = (index + 1 + CONTEXT_LINES).min(ops.len());
+        match hunks.last_mut() {
+            Some((_, last_end)) if start <= *last_end => *last_end = end,
+            _ => hunks.push((start, end)),
+        }
+    }
+    if hunks.is_empty() {
+        return String::new();
+    }
+
+    let mut diff = format!("--- {old_label}\n+++ {new_label}\n");
+    for (start, end) in hunks {
+        let count = |ops: &[(Edit, &str)], side: Edit| {
+            ops.iter().filter(|(edit, _)| *edit != side).count()
+        };
+        // Lines before the hunk on each side; inserts do not exist in the old text
+        let (old_before, new_before) = (count(&ops[..start], Edit::Insert), count(&ops[..start], Edit::Delete));
+        let (old_len, new_len) = (count(&ops[start..end], Edit::Insert), count(&ops[start..end], Edit::Delete));
+        // An empty range is numbered by the line before it
+        let old_start = if old_len == 0 { old_before } else { old_before + 1 };
+        let new_start = if new_len == 0 { new_before } else { new_before + 1 };
+        writeln!(diff, "@@ -{old_start},{old_len} +{new_start},{new_len} @@")
+            .expect("Writing to String buffer failed unexpectedly");
+        for (edit, line) in &ops[start..end] {
+            let marker = match edit {
+                Edit::Keep => ' ',
+                Edit::Delete => '-',
+                Edit::Insert => '+',
+            };
+            writeln!(diff, "{marker}{line}").expect("Writing to String buffer failed unexpectedly");
+        }
+    }
+    diff
+}
+
+/// The edits turning `old` into `new`. Common leading and trailing lines are matched
+/// first; the rest uses Myers' algorithm.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Edit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let mut edits = vec![Edit::Keep; prefix];
+    edits.extend(myers(&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]));
+    edits.extend(vec![Edit::Keep; suffix]);
+    edits
+}
+
+/// Myers' O(ND) shortest edit script. Falls back to deleting all of `a` and
+/// inserting all of `b` past [`MAX_EDIT_DISTANCE`].
+fn myers(a: &[&str], b: &[&str]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = a.len() + b.len();
+    let offset = max as isize;
+    // v[k + offset]: furthest x reached on diagonal k
+    let mut v = vec![0isize; 2 * max + 2];
+    // trace[d]: v on diagonals -d..=d before step d
+    let mut trace: Vec<Vec<isize>> = Vec::new();
+
+    for d in 0..=max.min(MAX_EDIT_DISTANCE) as isize {
+        trace.push(v[(offset - d) as usize..=(offset + d) as usize].to_vec());
+        for k in (-d..=d).step_by(2) {
+            let index = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[index - 1] < v[index + 1]) {
+                v[index + 1] // Down: an insert
+            } else {
+                v[index - 1] + 1 // Right: a delete
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[index] = x;
+            if x >= n && y >= m {
+                return backtrack(&trace, n, m);
+            }
+        }
+    }
+
+    let mut edits = vec![Edit::Delete; a.len()];
+    edits.extend(vec![Edit::Insert; b.len()]);
+    edits
+}
+
+/// Recovers the edit script from the `trace` of [`myers`], ending at (`n`, `m`).
+fn backtrack(trace: &[Vec<isize>], n: isize, m: isize) -> Vec<Edit> {
+    let (mut x, mut y) = (n, m);
+    let mut edits = Vec::new();
+    for d in (1..trace.len() as isize).rev() {
+        let v = &trace[d as usize];
+        let at = |k: isize| v[(k + d) as usize];
+        let k = x - y;
+        let previous_k = if k == -d || (k != d && at(k - 1) < at(k + 1)) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let previous_x = at(previous_k);
+        let previous_y = previous_x - previous_k;
+        while x > previous_x && y > previous_y {
+            edits.push(Edit::Keep);
+            x -= 1;
+            y -= 1;
+        }
+        edits.push(if x == previous_x { Edit::Insert } else { Edit::Delete });
+        x = previous_x;
+        y = previous_y;
+    }
+    // The initial snake
+    edits.extend((0..x).map(|_| Edit::Keep));
+    edits.reverse();
+    edits
+}
@@ -0,0 +1,302 @@
+use anyhow::{Result, bail};
+use regex::Regex;
+use std::fmt;
+
+use crate::{
+    LockPolicy,
+    filter::{self, Filter},
+    wrapper::WrapStyle,
+};
+
+/// The kinds of `[[kind: ...]]` directives a template may contain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DirectiveKind {
+    /// `[[include: path]]`: embed a single file.
+    Include,
+    /// `[[include?: path]]`: like `include`, but a missing file is skipped.
+    OptionalInclude,
+    /// `[[include-changed: ref]]`: embed every file changed relative to a git ref.
+    IncludeChanged,
+    /// `[[include-dir: path]]`: embed every file below a directory.
+    IncludeDir,
+    /// `[[gitdiff: ref]]` or `[[gitdiff: from to]]`: embed a git diff.
+    GitDiff,
+}
+
+impl DirectiveKind {
+    /// All directive kinds.
+    pub const ALL: &[DirectiveKind] = &[
+        DirectiveKind::Include,
+        DirectiveKind::OptionalInclude,
+        DirectiveKind::IncludeChanged,
+        DirectiveKind::IncludeDir,
+        DirectiveKind::GitDiff,
+    ];
+
+    /// The name written before the colon in the template.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Include => "include",
+            Self::OptionalInclude => "include?",
+            Self::IncludeChanged => "include-changed",
+            Self::IncludeDir => "include-dir",
+            Self::GitDiff => "gitdiff",
+        }
+    }
+
+    /// Whether the directive embeds files by path (`include` or `include?`).
+    pub fn is_include(self) -> bool {
+        matches!(self, Self::Include | Self::OptionalInclude)
+    }
+
+    /// Whether the directive's argument is a file system path rather than a git ref.
+    pub fn takes_path(self) -> bool {
+        self.is_include() || self == Self::IncludeDir
+    }
+
+    /// Looks up a directive kind by its name.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|kind| kind.name() == name)
+    }
+}
+
+/// Builds the regex matching `[[kind: ...]]` for every known directive kind.
+/// Capture 1 is the kind's name, capture 2 the text after the colon.
+pub fn directive_regex() -> Regex {
+    let names: Vec<String> = DirectiveKind::ALL
+        .iter()
+        .map(|kind| regex::escape(kind.name()))
+        .collect();
+    Regex::new(&format!(r"\[\[({}):\s*(.*?)\s*\]\]", names.join("|")))
+        .expect("Failed to compile directive regex")
+}
+
+/// A parsed `[[include: path modifier... | filter...]]` directive. Other directive kinds share
+/// the syntax, with `path` holding their main argument (e.g. a git ref).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeDirective {
+    /// The include path as written in the template.
+    pub path: String,
+    /// Alternatives written after `path` as `path || fallback || ...`, tried in order
+    /// when the paths before them do not exist.
+    pub fallbacks: Vec<String>,
+    /// Modifiers following the path.
+    pub modifiers: Modifiers,
+}
+
+/// Per-directive modifiers, written after the path as `name` or `name=value`.
+/// Unset modifiers fall back to template-wide settings.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Modifiers {
+    /// `linenos`: prefix each embedded line with its original line number.
+    pub linenos: Option<bool>,
+    /// `collapsible`: wrap the embedded file in a `<details>` block.
+    pub collapsible: Option<bool>,
+    /// `wrap=<style>`: embed the file with a built-in or `clamp.toml`-defined format.
+    pub wrap: Option<WrapStyle>,
+    /// `head=<n>`: embed only the first `n` lines.
+    pub head: Option<usize>,
+    /// `tail=<n>`: embed only the last `n` lines.
+    pub tail: Option<usize>,
+    /// `ext=<ext>,...`: for `include-dir`, only embed files with these extensions
+    /// (written without the dot, e.g. `rs` or `yml.j2`).
+    pub ext: Vec<String>,
+    /// `max-size=<bytes>`: for `include-dir`, skip files larger than this.
+    pub max_size: Option<u64>,
+    /// `policy=<track|pin>`: how changes to the included files are treated once
+    /// locked, overriding the `pin` patterns of `clamp.toml`.
+    pub policy: Option<LockPolicy>,
+    /// `allow=<lint>,...`: lint IDs suppressed for this directive (see `clamp lint`).
+    /// Does not affect the output.
+    pub allow: Vec<String>,
+    /// `| filter ...`: content filters written after the modifiers, applied in order.
+    pub filters: Vec<Filter>,
+}
+
+/// Names of all recognized modifiers.
+const MODIFIER_NAMES: &[&str] = &[
+    "linenos",
+    "collapsible",
+    "wrap",
+    "allow",
+    "head",
+    "tail",
+    "ext",
+    "max-size",
+    "policy",
+];
+
+impl IncludeDirective {
+    /// Parses the text between `[[include:` and `]]`.
+    ///
+    /// Modifiers are recognized as trailing whitespace-separated tokens whose name is a
+    /// known modifier; everything before them is the path, so paths may contain spaces.
+    /// Filters follow them, each introduced by a single `|`.
+    pub fn parse(inner: &str) -> Result<Self> {
+        let (mut path, pipeline) = match filter::pipeline_start(inner) {
+            Some(start) => (inner[..start].trim(), Some(&inner[start + 1..])),
+            None => (inner.trim(), None),
+        };
+        let filters: Vec<Filter> = pipeline
+            .map(|pipeline| filter::split_stages(pipeline).into_iter().map(Filter::parse).collect())
+            .transpose()?
+            .unwrap_or_default();
+        let mut modifier_tokens = Vec::new();
+
+        while let Some((rest, token)) = path.rsplit_once(char::is_whitespace) {
+            let name = token.split_once('=').map_or(token, |(name, _)| name);
+            if !MODIFIER_NAMES.contains(&name) {
+                break;
+            }
+            modifier_tokens.push(token);
+            path = rest.trim_end();
+        }
+
+        if path.is_empty() {
+            bail!("Include directive '{}' has no path", inner.trim());
+        }
+        let mut alternatives = path.split("||").map(str::trim);
+        let path = alternatives.next().unwrap_or_default();
+        let fallbacks: Vec<String> = alternatives.map(str::to_string).collect();
+        if path.is_empty() || fallbacks.iter().any(String::is_empty) {
+            bail!("Include directive '{}' has an empty alternative", inner.trim());
+        }
+
+        let mut modifiers = Modifiers::default();
+        // Tokens were collected right-to-left; apply them in written order
+        for token in modifier_tokens.into_iter().rev() {
+            modifiers.apply(token)?;
+        }
+        if modifiers.head.is_some() && modifiers.tail.is_some() {
+            bail!("Modifiers 'head' and 'tail' cannot be combined");
+        }
+        if (modifiers.head.is_some() || modifiers.tail.is_some()) && !filters.is_empty() {
+            bail!("Modifiers 'head' and 'tail' cannot be combined with filters (use '| head <n>' or '| tail <n>')");
+        }
+        modifiers.filters = filters;
+
+        Ok(Self {
+            path: path.to_string(),
+            fallbacks,
+            modifiers,
+        })
+    }
+
+    /// The path followed by its fallbacks, in the order they are tried.
+    pub fn alternatives(&self) -> impl Iterator<Item = &str> {
+        std::iter::once(self.path.as_str()).chain(self.fallbacks.iter().map(String::as_str))
+    }
+
+    /// The alternatives as written in canonical form, e.g. `docs/DESIGN.md || README.md`.
+    pub fn alternatives_text(&self) -> String {
+        self.alternatives().collect::<Vec<_>>().join(" || ")
+    }
+}
+
+impl Modifiers {
+    /// Applies a single `name` or `name=value` modifier token.
+    fn apply(&mut self, token: &str) -> Result<()> {
+        let (name, value) = match token.split_once('=') {
+            Some((name, value)) => (name, Some(value)),
+            None => (token, None),
+        };
+
+        match name {
+            "linenos" => self.linenos = Some(parse_flag(name, value)?),
+            "collapsible" => self.collapsible = Some(parse_flag(name, value)?),
+            "wrap" => self.wrap = Some(required_value(name, value)?.parse()?),
+            "head" => self.head = Some(parse_count(name, value)?),
+            "tail" => self.tail = Some(parse_count(name, value)?),
+            "ext" => self.ext.extend(
+                required_value(name, value)?
+                    .split(',')
+                    .map(|ext| ext.trim_start_matches('.'))
+                    .filter(|ext| !ext.is_empty())
+                    .map(str::to_string),
+            ),
+            "max-size" => {
+                let value = required_value(name, value)?;
+                self.max_size = Some(value.parse().map_err(|_| {
+                    anyhow::anyhow!("Invalid value '{value}' for modifier '{name}' (expected a size in bytes)")
+                })?);
+            }
+            "policy" => self.policy = Some(required_value(name, value)?.parse()?),
+            "allow" => self.allow.extend(
+                required_value(name, value)?
+                    .split(',')
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string),
+            ),
+            _ => bail!("Unknown include modifier '{name}'"),
+        }
+        Ok(())
+    }
+}
+
+/// The canonical spelling: the alternatives followed by the modifiers in alphabetical
+/// order, then the filters.
+impl fmt::Display for IncludeDirective {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.alternatives_text())?;
+        let modifiers = self.modifiers.to_string();
+        if !modifiers.is_empty() {
+            write!(f, " {modifiers}")?;
+        }
+        for filter in &self.modifiers.filters {
+            write!(f, " | {filter}")?;
+        }
+        Ok(())
+    }
+}
+
+/// The set modifiers in alphabetical order, as `name` for enabled flags and
+/// `name=value` otherwise. Filters are not included.
+impl fmt::Display for Modifiers {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let flag = |name: &str, value: Option<bool>| match value {
+            Some(true) => Some(name.to_string()),
+            Some(false) => Some(format!("{name}=false")),
+            None => None,
+        };
+        let mut allow = self.allow.clone();
+        allow.sort();
+        allow.dedup();
+        let tokens: Vec<String> = [
+            (!allow.is_empty()).then(|| format!("allow={}", allow.join(","))),
+            flag("collapsible", self.collapsible),
+            (!self.ext.is_empty()).then(|| format!("ext={}", self.ext.join(","))),
+            self.head.map(|count| format!("head={count}")),
+            flag("linenos", self.linenos),
+            self.max_size.map(|bytes| format!("max-size={bytes}")),
+            self.policy.map(|policy| format!("policy={}", policy.name())),
+            self.tail.map(|count| format!("tail={count}")),
+            self.wrap.as_ref().map(|style| format!("wrap={style}")),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        f.write_str(&tokens.join(" "))
+    }
+}
+
+/// Returns the value of a modifier that requires one.
+fn required_value<'a>(name: &str, value: Option<&'a str>) -> Result<&'a str> {
+    value.ok_or_else(|| anyhow::anyhow!("Modifier '{name}' requires a value ({name}=...)"))
+}
+
+/// Parses the line count of a `head`/`tail` modifier.
+fn parse_count(name: &str, value: Option<&str>) -> Result<usize> {
+    let value = required_value(name, value)?;
+    value
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid value '{value}' for modifier '{name}' (expected a line count)"))
+}
+
+/// Parses the value of a boolean modifier; a bare `name` means `true`.
+fn parse_flag(name: &str, value: Option<&str>) -> Result<bool> {
+    match value {
+        None | Some("true") => Ok(true),
+        Some("false") => Ok(false),
+        Some(other) => bail!("Invalid value '{other}' for modifier '{name}' (expected true or false)"),
+    }
+}
@@ -0,0 +1,80 @@
+use std::path::PathBuf;
+
+use crate::directive::DirectiveKind;
+
+/// One step of rendering a template, recorded in
+/// [`ProcessResult::events`](crate::ProcessResult::events) when
+/// [`ProcessOptions::events`](crate::ProcessOptions::events) is set.
+///
+/// The events walk the resolved template in output order, so tools can rebuild
+/// its structure (or render it their own way) instead of parsing the flattened
+/// output. Concatenating every [`Text`](Self::Text) and
+/// [`DirectiveEnd::output`](Self::DirectiveEnd) yields exactly
+/// [`ProcessResult::output_content`](crate::ProcessResult::output_content).
+///
+/// Each directive produces a `DirectiveStart`, then the events of the files it
+/// resolves to, then a `DirectiveEnd`:
+///
+/// ```text
+/// Text("Review this:\n")
+/// DirectiveStart { kind: Include, directive: "[[include: src/*.rs]]", .. }
+///   IncludeResolved { path: "src/lib.rs", .. }
+///   IncludeContent { path: "src/lib.rs", lang: "rust", .. }
+///   IncludeResolved { path: "src/main.rs", .. }
+///   IncludeContent { path: "src/main.rs", lang: "rust", .. }
+/// DirectiveEnd { output: "### `src/lib.rs`\n```rust\n..." }
+/// Text("\nThanks!\n")
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum IncludeEvent {
+    /// Template text between directives, copied to the output as-is.
+    Text(String),
+    /// A directive begins.
+    DirectiveStart {
+        kind: DirectiveKind,
+        /// The directive as written, e.g. `[[include: src/main.rs linenos]]`.
+        directive: String,
+        /// The template the directive comes from (a base template's path for
+        /// blocks inherited through `extends`).
+        origin: String,
+    },
+    /// A file the directive refers to was found and hashed.
+    IncludeResolved {
+        /// The path as written in the directive (or the glob match).
+        path: String,
+        /// The canonical path recorded in the lockfile.
+        canonical_path: PathBuf,
+        /// SHA256 hash (hex) of the embedded content.
+        hash: String,
+    },
+    /// Content is embedded: a resolved file (not repeated for files `dedup` refers
+    /// back to) or a git diff.
+    IncludeContent {
+        /// The path as written in the directive, or the diff's label.
+        path: String,
+        /// The language hint, e.g. `rust`.
+        lang: String,
+        /// The embedded lines, before line numbering and wrapping.
+        content: String,
+        /// Original line number of the first embedded line (after `tail=`).
+        first_line: usize,
+    },
+    /// An optional include (`[[include?: ...]]`) found nothing and was skipped.
+    IncludeSkipped {
+        /// The alternatives as written.
+        path: String,
+    },
+    /// An include failed and was replaced by a placeholder (see
+    /// [`ProcessOptions::keep_going`](crate::ProcessOptions::keep_going)).
+    IncludeFailed {
+        path: String,
+        /// Why it failed, including its causes.
+        error: String,
+    },
+    /// The directive ends.
+    DirectiveEnd {
+        /// Everything the directive wrote to the output: wrapped blocks,
+        /// placeholders and `dedup` references.
+        output: String,
+    },
+}
@@ -0,0 +1,308 @@
+use anyhow::{Context, Result, bail};
+use regex::{Regex, RegexBuilder};
+use std::fmt;
+
+/// A built-in content filter, chained after an include's path and modifiers as
+/// `[[include: app.log | grep ERROR | tail 100]]`. Filters run natively rather than
+/// in a shell, so templates behave the same everywhere and cannot run commands.
+/// Each one receives the lines the previous one kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Filter {
+    /// `grep [-i] [-v] <pattern>`: keep the lines matching a regex, ignoring case with
+    /// `-i`, or the lines not matching it with `-v`.
+    Grep {
+        pattern: String,
+        ignore_case: bool,
+        invert: bool,
+    },
+    /// `head <n>`: keep the first `n` lines.
+    Head(usize),
+    /// `tail <n>`: keep the last `n` lines.
+    Tail(usize),
+    /// `lines <from>-<to>`: keep lines `from` to `to` (counted from 1, inclusive).
+    Lines { from: usize, to: usize },
+}
+
+/// Names of all built-in filters.
+pub const FILTER_NAMES: &[&str] = &["grep", "head", "tail", "lines"];
+
+/// The lines of a file that filters kept, each with its line number in the file.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Filtered {
+    pub content: String,
+    pub line_numbers: Vec<usize>,
+}
+
+impl Filter {
+    /// Parses one stage of a pipeline, e.g. `grep -v "DEBUG|TRACE"`. Arguments are
+    /// separated by whitespace and may be quoted with `'` or `"`.
+    pub fn parse(stage: &str) -> Result<Self> {
+        let words = split_words(stage).with_context(|| format!("Invalid filter '{}'", stage.trim()))?;
+        let Some((name, args)) = words.split_first() else {
+            bail!("Empty filter between '|'");
+        };
+        let filter = match name.as_str() {
+            "grep" => {
+                let (mut ignore_case, mut invert) = (false, false);
+                let mut pattern = None;
+                for arg in args {
+                    match arg.as_str() {
+                        "-i" if pattern.is_none() => ignore_case = true,
+                        "-v" if pattern.is_none() => invert = true,
+                        "-iv" | "-vi" if pattern.is_none() => (ignore_case, invert) = (true, true),
+                        _ if pattern.is_none() => pattern = Some(arg.clone()),
+                        _ => bail!("Filter 'grep' takes one pattern (quote patterns containing spaces)"),
+                    }
+                }
+                let Some(pattern) = pattern else {
+                    bail!("Filter 'grep' requires a pattern (grep <pattern>)");
+                };
+                let filter = Filter::Grep {
+                    pattern,
+                    ignore_case,
+                    invert,
+                };
+                filter.regex()?;
+                filter
+            }
+            "head" => Filter::Head(parse_count(name, args)?),
+            "tail" => Filter::Tail(parse_count(name, args)?),
+            "lines" => {
+                let [range] = args else {
+                    bail!("Filter 'lines' takes one range (lines <from>-<to>)");
+                };
+                let bounds = range
+                    .split_once('-')
+                    .and_then(|(from, to)| Some((from.parse().ok()?, to.parse().ok()?)));
+                match bounds {
+                    Some((from, to)) if from > 0 && from <= to => Filter::Lines { from, to },
+                    _ => bail!("Invalid range '{range}' for filter 'lines' (expected e.g. 10-20)"),
+                }
+            }
+            other => bail!(
+                "Unknown filter '{other}' (expected one of: {})",
+                FILTER_NAMES.join(", ")
+            ),
+        };
+        Ok(filter)
+    }
+
+    /// The compiled pattern of a `grep` filter.
+    fn regex(&self) -> Result<Option<Regex>> {
+        let Filter::Grep {
+            pattern, ignore_case, ..
+        } = self
+        else {
+            return Ok(None);
+        };
+        let regex = RegexBuilder::new(pattern)
+            .case_insensitive(*ignore_case)
+            .build()
+            .with_context(|| format!("Invalid pattern '{pattern}' for filter 'grep'"))?;
+        Ok(Some(regex))
+    }
+
+    /// Keeps the `(line number, line)` pairs this filter lets through.
+    fn apply<'a>(&self, lines: Vec<(usize, &'a str)>) -> Result<Vec<(usize, &'a str)>> {
+        Ok(match self {
+            Filter::Grep { invert, .. } => {
+                let regex = self.regex()?.expect("grep filters have a pattern");
+                lines
+                    .into_iter()
+                    .filter(|(_, line)| regex.is_match(line.trim_end_matches(['\n', '\r'])) != *invert)
+                    .collect()
+            }
+            Filter::Head(count) => lines.into_iter().take(*count).collect(),
+            Filter::Tail(count) => {
+                let skipped = lines.len().saturating_sub(*count);
+                lines.into_iter().skip(skipped).collect()
+            }
+            Filter::Lines { from, to } => lines.into_iter().skip(from - 1).take(to - from + 1).collect(),
+        })
+    }
+}
+
+/// Runs `content` through `filters` in order.
+pub fn apply_filters(filters: &[Filter], content: &str) -> Result<Filtered> {
+    let mut lines: Vec<(usize, &str)> = content
+        .split_inclusive('\n')
+        .enumerate()
+        .map(|(index, line)| (index + 1, line))
+        .collect();
+    for filter in filters {
+        lines = filter.apply(lines)?;
+    }
+    Ok(Filtered {
+        content: lines.iter().map(|(_, line)| *line).collect(),
+        line_numbers: lines.iter().map(|(number, _)| *number).collect(),
+    })
+}
+
+/// Splits the text after an include's path and modifiers into its `| filter` stages.
+/// `|` inside quotes does not separate stages.
+pub(crate) fn split_stages(pipeline: &str) -> Vec<&str> {
+    let mut stages = Vec::new();
+    let mut quote = None;
+    let mut start = 0;
+    for (index, c) in pipeline.char_indices() {
+        match (quote, c) {
+            (None, '\'' | '"') => quote = Some(c),
+            (Some(open), _) if c == open => quote = None,
+            (None, '|') => {
+                stages.push(&pipeline[start..index]);
+                start = index + 1;
+            }
+            _ => {}
+        }
+    }
+    stages.push(&pipeline[start..]);
+    stages
+}
+
+/// The byte offset of the `|` starting an include's filters: the first one that is
+/// not part of a `||` fallback.
+pub(crate) fn pipeline_start(inner: &str) -> Option<usize> {
+    let bytes = inner.as_bytes();
+    (0..bytes.len()).find(|&index| {
+        bytes[index] == b'|'
+            && bytes.get(index + 1) != Some(&b'|')
+            && (index == 0 || bytes[index - 1] != b'|')
+    })
+}
+
+fn parse_count(name: &str, args: &[String]) -> Result<usize> {
+    let [count] = args else {
+        bail!("Filter '{name}' takes one line count ({name} <n>)");
+    };
+    count
+        .parse()
+        .map_err(|_| anyhow::anyhow!("Invalid line count '{count}' for filter '{name}'"))
+}
+
+/// Splits `text` at whitespace, keeping quoted parts (without their quotes) together.
+fn split_words(text: &str) -> Result<Vec<String>> {
+    let mut words = Vec::new();
+    let mut word: Option<String> = None;
+    let mut quote = None;
+    for c in text.chars() {
+        match (quote, c) {
+            (None, '\'' | '"') => {
+                quote = Some(c);
+                word.get_or_insert_with(String::new);
+            }
+            (Some(open), _) if c == open => quote = None,
+            (None, _) if c.is_whitespace() => words.extend(word.take()),
+            _ => word.get_or_insert_with(String::new).push(c),
+        }
+    }
+    if let Some(open) = quote {
+        bail!("Unclosed quote {open}");
+    }
+    words.extend(word);
+    Ok(words)
+}
+
+/// Quotes `word` if it would otherwise not survive [`split_words`].
+fn quote_word(word: &str) -> String {
+    if !word.is_empty() && !word.contains(|c: char| c.is_whitespace() || matches!(c, '|' | '\'' | '"')) {
+        word.to_string()
+    } else if word.contains('\'') {
+        format!("\"{word}\"")
+    } else {
+        format!("'{word}'")
+    }
+}
+
+/// The canonical spelling, e.g. `grep -v 'a b'`.
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Filter::Grep {
+                pattern,
+                ignore_case,
+                invert,
+            } => {
+                f.write_str("grep")?;
+                if *ignore_case {
+                    f.write_str(" -i")?;
+                }
+                if *invert {
+                    f.write_str(" -v")?;
+                }
+                write!(f, " {}", quote_word(pattern))
+            }
+            Filter::Head(count) => write!(f, "head {count}"),
+            Filter::Tail(count) => write!(f, "tail {count}"),
+            Filter::Lines { from, to } => write!(f, "lines {from}-{to}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grep(pattern: &str, ignore_case: bool, invert: bool) -> Filter {
+        Filter::Grep {
+            pattern: pattern.to_string(),
+            ignore_case,
+            invert,
+        }
+    }
+
+    fn parse_error(stage: &str) -> String {
+        format!("{:#}", Filter::parse(stage).unwrap_err())
+    }
+
+    #[test]
+    fn parses_stages() {
+        assert_eq!(Filter::parse(" grep -v \"DEBUG|TRACE\" ").unwrap(), grep("DEBUG|TRACE", false, true));
+        assert_eq!(Filter::parse("grep 'a b'").unwrap(), grep("a b", false, false));
+        assert_eq!(Filter::parse("grep -iv error").unwrap(), grep("error", true, true));
+        assert_eq!(Filter::parse("grep -i -- ").unwrap(), grep("--", true, false));
+        assert_eq!(Filter::parse("tail 100").unwrap(), Filter::Tail(100));
+        assert_eq!(Filter::parse("lines 2-2").unwrap(), Filter::Lines { from: 2, to: 2 });
+        // The canonical spelling parses back to the same filter
+        let filter = grep("it's | here", true, false);
+        assert_eq!(Filter::parse(&filter.to_string()).unwrap(), filter);
+    }
+
+    #[test]
+    fn rejects_invalid_stages() {
+        assert!(parse_error("lines 0-3").contains("Invalid range '0-3'"));
+        assert!(parse_error("lines 5-2").contains("Invalid range '5-2'"));
+        assert!(parse_error("lines 5").contains("Invalid range '5'"));
+        assert!(parse_error("sed s/a/b/").contains("Unknown filter 'sed' (expected one of: grep, head, tail, lines)"));
+        assert!(parse_error("head ten").contains("Invalid line count 'ten'"));
+        assert!(parse_error("grep").contains("requires a pattern"));
+        assert!(parse_error("grep a b").contains("takes one pattern"));
+        assert_eq!(parse_error("grep '(unclosed"), "Invalid filter 'grep '(unclosed': Unclosed quote '");
+        assert!(parse_error("grep (").contains("Invalid pattern '('"));
+
+        let stages = split_stages(" grep a | | tail 1");
+        assert_eq!(stages, [" grep a ", " ", " tail 1"]);
+        assert_eq!(Filter::parse(stages[1]).unwrap_err().to_string(), "Empty filter between '|'");
+    }
+
+    #[test]
+    fn chains_filters_keeping_line_numbers() {
+        let content = "ok 1\nERROR a\nok 2\nerror b\nERROR c\nok 3";
+        let filtered = apply_filters(&[grep("error", true, false), Filter::Tail(2)], content).unwrap();
+        assert_eq!(filtered.content, "error b\nERROR c\n");
+        assert_eq!(filtered.line_numbers, [4, 5]);
+
+        let filtered = apply_filters(&[grep("ERROR", false, true), Filter::Lines { from: 2, to: 9 }], content).unwrap();
+        assert_eq!(filtered.content, "ok 2\nerror b\nok 3");
+        assert_eq!(filtered.line_numbers, [3, 4, 6]);
+
+        let filtered = apply_filters(&[Filter::Head(0)], content).unwrap();
+        assert_eq!(filtered, Filtered::default());
+    }
+
+    #[test]
+    fn splits_pipelines_outside_quotes() {
+        assert_eq!(split_stages("grep 'a|b' | head 2"), ["grep 'a|b' ", " head 2"]);
+        assert_eq!(pipeline_start("a.rs || b.rs | head 1"), Some(13));
+        assert_eq!(pipeline_start("a.rs || b.rs"), None);
+    }
+}
@@ -0,0 +1,124 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+
+use crate::{
+    directive::{DirectiveKind, IncludeDirective, directive_regex},
+    relative_path,
+};
+
+/// Settings for [`format_template`].
+#[derive(Debug, Clone, Default)]
+pub struct FormatOptions {
+    /// Rewrite absolute include paths relative to the template's directory.
+    pub relative: bool,
+}
+
+/// Rewrites every directive in `content` into its canonical spelling,
+/// `[[kind: argument modifiers]]`: one space after the colon, none before the
+/// closing brackets, modifiers sorted alphabetically, and `/` as the path separator
+/// in includes. Text outside directives is left untouched.
+///
+/// `template_dir` is the directory absolute paths are made relative to when
+/// `options.relative` is set.
+pub fn format_template(content: &str, template_dir: &Path, options: &FormatOptions) -> Result<String> {
+    let regex = directive_regex();
+    let mut formatted = String::with_capacity(content.len());
+    let mut current_pos = 0;
+
+    for cap in regex.captures_iter(content) {
+        let full_match = cap.get(0).expect("capture 0 is the whole match");
+        let kind = DirectiveKind::from_name(&cap[1]).expect("regex only matches known kinds");
+        let mut directive = IncludeDirective::parse(&cap[2])
+            .with_context(|| format!("Invalid directive '{}'", full_match.as_str()))?;
+
+        if kind.takes_path() {
+            directive.path = format_path(&directive.path, template_dir, options)?;
+            for fallback in &mut directive.fallbacks {
+                *fallback = format_path(fallback, template_dir, options)?;
+            }
+        } else {
+            // Git refs are separated by single spaces
+            directive.path = directive.path.split_whitespace().collect::<Vec<_>>().join(" ");
+        }
+
+        formatted.push_str(&content[current_pos..full_match.start()]);
+        formatted.push_str(&format!("[[{}: {directive}]]", kind.name()));
+        current_pos = full_match.end();
+    }
+    formatted.push_str(&content[current_pos..]);
+    Ok(formatted)
+}
+
+/// Normalizes an include path: `/` separators, and relative if `options.relative` is set.
+fn format_path(path: &str, template_dir: &Path, options: &FormatOptions) -> Result<String> {
+    let path = path.replace('\\', "/");
+    if options.relative && Path::new(&path).is_absolute() {
+        let relative = relative_path(template_dir, Path::new(&path))?;
+        return Ok(relative.to_string_lossy().replace('\\', "/"));
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{ProcessOptions, process_template_content, vfs::MemoryFileSystem};
+
+    const MESSY: &str = "# Review\n\n[[include:src\\lib.rs   linenos head=3 ]]\n[[include?:  missing.rs||src/main.rs   ]]\n\
+                         [[include: src/lib.rs |grep  -v   'fn x'|tail 2]]\n[[gitdiff:  main   HEAD ]]\nText [[ not a directive ]]\n";
+
+    fn format(content: &str) -> String {
+        format_template(content, Path::new("/project"), &FormatOptions::default()).unwrap()
+    }
+
+    #[test]
+    fn canonicalizes_directives() {
+        assert_eq!(
+            format(MESSY),
+            "# Review\n\n[[include: src/lib.rs head=3 linenos]]\n[[include?: missing.rs || src/main.rs]]\n\
+             [[include: src/lib.rs | grep -v 'fn x' | tail 2]]\n[[gitdiff: main HEAD]]\nText [[ not a directive ]]\n"
+        );
+    }
+
+    #[test]
+    fn is_idempotent() {
+        let once = format(MESSY);
+        assert_eq!(format(&once), once);
+        assert_eq!(format("No directives\n"), "No directives\n");
+    }
+
+    #[test]
+    fn keeps_the_rendered_output() {
+        let mut files = MemoryFileSystem::new();
+        files.insert("/project/src/lib.rs", "pub fn a() {}\nfn x() {}\npub fn b() {}\npub fn c() {}\n");
+        files.insert("/project/src/main.rs", "fn main() {}\n");
+        let options = ProcessOptions {
+            file_system: Some(Arc::new(files)),
+            ..Default::default()
+        };
+        // Backslash separators only resolve on Windows, and the diff needs a repository
+        let template = MESSY.replace('\\', "/").replace("[[gitdiff:  main   HEAD ]]\n", "");
+        let render = |content: &str| {
+            process_template_content(content, Path::new("/project"), "t.clamp", &options).map(|result| result.output_content)
+        };
+        let formatted = format(&template);
+        assert_ne!(formatted, template);
+        assert_eq!(render(&formatted).unwrap(), render(&template).unwrap());
+    }
+
+    #[test]
+    fn makes_absolute_paths_relative() {
+        let options = FormatOptions { relative: true };
+        let formatted = format_template("[[include: /project/src/a.rs]]\n[[include: b.rs]]\n", Path::new("/project"), &options).unwrap();
+        assert_eq!(formatted, "[[include: src/a.rs]]\n[[include: b.rs]]\n");
+        assert_eq!(format("[[include: /project/src/a.rs]]"), "[[include: /project/src/a.rs]]");
+    }
+
+    #[test]
+    fn rejects_invalid_directives() {
+        let error = format_template("[[include: a.rs | sed x]]", Path::new("/project"), &FormatOptions::default()).unwrap_err();
+        assert!(format!("{error:#}").contains("Invalid directive '[[include: a.rs | sed x]]'"), "{error:#}");
+    }
+}
@@ -0,0 +1,146 @@
+use anyhow::{Context, Result, bail};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{expand_placeholders, git, wrapper::WrapStyle};
+
+/// Delimiter line that opens and closes a template's TOML frontmatter.
+pub const FRONTMATTER_DELIMITER: &str = "+++";
+
+/// Per-template settings declared in a TOML frontmatter block at the top of a template:
+///
+/// ```text
+/// +++
+/// dedup = true
+/// +++
+/// Template body...
+/// ```
+///
+/// Every setting is optional; unset values fall back to the command line or defaults.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Frontmatter {
+    /// Embed each file only once, replacing repeated includes with a reference.
+    pub dedup: Option<bool>,
+    /// Header line above each embedded file, e.g. ``"### `{path}`"``; `""` turns it off.
+    pub header: Option<String>,
+    /// Prefix embedded lines with their original line numbers.
+    pub linenos: Option<bool>,
+    /// Wrap embedded files in collapsible `<details>` blocks.
+    pub collapsible: Option<bool>,
+    /// Embedding format: `"markdown"`, `"xml"`, `"none"`, or a wrapper defined in `clamp.toml`.
+    pub wrap: Option<WrapStyle>,
+    /// Base template (relative to this one) whose `[[block: ...]]` sections this
+    /// template overrides.
+    pub extends: Option<PathBuf>,
+    /// Variables tested by `[[if: name]]` sections, with their default values.
+    #[serde(default)]
+    pub vars: BTreeMap<String, bool>,
+    /// Files written by `clamp build`, each rendered with its own variable values.
+    /// Not inherited by templates extending this one.
+    #[serde(default)]
+    pub outputs: Vec<OutputTarget>,
+}
+
+/// An `[[outputs]]` entry of the frontmatter.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct OutputTarget {
+    /// Where to write the rendered template, relative to the template. May contain
+    /// the placeholders expanded by [`OutputTarget::resolve_path`].
+    pub path: PathBuf,
+    /// Values overriding the template's `[vars]` for this output.
+    #[serde(default)]
+    pub vars: BTreeMap<String, bool>,
+}
+
+impl OutputTarget {
+    /// The file to write, below `base_dir`, with the build metadata placeholders of
+    /// [`path`](Self::path) expanded: `{date}` (today in UTC, `YYYY-MM-DD`),
+    /// `{git_sha}` (the abbreviated commit checked out in `base_dir`'s repository) and
+    /// `{template_stem}` (e.g. `review` for `review.clamp`).
+    pub fn resolve_path(&self, base_dir: &Path, template_stem: &str) -> Result<PathBuf> {
+        let path = self.path.to_string_lossy();
+        // Only ask git when the path needs it, so outputs work outside repositories
+        let git_sha = if path.contains("{git_sha}") {
+            git::head_commit(base_dir).with_context(|| {
+                format!("Output path '{path}' uses {{git_sha}}, which requires a git repository with a commit")
+            })?
+        } else {
+            String::new()
+        };
+        let date = utc_date(SystemTime::now());
+        let expanded = expand_placeholders(
+            &path,
+            &[("date", &date), ("git_sha", &git_sha), ("template_stem", template_stem)],
+        );
+        Ok(base_dir.join(expanded))
+    }
+}
+
+/// Formats the UTC calendar date of `time` as `YYYY-MM-DD`.
+fn utc_date(time: SystemTime) -> String {
+    let days = time.duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs() / 86_400) as i64;
+    // Civil-from-days (Howard Hinnant's algorithm), with eras of 400 years
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153; // March = 0
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+impl Frontmatter {
+    /// Takes the settings this template leaves unset from `base`, a template it extends.
+    pub fn inherit(self, base: Frontmatter) -> Self {
+        let mut vars = base.vars;
+        vars.extend(self.vars);
+        Self {
+            dedup: self.dedup.or(base.dedup),
+            header: self.header.or(base.header),
+            linenos: self.linenos.or(base.linenos),
+            collapsible: self.collapsible.or(base.collapsible),
+            wrap: self.wrap.or(base.wrap),
+            extends: self.extends,
+            vars,
+            outputs: self.outputs,
+        }
+    }
+}
+
+/// Splits a template into its frontmatter and body.
+///
+/// A template without a leading `+++` line has default (empty) frontmatter and the
+/// whole content as its body. Returns an error if the block is unterminated or
+/// is not valid frontmatter TOML.
+pub fn split_frontmatter(template_content: &str) -> Result<(Frontmatter, &str)> {
+    let Some(rest) = strip_delimiter_line(template_content) else {
+        return Ok((Frontmatter::default(), template_content));
+    };
+
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == FRONTMATTER_DELIMITER {
+            let frontmatter = toml::from_str(&rest[..offset])
+                .context("Failed to parse template frontmatter")?;
+            return Ok((frontmatter, &rest[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+
+    bail!("Template frontmatter is not terminated by a '{FRONTMATTER_DELIMITER}' line")
+}
+
+/// Returns the content after the opening delimiter line, if the content starts with one.
+fn strip_delimiter_line(content: &str) -> Option<&str> {
+    let rest = content.strip_prefix(FRONTMATTER_DELIMITER)?;
+    rest.strip_prefix("\r\n").or_else(|| rest.strip_prefix('\n'))
+}
@@ -0,0 +1,188 @@
+//! Git integration. Diffs, changed files and the checked-out commit are read directly
+//! from the repository's object database; listing a remote's refs and locating the
+//! hooks directory still run `git`.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::{
+    diff::unified_diff,
+    gitdb::{FileChange, MIN_ABBREV, Repository, Version},
+};
+
+/// Bytes of a file searched for a NUL byte to tell whether it is binary, as git does.
+const BINARY_PROBE_SIZE: usize = 8000;
+
+/// Runs `git` with `args` in `dir` and returns its stdout.
+///
+/// Returns an error if git cannot be started or exits unsuccessfully,
+/// including git's own error message.
+fn run_git(dir: &Path, args: &[&str]) -> Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(dir)
+        .args(args)
+        .output()
+        .context("Failed to run git (is it installed?)")?;
+
+    if !output.status.success() {
+        bail!(
+            "git {} failed: {}",
+            args.join(" "),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    String::from_utf8(output.stdout).context("git produced output that is not valid UTF-8")
+}
+
+/// Rejects refs that git would interpret as options.
+fn validate_ref(git_ref: &str) -> Result<()> {
+    if git_ref.is_empty() || git_ref.starts_with('-') {
+        bail!("Invalid git ref '{git_ref}'");
+    }
+    Ok(())
+}
+
+/// Returns the root directory of the git working tree containing `dir`.
+pub fn toplevel(dir: &Path) -> Result<PathBuf> {
+    Ok(Repository::discover(dir)?.work_tree().to_path_buf())
+}
+
+/// Returns the abbreviated hash of the commit checked out in the repository containing `dir`.
+pub fn head_commit(dir: &Path) -> Result<String> {
+    let repository = Repository::discover(dir)?;
+    repository.abbreviate(repository.rev_parse("HEAD")?)
+}
+
+/// Lists tracked files that differ between `git_ref` and the working tree, as paths
+/// relative to the repository root. Files deleted since `git_ref` are left out.
+pub fn changed_files(dir: &Path, git_ref: &str) -> Result<Vec<PathBuf>> {
+    validate_ref(git_ref)?;
+    let changes = Repository::discover(dir)?.changes(git_ref, None)?;
+    Ok(changes
+        .into_iter()
+        .filter(|change| change.new.is_some())
+        .map(|change| PathBuf::from(change.path))
+        .collect())
+}
+
+/// Returns the diff of the working tree against `refs[0]`, or between `refs[0]` and
+/// `refs[1]` when two refs are given. A single `from..to` range diffs its two ends,
+/// either of which defaults to `HEAD`.
+///
+/// The output has the form of `git diff` without rename detection: a `diff --git`
+/// header per changed file, then its hunks, or a note for binary files.
+pub fn diff(dir: &Path, refs: &[&str]) -> Result<String> {
+    let (from, to) = match refs {
+        [range] if range.contains("...") => bail!("Symmetric ranges ('{range}') are not supported"),
+        [range] => match range.split_once("..") {
+            Some((from, to)) => (non_empty_or_head(from), Some(non_empty_or_head(to))),
+            None => (*range, None),
+        },
+        [from, to] => (*from, Some(*to)),
+        [] => bail!("No git ref given"),
+        _ => bail!("At most two git refs can be given"),
+    };
+    validate_ref(from)?;
+    to.map(validate_ref).transpose()?;
+
+    let repository = Repository::discover(dir)?;
+    let mut output = String::new();
+    for change in repository.changes(from, to)? {
+        write_file_diff(&mut output, &repository, &change)?;
+    }
+    Ok(output)
+}
+
+fn non_empty_or_head(revision: &str) -> &str {
+    if revision.is_empty() { "HEAD" } else { revision }
+}
+
+/// Appends the diff of one changed file to `output`.
+fn write_file_diff(output: &mut String, repository: &Repository, change: &FileChange) -> Result<()> {
+    let path = &change.path;
+    let _ = writeln!(output, "diff --git a/{path} b/{path}");
+    let abbreviate = |version: Option<&Version>| match version {
+        Some(version) => repository.abbreviate(version.id),
+        None => Ok("0".repeat(MIN_ABBREV)),
+    };
+    let (old_id, new_id) = (abbreviate(change.old.as_ref())?, abbreviate(change.new.as_ref())?);
+    match (&change.old, &change.new) {
+        (None, Some(new)) => {
+            let _ = writeln!(output, "new file mode {:o}\nindex {old_id}..{new_id}", new.mode);
+        }
+        (Some(old), None) => {
+            let _ = writeln!(output, "deleted file mode {:o}\nindex {old_id}..{new_id}", old.mode);
+        }
+        (Some(old), Some(new)) if old.mode != new.mode => {
+            let _ = writeln!(output, "old mode {:o}\nnew mode {:o}", old.mode, new.mode);
+            if old.id != new.id {
+                let _ = writeln!(output, "index {old_id}..{new_id}");
+            }
+        }
+        (Some(old), Some(_)) => {
+            let _ = writeln!(output, "index {old_id}..{new_id} {:o}", old.mode);
+        }
+        (None, None) => return Ok(()),
+    }
+    if change.old.as_ref().map(|version| version.id) == change.new.as_ref().map(|version| version.id) {
+        return Ok(());
+    }
+
+    let read = |version: Option<&Version>| version.map(|version| repository.content(version)).transpose();
+    let (old, new) = (read(change.old.as_ref())?, read(change.new.as_ref())?);
+    let label = |version: &Option<Vec<u8>>, side: &str| match version {
+        Some(_) => format!("{side}/{path}"),
+        None => "/dev/null".to_string(),
+    };
+    let (old_label, new_label) = (label(&old, "a"), label(&new, "b"));
+    let (old, new) = (old.unwrap_or_default(), new.unwrap_or_default());
+    if is_binary(&old) || is_binary(&new) {
+        let _ = writeln!(output, "Binary files {old_label} and {new_label} differ");
+        return Ok(());
+    }
+    output.push_str(&unified_diff(
+        &String::from_utf8_lossy(&old),
+        &String::from_utf8_lossy(&new),
+        &old_label,
+        &new_label,
+    ));
+    Ok(())
+}
+
+/// Whether `content` looks binary to git: it has a NUL byte near the start.
+fn is_binary(content: &[u8]) -> bool {
+    content.iter().take(BINARY_PROBE_SIZE).any(|&byte| byte == 0)
+}
+
+/// Returns the directory git runs hooks from for the repository containing `dir`
+/// (honoring `core.hooksPath`).
+pub fn hooks_dir(dir: &Path) -> Result<PathBuf> {
+    let output = run_git(dir, &["rev-parse", "--git-path", "hooks"])?;
+    // Relative results are relative to `dir`
+    Ok(dir.join(output.trim_end_matches(['\n', '\r'])))
+}
+
+/// Returns the commit the branch or tag `git_ref` points to in the remote repository
+/// at `url`, or `None` if it has no such ref. Runs in `dir`, but needs no repository.
+pub fn remote_commit(dir: &Path, url: &str, git_ref: &str) -> Result<Option<String>> {
+    validate_ref(git_ref)?;
+    let heads = format!("refs/heads/{git_ref}");
+    let tags = format!("refs/tags/{git_ref}");
+    let peeled = format!("{tags}^{{}}");
+    let output = run_git(dir, &["ls-remote", "--", url, &heads, &tags])?;
+    let refs: Vec<(&str, &str)> = output
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .collect();
+    // An annotated tag's own object is not a commit; the peeled entry is
+    let commit = [&peeled, &heads, &tags]
+        .iter()
+        .find_map(|name| refs.iter().find(|(_, r)| r == name))
+        .map(|(commit, _)| commit.to_string());
+    Ok(commit)
+}
@@ -0,0 +1,1253 @@
+//! Reads git repositories directly instead of running `git`: refs, loose and packed
+//! objects, trees and the index, as far as [`git`](crate::git) needs them to compare
+//! a revision with another one or with the working tree. Only repositories in the
+//! default SHA-1 object format are supported.
+
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::{self, Read, Seek, SeekFrom},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use crate::archive::{MAX_INFLATED_SIZE, zlib_decompress};
+
+/// Most symbolic refs followed to resolve one ref.
+const MAX_SYMREF_DEPTH: usize = 5;
+
+/// Most deltas applied to reconstruct one packed object, which also ends cycles in
+/// corrupt packs.
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// Shortest abbreviation of an object id that is shown, as with git's default.
+pub(crate) const MIN_ABBREV: usize = 7;
+
+/// Tree entry modes.
+pub(crate) const MODE_TREE: u32 = 0o40000;
+pub(crate) const MODE_FILE: u32 = 0o100644;
+pub(crate) const MODE_EXECUTABLE: u32 = 0o100755;
+pub(crate) const MODE_SYMLINK: u32 = 0o120000;
+pub(crate) const MODE_GITLINK: u32 = 0o160000;
+
+/// The SHA-1 name of an object.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct ObjectId(pub(crate) [u8; 20]);
+
+impl ObjectId {
+    fn from_hex(hex: &str) -> Option<Self> {
+        if hex.len() != 40 {
+            return None;
+        }
+        Some(Self(hex::decode(hex).ok()?.try_into().ok()?))
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        Some(Self(bytes.try_into().ok()?))
+    }
+
+    pub(crate) fn to_hex(self) -> String {
+        hex::encode(self.0)
+    }
+
+    /// The id git gives a blob with `content`.
+    pub(crate) fn for_blob(content: &[u8]) -> Self {
+        let mut object = format!("blob {}\0", content.len()).into_bytes();
+        object.extend_from_slice(content);
+        Self(sha1(&object))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Kind {
+    Commit,
+    Tree,
+    Blob,
+    Tag,
+}
+
+impl Kind {
+    fn from_name(name: &[u8]) -> Option<Self> {
+        match name {
+            b"commit" => Some(Self::Commit),
+            b"tree" => Some(Self::Tree),
+            b"blob" => Some(Self::Blob),
+            b"tag" => Some(Self::Tag),
+            _ => None,
+        }
+    }
+
+    /// The kind of a packed object of type `number`, if it is not a delta.
+    fn from_pack_type(number: u8) -> Option<Self> {
+        match number {
+            1 => Some(Self::Commit),
+            2 => Some(Self::Tree),
+            3 => Some(Self::Blob),
+            4 => Some(Self::Tag),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) struct Object {
+    pub(crate) kind: Kind,
+    pub(crate) data: Vec<u8>,
+}
+
+/// One side of a [`FileChange`]: a file's mode and blob, with its content if it was
+/// read from the working tree rather than the object database.
+#[derive(Debug, Clone)]
+pub(crate) struct Version {
+    pub(crate) mode: u32,
+    pub(crate) id: ObjectId,
+    content: Option<Vec<u8>>,
+}
+
+/// A file that differs between two versions of a repository. `None` on a side means
+/// the file does not exist there.
+#[derive(Debug)]
+pub(crate) struct FileChange {
+    /// `/`-separated, relative to the repository root.
+    pub(crate) path: String,
+    pub(crate) old: Option<Version>,
+    pub(crate) new: Option<Version>,
+}
+
+/// An entry of the index.
+struct IndexEntry {
+    mode: u32,
+    id: ObjectId,
+    /// Size and modification time (seconds, nanoseconds) when the entry was written.
+    size: u32,
+    mtime: (u32, u32),
+}
+
+/// A packfile with its index (version 2).
+struct Pack {
+    path: PathBuf,
+    /// The whole `.idx` file.
+    index: Vec<u8>,
+    count: usize,
+    /// The offset of every object in the pack, sorted, to tell where each one ends.
+    offsets: Vec<u64>,
+    size: u64,
+}
+
+/// An object as stored in a pack.
+enum PackEntry {
+    Whole(Object),
+    /// Delta against the object at an earlier offset of the same pack.
+    OffsetDelta(u64, Vec<u8>),
+    /// Delta against the object with an id.
+    RefDelta(ObjectId, Vec<u8>),
+}
+
+fn u32_at(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]])
+}
+
+impl Pack {
+    /// Header, version and the fan-out table of 256 counts.
+    const INDEX_HEADER: usize = 8 + 256 * 4;
+
+    fn open(index_path: &Path) -> Result<Self> {
+        let index = fs::read(index_path)
+            .with_context(|| format!("Failed to read pack index '{}'", index_path.display()))?;
+        if index.len() < Self::INDEX_HEADER || !index.starts_with(b"\xfftOc") || u32_at(&index, 4) != 2 {
+            bail!("Unsupported pack index '{}' (only version 2 is read)", index_path.display());
+        }
+        let count = u32_at(&index, Self::INDEX_HEADER - 4) as usize;
+        // Ids, checksums and offsets, then the pack's and the index's checksum
+        if index.len() < Self::INDEX_HEADER + count * 28 + 40 {
+            bail!("Truncated pack index '{}'", index_path.display());
+        }
+        let path = index_path.with_extension("pack");
+        let size = fs::metadata(&path)
+            .with_context(|| format!("Failed to read packfile '{}'", path.display()))?
+            .len();
+        let mut pack = Self { path, index, count, offsets: Vec::new(), size };
+        let mut offsets = (0..count).map(|position| pack.offset(position)).collect::<Result<Vec<_>>>()?;
+        offsets.sort_unstable();
+        pack.offsets = offsets;
+        Ok(pack)
+    }
+
+    fn id(&self, position: usize) -> &[u8] {
+        let start = Self::INDEX_HEADER + position * 20;
+        &self.index[start..start + 20]
+    }
+
+    fn offset(&self, position: usize) -> Result<u64> {
+        let offsets = Self::INDEX_HEADER + self.count * 24;
+        let offset = u32_at(&self.index, offsets + position * 4);
+        if offset & 0x8000_0000 == 0 {
+            return Ok(u64::from(offset));
+        }
+        // An index into the table of 8-byte offsets, for packs over 2 GiB
+        let start = offsets + self.count * 4 + (offset & 0x7FFF_FFFF) as usize * 8;
+        let bytes = self.index.get(start..start + 8).context("Truncated pack index")?;
+        Ok(u64::from_be_bytes(bytes.try_into().expect("8 bytes")))
+    }
+
+    /// The positions of the ids whose first byte is `first`.
+    fn fan_out(&self, first: u8) -> std::ops::Range<usize> {
+        let count_below = |byte: usize| u32_at(&self.index, 8 + byte * 4) as usize;
+        let start = if first == 0 { 0 } else { count_below(first as usize - 1) };
+        start..count_below(first as usize).max(start)
+    }
+
+    /// The offset of object `id`, if the pack has it.
+    fn find(&self, id: &ObjectId) -> Result<Option<u64>> {
+        let range = self.fan_out(id.0[0]);
+        let ids: Vec<&[u8]> = range.clone().map(|position| self.id(position)).collect();
+        match ids.binary_search(&&id.0[..]) {
+            Ok(found) => self.offset(range.start + found).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Adds the ids starting with `prefix` (lowercase hex, at least two digits) to `found`.
+    fn find_prefix(&self, prefix: &str, found: &mut BTreeSet<ObjectId>) {
+        let Ok(first) = u8::from_str_radix(&prefix[..2], 16) else {
+            return;
+        };
+        for position in self.fan_out(first) {
+            let id = self.id(position);
+            if hex::encode(id).starts_with(prefix) {
+                found.insert(ObjectId::from_bytes(id).expect("20 bytes"));
+            }
+        }
+    }
+
+    /// Reads the entry at `offset`: its header, and the compressed data up to the next entry.
+    fn read_entry(&self, offset: u64) -> Result<PackEntry> {
+        let end = match self.offsets.partition_point(|&start| start <= offset) {
+            next if next < self.offsets.len() => self.offsets[next],
+            _ => self.size.saturating_sub(20), // The pack's checksum follows the last entry
+        };
+        let length = end
+            .checked_sub(offset)
+            .filter(|&length| length <= MAX_INFLATED_SIZE as u64)
+            .context("Invalid pack entry offset")?;
+        let mut raw = vec![0; length as usize];
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut raw)?;
+
+        let mut position = 0;
+        let mut next_byte = || -> Result<u8> {
+            let byte = *raw.get(position).context("Truncated pack entry")?;
+            position += 1;
+            Ok(byte)
+        };
+        let mut byte = next_byte()?;
+        let pack_type = (byte >> 4) & 0x07;
+        let mut size = usize::from(byte & 0x0F);
+        let mut shift = 4;
+        while byte & 0x80 != 0 {
+            byte = next_byte()?;
+            if shift > 60 {
+                bail!("Invalid pack entry size");
+            }
+            size |= usize::from(byte & 0x7F) << shift;
+            shift += 7;
+        }
+        if size > MAX_INFLATED_SIZE {
+            bail!("Pack entry exceeds {MAX_INFLATED_SIZE} bytes");
+        }
+
+        let entry = match pack_type {
+            6 => {
+                let distance = offset_varint(&mut next_byte)?;
+                let base = offset
+                    .checked_sub(distance)
+                    .filter(|_| distance > 0)
+                    .context("Invalid delta base offset")?;
+                PackEntry::OffsetDelta(base, Vec::new())
+            }
+            7 => {
+                let mut base = [0; 20];
+                for byte in &mut base {
+                    *byte = next_byte()?;
+                }
+                PackEntry::RefDelta(ObjectId(base), Vec::new())
+            }
+            number => {
+                let kind = Kind::from_pack_type(number).context("Invalid pack entry type")?;
+                PackEntry::Whole(Object { kind, data: Vec::new() })
+            }
+        };
+        let data = zlib_decompress(&raw[position..], size)?;
+        if data.len() != size {
+            bail!("Pack entry size mismatch");
+        }
+        Ok(match entry {
+            PackEntry::OffsetDelta(base, _) => PackEntry::OffsetDelta(base, data),
+            PackEntry::RefDelta(base, _) => PackEntry::RefDelta(base, data),
+            PackEntry::Whole(object) => PackEntry::Whole(Object { data, ..object }),
+        })
+    }
+}
+
+/// Reads the variable-length integer git uses for delta base offsets and in
+/// version 4 indexes: big-endian groups of 7 bits, each continued group adding one.
+fn offset_varint(next_byte: &mut impl FnMut() -> Result<u8>) -> Result<u64> {
+    let mut byte = next_byte()?;
+    let mut value = u64::from(byte & 0x7F);
+    while byte & 0x80 != 0 {
+        byte = next_byte()?;
+        value = value
+            .checked_add(1)
+            .and_then(|value| value.checked_mul(128))
+            .context("Invalid variable-length integer")?
+            | u64::from(byte & 0x7F);
+    }
+    Ok(value)
+}
+
+/// Applies a git `delta` (copy and insert instructions) to `base`.
+fn apply_delta(base: &[u8], delta: &[u8]) -> Result<Vec<u8>> {
+    let mut position = 0;
+    let mut next_byte = || -> Result<u8> {
+        let byte = *delta.get(position).context("Truncated delta")?;
+        position += 1;
+        Ok(byte)
+    };
+    // Sizes are little-endian groups of 7 bits
+    let mut size = || -> Result<usize> {
+        let (mut value, mut shift) = (0usize, 0);
+        loop {
+            let byte = next_byte()?;
+            if shift > 60 {
+                bail!("Invalid delta size");
+            }
+            value |= usize::from(byte & 0x7F) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    };
+    if size()? != base.len() {
+        bail!("Delta does not match its base object");
+    }
+    let target_size = size()?;
+    if target_size > MAX_INFLATED_SIZE {
+        bail!("Delta result exceeds {MAX_INFLATED_SIZE} bytes");
+    }
+
+    let mut target = Vec::with_capacity(target_size);
+    while position < delta.len() {
+        let instruction = delta[position];
+        position += 1;
+        if instruction & 0x80 != 0 {
+            // Copy from the base; the bits say which offset and size bytes follow
+            let (mut offset, mut length) = (0usize, 0usize);
+            for bit in 0..7 {
+                if instruction & (1 << bit) != 0 {
+                    let byte = usize::from(*delta.get(position).context("Truncated delta")?);
+                    position += 1;
+                    if bit < 4 {
+                        offset |= byte << (8 * bit);
+                    } else {
+                        length |= byte << (8 * (bit - 4));
+                    }
+                }
+            }
+            if length == 0 {
+                length = 0x10000;
+            }
+            let copied = offset
+                .checked_add(length)
+                .and_then(|end| base.get(offset..end))
+                .context("Delta copies past the end of its base object")?;
+            target.extend_from_slice(copied);
+        } else if instruction != 0 {
+            let end = position + usize::from(instruction);
+            target.extend_from_slice(delta.get(position..end).context("Truncated delta")?);
+            position = end;
+        } else {
+            bail!("Invalid delta instruction");
+        }
+        if target.len() > target_size {
+            bail!("Delta result exceeds its declared size");
+        }
+    }
+    if target.len() != target_size {
+        bail!("Delta result does not have its declared size");
+    }
+    Ok(target)
+}
+
+/// A repository with a working tree, opened for reading.
+pub(crate) struct Repository {
+    work_tree: PathBuf,
+    /// The repository's `.git` directory (for a linked worktree, its own one).
+    git_dir: PathBuf,
+    /// Where objects and shared refs are stored: `git_dir`, or the main repository's
+    /// for a linked worktree.
+    common_dir: PathBuf,
+    /// `objects` and its alternates.
+    object_dirs: Vec<PathBuf>,
+    packs: Vec<Pack>,
+    packed_refs: BTreeMap<String, ObjectId>,
+}
+
+impl Repository {
+    /// Opens the repository whose working tree contains `dir`.
+    pub(crate) fn discover(dir: &Path) -> Result<Self> {
+        let start = fs::canonicalize(dir).with_context(|| format!("Failed to resolve '{}'", dir.display()))?;
+        for root in start.ancestors() {
+            let dot_git = root.join(".git");
+            let git_dir = if dot_git.is_dir() {
+                dot_git
+            } else if let Ok(content) = fs::read_to_string(&dot_git) {
+                // Linked worktrees and submodules point to their git directory
+                let target = content
+                    .trim()
+                    .strip_prefix("gitdir:")
+                    .with_context(|| format!("Invalid git file '{}'", dot_git.display()))?;
+                root.join(target.trim())
+            } else {
+                continue;
+            };
+            return Self::open(root.to_path_buf(), git_dir);
+        }
+        bail!("'{}' is not inside a git repository", dir.display())
+    }
+
+    fn open(work_tree: PathBuf, git_dir: PathBuf) -> Result<Self> {
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(common_dir) => git_dir.join(common_dir.trim()),
+            Err(_) => git_dir.clone(),
+        };
+        if let Ok(config) = fs::read_to_string(common_dir.join("config"))
+            && config.lines().any(|line| {
+                let line = line.trim().to_ascii_lowercase();
+                line.starts_with("objectformat") && line.contains("sha256")
+            })
+        {
+            bail!("Repositories using SHA-256 object names are not supported");
+        }
+
+        let objects = common_dir.join("objects");
+        let mut object_dirs = vec![objects.clone()];
+        if let Ok(alternates) = fs::read_to_string(objects.join("info/alternates")) {
+            let alternates = alternates.lines().map(str::trim);
+            object_dirs.extend(
+                alternates
+                    .filter(|line| !line.is_empty() && !line.starts_with('#'))
+                    .map(|line| objects.join(line)),
+            );
+        }
+        let mut packs = Vec::new();
+        for dir in &object_dirs {
+            let Ok(entries) = fs::read_dir(dir.join("pack")) else {
+                continue;
+            };
+            let mut indexes: Vec<PathBuf> = entries
+                .filter_map(|entry| Some(entry.ok()?.path()))
+                .filter(|path| path.extension().is_some_and(|extension| extension == "idx"))
+                .collect();
+            indexes.sort();
+            for index in indexes {
+                packs.push(Pack::open(&index)?);
+            }
+        }
+
+        let mut packed_refs = BTreeMap::new();
+        if let Ok(content) = fs::read_to_string(common_dir.join("packed-refs")) {
+            // `^<id>` lines peel the tag above them, which resolving does itself
+            for line in content.lines().filter(|line| !line.starts_with(['#', '^'])) {
+                if let Some((id, name)) = line.split_once(' ')
+                    && let Some(id) = ObjectId::from_hex(id)
+                {
+                    packed_refs.insert(name.to_string(), id);
+                }
+            }
+        }
+
+        Ok(Self { work_tree, git_dir, common_dir, object_dirs, packs, packed_refs })
+    }
+
+    /// The root of the working tree.
+    pub(crate) fn work_tree(&self) -> &Path {
+        &self.work_tree
+    }
+
+    fn loose_path(dir: &Path, id: ObjectId) -> PathBuf {
+        let hex = id.to_hex();
+        dir.join(&hex[..2]).join(&hex[2..])
+    }
+
+    fn find_packed(&self, id: ObjectId) -> Result<Option<(usize, u64)>> {
+        for (index, pack) in self.packs.iter().enumerate() {
+            if let Some(offset) = pack.find(&id)? {
+                return Ok(Some((index, offset)));
+            }
+        }
+        Ok(None)
+    }
+
+    fn read_loose(&self, id: ObjectId) -> Result<Option<Object>> {
+        let Some(path) = self
+            .object_dirs
+            .iter()
+            .map(|dir| Self::loose_path(dir, id))
+            .find(|path| path.is_file())
+        else {
+            return Ok(None);
+        };
+        let compressed = fs::read(&path).with_context(|| format!("Failed to read object '{}'", path.display()))?;
+        let raw = zlib_decompress(&compressed, MAX_INFLATED_SIZE + 64)
+            .with_context(|| format!("Invalid object '{}'", path.display()))?;
+        let header_end = raw.iter().position(|&byte| byte == 0).context("Invalid object header")?;
+        let (kind, size) = raw[..header_end]
+            .split(|&byte| byte == b' ')
+            .collect::<Vec<_>>()
+            .split_first()
+            .and_then(|(kind, size)| Some((Kind::from_name(kind)?, std::str::from_utf8(size.first()?).ok()?)))
+            .with_context(|| format!("Invalid object header in '{}'", path.display()))?;
+        let data = raw[header_end + 1..].to_vec();
+        if size.parse::<usize>().ok() != Some(data.len()) {
+            bail!("Object '{}' does not have its declared size", path.display());
+        }
+        Ok(Some(Object { kind, data }))
+    }
+
+    /// Reads object `id`.
+    pub(crate) fn read_object(&self, id: ObjectId) -> Result<Object> {
+        if let Some(object) = self.read_loose(id)? {
+            return Ok(object);
+        }
+        let Some((mut pack, mut offset)) = self.find_packed(id)? else {
+            bail!("Object {} is missing from the repository", id.to_hex());
+        };
+
+        // Follow the delta chain down to a whole object, then apply the deltas
+        let mut deltas = Vec::new();
+        let mut object = loop {
+            if deltas.len() > MAX_DELTA_DEPTH {
+                bail!("Delta chain of object {} is too long", id.to_hex());
+            }
+            let entry = self.packs[pack]
+                .read_entry(offset)
+                .with_context(|| format!("Invalid packed object {}", id.to_hex()))?;
+            match entry {
+                PackEntry::Whole(object) => break object,
+                PackEntry::OffsetDelta(base, delta) => {
+                    deltas.push(delta);
+                    offset = base;
+                }
+                PackEntry::RefDelta(base, delta) => {
+                    deltas.push(delta);
+                    match self.find_packed(base)? {
+                        Some(location) => (pack, offset) = location,
+                        None => match self.read_loose(base)? {
+                            Some(object) => break object,
+                            None => bail!("Delta base {} is missing from the repository", base.to_hex()),
+                        },
+                    }
+                }
+            }
+        };
+        for delta in deltas.iter().rev() {
+            object.data = apply_delta(&object.data, delta)?;
+        }
+        Ok(object)
+    }
+
+    /// The ids of the objects whose hex name starts with `prefix`.
+    fn find_prefix(&self, prefix: &str) -> Result<BTreeSet<ObjectId>> {
+        let prefix = prefix.to_ascii_lowercase();
+        let mut found = BTreeSet::new();
+        for dir in &self.object_dirs {
+            let Ok(entries) = fs::read_dir(dir.join(&prefix[..2])) else {
+                continue;
+            };
+            for entry in entries {
+                let name = format!("{}{}", &prefix[..2], entry?.file_name().to_string_lossy());
+                if name.starts_with(&prefix)
+                    && let Some(id) = ObjectId::from_hex(&name)
+                {
+                    found.insert(id);
+                }
+            }
+        }
+        for pack in &self.packs {
+            pack.find_prefix(&prefix, &mut found);
+        }
+        Ok(found)
+    }
+
+    /// The shortest unambiguous abbreviation of `id`, of at least seven digits.
+    pub(crate) fn abbreviate(&self, id: ObjectId) -> Result<String> {
+        let hex = id.to_hex();
+        for length in MIN_ABBREV..hex.len() {
+            if self.find_prefix(&hex[..length])?.iter().all(|found| *found == id) {
+                return Ok(hex[..length].to_string());
+            }
+        }
+        Ok(hex)
+    }
+
+    /// The object the ref `name` (e.g. `refs/heads/main`) points to, following
+    /// symbolic refs, or `None` if there is no such ref.
+    fn resolve_ref(&self, name: &str) -> Result<Option<ObjectId>> {
+        let mut name = name.to_string();
+        for _ in 0..MAX_SYMREF_DEPTH {
+            validate_ref_name(&name)?;
+            // Per-worktree refs such as HEAD are in the worktree's own directory
+            let content = [&self.git_dir, &self.common_dir]
+                .iter()
+                .find_map(|dir| fs::read_to_string(dir.join(&name)).ok());
+            let Some(content) = content else {
+                return Ok(self.packed_refs.get(&name).copied());
+            };
+            let content = content.trim();
+            match content.strip_prefix("ref:") {
+                Some(target) => name = target.trim().to_string(),
+                None => {
+                    return ObjectId::from_hex(content)
+                        .map(Some)
+                        .with_context(|| format!("Invalid ref '{name}'"));
+                }
+            }
+        }
+        bail!("Too many levels of symbolic refs resolving '{name}'")
+    }
+
+    /// The object named by `revision`: a ref, branch, tag or remote-tracking branch,
+    /// a full or abbreviated object id, or `HEAD`/`@`, optionally followed by `~n`,
+    /// `^n` (parents) and `^{}` (a peeled tag).
+    pub(crate) fn rev_parse(&self, revision: &str) -> Result<ObjectId> {
+        let (name, mut suffix) = revision.split_at(revision.find(['~', '^']).unwrap_or(revision.len()));
+        let mut id = self.resolve_name(if name == "@" { "HEAD" } else { name })?;
+        while !suffix.is_empty() {
+            if let Some(rest) = suffix.strip_prefix("^{}") {
+                id = self.peel_tags(id)?.0;
+                suffix = rest;
+                continue;
+            }
+            let operator = suffix.as_bytes()[0];
+            let digits = suffix[1..].bytes().take_while(u8::is_ascii_digit).count();
+            let count: usize = match &suffix[1..1 + digits] {
+                "" => 1,
+                digits => digits.parse().with_context(|| format!("Invalid revision '{revision}'"))?,
+            };
+            suffix = &suffix[1 + digits..];
+            id = match operator {
+                b'~' => {
+                    for _ in 0..count {
+                        id = *self.parents(id)?.first().with_context(|| {
+                            format!("Revision '{revision}' goes past the first commit")
+                        })?;
+                    }
+                    id
+                }
+                _ if count == 0 => self.peel_to_commit(id)?,
+                _ => *self
+                    .parents(id)?
+                    .get(count - 1)
+                    .with_context(|| format!("Revision '{revision}' names a parent that does not exist"))?,
+            };
+        }
+        Ok(id)
+    }
+
+    fn resolve_name(&self, name: &str) -> Result<ObjectId> {
+        if let Some(id) = ObjectId::from_hex(name) {
+            return Ok(id);
+        }
+        // HEAD, ORIG_HEAD and the like name files in the git directory themselves
+        let special = !name.is_empty() && name.bytes().all(|byte| byte.is_ascii_uppercase() || byte == b'_');
+        let mut candidates = Vec::new();
+        if special || name.starts_with("refs/") {
+            candidates.push(name.to_string());
+        }
+        for pattern in ["refs/{}", "refs/tags/{}", "refs/heads/{}", "refs/remotes/{}", "refs/remotes/{}/HEAD"] {
+            candidates.push(pattern.replace("{}", name));
+        }
+        for candidate in &candidates {
+            if let Some(id) = self.resolve_ref(candidate)? {
+                return Ok(id);
+            }
+        }
+        if name.len() >= 4 && name.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+            let found = self.find_prefix(name)?;
+            match found.len() {
+                0 => {}
+                1 => return Ok(*found.first().expect("one id")),
+                _ => bail!("Short object id '{name}' is ambiguous"),
+            }
+        }
+        bail!("Unknown revision '{name}'")
+    }
+
+    /// Follows annotated tags from `id` to the object they tag.
+    fn peel_tags(&self, mut id: ObjectId) -> Result<(ObjectId, Object)> {
+        for _ in 0..MAX_SYMREF_DEPTH {
+            let object = self.read_object(id)?;
+            if object.kind != Kind::Tag {
+                return Ok((id, object));
+            }
+            id = header(&object.data, "object")
+                .next()
+                .and_then(ObjectId::from_hex)
+                .with_context(|| format!("Invalid tag {}", id.to_hex()))?;
+        }
+        bail!("Too many levels of tags at {}", id.to_hex())
+    }
+
+    fn peel_to_commit(&self, id: ObjectId) -> Result<ObjectId> {
+        match self.peel_tags(id)? {
+            (id, Object { kind: Kind::Commit, .. }) => Ok(id),
+            (id, _) => bail!("Object {} is not a commit", id.to_hex()),
+        }
+    }
+
+    fn parents(&self, id: ObjectId) -> Result<Vec<ObjectId>> {
+        let (id, commit) = self.peel_tags(id)?;
+        if commit.kind != Kind::Commit {
+            bail!("Object {} is not a commit", id.to_hex());
+        }
+        header(&commit.data, "parent")
+            .map(|parent| ObjectId::from_hex(parent).with_context(|| format!("Invalid commit {}", id.to_hex())))
+            .collect()
+    }
+
+    /// The tree of the commit (or tree) `id`.
+    fn tree_of(&self, id: ObjectId) -> Result<ObjectId> {
+        match self.peel_tags(id)? {
+            (id, Object { kind: Kind::Tree, .. }) => Ok(id),
+            (id, Object { kind: Kind::Commit, data }) => header(&data, "tree")
+                .next()
+                .and_then(ObjectId::from_hex)
+                .with_context(|| format!("Invalid commit {}", id.to_hex())),
+            (id, _) => bail!("Object {} is not a commit or tree", id.to_hex()),
+        }
+    }
+
+    /// Path -> (mode, id) of every file below the tree `id`.
+    fn tree_files(&self, id: ObjectId) -> Result<BTreeMap<String, (u32, ObjectId)>> {
+        let mut files = BTreeMap::new();
+        let mut pending = vec![(String::new(), id)];
+        while let Some((prefix, id)) = pending.pop() {
+            let tree = self.read_object(id)?;
+            if tree.kind != Kind::Tree {
+                bail!("Object {} is not a tree", id.to_hex());
+            }
+            let invalid = || format!("Invalid tree {}", id.to_hex());
+            let mut rest = &tree.data[..];
+            // Entries are `<octal mode> <name>\0<20-byte id>`
+            while !rest.is_empty() {
+                let space = rest.iter().position(|&byte| byte == b' ').with_context(invalid)?;
+                let nul = rest.iter().position(|&byte| byte == 0).filter(|&nul| nul > space).with_context(invalid)?;
+                let mode = std::str::from_utf8(&rest[..space])
+                    .ok()
+                    .and_then(|mode| u32::from_str_radix(mode, 8).ok())
+                    .with_context(invalid)?;
+                let entry_id = rest.get(nul + 1..nul + 21).and_then(ObjectId::from_bytes).with_context(invalid)?;
+                let name = String::from_utf8_lossy(&rest[space + 1..nul]);
+                let path = if prefix.is_empty() { name.into_owned() } else { format!("{prefix}/{name}") };
+                rest = &rest[nul + 21..];
+                if mode == MODE_TREE {
+                    pending.push((path, entry_id));
+                } else {
+                    files.insert(path, (mode, entry_id));
+                }
+            }
+        }
+        Ok(files)
+    }
+
+    /// Path -> entry of every file in the index (versions 2 to 4). A repository
+    /// without an index has none.
+    fn index_entries(&self) -> Result<BTreeMap<String, IndexEntry>> {
+        let path = self.git_dir.join("index");
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+            Err(error) => return Err(error).with_context(|| format!("Failed to read index '{}'", path.display())),
+        };
+        if data.len() < 12 || !data.starts_with(b"DIRC") {
+            bail!("Invalid index '{}'", path.display());
+        }
+        let version = u32_at(&data, 4);
+        if !(2..=4).contains(&version) {
+            bail!("Unsupported index version {version} in '{}'", path.display());
+        }
+        let truncated = || format!("Truncated index '{}'", path.display());
+
+        let mut entries = BTreeMap::new();
+        let mut position = 12;
+        let mut previous: Vec<u8> = Vec::new();
+        for _ in 0..u32_at(&data, 8) {
+            // ctime, mtime, dev, ino, mode, uid, gid and size (4 bytes each), id, flags
+            let fixed = data.get(position..position + 62).with_context(truncated)?;
+            let flags = u16::from_be_bytes([fixed[60], fixed[61]]);
+            let entry = IndexEntry {
+                mode: u32_at(fixed, 24),
+                id: ObjectId::from_bytes(&fixed[40..60]).expect("20 bytes"),
+                size: u32_at(fixed, 36),
+                mtime: (u32_at(fixed, 8), u32_at(fixed, 12)),
+            };
+            let mut name_start = position + 62;
+            if flags & 0x4000 != 0 {
+                name_start += 2; // Extended flags
+            }
+            let name_end = data
+                .get(name_start..)
+                .and_then(|rest| rest.iter().position(|&byte| byte == 0))
+                .map(|length| name_start + length)
+                .with_context(truncated)?;
+            let name = if version == 4 {
+                // The name replaces the end of the previous one
+                let mut at = name_start;
+                let strip = offset_varint(&mut || {
+                    let byte = *data.get(at).with_context(truncated)?;
+                    at += 1;
+                    Ok(byte)
+                })?;
+                let kept = previous.len().checked_sub(strip as usize).with_context(truncated)?;
+                let mut name = previous[..kept].to_vec();
+                name.extend_from_slice(&data[at..name_end]);
+                position = name_end + 1;
+                name
+            } else {
+                // Entries are padded with NULs to a multiple of eight bytes
+                position += (name_end - position + 8) & !7;
+                data[name_start..name_end].to_vec()
+            };
+            if entry.mode == MODE_TREE {
+                bail!("Sparse indexes are not supported ('{}')", path.display());
+            }
+            entries.insert(String::from_utf8_lossy(&name).into_owned(), entry);
+            previous = name;
+        }
+        Ok(entries)
+    }
+
+    /// The working tree's version of the file at `path`, tracked by `entry` of the
+    /// index, or `None` if it was deleted. A file whose size and modification time
+    /// are those the index recorded has the index's content, which is not read then.
+    fn work_tree_version(&self, path: &str, entry: &IndexEntry, index_mtime: Option<(u32, u32)>) -> Result<Option<Version>> {
+        if entry.mode == MODE_GITLINK {
+            // A submodule is compared by the commit the index records for it
+            return Ok(Some(Version { mode: MODE_GITLINK, id: entry.id, content: None }));
+        }
+        let full_path = self.work_tree.join(path);
+        let metadata = match fs::symlink_metadata(&full_path) {
+            Ok(metadata) if !metadata.is_dir() => metadata,
+            Ok(_) => return Ok(None),
+            Err(error) if matches!(error.kind(), io::ErrorKind::NotFound | io::ErrorKind::NotADirectory) => {
+                return Ok(None);
+            }
+            Err(error) => return Err(error).with_context(|| format!("Failed to read '{}'", full_path.display())),
+        };
+
+        if metadata.is_symlink() {
+            let target = fs::read_link(&full_path)
+                .with_context(|| format!("Failed to read symlink '{}'", full_path.display()))?;
+            let content = target.to_string_lossy().into_owned().into_bytes();
+            return Ok(Some(Version { mode: MODE_SYMLINK, id: ObjectId::for_blob(&content), content: Some(content) }));
+        }
+        let mode = file_mode(&metadata, entry.mode);
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|since| (since.as_secs() as u32, since.subsec_nanos()));
+        // A file written in the same instant as the index may have changed since
+        let racy = index_mtime.is_none_or(|index_mtime| mtime.is_none_or(|mtime| mtime >= index_mtime));
+        if mtime == Some(entry.mtime) && metadata.len() as u32 == entry.size && !racy {
+            return Ok(Some(Version { mode, id: entry.id, content: None }));
+        }
+        let content = fs::read(&full_path).with_context(|| format!("Failed to read '{}'", full_path.display()))?;
+        Ok(Some(Version { mode, id: ObjectId::for_blob(&content), content: Some(content) }))
+    }
+
+    /// The files that differ between the trees of revisions `from` and `to`, or
+    /// between `from` and the files of the working tree that the index tracks, as
+    /// `git diff` compares them (without rename detection). Sorted by path.
+    pub(crate) fn changes(&self, from: &str, to: Option<&str>) -> Result<Vec<FileChange>> {
+        let old = self.tree_files(self.tree_of(self.rev_parse(from)?)?)?;
+        let new: BTreeMap<String, Version> = match to {
+            Some(to) => self
+                .tree_files(self.tree_of(self.rev_parse(to)?)?)?
+                .into_iter()
+                .map(|(path, (mode, id))| (path, Version { mode, id, content: None }))
+                .collect(),
+            None => {
+                let index_mtime = fs::metadata(self.git_dir.join("index"))
+                    .and_then(|metadata| metadata.modified())
+                    .ok()
+                    .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                    .map(|since| (since.as_secs() as u32, since.subsec_nanos()));
+                let mut files = BTreeMap::new();
+                for (path, entry) in self.index_entries()? {
+                    if let Some(version) = self.work_tree_version(&path, &entry, index_mtime)? {
+                        files.insert(path, version);
+                    }
+                }
+                files
+            }
+        };
+
+        let paths: BTreeSet<&String> = old.keys().chain(new.keys()).collect();
+        Ok(paths
+            .into_iter()
+            .filter_map(|path| {
+                let old = old.get(path).map(|&(mode, id)| Version { mode, id, content: None });
+                let new = new.get(path).cloned();
+                let unchanged = matches!((&old, &new), (Some(old), Some(new)) if old.mode == new.mode && old.id == new.id);
+                (!unchanged).then(|| FileChange { path: path.clone(), old, new })
+            })
+            .collect())
+    }
+
+    /// The content of `version`: a blob, a symlink's target, or for a submodule
+    /// the line `git diff` shows for its commit.
+    pub(crate) fn content(&self, version: &Version) -> Result<Vec<u8>> {
+        if let Some(content) = &version.content {
+            return Ok(content.clone());
+        }
+        if version.mode == MODE_GITLINK {
+            return Ok(format!("Subproject commit {}\n", version.id.to_hex()).into_bytes());
+        }
+        let object = self.read_object(version.id)?;
+        if object.kind != Kind::Blob {
+            bail!("Object {} is not a blob", version.id.to_hex());
+        }
+        Ok(object.data)
+    }
+}
+
+/// The mode git records for a regular file with `metadata`. Without an executable
+/// bit on the platform, the index's mode is kept.
+fn file_mode(metadata: &fs::Metadata, index_mode: u32) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let _ = index_mode;
+        if metadata.permissions().mode() & 0o111 != 0 { MODE_EXECUTABLE } else { MODE_FILE }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = metadata;
+        if index_mode == MODE_EXECUTABLE { MODE_EXECUTABLE } else { MODE_FILE }
+    }
+}
+
+/// The values of the `name` header lines of a commit or tag object.
+fn header<'a>(data: &'a [u8], name: &'a str) -> impl Iterator<Item = &'a str> {
+    let text = std::str::from_utf8(data).unwrap_or_default();
+    let headers = text.split("\n\n").next().unwrap_or_default();
+    headers.lines().filter_map(move |line| line.strip_prefix(name)?.strip_prefix(' '))
+}
+
+/// Rejects ref names that could read files outside the git directory.
+fn validate_ref_name(name: &str) -> Result<()> {
+    let invalid = name.is_empty()
+        || name.starts_with(['/', '-'])
+        || name.ends_with(['/', '.'])
+        || name.contains("..")
+        || name.contains("//")
+        || name.contains("@{")
+        || name.chars().any(|c| c.is_control() || " ~^:?*[\\".contains(c))
+        || name.split('/').any(|part| part.starts_with('.'));
+    if invalid {
+        bail!("Invalid ref name '{name}'");
+    }
+    Ok(())
+}
+
+/// SHA-1 of `message`, which git names objects by.
+fn sha1(message: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    let whole = message.len() / 64 * 64;
+    for block in message[..whole].chunks_exact(64) {
+        sha1_block(&mut state, block);
+    }
+    let mut tail = message[whole..].to_vec();
+    tail.push(0x80);
+    while tail.len() % 64 != 56 {
+        tail.push(0);
+    }
+    tail.extend_from_slice(&(message.len() as u64 * 8).to_be_bytes());
+    for block in tail.chunks_exact(64) {
+        sha1_block(&mut state, block);
+    }
+
+    let mut digest = [0; 20];
+    for (bytes, word) in digest.chunks_exact_mut(4).zip(state) {
+        bytes.copy_from_slice(&word.to_be_bytes());
+    }
+    digest
+}
+
+fn sha1_block(state: &mut [u32; 5], block: &[u8]) {
+    let mut words = [0u32; 80];
+    for (word, bytes) in words.iter_mut().zip(block.chunks_exact(4)) {
+        *word = u32::from_be_bytes(bytes.try_into().expect("4 bytes"));
+    }
+    for index in 16..80 {
+        words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+    }
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (index, word) in words.iter().enumerate() {
+        let (f, k) = match index {
+            0..=19 => ((b & c) | (!b & d), 0x5A82_7999),
+            20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+            _ => (b ^ c ^ d, 0xCA62_C1D6),
+        };
+        let next = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+        (e, d, c, b, a) = (d, c, b.rotate_left(30), a, next);
+    }
+    for (total, value) in state.iter_mut().zip([a, b, c, d, e]) {
+        *total = total.wrapping_add(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{archive::adler32, git};
+
+    /// A repository in a temporary directory, removed when dropped.
+    struct TestRepo(PathBuf);
+
+    impl TestRepo {
+        fn new(name: &str) -> Self {
+            let dir = std::env::temp_dir().join(format!("clamp-gitdb-{name}-{}", std::process::id()));
+            let _ = fs::remove_dir_all(&dir);
+            fs::create_dir_all(dir.join(".git/objects/pack")).unwrap();
+            fs::create_dir_all(dir.join(".git/refs/heads")).unwrap();
+            fs::write(dir.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+            Self(dir)
+        }
+
+        fn git(&self, path: &str) -> PathBuf {
+            self.0.join(".git").join(path)
+        }
+
+        fn object(&self, kind: &str, data: &[u8]) -> ObjectId {
+            let mut raw = format!("{kind} {}\0", data.len()).into_bytes();
+            raw.extend_from_slice(data);
+            let id = ObjectId(sha1(&raw));
+            let path = Repository::loose_path(&self.git("objects"), id);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, zlib(&raw)).unwrap();
+            id
+        }
+
+        fn tree(&self, entries: &[(&str, &str, ObjectId)]) -> ObjectId {
+            let mut data = Vec::new();
+            for (mode, name, id) in entries {
+                data.extend_from_slice(format!("{mode} {name}\0").as_bytes());
+                data.extend_from_slice(&id.0);
+            }
+            self.object("tree", &data)
+        }
+
+        fn commit(&self, tree: ObjectId, parent: Option<ObjectId>) -> ObjectId {
+            let mut data = format!("tree {}\n", tree.to_hex());
+            if let Some(parent) = parent {
+                data += &format!("parent {}\n", parent.to_hex());
+            }
+            data += "author A <a@example.com> 0 +0000\ncommitter A <a@example.com> 0 +0000\n\nMessage\n";
+            self.object("commit", data.as_bytes())
+        }
+
+        /// Writes a version 2 index whose entries record no modification time, so the
+        /// working tree files are always read.
+        fn index(&self, entries: &[(&str, ObjectId)]) {
+            let mut data = b"DIRC".to_vec();
+            data.extend_from_slice(&2u32.to_be_bytes());
+            data.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+            for (name, id) in entries {
+                let start = data.len();
+                for field in [0, 0, 0, 0, 0, 0, MODE_FILE, 0, 0, 0] {
+                    data.extend_from_slice(&u32::to_be_bytes(field));
+                }
+                data.extend_from_slice(&id.0);
+                data.extend_from_slice(&(name.len() as u16).to_be_bytes());
+                data.extend_from_slice(name.as_bytes());
+                data.resize(start + ((62 + name.len() + 8) & !7), 0);
+            }
+            data.extend_from_slice(&[0; 20]);
+            fs::write(self.git("index"), data).unwrap();
+        }
+    }
+
+    impl Drop for TestRepo {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    /// Compresses `data` into zlib stored blocks.
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut out = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = if data.is_empty() { vec![&[]] } else { data.chunks(0xFFFF).collect() };
+        for (index, block) in blocks.iter().enumerate() {
+            out.push(u8::from(index + 1 == blocks.len()));
+            let length = block.len() as u16;
+            out.extend_from_slice(&length.to_le_bytes());
+            out.extend_from_slice(&(!length).to_le_bytes());
+            out.extend_from_slice(block);
+        }
+        out.extend_from_slice(&adler32(data).to_be_bytes());
+        out
+    }
+
+    /// Two commits on `main`: `a.txt` changes and `new.txt` is added in the second.
+    /// The first is also tagged `v1` in `packed-refs`.
+    fn two_commits(repo: &TestRepo) -> (ObjectId, ObjectId) {
+        let (one_two, one_2, hello) = (
+            repo.object("blob", b"one\ntwo\n"),
+            repo.object("blob", b"one\n2\n"),
+            repo.object("blob", b"hello\n"),
+        );
+        let sub = repo.tree(&[("100644", "x.txt", hello)]);
+        let first = repo.commit(repo.tree(&[("100644", "a.txt", one_two), ("40000", "sub", sub)]), None);
+        let second_tree = repo.tree(&[("100644", "a.txt", one_2), ("100644", "new.txt", hello), ("40000", "sub", sub)]);
+        let second = repo.commit(second_tree, Some(first));
+        fs::write(repo.git("refs/heads/main"), format!("{}\n", second.to_hex())).unwrap();
+        fs::write(repo.git("packed-refs"), format!("# pack-refs with: peeled\n{} refs/tags/v1\n", first.to_hex())).unwrap();
+        (first, second)
+    }
+
+    fn short(content: &[u8]) -> String {
+        ObjectId::for_blob(content).to_hex()[..MIN_ABBREV].to_string()
+    }
+
+    #[test]
+    fn hashes_like_git() {
+        assert_eq!(hex::encode(sha1(b"abc")), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(ObjectId::for_blob(b"").to_hex(), "e69de29bb2d1d6434b8b29ae775ad8c2e48c5391");
+        // Padding spills into a second block
+        assert_eq!(hex::encode(sha1(&[b'a'; 56])), "c2db330f6083854c99d4b5bfb6e8f29f201be699");
+    }
+
+    #[test]
+    fn resolves_revisions() {
+        let repo = TestRepo::new("revisions");
+        let (first, second) = two_commits(&repo);
+        fs::create_dir_all(repo.0.join("sub")).unwrap();
+        let repository = Repository::discover(&repo.0.join("sub")).unwrap();
+        assert_eq!(repository.work_tree(), fs::canonicalize(&repo.0).unwrap());
+
+        for (revision, expected) in [
+            ("HEAD", second),
+            ("@", second),
+            ("main", second),
+            ("refs/heads/main", second),
+            ("main~1", first),
+            ("HEAD^", first),
+            ("v1", first),
+            ("v1^{}", first),
+        ] {
+            assert_eq!(repository.rev_parse(revision).unwrap(), expected, "{revision}");
+        }
+        let hex = first.to_hex();
+        assert_eq!(repository.rev_parse(&hex[..10]).unwrap(), first);
+        assert!(repository.rev_parse("main~2").is_err());
+        assert!(repository.rev_parse("missing").is_err());
+        assert!(repository.resolve_ref("refs/../../config").is_err());
+    }
+
+    #[test]
+    fn diffs_two_revisions() {
+        let repo = TestRepo::new("revisions-diff");
+        two_commits(&repo);
+
+        let expected = format!(
+            "diff --git a/a.txt b/a.txt\nindex {}..{} 100644\n--- a/a.txt\n+++ b/a.txt\n@@ -1,2 +1,2 @@\n one\n-two\n+2\n\
+             diff --git a/new.txt b/new.txt\nnew file mode 100644\nindex 0000000..{}\n--- /dev/null\n+++ b/new.txt\n@@ -0,0 +1,1 @@\n+hello\n",
+            short(b"one\ntwo\n"),
+            short(b"one\n2\n"),
+            short(b"hello\n"),
+        );
+        assert_eq!(git::diff(&repo.0, &["v1", "main"]).unwrap(), expected);
+        assert_eq!(git::diff(&repo.0, &["v1..main"]).unwrap(), expected);
+        assert_eq!(git::diff(&repo.0, &["v1.."]).unwrap(), expected);
+        assert!(git::diff(&repo.0, &["v1...main"]).is_err());
+    }
+
+    #[test]
+    fn diffs_the_working_tree() {
+        let repo = TestRepo::new("work-tree");
+        two_commits(&repo);
+        repo.index(&[
+            ("a.txt", ObjectId::for_blob(b"one\n2\n")),
+            ("new.txt", ObjectId::for_blob(b"hello\n")),
+            ("sub/x.txt", ObjectId::for_blob(b"hello\n")),
+        ]);
+        fs::create_dir_all(repo.0.join("sub")).unwrap();
+        fs::write(repo.0.join("a.txt"), "one\n2\nthree\n").unwrap();
+        fs::write(repo.0.join("sub/x.txt"), "hello\n").unwrap();
+        fs::write(repo.0.join("untracked.txt"), "ignored\n").unwrap();
+
+        // `new.txt` was deleted from the working tree
+        assert_eq!(git::changed_files(&repo.0, "main").unwrap(), [PathBuf::from("a.txt")]);
+        let diff = git::diff(&repo.0, &["main"]).unwrap();
+        assert!(diff.contains("@@ -1,2 +1,3 @@\n one\n 2\n+three\n"), "{diff}");
+        assert!(diff.contains("diff --git a/new.txt b/new.txt\ndeleted file mode 100644\n"), "{diff}");
+        assert!(!diff.contains("untracked") && !diff.contains("sub/x.txt"), "{diff}");
+    }
+
+    #[test]
+    fn reads_packed_deltas() {
+        let repo = TestRepo::new("pack");
+        let base = b"line one\nline two\n".to_vec();
+        let target = b"line one\nline 2\n".to_vec();
+        // Copy the first 14 bytes of the base, then insert "2\n"
+        let delta = [&[base.len() as u8, target.len() as u8, 0x90, 14, 2][..], b"2\n"].concat();
+
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x02".to_vec();
+        let base_offset = pack.len();
+        pack.push(0x30 | base.len() as u8 & 0x0F | 0x80);
+        pack.push(base.len() as u8 >> 4);
+        pack.extend(zlib(&base));
+        let delta_offset = pack.len();
+        pack.push(0x60 | delta.len() as u8);
+        pack.push((delta_offset - base_offset) as u8);
+        pack.extend(zlib(&delta));
+        pack.extend_from_slice(&[0; 20]);
+
+        let mut objects = [(ObjectId::for_blob(&base), base_offset), (ObjectId::for_blob(&target), delta_offset)];
+        objects.sort();
+        let mut index = b"\xfftOc\0\0\0\x02".to_vec();
+        for byte in 0..=255u8 {
+            let count = objects.iter().filter(|(id, _)| id.0[0] <= byte).count() as u32;
+            index.extend_from_slice(&count.to_be_bytes());
+        }
+        objects.iter().for_each(|(id, _)| index.extend_from_slice(&id.0));
+        objects.iter().for_each(|_| index.extend_from_slice(&[0; 4]));
+        objects.iter().for_each(|(_, offset)| index.extend_from_slice(&(*offset as u32).to_be_bytes()));
+        index.extend_from_slice(&[0; 40]);
+        fs::write(repo.git("objects/pack/pack-test.pack"), pack).unwrap();
+        fs::write(repo.git("objects/pack/pack-test.idx"), index).unwrap();
+
+        let repository = Repository::discover(&repo.0).unwrap();
+        for content in [base, target] {
+            let object = repository.read_object(ObjectId::for_blob(&content)).unwrap();
+            assert_eq!(object.kind, Kind::Blob);
+            assert_eq!(object.data, content);
+        }
+        let prefix = &ObjectId::for_blob(b"line one\nline 2\n").to_hex()[..6];
+        assert!(repository.rev_parse(prefix).is_ok());
+    }
+
+    #[test]
+    fn rejects_corrupt_deltas() {
+        // Copies past the end of the base object
+        assert!(apply_delta(b"abc", &[3, 4, 0x90, 4]).is_err());
+        // Declares the wrong base size
+        assert!(apply_delta(b"abc", &[2, 1, 1, b'x']).is_err());
+        assert_eq!(apply_delta(b"abc", &[3, 4, 0x90, 3, 1, b'd']).unwrap(), b"abcd");
+    }
+}
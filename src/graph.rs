@@ -0,0 +1,139 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{ProcessOptions, json::Json, process_template};
+
+/// Extension of template files; included files with it are treated as nested templates.
+pub const TEMPLATE_EXTENSION: &str = "clamp";
+
+/// The template -> include dependency graph of a set of templates.
+///
+/// Included `.clamp` files are nested templates: they appear as nodes with their own
+/// includes. All paths are canonical.
+#[derive(Debug, Default, Clone)]
+pub struct DependencyGraph {
+    /// Each template (including nested ones) and the files it includes directly.
+    pub templates: BTreeMap<PathBuf, BTreeSet<PathBuf>>,
+}
+
+impl DependencyGraph {
+    /// Builds the graph for `templates`, following nested templates.
+    pub fn build(templates: &[PathBuf], options: &ProcessOptions) -> Result<Self> {
+        let mut graph = Self::default();
+        let mut pending: Vec<PathBuf> = templates
+            .iter()
+            .map(|template| {
+                fs::canonicalize(template).with_context(|| {
+                    format!("Failed to canonicalize template '{}'", template.display())
+                })
+            })
+            .collect::<Result<_>>()?;
+
+        while let Some(template) = pending.pop() {
+            if graph.templates.contains_key(&template) {
+                continue; // Already visited (shared or cyclic nesting)
+            }
+            let result = process_template(&template, options).with_context(|| {
+                format!("Failed to process template '{}'", template.display())
+            })?;
+            let includes: BTreeSet<PathBuf> = result.current_hashes.into_keys().collect();
+            pending.extend(includes.iter().filter(|path| is_template(path)).cloned());
+            graph.templates.insert(template, includes);
+        }
+        Ok(graph)
+    }
+
+    /// Maps every included file to the templates that depend on it, directly or
+    /// through nested templates.
+    pub fn dependents(&self) -> BTreeMap<PathBuf, BTreeSet<PathBuf>> {
+        let mut dependents: BTreeMap<PathBuf, BTreeSet<PathBuf>> = BTreeMap::new();
+        for template in self.templates.keys() {
+            for file in self.transitive_includes(template) {
+                dependents.entry(file).or_default().insert(template.clone());
+            }
+        }
+        dependents
+    }
+
+    /// All files `template` depends on, following nested templates.
+    pub fn transitive_includes(&self, template: &Path) -> BTreeSet<PathBuf> {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![template.to_path_buf()];
+        while let Some(current) = pending.pop() {
+            for file in self.templates.get(&current).into_iter().flatten() {
+                if seen.insert(file.clone()) && self.templates.contains_key(file) {
+                    pending.push(file.clone());
+                }
+            }
+        }
+        seen
+    }
+
+    /// Renders the graph in Graphviz DOT format. Templates are drawn as boxes;
+    /// paths are shown relative to `display_root` where possible.
+    pub fn to_dot(&self, display_root: &Path) -> String {
+        let label = |path: &Path| quote_dot(&display_path(path, display_root));
+
+        let mut dot = String::from("digraph clamp {\n    rankdir=LR;\n");
+        for template in self.templates.keys() {
+            writeln!(dot, "    {} [shape=box];", label(template))
+                .expect("Writing to String buffer failed unexpectedly");
+        }
+        for (template, includes) in &self.templates {
+            for file in includes {
+                writeln!(dot, "    {} -> {};", label(template), label(file))
+                    .expect("Writing to String buffer failed unexpectedly");
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Renders the graph as JSON: each template's direct includes, and for each file
+    /// the templates it affects.
+    pub fn to_json(&self, display_root: &Path) -> Json {
+        let paths = |set: &BTreeSet<PathBuf>| -> Json {
+            Json::Array(
+                set.iter()
+                    .map(|path| Json::from(display_path(path, display_root)))
+                    .collect(),
+            )
+        };
+        let templates = self
+            .templates
+            .iter()
+            .map(|(template, includes)| (display_path(template, display_root), paths(includes)));
+        let dependents = self
+            .dependents()
+            .into_iter()
+            .map(|(file, templates)| (display_path(&file, display_root), paths(&templates)))
+            .collect::<Vec<_>>();
+
+        Json::object([
+            ("templates", Json::object(templates)),
+            ("dependents", Json::object(dependents)),
+        ])
+    }
+}
+
+/// Whether `path` is a template file (by extension).
+pub fn is_template(path: &Path) -> bool {
+    path.extension().is_some_and(|ext| ext == TEMPLATE_EXTENSION)
+}
+
+/// Shows `path` relative to `root` if it lies below it, otherwise as-is.
+pub fn display_path(path: &Path, root: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .to_string_lossy()
+        .into_owned()
+}
+
+fn quote_dot(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
@@ -0,0 +1,201 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use crate::git;
+
+/// First line of the block `install` adds to a hook script.
+const BLOCK_START: &str = "# >>> clamp check >>>";
+/// Last line of the block `install` adds to a hook script.
+const BLOCK_END: &str = "# <<< clamp check <<<";
+
+/// The git hooks clamp can install itself into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HookKind {
+    #[default]
+    PreCommit,
+    PrePush,
+}
+
+impl HookKind {
+    /// The hook's file name in the hooks directory.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::PreCommit => "pre-commit",
+            Self::PrePush => "pre-push",
+        }
+    }
+}
+
+impl FromStr for HookKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "pre-commit" => Ok(Self::PreCommit),
+            "pre-push" => Ok(Self::PrePush),
+            other => bail!("Unknown hook '{other}' (expected pre-commit or pre-push)"),
+        }
+    }
+}
+
+/// Adds a `clamp check` step to the `kind` hook of the repository containing `dir`,
+/// returning the hook's path. An existing hook script is kept, with the step inserted
+/// before its first unindented `exit` or `exec` line, if any; installing twice is a no-op.
+pub fn install(dir: &Path, kind: HookKind) -> Result<PathBuf> {
+    let path = hook_path(dir, kind)?;
+    let existing = read_hook(&path)?;
+    if existing.as_deref().is_some_and(|script| script.contains(BLOCK_START)) {
+        return Ok(path);
+    }
+    let script = add_block(existing);
+
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create hooks directory '{}'", parent.display()))?;
+    }
+    fs::write(&path, script)
+        .with_context(|| format!("Failed to write hook '{}'", path.display()))?;
+    make_executable(&path)?;
+    Ok(path)
+}
+
+/// Removes the `clamp check` step from the `kind` hook, deleting the hook if nothing
+/// else is left in it. Returns whether a step was removed.
+pub fn uninstall(dir: &Path, kind: HookKind) -> Result<bool> {
+    let path = hook_path(dir, kind)?;
+    let Some(script) = read_hook(&path)? else {
+        return Ok(false);
+    };
+    let Some(remaining) = remove_block(&script) else {
+        return Ok(false);
+    };
+
+    let is_empty = remaining
+        .lines()
+        .all(|line| line.trim().is_empty() || line.starts_with("#!"));
+    if is_empty {
+        fs::remove_file(&path)
+            .with_context(|| format!("Failed to remove hook '{}'", path.display()))?;
+    } else {
+        fs::write(&path, remaining)
+            .with_context(|| format!("Failed to write hook '{}'", path.display()))?;
+    }
+    Ok(true)
+}
+
+/// Returns `script` with the `clamp check` block added, or a new shell script with
+/// only the block. The block goes before the first top-level `exit` or `exec`, which
+/// would end the script before reaching it, and at the end otherwise.
+fn add_block(script: Option<String>) -> String {
+    let block = format!(
+        "{BLOCK_START}\n# Added by `clamp hook install`; remove with `clamp hook uninstall`.\nclamp check || exit 1\n{BLOCK_END}\n"
+    );
+    let Some(mut script) = script else {
+        return format!("#!/bin/sh\n{block}");
+    };
+    if !script.ends_with('\n') {
+        script.push('\n');
+    }
+
+    let mut offset = 0;
+    for line in script.split_inclusive('\n') {
+        // Indented lines are taken to be inside a function or conditional
+        let ends_script = ["exit", "exec"].iter().any(|command| {
+            line.strip_prefix(command)
+                .is_some_and(|rest| rest.trim().is_empty() || rest.starts_with([' ', '\t', ';']))
+        });
+        if ends_script {
+            return format!("{}{block}\n{}", &script[..offset], &script[offset..]);
+        }
+        offset += line.len();
+    }
+    script + "\n" + &block
+}
+
+/// Returns `script` without the `clamp check` block, or `None` if it has none.
+fn remove_block(script: &str) -> Option<String> {
+    let (start, end) = (script.find(BLOCK_START)?, script.find(BLOCK_END)?);
+    let end = end + BLOCK_END.len();
+    let end = end + usize::from(script[end..].starts_with('\n'));
+    let (before, after) = (&script[..start], &script[end..]);
+
+    let remaining = match after.strip_prefix('\n') {
+        // Inserted before the rest of the script, followed by a blank line
+        Some(after) if !after.is_empty() => format!("{before}{after}"),
+        // Appended, after a blank line
+        _ => format!("{}\n{}", before.trim_end_matches('\n'), after.trim_start_matches('\n')),
+    };
+    Some(remaining.trim_end_matches('\n').to_string() + "\n")
+}
+
+fn hook_path(dir: &Path, kind: HookKind) -> Result<PathBuf> {
+    let hooks_dir = git::hooks_dir(dir).context("Installing hooks requires a git repository")?;
+    Ok(hooks_dir.join(kind.name()))
+}
+
+/// Reads the hook script at `path`, or `None` if there is none.
+fn read_hook(path: &Path) -> Result<Option<String>> {
+    match fs::read_to_string(path) {
+        Ok(script) => Ok(Some(script)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(e).with_context(|| format!("Failed to read hook '{}'", path.display())),
+    }
+}
+
+#[cfg(unix)]
+fn make_executable(path: &Path) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mut permissions = fs::metadata(path)
+        .with_context(|| format!("Failed to stat hook '{}'", path.display()))?
+        .permissions();
+    permissions.set_mode(permissions.mode() | 0o111);
+    fs::set_permissions(path, permissions)
+        .with_context(|| format!("Failed to make hook '{}' executable", path.display()))
+}
+
+#[cfg(not(unix))]
+fn make_executable(_path: &Path) -> Result<()> {
+    Ok(()) // Git for Windows runs hooks through its own shell
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_before_an_existing_exit() {
+        let existing = "#!/bin/sh\nif [ -n \"$SKIP\" ]; then\n  exit 0\nfi\nnpm test\nexit 0\n";
+        let script = add_block(Some(existing.to_string()));
+        let check = script.find("clamp check || exit 1").unwrap();
+        assert!(check < script.rfind("exit 0").unwrap(), "{script}");
+        assert!(check > script.find("npm test").unwrap(), "{script}");
+        assert_eq!(remove_block(&script).unwrap(), existing);
+    }
+
+    #[test]
+    fn runs_before_an_exec() {
+        let existing = "#!/bin/sh\nexec lint-staged\n";
+        let script = add_block(Some(existing.to_string()));
+        assert!(script.find(BLOCK_START).unwrap() < script.find("exec lint-staged").unwrap(), "{script}");
+        assert_eq!(remove_block(&script).unwrap(), existing);
+    }
+
+    #[test]
+    fn appends_to_scripts_that_run_to_the_end() {
+        let existing = "#!/bin/sh\nnpm test\nexiting=1";
+        let script = add_block(Some(existing.to_string()));
+        assert!(script.starts_with("#!/bin/sh\nnpm test\nexiting=1\n\n# >>> clamp check >>>\n"), "{script}");
+        assert!(script.ends_with(&format!("{BLOCK_END}\n")), "{script}");
+        assert_eq!(remove_block(&script).unwrap(), format!("{existing}\n"));
+
+        let script = add_block(None);
+        assert!(script.starts_with("#!/bin/sh\n# >>> clamp check >>>\n"), "{script}");
+        assert_eq!(remove_block(&script).unwrap(), "#!/bin/sh\n");
+        assert_eq!(remove_block("#!/bin/sh\nnpm test\n"), None);
+    }
+}
@@ -0,0 +1,317 @@
+use anyhow::{Context, Result};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::{Arc, Condvar, Mutex},
+    thread,
+    time::Duration,
+};
+
+use crate::{json::Json, log, workspace::Workspace};
+
+/// Longest request line or header accepted, in bytes.
+const MAX_LINE_LENGTH: usize = 8 * 1024;
+
+/// Most headers accepted in a request.
+const MAX_HEADERS: usize = 100;
+
+/// Most connections handled at once; further ones wait to be accepted.
+const MAX_CONNECTIONS: usize = 32;
+
+/// How long a read from or write to a client may block before its connection is dropped.
+const IO_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A response: status code, content type and body.
+pub(crate) struct Response {
+    pub(crate) status: u16,
+    pub(crate) content_type: &'static str,
+    pub(crate) body: String,
+}
+
+impl Response {
+    pub(crate) fn json(status: u16, body: &Json) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: body.to_pretty_string() + "\n",
+        }
+    }
+
+    pub(crate) fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &Json::object([("error", Json::from(message))]))
+    }
+}
+
+/// Serves `workspace` over HTTP on `addr` (e.g. `127.0.0.1:8080`) until the process
+/// is stopped. Each connection is handled on its own thread, at most 32 at once, and
+/// is dropped if the client stalls for 30 seconds.
+///
+/// Endpoints: `GET /templates` (JSON list), `GET /render/<template>` (the rendered
+/// template as text) and `GET /check/<template>` (JSON change report).
+pub fn serve(workspace: Workspace, addr: &str) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("Failed to listen on '{addr}'"))?;
+    let local_addr = listener.local_addr().context("Failed to determine listen address")?;
+    log::info!(
+        "Serving templates below '{}' on http://{local_addr}/",
+        workspace.root().display()
+    );
+
+    let workspace = Arc::new(workspace);
+    let slots = Arc::new(Slots::default());
+    loop {
+        // Wait for a free handler before accepting, leaving clients in the backlog
+        let slot = slots.acquire();
+        let Ok((stream, _)) = listener.accept() else {
+            continue; // The client went away before the connection was accepted
+        };
+        if let Err(e) = stream
+            .set_read_timeout(Some(IO_TIMEOUT))
+            .and_then(|()| stream.set_write_timeout(Some(IO_TIMEOUT)))
+        {
+            log::warning!("Failed to set timeouts of HTTP connection: {e}");
+            continue;
+        }
+        let workspace = workspace.clone();
+        thread::spawn(move || {
+            let _slot = slot;
+            if let Err(e) = handle_connection(&workspace, stream) {
+                log::warning!("HTTP connection failed: {e:#}");
+            }
+        });
+    }
+}
+
+/// Counts the running connection handlers, up to [`MAX_CONNECTIONS`].
+#[derive(Default)]
+struct Slots {
+    active: Mutex<usize>,
+    freed: Condvar,
+}
+
+impl Slots {
+    /// Takes a slot, waiting until one is free. It is given back when the guard is dropped.
+    fn acquire(self: &Arc<Self>) -> Slot {
+        let mut active = self.active.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        while *active >= MAX_CONNECTIONS {
+            active = self.freed.wait(active).unwrap_or_else(|poisoned| poisoned.into_inner());
+        }
+        *active += 1;
+        Slot(self.clone())
+    }
+}
+
+struct Slot(Arc<Slots>);
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        *self.0.active.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) -= 1;
+        self.0.freed.notify_one();
+    }
+}
+
+fn handle_connection(workspace: &Workspace, stream: TcpStream) -> Result<()> {
+    let response = match read_get_target(&stream)? {
+        Ok(target) => route(workspace, &target),
+        Err(response) => response,
+    };
+    write_response(&stream, &response)
+}
+
+/// Reads a request, returning the target of a `GET` request or the error response
+/// for any other.
+pub(crate) fn read_get_target(stream: &TcpStream) -> Result<Result<String, Response>> {
+    let mut reader = BufReader::new(stream);
+    let request_line = read_line(&mut reader)?;
+    // Headers are not needed, but must be consumed before responding
+    let mut headers = 0;
+    while !read_line(&mut reader)?.is_empty() {
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Ok(Err(Response::error(431, "Too many request headers")));
+        }
+    }
+
+    Ok(match request_line.split_whitespace().collect::<Vec<_>>()[..] {
+        ["GET", target, _version] => Ok(target.to_string()),
+        [_, _, _] => Err(Response::error(405, "Only GET requests are supported")),
+        _ => Err(Response::error(400, "Malformed request line")),
+    })
+}
+
+/// Reads one CRLF-terminated line, without the terminator.
+fn read_line(reader: &mut BufReader<&TcpStream>) -> Result<String> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE_LENGTH as u64)
+        .read_until(b'\n', &mut line)
+        .context("Failed to read HTTP request")?;
+    let line = String::from_utf8(line).context("HTTP request is not valid UTF-8")?;
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn route(workspace: &Workspace, target: &str) -> Response {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let Some(path) = percent_decode(path) else {
+        return Response::error(400, "Invalid percent-encoding in request path");
+    };
+
+    if path == "/templates" {
+        return match workspace.list_templates() {
+            Ok(templates) => Response::json(
+                200,
+                &Json::object([("templates", Json::from(templates))]),
+            ),
+            Err(e) => Response::error(500, &format!("{e:#}")),
+        };
+    }
+
+    let (handler, name) = if let Some(name) = path.strip_prefix("/render/") {
+        ("render", name)
+    } else if let Some(name) = path.strip_prefix("/check/") {
+        ("check", name)
+    } else {
+        return Response::error(404, &format!("No such endpoint '{path}'"));
+    };
+    if let Err(e) = workspace.resolve_template(name) {
+        return Response::error(404, &format!("{e:#}"));
+    }
+
+    let outcome = match handler {
+        "render" => workspace.render(name).map(|result| Response {
+            status: 200,
+            content_type: "text/markdown; charset=utf-8",
+            body: result.output_content,
+        }),
+        _ => workspace
+            .check(name)
+            .map(|report| Response::json(200, &report)),
+    };
+    outcome.unwrap_or_else(|e| Response::error(500, &format!("{e:#}")))
+}
+
+pub(crate) fn write_response(mut stream: &TcpStream, response: &Response) -> Result<()> {
+    let reason = match response.status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {} {reason}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.content_type,
+        response.body.len()
+    )
+    .and_then(|()| stream.write_all(response.body.as_bytes()))
+    .and_then(|()| stream.flush())
+    .context("Failed to write HTTP response")
+}
+
+/// Decodes `%XX` escapes in a request path. Returns `None` for malformed escapes
+/// or if the result is not UTF-8.
+pub(crate) fn percent_decode(path: &str) -> Option<String> {
+    let bytes = path.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' {
+            let hex = std::str::from_utf8(bytes.get(i + 1..i + 3)?).ok()?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 3;
+        } else {
+            decoded.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Shutdown;
+
+    use super::*;
+    use crate::workspace::tests::workspace;
+
+    /// Sends `request` to a connection handled by [`handle_connection`] and returns
+    /// the raw response.
+    fn exchange(request: &str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (server, _) = listener.accept().unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        client.shutdown(Shutdown::Write).unwrap();
+        handle_connection(&workspace(), server).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    fn body_json(response: &Response) -> Json {
+        Json::parse(&response.body).unwrap()
+    }
+
+    #[test]
+    fn routes_endpoints() {
+        let workspace = workspace();
+        let response = route(&workspace, "/templates?fresh=1");
+        assert_eq!((response.status, response.content_type), (200, "application/json"));
+        let templates = body_json(&response).get("templates").cloned();
+        assert_eq!(templates, Some(Json::from(vec!["docs/notes.clamp", "leak.clamp", "review.clamp"])));
+
+        let response = route(&workspace, "/render/review.clamp");
+        assert_eq!((response.status, response.content_type), (200, "text/markdown; charset=utf-8"));
+        assert!(response.body.contains("pub fn g() {}"), "{}", response.body);
+        let response = route(&workspace, "/render/docs%2Fnotes.clamp");
+        assert_eq!(response.status, 200);
+
+        let response = route(&workspace, "/check/review.clamp");
+        assert_eq!(response.status, 200);
+        assert_eq!(body_json(&response).get("up_to_date"), Some(&Json::Bool(false)));
+    }
+
+    #[test]
+    fn reports_errors_with_status_codes() {
+        let workspace = workspace();
+        for (target, status) in [
+            ("/", 404),
+            ("/render/missing.clamp", 404),
+            ("/check/../../secret.txt", 404),
+            ("/render/%ZZ", 400),
+            ("/render/%FF", 400),
+            // Exists, but its include is outside the root
+            ("/render/leak.clamp", 500),
+        ] {
+            let response = route(&workspace, target);
+            assert_eq!(response.status, status, "{target}: {}", response.body);
+            assert!(body_json(&response).get("error").is_some(), "{target}");
+        }
+    }
+
+    #[test]
+    fn answers_over_a_connection() {
+        let response = exchange("GET /templates HTTP/1.1\r\nHost: localhost\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"), "{response}");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.contains(&format!("Content-Length: {}", body.len())), "{response}");
+
+        let response = exchange("POST /templates HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 405 Method Not Allowed\r\n"), "{response}");
+        let response = exchange("GET\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"), "{response}");
+        let headers = "X-Header: 1\r\n".repeat(MAX_HEADERS + 1);
+        let response = exchange(&format!("GET /templates HTTP/1.1\r\n{headers}\r\n"));
+        assert!(response.starts_with("HTTP/1.1 431 Request Header Fields Too Large\r\n"), "{response}");
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("/render/a%20b%2Fc.clamp").as_deref(), Some("/render/a b/c.clamp"));
+        assert_eq!(percent_decode("/render/%2"), None);
+        assert_eq!(percent_decode("/%C3%A9").as_deref(), Some("/é"));
+    }
+}
@@ -0,0 +1,388 @@
+use anyhow::{Context, Result, bail};
+use regex::Regex;
+use std::path::{Path, PathBuf};
+
+use crate::vfs::{FileKind, FileSystem};
+
+/// Ignore files honored while walking directories.
+pub const IGNORE_FILE_NAMES: &[&str] = &[".gitignore", ".clampignore"];
+
+/// A single pattern line from an ignore file.
+#[derive(Debug, Clone)]
+struct Rule {
+    /// Directory the pattern is relative to (the ignore file's directory).
+    base: PathBuf,
+    regex: Regex,
+    negated: bool,
+    dir_only: bool,
+}
+
+/// A set of gitignore-style rules.
+///
+/// Supports the usual syntax: `#` comments, `!` negation, a trailing `/` for
+/// directory-only patterns, patterns containing `/` anchored to the ignore file's
+/// directory, and the `*`, `?`, `[...]` and `**` wildcards. Later rules take
+/// precedence over earlier ones.
+#[derive(Debug, Clone, Default)]
+pub struct IgnoreRules {
+    rules: Vec<Rule>,
+}
+
+impl IgnoreRules {
+    /// Creates an empty rule set that ignores nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the patterns of the ignore file at `path`, relative to its directory.
+    /// A missing file adds nothing.
+    pub fn add_file(&mut self, fs: &dyn FileSystem, path: &Path) -> Result<()> {
+        let content = match fs.read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => {
+                return Err(e)
+                    .with_context(|| format!("Failed to read ignore file '{}'", path.display()));
+            }
+        };
+        let base = path.parent().unwrap_or(Path::new(""));
+        self.add_patterns(base, &content);
+        Ok(())
+    }
+
+    /// Adds gitignore-style `patterns` (one per line) relative to `base`.
+    pub fn add_patterns(&mut self, base: &Path, patterns: &str) {
+        self.rules
+            .extend(patterns.lines().filter_map(|line| parse_rule(base, line)));
+    }
+
+    /// Returns whether `path` is ignored. Only the rules whose base directory
+    /// contains `path` apply; the last matching rule decides.
+    ///
+    /// This does not check `path`'s ancestors; walkers skip ignored directories.
+    pub fn is_ignored(&self, path: &Path, is_dir: bool) -> bool {
+        let mut ignored = false;
+        for rule in &self.rules {
+            if rule.dir_only && !is_dir {
+                continue;
+            }
+            let Ok(relative) = path.strip_prefix(&rule.base) else {
+                continue;
+            };
+            if rule.regex.is_match(&slash_path(relative)) {
+                ignored = !rule.negated;
+            }
+        }
+        ignored
+    }
+
+    /// Returns whether `path` or any of its ancestors below `root` is ignored.
+    pub fn is_ignored_within(&self, fs: &dyn FileSystem, root: &Path, path: &Path) -> bool {
+        let Ok(relative) = path.strip_prefix(root) else {
+            return false;
+        };
+        let mut current = root.to_path_buf();
+        let mut components = relative.components().peekable();
+        while let Some(component) = components.next() {
+            current.push(component);
+            let is_dir = components.peek().is_some() || fs.is_dir(&current);
+            if self.is_ignored(&current, is_dir) {
+                return true;
+            }
+        }
+        false
+    }
+}
+
+/// Loads the ignore files of every ancestor of `dir` (excluding `dir` itself), up to
+/// and including the enclosing repository root, so a walk starting in a subdirectory
+/// still honors the rules declared above it. `dir` should be canonical.
+pub fn ancestor_rules(fs: &dyn FileSystem, dir: &Path) -> Result<IgnoreRules> {
+    let mut rules = IgnoreRules::new();
+    let Some(repo_root) = dir.ancestors().find(|ancestor| fs.exists(&ancestor.join(".git"))) else {
+        return Ok(rules); // Outside a repository there is no natural boundary
+    };
+
+    let ancestors: Vec<&Path> = dir
+        .ancestors()
+        .skip(1)
+        .take_while(|ancestor| ancestor.starts_with(repo_root))
+        .collect();
+    // Outermost first, so rules closer to `dir` take precedence
+    for ancestor in ancestors.into_iter().rev() {
+        for name in IGNORE_FILE_NAMES {
+            rules.add_file(fs, &ancestor.join(name))?;
+        }
+    }
+    Ok(rules)
+}
+
+/// Joins a relative path's components with `/`, regardless of platform.
+fn slash_path(path: &Path) -> String {
+    path.components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Parses one ignore file line, returning `None` for blank lines and comments.
+fn parse_rule(base: &Path, line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (negated, pattern) = match line.strip_prefix('!') {
+        Some(rest) => (true, rest),
+        None => (false, line.strip_prefix('\\').unwrap_or(line)),
+    };
+    let (dir_only, pattern) = match pattern.strip_suffix('/') {
+        Some(rest) => (true, rest),
+        None => (false, pattern),
+    };
+
+    // A slash anywhere but the end anchors the pattern to the ignore file's directory
+    let anchored = pattern.contains('/');
+    let pattern = pattern.strip_prefix('/').unwrap_or(pattern);
+    if pattern.is_empty() {
+        return None;
+    }
+
+    let body = glob_to_regex(pattern);
+    let regex = if anchored {
+        format!("^{body}$")
+    } else {
+        format!("^(?:.*/)?{body}$")
+    };
+
+    Some(Rule {
+        base: base.to_path_buf(),
+        regex: Regex::new(&regex).ok()?,
+        negated,
+        dir_only,
+    })
+}
+
+/// Translates a glob (`*`, `?`, `[...]`, `**`) into an unanchored regex body
+/// matching `/`-separated relative paths.
+pub fn glob_to_regex(glob: &str) -> String {
+    let chars: Vec<char> = glob.chars().collect();
+    let mut regex = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        match chars[i] {
+            '*' if chars.get(i + 1) == Some(&'*') => {
+                let at_segment_start = i == 0 || chars[i - 1] == '/';
+                match chars.get(i + 2) {
+                    // `**/` matches zero or more directories
+                    Some('/') if at_segment_start => {
+                        regex.push_str("(?:.*/)?");
+                        i += 3;
+                    }
+                    // `**` at the end matches everything below
+                    None => {
+                        regex.push_str(".*");
+                        i += 2;
+                    }
+                    _ => {
+                        regex.push_str("[^/]*");
+                        i += 2;
+                    }
+                }
+                continue;
+            }
+            '*' => regex.push_str("[^/]*"),
+            '?' => regex.push_str("[^/]"),
+            '[' => {
+                if let Some(end) = chars[i + 1..].iter().position(|&c| c == ']') {
+                    let class: String = chars[i + 1..i + 1 + end].iter().collect();
+                    let class = class.strip_prefix('!').map_or(class.clone(), |rest| {
+                        format!("^{rest}")
+                    });
+                    regex.push('[');
+                    regex.push_str(&class.replace('\\', "\\\\"));
+                    regex.push(']');
+                    i += end + 2;
+                    continue;
+                }
+                regex.push_str("\\[");
+            }
+            '\\' if i + 1 < chars.len() => {
+                regex.push_str(&regex::escape(&chars[i + 1].to_string()));
+                i += 2;
+                continue;
+            }
+            c => regex.push_str(&regex::escape(&c.to_string())),
+        }
+        i += 1;
+    }
+    regex
+}
+
+/// Recursively lists the files below `root`, as paths relative to `root`, sorted.
+///
+/// The `.git` directory is always skipped. Unless `use_ignore_files` is false, the
+/// [`IGNORE_FILE_NAMES`] found while walking (plus `extra_rules`) exclude matching
+/// files and directories. `max_depth` limits how many directory levels are entered.
+pub fn walk_files(
+    fs: &dyn FileSystem,
+    root: &Path,
+    extra_rules: &IgnoreRules,
+    use_ignore_files: bool,
+    max_depth: Option<usize>,
+) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    let mut rules = if use_ignore_files {
+        extra_rules.clone()
+    } else {
+        IgnoreRules::new()
+    };
+    walk_dir(fs, root, &mut rules, use_ignore_files, max_depth, 0, &mut files)?;
+    let mut files: Vec<PathBuf> = files
+        .iter()
+        .filter_map(|path| path.strip_prefix(root).ok())
+        .map(Path::to_path_buf)
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+/// Collects the files below `dir` into `files`, as full paths.
+fn walk_dir(
+    fs: &dyn FileSystem,
+    dir: &Path,
+    rules: &mut IgnoreRules,
+    use_ignore_files: bool,
+    max_depth: Option<usize>,
+    depth: usize,
+    files: &mut Vec<PathBuf>,
+) -> Result<()> {
+    // Rules from this directory's ignore files only apply within it
+    let saved_rule_count = rules.rules.len();
+    if use_ignore_files {
+        for name in IGNORE_FILE_NAMES {
+            rules.add_file(fs, &dir.join(name))?;
+        }
+    }
+
+    let mut entries = fs
+        .read_dir(dir)
+        .with_context(|| format!("Failed to read directory '{}'", dir.display()))?;
+    entries.sort_by(|a, b| a.path.file_name().cmp(&b.path.file_name()));
+
+    for entry in entries {
+        let path = entry.path;
+        // Resolve symlinks so linked files and directories are treated like their targets
+        let is_dir = match entry.kind {
+            FileKind::Symlink => fs.is_dir(&path),
+            kind => kind == FileKind::Dir,
+        };
+
+        if path.file_name().is_some_and(|name| name == ".git") || rules.is_ignored(&path, is_dir) {
+            continue;
+        }
+
+        if is_dir {
+            if max_depth.is_none_or(|max| depth < max) {
+                walk_dir(fs, &path, rules, use_ignore_files, max_depth, depth + 1, files)?;
+            }
+        } else {
+            files.push(path);
+        }
+    }
+
+    rules.rules.truncate(saved_rule_count);
+    Ok(())
+}
+
+/// Whether `path` contains glob wildcards (`*`, `?` or `[`).
+pub fn is_glob_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+/// Lists every file below the directory `dir` (relative to `base_dir`), recursively,
+/// as sorted `/`-separated paths relative to `base_dir`. Unless `use_ignore_files`
+/// is false, ignored files are skipped as in [`expand_glob`].
+pub fn list_directory(
+    fs: &dyn FileSystem,
+    base_dir: &Path,
+    dir: &str,
+    use_ignore_files: bool,
+) -> Result<Vec<String>> {
+    let dir = dir.trim_end_matches('/');
+    let walk_root = fs
+        .canonicalize(&base_dir.join(dir))
+        .with_context(|| format!("Failed to resolve directory '{}'", base_dir.join(dir).display()))?;
+    if !fs.is_dir(&walk_root) {
+        bail!("'{}' is not a directory", base_dir.join(dir).display());
+    }
+    let extra_rules = if use_ignore_files {
+        ancestor_rules(fs, &walk_root)?
+    } else {
+        IgnoreRules::new()
+    };
+
+    let files = walk_files(fs, &walk_root, &extra_rules, use_ignore_files, None)?
+        .into_iter()
+        .map(|relative| slash_path(&relative))
+        .map(|relative| match dir {
+            "" | "." => relative,
+            _ => format!("{dir}/{relative}"),
+        })
+        .collect();
+    Ok(files)
+}
+
+/// Expands a `/`-separated glob `pattern` relative to `base_dir` into the matching
+/// files, as sorted `/`-separated paths relative to `base_dir`.
+///
+/// Only the directory named by the pattern's leading wildcard-free components is
+/// walked. Unless `use_ignore_files` is false, files excluded by `.gitignore` or
+/// `.clampignore` (in the walked tree or its ancestors up to the repository root)
+/// are skipped.
+pub fn expand_glob(
+    fs: &dyn FileSystem,
+    base_dir: &Path,
+    pattern: &str,
+    use_ignore_files: bool,
+) -> Result<Vec<String>> {
+    let components: Vec<&str> = pattern.split('/').filter(|c| !c.is_empty()).collect();
+    let fixed_count = components
+        .iter()
+        .take_while(|component| !is_glob_pattern(component))
+        .count();
+    let prefix = components[..fixed_count].join("/");
+    let rest = components[fixed_count..].join("/");
+
+    let walk_root = fs.canonicalize(&base_dir.join(&prefix)).with_context(|| {
+        format!(
+            "Failed to resolve directory '{}' of pattern '{pattern}'",
+            base_dir.join(&prefix).display()
+        )
+    })?;
+    let extra_rules = if use_ignore_files {
+        ancestor_rules(fs, &walk_root)?
+    } else {
+        IgnoreRules::new()
+    };
+
+    // Without `**`, the pattern's depth bounds how far the walk needs to go
+    let max_depth = (!rest.contains("**")).then(|| components.len() - fixed_count - 1);
+    let regex = Regex::new(&format!("^{}$", glob_to_regex(&rest)))
+        .with_context(|| format!("Invalid glob pattern '{pattern}'"))?;
+
+    let matches = walk_files(fs, &walk_root, &extra_rules, use_ignore_files, max_depth)?
+        .into_iter()
+        .map(|relative| slash_path(&relative))
+        .filter(|relative| regex.is_match(relative))
+        .map(|relative| {
+            if prefix.is_empty() {
+                relative
+            } else {
+                format!("{prefix}/{relative}")
+            }
+        })
+        .collect();
+    Ok(matches)
+}
@@ -0,0 +1,145 @@
+use std::{collections::BTreeMap, path::Path};
+
+/// Built-in language hints for well-known file names.
+const FILE_NAMES: &[(&str, &str)] = &[
+    ("Dockerfile", "dockerfile"),
+    ("Containerfile", "dockerfile"),
+    ("Makefile", "makefile"),
+    ("GNUmakefile", "makefile"),
+    ("CMakeLists.txt", "cmake"),
+    ("Cargo.lock", "toml"),
+    ("Gemfile", "ruby"),
+    ("Rakefile", "ruby"),
+    ("Jenkinsfile", "groovy"),
+    ("Vagrantfile", "ruby"),
+    (".bashrc", "bash"),
+    (".bash_profile", "bash"),
+    (".zshrc", "zsh"),
+    (".gitignore", "gitignore"),
+    (".editorconfig", "ini"),
+];
+
+/// Built-in language hints for file extensions (without the leading dot).
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("rs", "rust"),
+    ("py", "python"),
+    ("pyi", "python"),
+    ("js", "javascript"),
+    ("mjs", "javascript"),
+    ("cjs", "javascript"),
+    ("ts", "typescript"),
+    ("mts", "typescript"),
+    ("c", "c"),
+    ("h", "c"),
+    ("cc", "cpp"),
+    ("cpp", "cpp"),
+    ("cxx", "cpp"),
+    ("hh", "cpp"),
+    ("hpp", "cpp"),
+    ("hxx", "cpp"),
+    ("cs", "csharp"),
+    ("m", "objectivec"),
+    ("mm", "objectivec"),
+    ("kt", "kotlin"),
+    ("kts", "kotlin"),
+    ("rb", "ruby"),
+    ("pl", "perl"),
+    ("pm", "perl"),
+    ("sh", "bash"),
+    ("bash", "bash"),
+    ("ps1", "powershell"),
+    ("md", "markdown"),
+    ("markdown", "markdown"),
+    ("yml", "yaml"),
+    ("yaml", "yaml"),
+    ("htm", "html"),
+    ("hs", "haskell"),
+    ("ex", "elixir"),
+    ("exs", "elixir"),
+    ("erl", "erlang"),
+    ("ml", "ocaml"),
+    ("mli", "ocaml"),
+    ("tf", "hcl"),
+    ("j2", "jinja"),
+    ("jinja", "jinja"),
+    ("txt", "text"),
+    ("clamp", "text"),
+];
+
+/// Built-in language hints for shebang interpreters (`#!/usr/bin/env python3`).
+const INTERPRETERS: &[(&str, &str)] = &[
+    ("sh", "bash"),
+    ("bash", "bash"),
+    ("zsh", "zsh"),
+    ("fish", "fish"),
+    ("python", "python"),
+    ("python3", "python"),
+    ("node", "javascript"),
+    ("deno", "typescript"),
+    ("ruby", "ruby"),
+    ("perl", "perl"),
+    ("php", "php"),
+    ("lua", "lua"),
+];
+
+/// Determines the language hint for an included file.
+///
+/// Lookup order: the exact file name, then each dotted suffix from longest to shortest
+/// (`deploy.yml.j2` tries `yml.j2`, then `j2`), first in `overrides` (the `[languages]`
+/// table of `clamp.toml`) and then in the built-in tables. Extensionless files fall back
+/// to their shebang interpreter. Unknown extensions are used as-is, and an empty string
+/// is returned if nothing applies.
+pub fn detect_language(path: &Path, content: &str, overrides: &BTreeMap<String, String>) -> String {
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return String::new();
+    };
+
+    let lookup = |key: &str, built_in: &[(&str, &str)]| -> Option<String> {
+        overrides
+            .get(key)
+            .cloned()
+            .or_else(|| find(built_in, key).map(str::to_string))
+    };
+
+    if let Some(lang) = lookup(file_name, FILE_NAMES) {
+        return lang;
+    }
+
+    // Dotted suffixes, longest first; a leading dot (hidden file) does not start an extension
+    let stem_start = usize::from(file_name.starts_with('.'));
+    let suffixes: Vec<&str> = file_name[stem_start..]
+        .match_indices('.')
+        .map(|(index, _)| &file_name[stem_start + index + 1..])
+        .filter(|suffix| !suffix.is_empty())
+        .collect();
+
+    for suffix in &suffixes {
+        if let Some(lang) = lookup(suffix, EXTENSIONS) {
+            return lang;
+        }
+    }
+
+    match suffixes.last() {
+        Some(extension) => extension.to_string(),
+        None => shebang_language(content).unwrap_or_default(),
+    }
+}
+
+/// Derives a language hint from a `#!` line, e.g. `#!/usr/bin/env python3` -> `python`.
+fn shebang_language(content: &str) -> Option<String> {
+    let shebang = content.lines().next()?.strip_prefix("#!")?;
+    let mut words = shebang.split_whitespace();
+    let mut interpreter = Path::new(words.next()?).file_name()?.to_str()?;
+    if interpreter == "env" {
+        // Skip `env` flags such as `-S`
+        interpreter = words.find(|word| !word.starts_with('-'))?;
+    }
+    find(INTERPRETERS, interpreter).map(str::to_string)
+}
+
+fn find<'a>(table: &[(&str, &'a str)], key: &str) -> Option<&'a str> {
+    table
+        .iter()
+        .find(|(name, _)| *name == key)
+        .map(|(_, lang)| *lang)
+}
@@ -0,0 +1,493 @@
+//! Template inheritance: `extends`, `[[block: name]]` and `[[super]]`.
+
+use anyhow::{Context, Result, bail};
+use regex::{Match, Regex};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    calculate_hash,
+    frontmatter::{Frontmatter, split_frontmatter},
+    vfs::FileSystem,
+};
+
+/// A run of template text, with the template it was written in. Include paths in
+/// `text` are relative to `base_dir`.
+pub(crate) struct Piece {
+    pub(crate) text: String,
+    pub(crate) base_dir: PathBuf,
+    pub(crate) origin: String,
+}
+
+/// A template with its inheritance resolved: the text of its root base template,
+/// with every `[[block: name]]` replaced by the most derived definition of the block.
+pub(crate) struct Layout {
+    /// The template's settings, with unset ones taken from its base templates.
+    pub(crate) frontmatter: Frontmatter,
+    pub(crate) pieces: Vec<Piece>,
+    /// Canonical path -> hash of every base template, so edits to them are tracked.
+    pub(crate) bases: BTreeMap<PathBuf, String>,
+}
+
+/// Parsed template body.
+enum Node {
+    Text(String),
+    /// `[[block: name]] ... [[endblock]]`
+    Block { name: String, body: Vec<Node> },
+    /// `[[super]]`: the content of the overridden block.
+    Super,
+    /// `[[if: var]] ... [[else]] ... [[endif]]`, or `[[if: !var]]`.
+    If {
+        var: String,
+        negated: bool,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+}
+
+/// A construct being parsed, innermost last.
+enum Open {
+    Block { name: String, body: Vec<Node> },
+    If {
+        var: String,
+        negated: bool,
+        then: Vec<Node>,
+        /// Set once `[[else]]` was seen.
+        otherwise: Option<Vec<Node>>,
+    },
+}
+
+impl Open {
+    /// Where parsed nodes currently go.
+    fn body(&mut self) -> &mut Vec<Node> {
+        match self {
+            Self::Block { body, .. } => body,
+            Self::If {
+                otherwise: Some(otherwise),
+                ..
+            } => otherwise,
+            Self::If { then, .. } => then,
+        }
+    }
+}
+
+/// One template of an inheritance chain.
+struct Level {
+    base_dir: PathBuf,
+    origin: String,
+    nodes: Vec<Node>,
+}
+
+impl Layout {
+    /// Resolves the inheritance chain of a template (`extends` in its frontmatter)
+    /// and flattens its blocks and conditionals. A template that extends nothing
+    /// yields its own body, with block markers removed.
+    ///
+    /// Base templates are read from `fs`, and must lie below `restrict_root` (a
+    /// canonical path) if one is given. `vars` overrides the values of variables
+    /// declared under `[vars]` in the frontmatter.
+    pub(crate) fn load(
+        fs: &dyn FileSystem,
+        content: &str,
+        base_dir: &Path,
+        origin: &str,
+        vars: &BTreeMap<String, bool>,
+        restrict_root: Option<&Path>,
+    ) -> Result<Self> {
+        let (mut frontmatter, body) = split_frontmatter(content)
+            .with_context(|| format!("Invalid frontmatter in template '{origin}'"))?;
+        // Most derived first
+        let mut levels = vec![Level {
+            base_dir: base_dir.to_path_buf(),
+            origin: origin.to_string(),
+            nodes: parse(body).with_context(|| format!("Invalid blocks in template '{origin}'"))?,
+        }];
+        let mut bases = BTreeMap::new();
+
+        let mut extends = frontmatter.extends.clone();
+        while let Some(base) = extends {
+            let child = levels.last().expect("the template itself is a level");
+            let path = child.base_dir.join(&base);
+            let canonical = fs.canonicalize(&path).with_context(|| {
+                format!(
+                    "Failed to resolve base template '{}' (extended by '{}')",
+                    path.display(),
+                    child.origin
+                )
+            })?;
+            if let Some(root) = restrict_root
+                && !canonical.starts_with(root)
+            {
+                bail!(
+                    "Base template '{}' (extended by '{}') resolves outside the restrict root '{}'",
+                    canonical.display(),
+                    child.origin,
+                    root.display()
+                );
+            }
+            if bases.contains_key(&canonical) {
+                bail!(
+                    "Template inheritance cycle: '{}' extends '{}', which it already inherits from",
+                    child.origin,
+                    path.display()
+                );
+            }
+            let base_content = fs.read_to_string(&canonical).with_context(|| {
+                format!("Failed to read base template '{}'", canonical.display())
+            })?;
+            bases.insert(canonical.clone(), calculate_hash(base_content.as_bytes()));
+
+            let base_origin = path.display().to_string();
+            let (base_frontmatter, body) = split_frontmatter(&base_content)
+                .with_context(|| format!("Invalid frontmatter in template '{base_origin}'"))?;
+            let nodes = parse(body)
+                .with_context(|| format!("Invalid blocks in template '{base_origin}'"))?;
+            extends = base_frontmatter.extends.clone();
+            frontmatter = frontmatter.inherit(base_frontmatter);
+            levels.push(Level {
+                base_dir: canonical.parent().map(Path::to_path_buf).unwrap_or_default(),
+                origin: base_origin,
+                nodes,
+            });
+        }
+
+        for (name, value) in vars {
+            let Some(declared) = frontmatter.vars.get_mut(name) else {
+                bail!("Unknown variable '{name}' (templates declare variables under [vars] in the frontmatter)");
+            };
+            *declared = *value;
+        }
+
+        let resolver = Resolver::new(&levels, &frontmatter.vars)?;
+        let mut pieces = Vec::new();
+        let root = levels.len() - 1;
+        resolver.flatten(&levels[root].nodes, root, None, &mut Vec::new(), &mut pieces)?;
+
+        Ok(Self {
+            frontmatter,
+            pieces: pieces
+                .into_iter()
+                .map(|(level, text)| Piece {
+                    text,
+                    base_dir: levels[level].base_dir.clone(),
+                    origin: levels[level].origin.clone(),
+                })
+                .collect(),
+            bases,
+        })
+    }
+}
+
+/// Looks up block definitions across the levels of an inheritance chain.
+struct Resolver<'a> {
+    levels: &'a [Level],
+    vars: &'a BTreeMap<String, bool>,
+    /// Per level: block name -> body
+    blocks: Vec<BTreeMap<&'a str, &'a [Node]>>,
+}
+
+impl<'a> Resolver<'a> {
+    fn new(levels: &'a [Level], vars: &'a BTreeMap<String, bool>) -> Result<Self> {
+        let blocks: Vec<_> = levels
+            .iter()
+            .map(|level| {
+                let mut blocks = BTreeMap::new();
+                collect_blocks(&level.nodes, &mut blocks);
+                blocks
+            })
+            .collect();
+
+        // Every level but the root base template contributes only block overrides
+        for (index, level) in levels.iter().enumerate().take(levels.len() - 1) {
+            let stray_text = level.nodes.iter().any(|node| match node {
+                Node::Text(text) => !text.trim().is_empty(),
+                _ => false,
+            });
+            if stray_text {
+                bail!(
+                    "Template '{}' extends another template, so its content must be inside [[block: ...]] sections",
+                    level.origin
+                );
+            }
+            for name in blocks[index].keys() {
+                if !blocks[index + 1..].iter().any(|base| base.contains_key(name)) {
+                    bail!(
+                        "Block '{name}' in '{}' is not defined by any template it extends",
+                        level.origin
+                    );
+                }
+            }
+        }
+        Ok(Self {
+            levels,
+            vars,
+            blocks,
+        })
+    }
+
+    /// Appends the text of `nodes`, written at `level`, to `pieces`. `block` is the
+    /// enclosing block's name and level (for `[[super]]`); `active` the blocks being
+    /// expanded, to reject a block that ends up containing itself.
+    fn flatten(
+        &self,
+        nodes: &'a [Node],
+        level: usize,
+        block: Option<(&'a str, usize)>,
+        active: &mut Vec<&'a str>,
+        pieces: &mut Vec<(usize, String)>,
+    ) -> Result<()> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => match pieces.last_mut() {
+                    Some((last_level, last_text)) if *last_level == level => last_text.push_str(text),
+                    _ => pieces.push((level, text.clone())),
+                },
+                Node::Block { name, .. } => {
+                    // The most derived definition wins
+                    let (defined_at, body) = self
+                        .definition(name, 0)
+                        .expect("a block is defined where it appears");
+                    self.expand(name, defined_at, body, active, pieces)?;
+                }
+                Node::Super => {
+                    let (name, block_level) = block.expect("[[super]] is only parsed inside blocks");
+                    let Some((defined_at, body)) = self.definition(name, block_level + 1) else {
+                        bail!(
+                            "[[super]] in block '{name}' of '{}' has no base block to refer to",
+                            self.levels[block_level].origin
+                        );
+                    };
+                    self.expand(name, defined_at, body, active, pieces)?;
+                }
+                Node::If {
+                    var,
+                    negated,
+                    then,
+                    otherwise,
+                } => {
+                    let Some(value) = self.vars.get(var) else {
+                        bail!(
+                            "Unknown variable '{var}' in '{}' (declare it under [vars] in the frontmatter)",
+                            self.levels[level].origin
+                        );
+                    };
+                    let branch = if *value != *negated { then } else { otherwise };
+                    self.flatten(branch, level, block, active, pieces)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn expand(
+        &self,
+        name: &'a str,
+        level: usize,
+        body: &'a [Node],
+        active: &mut Vec<&'a str>,
+        pieces: &mut Vec<(usize, String)>,
+    ) -> Result<()> {
+        // [[super]] legitimately re-enters the block, at a less derived level
+        if active.iter().filter(|active| **active == name).count() >= self.levels.len() {
+            bail!("Block '{name}' contains itself");
+        }
+        active.push(name);
+        self.flatten(body, level, Some((name, level)), active, pieces)?;
+        active.pop();
+        Ok(())
+    }
+
+    /// The definition of block `name` at the most derived level from `from` on.
+    fn definition(&self, name: &str, from: usize) -> Option<(usize, &'a [Node])> {
+        (from..self.levels.len()).find_map(|level| Some((level, *self.blocks[level].get(name)?)))
+    }
+}
+
+fn collect_blocks<'a>(nodes: &'a [Node], blocks: &mut BTreeMap<&'a str, &'a [Node]>) {
+    for node in nodes {
+        match node {
+            Node::Block { name, body } => {
+                blocks.insert(name, body);
+                collect_blocks(body, blocks);
+            }
+            Node::If {
+                then, otherwise, ..
+            } => {
+                collect_blocks(then, blocks);
+                collect_blocks(otherwise, blocks);
+            }
+            Node::Text(_) | Node::Super => {}
+        }
+    }
+}
+
+/// Builds the regex matching the structural markers: `[[block: name]]`,
+/// `[[endblock]]` (optionally `[[endblock: name]]`), `[[super]]`, `[[if: var]]`,
+/// `[[else]]` and `[[endif]]`.
+fn marker_regex() -> Regex {
+    Regex::new(r"\[\[(block|endblock|super|if|else|endif)(?::\s*(.*?))?\s*\]\]")
+        .expect("Failed to compile marker regex")
+}
+
+/// Parses the blocks and conditionals of a template body. Everything but their
+/// markers is text.
+fn parse(body: &str) -> Result<Vec<Node>> {
+    let mut root = Vec::new();
+    let mut open: Vec<Open> = Vec::new();
+    let mut names = BTreeSet::new();
+    let mut pos = 0;
+
+    for cap in marker_regex().captures_iter(body) {
+        let marker = marker_span(body, cap.get(0).expect("capture 0 is the whole match"));
+        let marker = marker.start.max(pos)..marker.end;
+        if marker.start > pos {
+            let text = Node::Text(body[pos..marker.start].to_string());
+            open.last_mut().map_or(&mut root, Open::body).push(text);
+        }
+        pos = marker.end;
+
+        let kind = &cap[1];
+        let argument = cap.get(2).map(|argument| argument.as_str().trim());
+        if matches!(kind, "super" | "else" | "endif") && argument.is_some() {
+            bail!("[[{kind}]] takes no argument");
+        }
+        let node = match kind {
+            "block" => {
+                let name = argument.unwrap_or_default();
+                if name.is_empty() || name.contains(char::is_whitespace) {
+                    bail!("Invalid block name '{name}'");
+                }
+                if !names.insert(name.to_string()) {
+                    bail!("Block '{name}' is defined twice");
+                }
+                open.push(Open::Block {
+                    name: name.to_string(),
+                    body: Vec::new(),
+                });
+                continue;
+            }
+            "if" => {
+                let condition = argument.unwrap_or_default();
+                let (negated, var) = match condition.strip_prefix('!') {
+                    Some(var) => (true, var.trim()),
+                    None => (false, condition),
+                };
+                if var.is_empty() || var.contains(char::is_whitespace) {
+                    bail!("Invalid condition '{condition}' (expected a variable name or !name)");
+                }
+                open.push(Open::If {
+                    var: var.to_string(),
+                    negated,
+                    then: Vec::new(),
+                    otherwise: None,
+                });
+                continue;
+            }
+            "else" => match open.last_mut() {
+                Some(Open::If { otherwise, .. }) if otherwise.is_none() => {
+                    *otherwise = Some(Vec::new());
+                    continue;
+                }
+                _ => bail!("[[else]] without a matching [[if: ...]]"),
+            },
+            "endblock" => {
+                let Some(Open::Block { name, body }) = open.pop() else {
+                    bail!("[[endblock]] without a matching [[block: ...]]");
+                };
+                if let Some(end_name) = argument
+                    && end_name != name
+                {
+                    bail!("[[endblock: {end_name}]] closes block '{name}'");
+                }
+                Node::Block { name, body }
+            }
+            "endif" => {
+                let Some(Open::If {
+                    var,
+                    negated,
+                    then,
+                    otherwise,
+                }) = open.pop()
+                else {
+                    bail!("[[endif]] without a matching [[if: ...]]");
+                };
+                Node::If {
+                    var,
+                    negated,
+                    then,
+                    otherwise: otherwise.unwrap_or_default(),
+                }
+            }
+            _ => {
+                if !open.iter().any(|open| matches!(open, Open::Block { .. })) {
+                    bail!("[[super]] must be inside a [[block: ...]]");
+                }
+                Node::Super
+            }
+        };
+        open.last_mut().map_or(&mut root, Open::body).push(node);
+    }
+
+    match open.last() {
+        Some(Open::Block { name, .. }) => bail!("Block '{name}' is not closed by [[endblock]]"),
+        Some(Open::If { var, .. }) => bail!("[[if: {var}]] is not closed by [[endif]]"),
+        None => {}
+    }
+    if pos < body.len() {
+        root.push(Node::Text(body[pos..].to_string()));
+    }
+    Ok(root)
+}
+
+/// The range to remove for a marker: its whole line if it stands alone on
+/// one, so markers do not leave blank lines behind.
+fn marker_span(text: &str, marker: Match) -> Range<usize> {
+    let line_start = text[..marker.start()].rfind('\n').map_or(0, |i| i + 1);
+    let line_end = text[marker.end()..]
+        .find('\n')
+        .map_or(text.len(), |i| marker.end() + i + 1);
+    let alone = text[line_start..marker.start()].trim().is_empty()
+        && text[marker.end()..line_end].trim().is_empty();
+    if alone { line_start..line_end } else { marker.range() }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use crate::{ProcessOptions, process_template_content, vfs::MemoryFileSystem};
+
+    fn options(restrict_root: Option<&str>) -> ProcessOptions {
+        let mut files = MemoryFileSystem::new();
+        files.insert("/outside.txt", "[[block: body]]secret[[endblock]]");
+        files.insert("/project/base.clamp", "Intro\n[[block: body]]base[[endblock]]\n");
+        ProcessOptions {
+            file_system: Some(Arc::new(files)),
+            restrict_root: restrict_root.map(Into::into),
+            ..Default::default()
+        }
+    }
+
+    fn render(extends: &str, options: &ProcessOptions) -> anyhow::Result<String> {
+        let template = format!("+++\nextends = \"{extends}\"\n+++\n[[block: body]]child[[endblock]]\n");
+        process_template_content(&template, Path::new("/project"), "t.clamp", options)
+            .map(|result| result.output_content)
+    }
+
+    #[test]
+    fn a_base_template_inside_the_restrict_root_is_extended() {
+        let output = render("base.clamp", &options(Some("/project"))).unwrap();
+        assert_eq!(output, "Intro\nchild\n");
+    }
+
+    #[test]
+    fn a_base_template_outside_the_restrict_root_is_rejected() {
+        let error = render("../outside.txt", &options(Some("/project"))).unwrap_err();
+        assert!(format!("{error:#}").contains("outside the restrict root"), "{error:#}");
+        // Without a restrict root, the same template is read
+        assert!(render("../outside.txt", &options(None)).unwrap().contains("child"));
+    }
+}
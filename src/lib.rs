@@ -1,18 +1,185 @@
 use anyhow::{Context, Result, bail};
-use regex::Regex;
 use serde::{Deserialize, Serialize};
 use sha2::{Digest, Sha256};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     fmt::Write,
-    fs, io,
-    path::{Path, PathBuf}, process::ExitCode,
+    fs,
+    io::{self, Read},
+    ops::Range,
+    path::{Component, Path, PathBuf}, process::ExitCode,
+    str::FromStr,
+    sync::Arc,
 };
 
+pub mod apply;
+pub mod archive;
+pub mod clipboard;
+pub mod config;
+pub mod diff;
+pub mod directive;
+pub mod events;
+pub mod filter;
+pub mod formatter;
+pub mod frontmatter;
+pub mod git;
+mod gitdb;
+pub mod graph;
+pub mod hook;
+pub mod http;
+pub mod ignore;
+pub mod json;
+pub mod language;
+mod layout;
+pub mod lint;
+pub mod lockfile;
+pub mod log;
+pub mod lsp;
+pub mod man;
+mod markdown;
+pub mod mcp;
+pub mod permalink;
+#[cfg(feature = "tokio")]
+pub mod nonblocking;
+pub mod preview;
+pub mod progress;
+pub mod redact;
+mod render;
+pub mod scan;
+pub mod snapshot;
+pub mod split;
+pub mod starters;
+pub mod vfs;
+pub mod workspace;
+pub mod wrapper;
+
+use config::Config;
+use directive::IncludeDirective;
+use events::IncludeEvent;
+use frontmatter::{OutputTarget, split_frontmatter};
+use layout::Layout;
+use progress::ProgressListener;
+
+pub use lockfile::Lockfile;
+#[cfg(feature = "tokio")]
+pub use nonblocking::{process_template_async, process_template_content_async};
+use render::Renderer;
+use vfs::{FileSystem, OsFileSystem};
+use wrapper::{WrapStyle, Wrapper};
+
 /// Represents the data stored in the .clamp.lock file.
-#[derive(Serialize, Deserialize, Debug, Default)]
+///
+/// Each file is written as its own small table, sorted by path, so that branches
+/// adding or updating different includes change separate blocks and usually merge
+/// without conflicts:
+///
+/// ```toml
+/// [files."/home/me/project/src/lib.rs"]
+/// hash = "9f86d081884c7d659a2feaa0c55ad015a3bf4f1b2b0b822cd15d6c15b0f00a08"
+///
+/// [files."/home/me/project/src/main.rs"]
+/// hash = "60303ae22b998861bce3b28f33eec1be758a213c86c93c076dbe9f558c11c752"
+/// ```
+///
+/// A pinned file's table also carries `policy = "pin"` (see [`LockPolicy`]).
+///
+/// The earlier layout, a single `[files]` table mapping each path to its hash, is
+/// still read.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+#[serde(from = "LockfileLayout", into = "LockfileLayout")]
 pub struct LockfileData {
     pub files: BTreeMap<PathBuf, String>, // Canonicalized Path -> SHA256 Hash (hex string)
+    /// Paths of the entries locked with [`LockPolicy::Pin`].
+    pub pinned: BTreeSet<PathBuf>,
+}
+
+/// The on-disk shape of [`LockfileData`].
+#[derive(Serialize, Deserialize)]
+struct LockfileLayout {
+    #[serde(default)]
+    files: BTreeMap<PathBuf, LockEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum LockEntry {
+    Table {
+        hash: String,
+        #[serde(default, skip_serializing_if = "LockPolicy::is_track")]
+        policy: LockPolicy,
+    },
+    /// A bare hash, as in lockfiles written before per-file tables
+    Hash(String),
+}
+
+impl From<LockfileLayout> for LockfileData {
+    fn from(layout: LockfileLayout) -> Self {
+        let mut data = Self::default();
+        for (path, entry) in layout.files {
+            let (hash, policy) = match entry {
+                LockEntry::Table { hash, policy } => (hash, policy),
+                LockEntry::Hash(hash) => (hash, LockPolicy::Track),
+            };
+            if policy == LockPolicy::Pin {
+                data.pinned.insert(path.clone());
+            }
+            data.files.insert(path, hash);
+        }
+        data
+    }
+}
+
+impl From<LockfileData> for LockfileLayout {
+    fn from(data: LockfileData) -> Self {
+        let files = data
+            .files
+            .into_iter()
+            .map(|(path, hash)| {
+                let policy = if data.pinned.contains(&path) { LockPolicy::Pin } else { LockPolicy::Track };
+                (path, LockEntry::Table { hash, policy })
+            })
+            .collect();
+        Self { files }
+    }
+}
+
+/// How changes to a locked include are treated, set with the `policy=` modifier or
+/// the `pin` patterns in `clamp.toml`.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum LockPolicy {
+    /// Changes are reported, and accepted by `clamp update-lock`.
+    #[default]
+    Track,
+    /// Changes fail the build and `clamp check` until the entry is re-locked with
+    /// `clamp bump` or `clamp update-lock`.
+    Pin,
+}
+
+impl LockPolicy {
+    /// The policy's name in templates and lockfiles, e.g. `pin`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Track => "track",
+            Self::Pin => "pin",
+        }
+    }
+
+    fn is_track(&self) -> bool {
+        *self == Self::Track
+    }
+}
+
+impl FromStr for LockPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "track" => Ok(Self::Track),
+            "pin" => Ok(Self::Pin),
+            other => bail!("Unknown lock policy '{other}' (expected track or pin)"),
+        }
+    }
 }
 
 /// Represents the result of processing a template.
@@ -22,19 +189,195 @@ pub struct ProcessResult {
     pub output_content: String,
     /// Map of included files (canonicalized paths) and their *current* SHA256 hashes.
     pub current_hashes: BTreeMap<PathBuf, String>,
+    /// The included files (canonicalized paths) with [`LockPolicy::Pin`], set by a
+    /// `policy=pin` modifier or a `pin` pattern in `clamp.toml`.
+    pub pinned: BTreeSet<PathBuf>,
+    /// Every block embedded into the output, in output order.
+    pub includes: Vec<EmbeddedBlock>,
+    /// How each included file was resolved, in output order.
+    pub resolutions: Vec<Resolution>,
+    /// Includes replaced by a placeholder because they failed (see
+    /// [`ProcessOptions::keep_going`]). Empty unless that option is set.
+    pub failures: Vec<IncludeFailure>,
+    /// The rendering steps in output order. Empty unless [`ProcessOptions::events`]
+    /// is set.
+    pub events: Vec<IncludeEvent>,
+}
+
+/// An include that could not be embedded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IncludeFailure {
+    /// The include path as written in the directive (or the glob match).
+    pub path: String,
+    /// Why it failed, including its causes.
+    pub error: String,
+}
+
+/// How an included file was found, for explaining a build (see `clamp --trace`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Resolution {
+    /// The directive as written in the template, e.g. `[[include: src/*.rs]]`.
+    pub directive: String,
+    /// The file's path relative to `base_dir` (a glob match, or the directive's path).
+    pub path: String,
+    /// The directory the path was resolved against (for a linked file, the repository's URL).
+    pub base_dir: PathBuf,
+    /// The canonical path recorded in the lockfile (for a linked file, the link at its commit).
+    pub canonical_path: PathBuf,
+    /// SHA256 hash (hex) of the file's content.
+    pub hash: String,
+    /// Size of the embedded content in bytes (after `head=`/`tail=`).
+    pub size: usize,
+    /// Byte range of the file's block in the output, wrapper included. `None` if
+    /// `dedup` replaced it by a reference to an earlier block.
+    pub output_range: Option<Range<usize>>,
+}
+
+/// How much of the output a single embedded file (or diff) accounts for.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EmbeddedBlock {
+    /// The path as written in the directive (or the diff's label).
+    pub path: String,
+    /// Bytes of output produced, including the wrapper and header.
+    pub bytes: usize,
+    /// Lines of output produced, including the wrapper and header.
+    pub lines: usize,
+    /// Byte range of the block in the output.
+    pub output_range: Range<usize>,
+}
+
+/// Options controlling how a template is processed.
+#[derive(Debug, Clone, Default)]
+pub struct ProcessOptions {
+    /// If set, includes whose canonical path lies outside this directory are rejected.
+    pub restrict_root: Option<PathBuf>,
+    /// How symlinks encountered while resolving includes are treated.
+    pub symlink_policy: SymlinkPolicy,
+    /// Embed each file only once, replacing later includes of it with a reference.
+    /// `None` defers to the template's frontmatter (default: off).
+    pub dedup: Option<bool>,
+    /// Format of the header line emitted above each embedded file (see [`DEFAULT_HEADER`]).
+    /// An empty string turns the header off, restoring the plain `Contents of <path>:` line.
+    /// `None` defers to the template's frontmatter.
+    pub header: Option<String>,
+    /// Prefix each embedded line with its line number in the original file.
+    /// Per-directive `linenos` modifiers take precedence; `None` defers to the frontmatter.
+    pub linenos: Option<bool>,
+    /// Wrap each embedded file in a collapsible `<details>` block.
+    /// Per-directive `collapsible` modifiers take precedence; `None` defers to the frontmatter.
+    pub collapsible: Option<bool>,
+    /// Format used to embed files. `None` defers to the frontmatter, then to
+    /// the project configuration (default: markdown).
+    pub wrap: Option<WrapStyle>,
+    /// A custom embedding format, taking precedence over `wrap`.
+    pub custom_wrapper: Option<Arc<dyn Wrapper>>,
+    /// Project configuration, usually discovered from `clamp.toml`.
+    pub config: Config,
+    /// Expand glob patterns without honoring `.gitignore`/`.clampignore` files.
+    pub no_ignore: bool,
+    /// Notified as included files are embedded, e.g. to drive a progress bar.
+    pub progress: Option<Arc<dyn ProgressListener>>,
+    /// Replace includes that are missing or cannot be read with a visible
+    /// `[clamp: missing <path>]` placeholder instead of failing, recording them in
+    /// [`ProcessResult::failures`].
+    pub keep_going: bool,
+    /// Values overriding the variables a template declares under `[vars]` in its
+    /// frontmatter, e.g. those of an `[[outputs]]` entry.
+    pub vars: BTreeMap<String, bool>,
+    /// Record the template's structure as a stream of [`IncludeEvent`]s in
+    /// [`ProcessResult::events`].
+    pub events: bool,
+    /// Where templates and includes are read from. `None` uses the real file system
+    /// (see [`vfs::FileSystem`]).
+    pub file_system: Option<Arc<dyn FileSystem>>,
+}
+
+impl ProcessOptions {
+    /// The file system templates and includes are read from.
+    pub fn fs(&self) -> &dyn FileSystem {
+        self.file_system.as_deref().unwrap_or(&OsFileSystem)
+    }
+}
+
+/// Default header emitted above each embedded file. `{path}` is replaced by the
+/// include path as written in the directive, `{lang}` by the language hint.
+pub const DEFAULT_HEADER: &str = "### `{path}`";
+
+/// Replaces each `{name}` placeholder in `format` with its value from `values`.
+/// Unknown placeholders are left untouched.
+pub fn expand_placeholders(format: &str, values: &[(&str, &str)]) -> String {
+    let mut expanded = format.to_string();
+    for (name, value) in values {
+        expanded = expanded.replace(&format!("{{{name}}}"), value);
+    }
+    expanded
+}
+
+/// Controls how include paths containing symlinks are resolved.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum SymlinkPolicy {
+    /// Follow symlinks and record the canonical target path (the traditional behavior).
+    #[default]
+    Follow,
+    /// Reject includes whose path (below the base directory) passes through a symlink.
+    Deny,
+    /// Do not dereference an included symlink: record the link's own path and hash
+    /// the path it points to, instead of the target's content.
+    NoFollow,
+}
+
+impl SymlinkPolicy {
+    /// The policy's name on the command line, e.g. `no-follow`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Follow => "follow",
+            Self::Deny => "deny",
+            Self::NoFollow => "no-follow",
+        }
+    }
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "follow" => Ok(Self::Follow),
+            "deny" => Ok(Self::Deny),
+            "no-follow" => Ok(Self::NoFollow),
+            other => bail!("Unknown symlink policy '{other}' (expected follow, deny or no-follow)"),
+        }
+    }
 }
 
 /// Represents the status of a file compared to the lockfile.
-#[derive(Debug, PartialEq, Eq, Clone, Copy)] // Added Clone, Copy for potential future use
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum ChangeStatus {
     Unchanged,
     Modified,
     Added,   // Present now, but not in lockfile.
     Removed, // Present in lockfile, but not included now.
+    /// Present now with the hash of a locked path that is no longer included,
+    /// i.e. the file moved from `from`.
+    Renamed { from: PathBuf },
 }
 
-/// Calculates the SHA256 hash of byte content and returns it as a hex string.
-fn calculate_hash(content: &[u8]) -> String {
+impl ChangeStatus {
+    /// Lowercase name used in machine-readable reports, e.g. `modified`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Unchanged => "unchanged",
+            Self::Modified => "modified",
+            Self::Added => "added",
+            Self::Removed => "removed",
+            Self::Renamed { .. } => "renamed",
+        }
+    }
+}
+
+/// Calculates the SHA256 hash of byte content and returns it as a hex string, the
+/// form of lockfile entries.
+pub fn calculate_hash(content: &[u8]) -> String {
     let mut hasher = Sha256::new();
     hasher.update(content);
     let result = hasher.finalize();
@@ -47,114 +390,272 @@ fn calculate_hash(content: &[u8]) -> String {
 /// directory, calculates hashes of included files, and returns the final content
 /// along with the map of included files and their current hashes.
 ///
+/// An optional TOML frontmatter block (see [`frontmatter`]) at the top of the template
+/// configures per-template settings and is stripped from the output.
+///
 /// Included file paths are resolved relative to the directory containing the template file.
 /// Included file content is assumed to be UTF-8 and is wrapped in markdown code blocks
-/// (e.g., ```rust ... ```) in the output.
+/// (e.g., ```rust ... ```) in the output, with the language hint determined by
+/// [`language::detect_language`].
 ///
 /// Returns an error if the template or any included file cannot be read, or if an
 /// included file path does not exist, or if included content is not valid UTF-8.
-pub fn process_template(template_path: &Path) -> Result<ProcessResult> {
-    let template_content = fs::read_to_string(template_path)
+pub fn process_template(template_path: &Path, options: &ProcessOptions) -> Result<ProcessResult> {
+    let template_content = options
+        .fs()
+        .read_to_string(template_path)
         .with_context(|| format!("Failed to read template file '{}'", template_path.display()))?;
 
     let base_dir = template_path
         .parent()
         .context("Template path must have a parent directory")?;
 
-    // regex for [[include: path/to/file.ext]], allowing whitespace around the path.
-    let include_regex =
-        Regex::new(r"\[\[include:\s*(.*?)\s*\]\]").expect("Failed to compile include regex");
-
-    let mut output_buffer = String::with_capacity(template_content.len());
-    let mut current_pos = 0;
-    let mut current_hashes = BTreeMap::new();
+    process_template_content(
+        &template_content,
+        base_dir,
+        &template_path.display().to_string(),
+        options,
+    )
+}
 
-    for cap in include_regex.captures_iter(&template_content) {
-        let full_match = cap.get(0).unwrap(); // The whole [[include: ...]]
-        let path_match = cap.get(1).unwrap(); // The path inside
-        let relative_path_str = path_match.as_str().trim(); // Trim whitespace just in case
+/// Processes template content that is already in memory (e.g., read from stdin).
+///
+/// Include paths are resolved relative to `base_dir`. `origin` names the template
+/// in error messages (a file path, or something like `<stdin>`).
+pub fn process_template_content(
+    template_content: &str,
+    base_dir: &Path,
+    origin: &str,
+    options: &ProcessOptions,
+) -> Result<ProcessResult> {
+    log::debug!("Processing template '{origin}' (base directory '{}')", base_dir.display());
+    let restrict_root = options
+        .restrict_root
+        .as_deref()
+        .map(|root| {
+            options.fs().canonicalize(root).with_context(|| {
+                format!("Failed to canonicalize restrict root '{}'", root.display())
+            })
+        })
+        .transpose()?;
+    let layout = Layout::load(
+        options.fs(),
+        template_content,
+        base_dir,
+        origin,
+        &options.vars,
+        restrict_root.as_deref(),
+    )?;
+
+    let mut result = Renderer::new(base_dir, origin, options, restrict_root, layout.frontmatter)?
+        .render(&layout.pieces)?;
+    result.current_hashes.extend(layout.bases);
+    Ok(result)
+}
 
-        // append text before the match
-        output_buffer.push_str(&template_content[current_pos..full_match.start()]);
+/// Renders each output declared under `[[outputs]]` in the template's frontmatter,
+/// with that output's variable values. A template without outputs yields none.
+pub fn process_template_outputs(
+    template_content: &str,
+    base_dir: &Path,
+    origin: &str,
+    options: &ProcessOptions,
+) -> Result<Vec<(OutputTarget, ProcessResult)>> {
+    let (frontmatter, _) = split_frontmatter(template_content)
+        .with_context(|| format!("Invalid frontmatter in template '{origin}'"))?;
+    frontmatter
+        .outputs
+        .into_iter()
+        .map(|output| {
+            let mut options = options.clone();
+            options.vars.extend(output.vars.clone());
+            let result = process_template_content(template_content, base_dir, origin, &options)
+                .with_context(|| format!("Failed to render output '{}'", output.path.display()))?;
+            Ok((output, result))
+        })
+        .collect()
+}
 
-        let include_path = base_dir.join(relative_path_str);
+/// The included files a template's lockfile tracks: those of its default render
+/// `result` and of every `[[outputs]]` variant, so each variant's drift is reported.
+pub fn tracked_hashes(
+    template_content: &str,
+    base_dir: &Path,
+    origin: &str,
+    options: &ProcessOptions,
+    result: &ProcessResult,
+) -> Result<BTreeMap<PathBuf, String>> {
+    let mut hashes = result.current_hashes.clone();
+    for (_, output) in process_template_outputs(template_content, base_dir, origin, options)? {
+        hashes.extend(output.current_hashes);
+    }
+    Ok(hashes)
+}
 
-        if !include_path.exists() {
-            bail!(
-                "Include directive error: File not found at resolved path '{}' (referenced in '{}' as '{}')",
-                include_path.display(),
-                template_path.display(),
-                relative_path_str
-            );
+/// Resolves the path of an `[[include: ...]]` directive against `base_dir` to the
+/// files it embeds: the file itself, or every match of a glob pattern. Used by tooling
+/// that inspects templates without rendering them.
+pub(crate) fn resolve_include_paths(
+    fs: &dyn FileSystem,
+    base_dir: &Path,
+    path: &str,
+    use_ignore_files: bool,
+) -> Result<Vec<PathBuf>> {
+    // Finding the member itself would mean decompressing the archive
+    if let Some((archive, _)) = archive::split_member_path(path) {
+        let resolved = base_dir.join(archive);
+        if !fs.is_file(&resolved) {
+            bail!("Archive not found: '{}'", resolved.display());
         }
-        let canonical_path = fs::canonicalize(&include_path).with_context(|| {
-            format!(
-                "Failed to canonicalize include path '{}'",
-                include_path.display()
-            )
-        })?;
-
-        let included_content_bytes = fs::read(&canonical_path).with_context(|| {
-            format!(
-                "Failed to read included file '{}'",
-                canonical_path.display()
-            )
-        })?;
-
-        let hash = calculate_hash(&included_content_bytes);
-
-        current_hashes.insert(canonical_path.clone(), hash); // Clone path for insertion
+        return Ok(vec![resolved]);
+    }
+    if ignore::is_glob_pattern(path) && !fs.exists(&base_dir.join(path)) {
+        let matches = ignore::expand_glob(fs, base_dir, path, use_ignore_files)?;
+        if matches.is_empty() {
+            bail!("Pattern '{path}' matches no files");
+        }
+        return Ok(matches.iter().map(|matched| base_dir.join(matched)).collect());
+    }
+    let resolved = base_dir.join(path);
+    if !fs.is_file(&resolved) {
+        bail!("File not found: '{}'", resolved.display());
+    }
+    Ok(vec![resolved])
+}
 
-        let content_str = String::from_utf8(included_content_bytes).with_context(|| {
-            format!(
-                "Included file '{}' does not contain valid UTF-8 content",
-                canonical_path.display()
-            )
-        })?;
+/// Directories searched for an include `path`: `base_dir`, then for a relative path
+/// each of the `roots` (the `include_roots` of `clamp.toml`) in order.
+fn search_dirs<'a>(base_dir: &'a Path, roots: &'a [PathBuf], path: &str) -> impl Iterator<Item = &'a Path> {
+    let roots = if Path::new(path).is_absolute() { &[] } else { roots };
+    std::iter::once(base_dir).chain(roots.iter().map(PathBuf::as_path))
+}
 
-        let lang_hint = include_path
-            .extension()
-            .and_then(|os_str| os_str.to_str())
-            .unwrap_or("");
+/// Picks the alternative of `directive` (`path || fallback || ...`) to include and the
+/// directory it resolves against: the first alternative found next to the template or
+/// in one of the `roots`, or `None` if none is found. A directive without fallbacks
+/// always yields its path (against `base_dir` if it is found nowhere), leaving errors
+/// to the caller.
+pub(crate) fn select_alternative<'a>(
+    fs: &dyn FileSystem,
+    base_dir: &Path,
+    roots: &[PathBuf],
+    directive: &'a IncludeDirective,
+    use_ignore_files: bool,
+) -> Option<(PathBuf, &'a str)> {
+    if directive.fallbacks.is_empty() && roots.is_empty() {
+        return Some((base_dir.to_path_buf(), &directive.path));
+    }
+    let found = directive.alternatives().find_map(|path| {
+        let dir = search_dirs(base_dir, roots, path)
+            .find(|dir| resolve_include_paths(fs, dir, path, use_ignore_files).is_ok())?;
+        if dir != base_dir {
+            log::debug!("Found include '{path}' in include root '{}'", dir.display());
+        }
+        Some((dir.to_path_buf(), path))
+    });
+    match found {
+        None if directive.fallbacks.is_empty() => Some((base_dir.to_path_buf(), &directive.path)),
+        found => found,
+    }
+}
 
-        // Format and append the included content block
-        // Use writeln! style formatting for clarity if multi-line
-        write!(output_buffer, "Contents of {relative_path_str}:\n```{lang_hint}\n{content_str}\n```\n")
-            .expect("Writing to String buffer failed unexpectedly");
+/// Resolves an include directive to the files it embeds, trying its fallbacks in order
+/// and searching the include `roots` for relative paths not found next to the template.
+pub(crate) fn resolve_include(
+    fs: &dyn FileSystem,
+    base_dir: &Path,
+    roots: &[PathBuf],
+    directive: &IncludeDirective,
+    use_ignore_files: bool,
+) -> Result<Vec<PathBuf>> {
+    match select_alternative(fs, base_dir, roots, directive, use_ignore_files) {
+        Some((dir, path)) => resolve_include_paths(fs, &dir, path, use_ignore_files),
+        None => bail!("None of the alternatives '{}' exist", directive.alternatives_text()),
+    }
+}
 
-        current_pos = full_match.end();
+/// Resolves an `include-dir` directive to the files it embeds, as sorted `/`-separated
+/// paths relative to `base_dir`: every text file below the directory that passes the
+/// `ext=` and `max-size=` filters. Files containing a NUL byte count as binary.
+pub(crate) fn resolve_include_dir(
+    fs: &dyn FileSystem,
+    base_dir: &Path,
+    directive: &IncludeDirective,
+    use_ignore_files: bool,
+) -> Result<Vec<String>> {
+    let modifiers = &directive.modifiers;
+    let files: Vec<String> = ignore::list_directory(fs, base_dir, &directive.path, use_ignore_files)?
+        .into_iter()
+        .filter(|file| {
+            modifiers.ext.is_empty()
+                || modifiers.ext.iter().any(|ext| file.ends_with(&format!(".{ext}")))
+        })
+        .filter(|file| {
+            let path = base_dir.join(file);
+            let small_enough = modifiers
+                .max_size
+                .is_none_or(|max| fs.metadata(&path).is_ok_and(|meta| meta.len <= max));
+            small_enough && !is_binary(fs, &path)
+        })
+        .collect();
+    if files.is_empty() {
+        bail!("Directory '{}' contains no matching files", directive.path);
     }
+    Ok(files)
+}
 
-    // append remaining text after the last include
-    output_buffer.push_str(&template_content[current_pos..]);
+/// Whether the file at `path` looks binary: its first 8 KiB contain a NUL byte.
+/// Unreadable files are not binary, leaving the error to whoever reads them.
+fn is_binary(fs: &dyn FileSystem, path: &Path) -> bool {
+    let mut buffer = [0; 8192];
+    let read = fs.open(path).and_then(|mut file| file.read(&mut buffer));
+    read.is_ok_and(|count| buffer[..count].contains(&0))
+}
 
-    Ok(ProcessResult {
-        output_content: output_buffer,
-        current_hashes,
-    })
+/// Prefixes each line of `content` with its line number, starting at `first_line`,
+/// right-aligned to the widest number (e.g. ` 9 | ...`, `10 | ...`).
+/// A trailing newline is preserved.
+pub fn number_lines(content: &str, first_line: usize) -> String {
+    let line_count = content.lines().count();
+    let numbers: Vec<usize> = (first_line..first_line + line_count).collect();
+    number_lines_as(content, &numbers)
 }
 
-/// Reads and deserializes the lockfile. Returns default (empty) if not found.
-pub fn read_lockfile(lockfile_path: &Path) -> Result<LockfileData> {
-    if !lockfile_path.exists() {
-        eprintln!(
-            "Warning: Lockfile '{}' not found. Treating all includes as added.",
-            lockfile_path.display()
-        );
-        return Ok(LockfileData {
-            files: std::collections::BTreeMap::new(),
-        });
+/// Like [`number_lines`], with the number of each line given in `numbers`, e.g. the
+/// original numbers of the lines a [filter](filter::Filter) kept.
+pub(crate) fn number_lines_as(content: &str, numbers: &[usize]) -> String {
+    let line_count = content.lines().count();
+    let width = numbers.iter().max().map_or(1, |widest| widest.to_string().len());
+
+    let mut numbered = String::with_capacity(content.len() + line_count * (width + 3));
+    for (index, (line, number)) in content.lines().zip(numbers).enumerate() {
+        if index > 0 {
+            numbered.push('\n');
+        }
+        write!(numbered, "{number:>width$} | {line}")
+            .expect("Writing to String buffer failed unexpectedly");
+    }
+    if content.ends_with('\n') {
+        numbered.push('\n');
     }
+    numbered
+}
 
-    match fs::read_to_string(lockfile_path) {
-        Ok(content) => toml::from_str(&content).with_context(|| {
+/// Reads and deserializes the lockfile from `fs`. Returns default (empty) if not found.
+pub fn read_lockfile(fs: &dyn FileSystem, lockfile_path: &Path) -> Result<LockfileData> {
+    match fs.read_to_string(lockfile_path) {
+        Ok(content) => parse_lockfile(&content).with_context(|| {
             format!(
                 "Failed to parse TOML from lockfile '{}'",
                 lockfile_path.display()
             )
         }),
         Err(e) if e.kind() == io::ErrorKind::NotFound => {
-            Ok(LockfileData::default()) // Return empty data if lockfile doesn't exist
+            log::warning!(
+                "Lockfile '{}' not found. Treating all includes as added.",
+                lockfile_path.display()
+            );
+            Ok(LockfileData::default())
         }
         Err(e) => {
             Err(e).with_context(|| format!("Failed to read lockfile '{}'", lockfile_path.display()))
@@ -162,27 +663,140 @@ pub fn read_lockfile(lockfile_path: &Path) -> Result<LockfileData> {
     }
 }
 
-/// Serializes lockfile data to TOML and writes it to the specified path.
-pub fn write_lockfile(lockfile_path: &Path, data: &LockfileData) -> Result<()> {
-    let toml_content =
-        toml::to_string_pretty(data).context("Failed to serialize lockfile data to TOML")?;
-    fs::write(lockfile_path, toml_content)
+/// Deserializes the TOML `content` of a lockfile, in the current or the earlier layout.
+pub fn parse_lockfile(content: &str) -> Result<LockfileData> {
+    Ok(toml::from_str(content)?)
+}
+
+/// Serializes lockfile data to TOML, with paths normalized (see [`normalize_lock_path`]).
+pub fn serialize_lockfile(data: &LockfileData) -> Result<String> {
+    let normalized = LockfileData {
+        files: data
+            .files
+            .iter()
+            .map(|(path, hash)| (normalize_lock_path(path), hash.clone()))
+            .collect(),
+        pinned: data.pinned.iter().map(|path| normalize_lock_path(path)).collect(),
+    };
+    toml::to_string_pretty(&normalized).context("Failed to serialize lockfile data to TOML")
+}
+
+/// Serializes lockfile data to TOML and writes it to the specified path in `fs`.
+/// Paths are stored normalized (see [`normalize_lock_path`]).
+pub fn write_lockfile(fs: &dyn FileSystem, lockfile_path: &Path, data: &LockfileData) -> Result<()> {
+    let toml_content = serialize_lockfile(data)?;
+    fs.write(lockfile_path, toml_content.as_bytes())
         .with_context(|| format!("Failed to write lockfile to '{}'", lockfile_path.display()))?;
+    log::debug!(
+        "Wrote lockfile '{}' ({} entries)",
+        lockfile_path.display(),
+        data.files.len()
+    );
     Ok(())
 }
 
+/// Expresses the absolute `path` relative to the directory `from`, using `..`
+/// components where needed.
+pub fn relative_path(from: &Path, path: &Path) -> Result<PathBuf> {
+    let from = std::path::absolute(from)
+        .with_context(|| format!("Failed to resolve directory '{}'", from.display()))?;
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = path.components().collect();
+
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        return Ok(path.to_path_buf()); // Different roots (e.g. Windows drives)
+    }
+    let mut relative = PathBuf::new();
+    for _ in common..from.len() {
+        relative.push("..");
+    }
+    relative.extend(&to[common..]);
+    Ok(relative)
+}
+
+/// Normalizes a path for storage in (and comparison against) the lockfile.
+///
+/// On Windows this strips the verbatim `\\?\` prefix produced by `fs::canonicalize`,
+/// uses forward slashes, and upper-cases the drive letter, so lockfiles stay readable
+/// and portable across platforms. Other platforms keep the path as-is.
+pub fn normalize_lock_path(path: &Path) -> PathBuf {
+    if !cfg!(windows) {
+        return path.to_path_buf();
+    }
+
+    let raw = path.to_string_lossy();
+    let stripped = if let Some(unc) = raw.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{unc}")
+    } else {
+        raw.strip_prefix(r"\\?\").unwrap_or(&raw).to_string()
+    };
+    let mut normalized = stripped.replace('\\', "/");
+
+    // Consistent drive letter case, e.g. `c:/...` -> `C:/...`
+    if normalized.as_bytes().get(1) == Some(&b':') {
+        normalized[..1].make_ascii_uppercase();
+    }
+    PathBuf::from(normalized)
+}
+
+/// Key used to match lockfile entries. Windows file systems are case-insensitive,
+/// so paths there are compared case-folded.
+fn lock_path_key(path: &Path) -> String {
+    let normalized = normalize_lock_path(path).to_string_lossy().into_owned();
+    if cfg!(windows) {
+        normalized.to_lowercase()
+    } else {
+        normalized
+    }
+}
+
+/// Re-keys a hash map by [`lock_path_key`], keeping the normalized path alongside each hash.
+fn keyed_hashes(hashes: &BTreeMap<PathBuf, String>) -> BTreeMap<String, (PathBuf, &String)> {
+    hashes
+        .iter()
+        .map(|(path, hash)| (lock_path_key(path), (normalize_lock_path(path), hash)))
+        .collect()
+}
+
+/// Looks up the locked hash of `path`, matching paths the way [`compare_hashes`] does.
+pub fn locked_hash<'a>(locked_hashes: &'a BTreeMap<PathBuf, String>, path: &Path) -> Option<&'a String> {
+    let key = lock_path_key(path);
+    locked_hashes
+        .iter()
+        .find(|(locked_path, _)| lock_path_key(locked_path) == key)
+        .map(|(_, hash)| hash)
+}
+
+/// Sets the locked hash of `path`, or removes its entry for `None`, replacing any
+/// entry that matches it the way [`compare_hashes`] does.
+pub fn set_locked_hash(locked_hashes: &mut BTreeMap<PathBuf, String>, path: &Path, hash: Option<String>) {
+    let key = lock_path_key(path);
+    locked_hashes.retain(|locked_path, _| lock_path_key(locked_path) != key);
+    if let Some(hash) = hash {
+        locked_hashes.insert(normalize_lock_path(path), hash);
+    }
+}
+
 /// Compares current file hashes with locked hashes and identifies changes.
-/// Returns a map of changed paths to their status (Modified, Added, Removed).
+/// Returns a map of changed paths to their status (Modified, Added, Removed, Renamed).
+///
+/// An added path whose hash equals that of a removed path is reported once, as
+/// Renamed from the removed path. Paths are matched after normalization (see
+/// [`normalize_lock_path`]), and the returned map is keyed by normalized paths.
 pub fn compare_hashes(
     current_hashes: &BTreeMap<PathBuf, String>,
     locked_hashes: &BTreeMap<PathBuf, String>,
 ) -> BTreeMap<PathBuf, ChangeStatus> {
+    let current = keyed_hashes(current_hashes);
+    let locked = keyed_hashes(locked_hashes);
+
     let mut changes = BTreeMap::new();
 
     // Check files currently included
-    for (path, current_hash) in current_hashes {
-        match locked_hashes.get(path) {
-            Some(locked_hash) => {
+    for (key, (path, current_hash)) in &current {
+        match locked.get(key) {
+            Some((_, locked_hash)) => {
                 if current_hash != locked_hash {
                     changes.insert(path.clone(), ChangeStatus::Modified);
                 }
@@ -196,12 +810,28 @@ pub fn compare_hashes(
     }
 
     // Check for files that were in the lockfile but are no longer included
-    for path in locked_hashes.keys() {
-        if !current_hashes.contains_key(path) {
-            changes.insert(path.clone(), ChangeStatus::Removed);
+    let mut removed: Vec<(&PathBuf, &String)> = Vec::new();
+    for (key, (path, locked_hash)) in &locked {
+        if !current.contains_key(key) {
+            removed.push((path, locked_hash));
         }
     }
 
+    // Pair each added file with a removed file of the same content, in path order
+    for (path, status) in changes.iter_mut() {
+        if *status != ChangeStatus::Added {
+            continue;
+        }
+        let hash = current[&lock_path_key(path)].1;
+        if let Some(index) = removed.iter().position(|(_, locked_hash)| *locked_hash == hash) {
+            let (from, _) = removed.remove(index);
+            *status = ChangeStatus::Renamed { from: from.clone() };
+        }
+    }
+    for (path, _) in removed {
+        changes.insert(path.clone(), ChangeStatus::Removed);
+    }
+
     changes
 }
 
@@ -217,14 +847,44 @@ pub fn get_lockfile_path(template_path: &Path) -> PathBuf {
     template_path.with_extension(extension)
 }
 
-/// Writes a sample .clamp file to given path, othervise `problem.clamp`
-pub fn init(new: Option<PathBuf>) -> Result<ExitCode> {
-    const SAMPLE: &str = "
-TL;DR how to use this?
-
-[[include: README.md]]";
+/// Finds the root of the repository containing `start` by walking up to the
+/// nearest directory that contains a `.git` entry in `fs`.
+pub fn find_repo_root(fs: &dyn FileSystem, start: &Path) -> Option<PathBuf> {
+    let start = fs.canonicalize(start).ok()?;
+    start
+        .ancestors()
+        .find(|dir| fs.exists(&dir.join(".git")))
+        .map(Path::to_path_buf)
+}
 
+/// Writes a starter .clamp file to given path, othervise `problem.clamp`.
+/// `template` selects one of the built-in [`starters::STARTERS`] (default: a trivial sample).
+/// With `scan`, the template instead includes the key files found by [`scan::scan_project`]
+/// in the directory the template is written to.
+pub fn init(new: Option<PathBuf>, template: Option<&str>, scan: bool) -> Result<ExitCode> {
     let path = new.unwrap_or("problem.clamp".into());
-    fs::write(path, SAMPLE)?;
+
+    let content = if scan {
+        let root = match path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let files = scan::scan_project(root)?;
+        if files.is_empty() {
+            bail!("No key files found in '{}'", root.display());
+        }
+        scan::scanned_template(&files)
+    } else {
+        let starter = match template {
+            Some(name) => starters::find_starter(name).with_context(|| {
+                let names: Vec<&str> = starters::STARTERS.iter().map(|s| s.name).collect();
+                format!("Unknown starter template '{name}' (available: {})", names.join(", "))
+            })?,
+            None => &starters::DEFAULT_STARTER,
+        };
+        starter.content.to_string()
+    };
+
+    fs::write(path, content)?;
     Ok(ExitCode::SUCCESS)
 }
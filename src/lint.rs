@@ -0,0 +1,279 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ProcessOptions,
+    archive::split_member_path,
+    directive::{DirectiveKind, IncludeDirective, directive_regex},
+    find_repo_root,
+    frontmatter::split_frontmatter,
+    permalink::Permalink,
+    progress::format_bytes,
+    resolve_include, resolve_include_dir,
+};
+
+/// Size above which `large-include` flags an include, unless configured otherwise.
+pub const DEFAULT_MAX_INCLUDE_SIZE: u64 = 100 * 1024;
+
+/// Every lint, by stable ID, with a short description.
+pub const LINTS: &[(&str, &str)] = &[
+    (
+        "duplicate-include",
+        "A file is included more than once (and `dedup` is off)",
+    ),
+    (
+        "large-include",
+        "An included file is larger than the configured maximum size",
+    ),
+    (
+        "missing-optional",
+        "An optional include (`include?`) matches no files and is skipped",
+    ),
+    (
+        "outside-repo",
+        "An include resolves outside the template's repository",
+    ),
+];
+
+/// A problem found by [`lint_template`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lint {
+    /// Stable ID of the lint (see [`LINTS`]).
+    pub id: &'static str,
+    /// 1-based line of the directive the lint is about.
+    pub line: usize,
+    pub message: String,
+}
+
+/// Settings for [`lint_template`] beyond those in `clamp.toml`.
+#[derive(Debug, Clone, Default)]
+pub struct LintOptions {
+    /// Overrides the `large-include` threshold from the `[lint]` configuration.
+    pub max_include_size: Option<u64>,
+}
+
+/// Checks the template at `template_path` for problems that are not errors.
+///
+/// A lint is suppressed for a single directive with the `allow=<id>,...` modifier,
+/// or for the whole project with `allow = [...]` in the `[lint]` table of `clamp.toml`.
+/// Required includes that cannot be resolved are left to the build to report.
+pub fn lint_template(
+    template_path: &Path,
+    options: &ProcessOptions,
+    lint_options: &LintOptions,
+) -> Result<Vec<Lint>> {
+    let fs = options.fs();
+    let content = fs
+        .read_to_string(template_path)
+        .with_context(|| format!("Failed to read template file '{}'", template_path.display()))?;
+    let (frontmatter, _) = split_frontmatter(&content)
+        .with_context(|| format!("Invalid frontmatter in template '{}'", template_path.display()))?;
+    let base_dir = match template_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+
+    let config = &options.config.lint;
+    for id in &config.allow {
+        validate_id(id).context("Invalid [lint] allow list in clamp.toml")?;
+    }
+    let max_size = lint_options
+        .max_include_size
+        .or(config.max_include_size)
+        .unwrap_or(DEFAULT_MAX_INCLUDE_SIZE);
+    let dedup = options.dedup.or(frontmatter.dedup).unwrap_or(false);
+    let repo_root = find_repo_root(fs, base_dir);
+
+    let mut lints = Vec::new();
+    // Canonical path -> line of its first include
+    let mut first_seen: BTreeMap<PathBuf, usize> = BTreeMap::new();
+
+    for cap in directive_regex().captures_iter(&content) {
+        let full_match = cap.get(0).expect("capture 0 is the whole match");
+        let Some(kind) = DirectiveKind::from_name(&cap[1]).filter(|kind| kind.takes_path()) else {
+            continue;
+        };
+        let line = content[..full_match.start()].matches('\n').count() + 1;
+        let Ok(directive) = IncludeDirective::parse(&cap[2]) else {
+            continue; // Reported by the build
+        };
+        for id in &directive.modifiers.allow {
+            validate_id(id).with_context(|| format!("Invalid allow modifier on line {line}"))?;
+        }
+        if kind.is_include()
+            && (Permalink::parse(&directive.path).is_some() || split_member_path(&directive.path).is_some())
+        {
+            continue; // Remote files and files inside archives are not checked
+        }
+        let allowed = |id: &str| {
+            config.allow.iter().chain(&directive.modifiers.allow).any(|allowed| allowed == id)
+        };
+        let files = if kind == DirectiveKind::IncludeDir {
+            resolve_include_dir(fs, base_dir, &directive, !options.no_ignore)
+                .map(|files| files.iter().map(|file| base_dir.join(file)).collect())
+        } else {
+            resolve_include(fs, base_dir, &options.config.include_roots, &directive, !options.no_ignore)
+        };
+        let Ok(files) = files else {
+            // Required includes are reported by the build; optional ones would pass silently
+            if kind == DirectiveKind::OptionalInclude && !allowed("missing-optional") {
+                lints.push(Lint {
+                    id: "missing-optional",
+                    line,
+                    message: format!(
+                        "'{}' matches no files and is skipped",
+                        directive.alternatives_text()
+                    ),
+                });
+            }
+            continue;
+        };
+
+        for file in files {
+            let Ok(canonical) = fs.canonicalize(&file) else {
+                continue;
+            };
+            let display = file.strip_prefix(base_dir).unwrap_or(&file).display().to_string();
+
+            if !dedup
+                && !allowed("duplicate-include")
+                && let Some(first_line) = first_seen.get(&canonical)
+            {
+                lints.push(Lint {
+                    id: "duplicate-include",
+                    line,
+                    message: format!("'{display}' is already included on line {first_line}"),
+                });
+            }
+            first_seen.entry(canonical.clone()).or_insert(line);
+
+            if !allowed("large-include")
+                && let Ok(metadata) = fs.metadata(&canonical)
+                && metadata.len > max_size
+            {
+                lints.push(Lint {
+                    id: "large-include",
+                    line,
+                    message: format!(
+                        "'{display}' is {} (limit {})",
+                        format_bytes(metadata.len as usize),
+                        format_bytes(max_size as usize)
+                    ),
+                });
+            }
+
+            if !allowed("outside-repo")
+                && let Some(root) = &repo_root
+                && !canonical.starts_with(root)
+            {
+                lints.push(Lint {
+                    id: "outside-repo",
+                    line,
+                    message: format!(
+                        "'{display}' resolves to '{}', outside the repository '{}'",
+                        canonical.display(),
+                        root.display()
+                    ),
+                });
+            }
+        }
+    }
+    Ok(lints)
+}
+
+fn validate_id(id: &str) -> Result<()> {
+    if !LINTS.iter().any(|(known, _)| *known == id) {
+        let known: Vec<&str> = LINTS.iter().map(|(known, _)| *known).collect();
+        bail!("Unknown lint '{id}' (known lints: {})", known.join(", "));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{config::LintConfig, vfs::MemoryFileSystem};
+
+    /// Lints `template` as `/repo/review.clamp` in a repository holding `src/lib.rs`
+    /// (12 bytes), `src/big.rs` (2 KiB) and, outside it, `/shared/notes.md`.
+    fn lint(template: &str, config: LintConfig, lint_options: &LintOptions) -> Result<Vec<Lint>> {
+        let mut files = MemoryFileSystem::new();
+        files.insert("/repo/.git/HEAD", "ref: refs/heads/main\n");
+        files.insert("/repo/review.clamp", template);
+        files.insert("/repo/src/lib.rs", "pub fn f() {}");
+        files.insert("/repo/src/big.rs", vec![b'x'; 2048]);
+        files.insert("/shared/notes.md", "Notes\n");
+        let mut options = ProcessOptions {
+            file_system: Some(Arc::new(files)),
+            ..Default::default()
+        };
+        options.config.lint = config;
+        lint_template(Path::new("/repo/review.clamp"), &options, lint_options)
+    }
+
+    fn ids(lints: &[Lint]) -> Vec<(&'static str, usize)> {
+        lints.iter().map(|lint| (lint.id, lint.line)).collect()
+    }
+
+    #[test]
+    fn reports_each_lint_on_its_line() {
+        let template = "[[include: src/lib.rs]]\n\
+                        [[include: src/lib.rs]]\n\
+                        [[include?: src/missing.rs]]\n\
+                        [[include: ../shared/notes.md]]\n";
+        let lints = lint(template, LintConfig::default(), &LintOptions { max_include_size: Some(8) }).unwrap();
+        assert_eq!(
+            ids(&lints),
+            [
+                ("large-include", 1),
+                ("duplicate-include", 2),
+                ("large-include", 2),
+                ("missing-optional", 3),
+                ("outside-repo", 4)
+            ]
+        );
+        assert_eq!(lints[1].message, "'src/lib.rs' is already included on line 1");
+        assert_eq!(lints[3].message, "'src/missing.rs' matches no files and is skipped");
+        assert!(lints[4].message.contains("outside the repository '/repo'"), "{}", lints[4].message);
+    }
+
+    #[test]
+    fn size_limit_prefers_the_command_line() {
+        let template = "[[include: src/big.rs]]\n";
+        assert_eq!(ids(&lint(template, LintConfig::default(), &LintOptions::default()).unwrap()), []);
+
+        let config = LintConfig { max_include_size: Some(1024), ..Default::default() };
+        let lints = lint(template, config.clone(), &LintOptions::default()).unwrap();
+        assert_eq!(lints[0].message, "'src/big.rs' is 2.0 KiB (limit 1.0 KiB)");
+        assert_eq!(ids(&lint(template, config, &LintOptions { max_include_size: Some(4096) }).unwrap()), []);
+    }
+
+    #[test]
+    fn allow_suppresses_lints() {
+        let template = "[[include: src/lib.rs]]\n\
+                        [[include: src/lib.rs allow=duplicate-include]]\n\
+                        [[include?: src/missing.rs]]\n";
+        assert_eq!(ids(&lint(template, LintConfig::default(), &LintOptions::default()).unwrap()), [("missing-optional", 3)]);
+
+        let config = LintConfig { allow: vec!["missing-optional".into()], ..Default::default() };
+        assert_eq!(ids(&lint(template, config, &LintOptions::default()).unwrap()), []);
+
+        let deduplicated = "+++\ndedup = true\n+++\n[[include: src/lib.rs]]\n[[include: src/lib.rs]]\n";
+        assert_eq!(ids(&lint(deduplicated, LintConfig::default(), &LintOptions::default()).unwrap()), []);
+    }
+
+    #[test]
+    fn rejects_unknown_lint_ids() {
+        let error = lint("[[include: src/lib.rs allow=typo]]\n", LintConfig::default(), &LintOptions::default()).unwrap_err();
+        assert_eq!(format!("{error:#}"), "Invalid allow modifier on line 1: Unknown lint 'typo' (known lints: duplicate-include, large-include, missing-optional, outside-repo)");
+
+        let config = LintConfig { allow: vec!["typo".into()], ..Default::default() };
+        let error = lint("", config, &LintOptions::default()).unwrap_err();
+        assert!(format!("{error:#}").starts_with("Invalid [lint] allow list in clamp.toml"), "{error:#}");
+    }
+}
@@ -0,0 +1,196 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    ChangeStatus, LockPolicy, LockfileData, ProcessResult, compare_hashes, get_lockfile_path,
+    lock_path_key, locked_hash, normalize_lock_path, parse_lockfile, read_lockfile,
+    serialize_lockfile, set_locked_hash, vfs::FileSystem, write_lockfile,
+};
+
+/// A lockfile and its entries, loaded into memory.
+///
+/// Wraps [`read_lockfile`], [`write_lockfile`] and [`compare_hashes`] so consumers
+/// can check and update a template's lock without re-implementing the steps:
+/// [`load`](Self::load) it, [`diff`](Self::diff) a [`ProcessResult`] against it,
+/// apply [`update_from`](Self::update_from) or [`update_entry`](Self::update_entry),
+/// then [`save`](Self::save) it.
+///
+/// Entries are matched the way [`compare_hashes`] matches them, so lookups and
+/// updates work with the canonical paths in a [`ProcessResult`].
+#[derive(Debug)]
+pub struct Lockfile {
+    path: PathBuf,
+    data: LockfileData,
+}
+
+impl Lockfile {
+    /// An empty lockfile to be saved at `path`.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            data: LockfileData::default(),
+        }
+    }
+
+    /// Reads the lockfile at `path` in `fs`. A missing file loads as empty (with a
+    /// warning on stderr, like [`read_lockfile`]).
+    pub fn load(fs: &dyn FileSystem, path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let data = read_lockfile(fs, &path)?;
+        Ok(Self { path, data })
+    }
+
+    /// Parses lockfile `content` (TOML) that was read from `path` by other means,
+    /// e.g. sent by a browser. Nothing is read from the file system.
+    pub fn parse(path: impl Into<PathBuf>, content: &str) -> Result<Self> {
+        let path = path.into();
+        let data = parse_lockfile(content)
+            .with_context(|| format!("Failed to parse TOML from lockfile '{}'", path.display()))?;
+        Ok(Self { path, data })
+    }
+
+    /// Reads the lockfile next to `template_path` (see [`get_lockfile_path`]).
+    pub fn for_template(fs: &dyn FileSystem, template_path: &Path) -> Result<Self> {
+        Self::load(fs, get_lockfile_path(template_path))
+    }
+
+    /// Where the lockfile is read from and saved to.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Whether the lockfile exists in `fs`.
+    pub fn exists(&self, fs: &dyn FileSystem) -> bool {
+        fs.exists(&self.path)
+    }
+
+    /// The locked entries: canonical path -> SHA256 hash (hex).
+    pub fn entries(&self) -> &BTreeMap<PathBuf, String> {
+        &self.data.files
+    }
+
+    /// The locked hash of `path`, if it has an entry.
+    pub fn hash(&self, path: &Path) -> Option<&String> {
+        locked_hash(&self.data.files, path)
+    }
+
+    /// Whether the entry of `path` is locked with [`LockPolicy::Pin`].
+    pub fn is_pinned(&self, path: &Path) -> bool {
+        let key = lock_path_key(path);
+        self.data.pinned.iter().any(|pinned| lock_path_key(pinned) == key)
+    }
+
+    /// Sets the [`LockPolicy`] of the entry of `path`.
+    pub fn set_policy(&mut self, path: &Path, policy: LockPolicy) {
+        let key = lock_path_key(path);
+        self.data.pinned.retain(|pinned| lock_path_key(pinned) != key);
+        if policy == LockPolicy::Pin {
+            self.data.pinned.insert(normalize_lock_path(path));
+        }
+    }
+
+    /// The changes of a processed template's includes relative to the locked entries.
+    pub fn diff(&self, result: &ProcessResult) -> BTreeMap<PathBuf, ChangeStatus> {
+        self.diff_hashes(&result.current_hashes)
+    }
+
+    /// The changes of `hashes` (path -> hash) relative to the locked entries, e.g. of
+    /// the hashes returned by [`tracked_hashes`](crate::tracked_hashes).
+    pub fn diff_hashes(&self, hashes: &BTreeMap<PathBuf, String>) -> BTreeMap<PathBuf, ChangeStatus> {
+        compare_hashes(hashes, &self.data.files)
+    }
+
+    /// The modified entries among `changes` that are pinned, in the lockfile or by
+    /// `pinned` (e.g. [`ProcessResult::pinned`]): the changes that fail a check until
+    /// the entry is re-locked.
+    pub fn pinned_changes<'a>(
+        &self,
+        changes: &'a BTreeMap<PathBuf, ChangeStatus>,
+        pinned: &BTreeSet<PathBuf>,
+    ) -> Vec<&'a Path> {
+        changes
+            .iter()
+            .filter(|(path, status)| {
+                **status == ChangeStatus::Modified
+                    && (self.is_pinned(path)
+                        || pinned.iter().any(|pinned| lock_path_key(pinned) == lock_path_key(path)))
+            })
+            .map(|(path, _)| path.as_path())
+            .collect()
+    }
+
+    /// Sets the locked hash of `path`, or removes its entry (and policy) for `None`.
+    pub fn update_entry(&mut self, path: &Path, hash: Option<String>) {
+        if hash.is_none() {
+            self.set_policy(path, LockPolicy::Track);
+        }
+        set_locked_hash(&mut self.data.files, path, hash);
+    }
+
+    /// Replaces every entry with the includes of a processed template, locked with
+    /// the policies they were rendered with.
+    pub fn update_from(&mut self, result: &ProcessResult) {
+        self.set_entries(result.current_hashes.clone());
+        self.set_pinned(result.pinned.clone());
+    }
+
+    /// Replaces every entry with `hashes` (path -> hash).
+    pub fn set_entries(&mut self, hashes: BTreeMap<PathBuf, String>) {
+        self.data.files = hashes;
+    }
+
+    /// Replaces the policies: the entries of `paths` are pinned, all others tracked.
+    pub fn set_pinned(&mut self, paths: BTreeSet<PathBuf>) {
+        self.data.pinned = paths;
+    }
+
+    /// The lockfile's TOML, as [`save`](Self::save) writes it.
+    pub fn to_toml(&self) -> Result<String> {
+        serialize_lockfile(&self.data)
+    }
+
+    /// Writes the entries to [`path`](Self::path) in `fs`.
+    pub fn save(&self, fs: &dyn FileSystem) -> Result<()> {
+        write_lockfile(fs, &self.path, &self.data)
+    }
+
+    /// The raw lockfile contents.
+    pub fn into_data(self) -> LockfileData {
+        self.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::Config, find_repo_root, vfs::MemoryFileSystem};
+
+    #[test]
+    fn lockfiles_round_trip_through_the_file_system() {
+        let fs = MemoryFileSystem::new();
+        let mut lockfile = Lockfile::for_template(&fs, Path::new("/project/prompt.clamp")).unwrap();
+        assert!(!lockfile.exists(&fs));
+        lockfile.update_entry(Path::new("/project/notes.txt"), Some("abc".to_string()));
+        lockfile.save(&fs).unwrap();
+
+        assert!(fs.exists(Path::new("/project/prompt.clamp.lock")));
+        let loaded = Lockfile::load(&fs, "/project/prompt.clamp.lock").unwrap();
+        assert_eq!(loaded.hash(Path::new("/project/notes.txt")).map(String::as_str), Some("abc"));
+    }
+
+    #[test]
+    fn config_and_repo_root_are_found_in_the_file_system() {
+        let mut fs = MemoryFileSystem::new();
+        fs.insert("/repo/.git/HEAD", "ref: refs/heads/main\n");
+        fs.insert("/repo/clamp.toml", "pin = [\"vendor/\"]\n");
+        fs.insert("/repo/src/vendor/lib.rs", "");
+
+        assert_eq!(find_repo_root(&fs, Path::new("/repo/src")), Some(PathBuf::from("/repo")));
+        let config = Config::discover(&fs, Path::new("/repo/src")).unwrap();
+        assert!(config.is_pinned(&fs, Path::new("/repo/src/vendor/lib.rs")));
+        assert!(!config.is_pinned(&fs, Path::new("/repo/clamp.toml")));
+    }
+}
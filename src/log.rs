@@ -0,0 +1,145 @@
+//! Leveled log messages with a pluggable [`Logger`], without depending on the `log`
+//! or `tracing` crates. Records carry a level, target and message only; there are
+//! no spans.
+
+use std::{
+    fmt,
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU8, Ordering},
+    },
+};
+
+/// Severity of a log [`Record`], from most to least severe.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error = 1,
+    Warn,
+    /// Status messages such as "Lockfile updated"
+    Info,
+    Debug,
+    Trace,
+}
+
+impl Level {
+    /// The name used in structured output, e.g. `warn`.
+    pub fn name(self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+            Level::Trace => "trace",
+        }
+    }
+
+    /// The prefix of a message printed for people, e.g. `Warning`.
+    pub fn label(self) -> &'static str {
+        match self {
+            Level::Error => "Error",
+            Level::Warn => "Warning",
+            Level::Info => "Status",
+            Level::Debug => "Debug",
+            Level::Trace => "Trace",
+        }
+    }
+
+    fn from_u8(value: u8) -> Option<Self> {
+        [Level::Error, Level::Warn, Level::Info, Level::Debug, Level::Trace]
+            .into_iter()
+            .find(|level| *level as u8 == value)
+    }
+}
+
+/// A message logged by clamp.
+#[derive(Debug, Clone, Copy)]
+pub struct Record<'a> {
+    pub level: Level,
+    /// The module the message comes from, e.g. `clamp_lib::render`.
+    pub target: &'a str,
+    pub message: fmt::Arguments<'a>,
+}
+
+/// Receives the messages clamp logs at or above the [maximum level](set_max_level).
+pub trait Logger: Send + Sync {
+    fn log(&self, record: &Record);
+}
+
+/// Prints each message to stderr as `<label>: <message>`, e.g.
+/// `Warning: Lockfile 'x.lock' not found. ...`. The logger used until
+/// [`set_logger`] installs another one.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct StderrLogger;
+
+impl Logger for StderrLogger {
+    fn log(&self, record: &Record) {
+        eprintln!("{}: {}", record.level.label(), record.message);
+    }
+}
+
+/// 0 while logging is off, else the most verbose enabled level.
+static MAX_LEVEL: AtomicU8 = AtomicU8::new(Level::Info as u8);
+
+static LOGGER: RwLock<Option<Arc<dyn Logger>>> = RwLock::new(None);
+
+/// Sends every subsequent message to `logger`, e.g. to capture warnings instead of
+/// printing them.
+pub fn set_logger(logger: impl Logger + 'static) {
+    *LOGGER.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(Arc::new(logger));
+}
+
+/// Discards messages less severe than `level`, or all messages for `None`.
+/// Defaults to [`Level::Info`]: errors, warnings and status messages.
+pub fn set_max_level(level: Option<Level>) {
+    MAX_LEVEL.store(level.map_or(0, |level| level as u8), Ordering::Relaxed);
+}
+
+/// The most verbose level that is logged, or `None` if logging is off.
+pub fn max_level() -> Option<Level> {
+    Level::from_u8(MAX_LEVEL.load(Ordering::Relaxed))
+}
+
+/// Whether messages at `level` are logged.
+pub fn enabled(level: Level) -> bool {
+    level as u8 <= MAX_LEVEL.load(Ordering::Relaxed)
+}
+
+/// Passes a message to the installed logger if its level is enabled. Usually called
+/// through the crate's logging macros.
+pub fn log(level: Level, target: &str, message: fmt::Arguments) {
+    if !enabled(level) {
+        return;
+    }
+    let record = Record { level, target, message };
+    let logger = LOGGER.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    match logger {
+        Some(logger) => logger.log(&record),
+        None => StderrLogger.log(&record),
+    }
+}
+
+macro_rules! warning {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Warn, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! info {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Info, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! debug {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Debug, module_path!(), format_args!($($arg)*))
+    };
+}
+
+macro_rules! trace {
+    ($($arg:tt)*) => {
+        $crate::log::log($crate::log::Level::Trace, module_path!(), format_args!($($arg)*))
+    };
+}
+
+pub(crate) use {debug, info, trace, warning};
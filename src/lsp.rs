@@ -0,0 +1,686 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    io::{BufRead, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    Lockfile, ProcessOptions, calculate_hash,
+    config::Config,
+    directive::{DirectiveKind, IncludeDirective, directive_regex},
+    filter,
+    frontmatter::split_frontmatter,
+    get_lockfile_path,
+    http::percent_decode,
+    json::Json,
+    log,
+    mcp::{error_response, success_response},
+    normalize_lock_path,
+    permalink::Permalink,
+    resolve_include,
+    vfs::{FileKind, FileSystem},
+};
+
+// LSP constants
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const SEVERITY_ERROR: f64 = 1.0;
+const COMPLETION_KIND_FILE: f64 = 17.0;
+const COMPLETION_KIND_FOLDER: f64 = 19.0;
+const TEXT_DOCUMENT_SYNC_FULL: f64 = 1.0;
+
+/// A directive found in an open document, with its location.
+struct DirectiveSpan {
+    kind: DirectiveKind,
+    /// Byte range of the whole `[[...]]` in the document.
+    start: usize,
+    end: usize,
+    /// The text after the colon.
+    inner: String,
+}
+
+/// Language server for `.clamp` templates.
+struct Server<'a> {
+    options: &'a ProcessOptions,
+    /// Open documents: URI -> current text.
+    documents: BTreeMap<String, String>,
+}
+
+/// Runs a Language Server Protocol server for `.clamp` files, reading
+/// `Content-Length`-framed JSON-RPC messages from `input` until `exit` or EOF.
+///
+/// Offers diagnostics for unresolvable includes, path completion inside
+/// `[[include: ...]]`, go-to-definition onto included files, and hovers with the
+/// included file's size, hash and lockfile status.
+pub fn serve(options: &ProcessOptions, mut input: impl BufRead, mut output: impl Write) -> Result<()> {
+    let mut server = Server {
+        options,
+        documents: BTreeMap::new(),
+    };
+    while let Some(body) = read_message(&mut input)? {
+        let message = match Json::parse(&body) {
+            Ok(message) => message,
+            Err(e) => {
+                log::warning!("Ignoring malformed LSP message: {e}");
+                continue;
+            }
+        };
+        let method = message.get("method").and_then(Json::as_str).unwrap_or_default();
+        if method == "exit" {
+            break;
+        }
+        let params = message.get("params").cloned().unwrap_or(Json::Null);
+
+        let mut outgoing = Vec::new();
+        match message.get("id").cloned() {
+            Some(id) => outgoing.push(match server.request(method, &params) {
+                Some(result) => success_response(id, result),
+                None => error_response(id, METHOD_NOT_FOUND, &format!("Unknown method '{method}'")),
+            }),
+            None => outgoing.extend(server.notification(method, &params)),
+        }
+        for message in outgoing {
+            write_message(&mut output, &message)?;
+        }
+    }
+    Ok(())
+}
+
+impl Server<'_> {
+    /// Answers a request; `None` if the method is not supported.
+    fn request(&mut self, method: &str, params: &Json) -> Option<Json> {
+        match method {
+            "initialize" => Some(Json::object([
+                (
+                    "capabilities",
+                    Json::object([
+                        ("textDocumentSync", Json::from(TEXT_DOCUMENT_SYNC_FULL)),
+                        (
+                            "completionProvider",
+                            Json::object([("triggerCharacters", Json::from(vec![":", "/", " "]))]),
+                        ),
+                        ("definitionProvider", Json::from(true)),
+                        ("hoverProvider", Json::from(true)),
+                    ]),
+                ),
+                (
+                    "serverInfo",
+                    Json::object([
+                        ("name", Json::from("clamp")),
+                        ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+                    ]),
+                ),
+            ])),
+            "shutdown" => Some(Json::Null),
+            "textDocument/completion" => Some(self.with_position(params, Self::completion)),
+            "textDocument/definition" => Some(self.with_position(params, Self::definition)),
+            "textDocument/hover" => Some(self.with_position(params, Self::hover)),
+            _ => None,
+        }
+    }
+
+    /// Handles a notification, returning the notifications to send back.
+    fn notification(&mut self, method: &str, params: &Json) -> Vec<Json> {
+        let Some(uri) = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str)
+            .map(str::to_string)
+        else {
+            return Vec::new();
+        };
+
+        match method {
+            "textDocument/didOpen" => {
+                let text = params
+                    .get("textDocument")
+                    .and_then(|document| document.get("text"))
+                    .and_then(Json::as_str)
+                    .unwrap_or_default();
+                self.documents.insert(uri.clone(), text.to_string());
+            }
+            "textDocument/didChange" => {
+                // Full sync: the last change holds the whole document
+                let text = match params.get("contentChanges") {
+                    Some(Json::Array(changes)) => changes
+                        .last()
+                        .and_then(|change| change.get("text"))
+                        .and_then(Json::as_str),
+                    _ => None,
+                };
+                if let Some(text) = text {
+                    self.documents.insert(uri.clone(), text.to_string());
+                }
+            }
+            "textDocument/didSave" => {} // Included files may have changed; re-check below
+            "textDocument/didClose" => {
+                self.documents.remove(&uri);
+                return vec![publish_diagnostics(&uri, Vec::new())];
+            }
+            _ => return Vec::new(),
+        }
+
+        let diagnostics = self.diagnostics(&uri);
+        vec![publish_diagnostics(&uri, diagnostics)]
+    }
+
+    /// Runs `handler` with the document text and cursor byte offset from `params`,
+    /// answering `null` if the document is unknown.
+    fn with_position(
+        &self,
+        params: &Json,
+        handler: fn(&Self, &Path, &str, usize) -> Json,
+    ) -> Json {
+        let uri = params
+            .get("textDocument")
+            .and_then(|document| document.get("uri"))
+            .and_then(Json::as_str);
+        let (Some(uri), Some(position)) = (uri, params.get("position")) else {
+            return Json::Null;
+        };
+        let (Some(text), Some(template_path)) = (self.documents.get(uri), uri_to_path(uri)) else {
+            return Json::Null;
+        };
+        let line = position.get("line").and_then(Json::as_f64).unwrap_or(0.0) as usize;
+        let character = position.get("character").and_then(Json::as_f64).unwrap_or(0.0) as usize;
+        handler(self, &template_path, text, offset_at(text, line, character))
+    }
+
+    /// Diagnostics for the document at `uri`: invalid frontmatter and directives,
+    /// and includes that do not resolve to a file.
+    fn diagnostics(&self, uri: &str) -> Vec<Json> {
+        let Some(text) = self.documents.get(uri) else {
+            return Vec::new();
+        };
+        let mut diagnostics = Vec::new();
+        if let Err(e) = split_frontmatter(text) {
+            diagnostics.push(diagnostic(text, 0, 0, &format!("{e:#}")));
+        }
+        let Some(base_dir) = uri_to_path(uri).and_then(|path| path.parent().map(Path::to_path_buf))
+        else {
+            return diagnostics; // Unsaved documents have no directory to resolve against
+        };
+
+        for span in find_directives(text) {
+            let directive = match IncludeDirective::parse(&span.inner) {
+                Ok(directive) => directive,
+                Err(e) => {
+                    diagnostics.push(diagnostic(text, span.start, span.end, &format!("{e:#}")));
+                    continue;
+                }
+            };
+            // Links are only checked for their form: resolving them means downloading
+            if span.kind.is_include()
+                && let Some(link) = Permalink::parse(&directive.path)
+            {
+                if let Err(e) = link {
+                    diagnostics.push(diagnostic(text, span.start, span.end, &format!("{e:#}")));
+                }
+                continue;
+            }
+            // Optional includes may legitimately be missing
+            if span.kind != DirectiveKind::Include {
+                continue;
+            }
+            if let Err(e) = self.resolve_include(&base_dir, &directive) {
+                diagnostics.push(diagnostic(text, span.start, span.end, &format!("{e:#}")));
+            }
+        }
+        diagnostics
+    }
+
+    /// Resolves an include to the files it embeds (several for a glob).
+    fn resolve_include(&self, base_dir: &Path, directive: &IncludeDirective) -> Result<Vec<PathBuf>> {
+        resolve_include(
+            self.options.fs(),
+            base_dir,
+            &self.options.config.include_roots,
+            directive,
+            !self.options.no_ignore,
+        )
+    }
+
+    /// The include under the cursor, resolved to its files.
+    fn include_at(&self, template_path: &Path, text: &str, offset: usize) -> Option<Vec<PathBuf>> {
+        let span = find_directives(text)
+            .into_iter()
+            .find(|span| span.kind.is_include() && (span.start..span.end).contains(&offset))?;
+        let directive = IncludeDirective::parse(&span.inner).ok()?;
+        self.resolve_include(template_path.parent()?, &directive).ok()
+    }
+
+    fn definition(&self, template_path: &Path, text: &str, offset: usize) -> Json {
+        let Some(files) = self.include_at(template_path, text, offset) else {
+            return Json::Null;
+        };
+        let start = Json::object([("line", Json::from(0.0)), ("character", Json::from(0.0))]);
+        Json::Array(
+            files
+                .iter()
+                .map(|file| {
+                    Json::object([
+                        ("uri", Json::from(path_to_uri(file))),
+                        (
+                            "range",
+                            Json::object([("start", start.clone()), ("end", start.clone())]),
+                        ),
+                    ])
+                })
+                .collect(),
+        )
+    }
+
+    fn hover(&self, template_path: &Path, text: &str, offset: usize) -> Json {
+        let Some(files) = self.include_at(template_path, text, offset) else {
+            return Json::Null;
+        };
+        let value = match &files[..] {
+            [file] => describe_file(self.options.fs(), &self.options.config, template_path, file),
+            files => format!("Matches {} files", files.len()),
+        };
+        Json::object([(
+            "contents",
+            Json::object([("kind", Json::from("markdown")), ("value", Json::from(value))]),
+        )])
+    }
+
+    /// Completes the path being typed in an `[[include: ...]]` or `[[include?: ...]]`
+    /// directive with the entries of the directory typed so far.
+    fn completion(&self, template_path: &Path, text: &str, offset: usize) -> Json {
+        let line_start = text[..offset].rfind('\n').map_or(0, |i| i + 1);
+        let before_cursor = &text[line_start..offset];
+        // The nearest `[[include:` or `[[include?:` before the cursor
+        let Some(typed_start) = DirectiveKind::ALL
+            .iter()
+            .filter(|kind| kind.is_include())
+            .filter_map(|kind| {
+                let opener = format!("[[{}:", kind.name());
+                before_cursor.rfind(&opener).map(|start| start + opener.len())
+            })
+            .max()
+        else {
+            return Json::Null;
+        };
+        if before_cursor[typed_start..].contains("]]") {
+            return Json::Null; // The cursor is past the directive
+        }
+        let typed = before_cursor[typed_start..].trim_start();
+        if filter::pipeline_start(typed).is_some() {
+            return Json::Null; // The cursor is in the filters
+        }
+        let (dir, prefix) = match typed.rfind('/') {
+            Some(slash) => (&typed[..=slash], &typed[slash + 1..]),
+            None => ("", typed),
+        };
+
+        let Some(base_dir) = template_path.parent() else {
+            return Json::Null;
+        };
+        let fs = self.options.fs();
+        let Ok(entries) = fs.read_dir(&base_dir.join(dir)) else {
+            return Json::Null;
+        };
+        let mut names: Vec<(String, bool)> = entries
+            .into_iter()
+            .filter_map(|entry| {
+                let is_dir = match entry.kind {
+                    FileKind::Symlink => fs.is_dir(&entry.path),
+                    kind => kind == FileKind::Dir,
+                };
+                Some((entry.path.file_name()?.to_string_lossy().into_owned(), is_dir))
+            })
+            .filter(|(name, _)| name.starts_with(prefix))
+            .filter(|(name, _)| !name.starts_with('.') || prefix.starts_with('.'))
+            .collect();
+        names.sort();
+
+        // Replace the partially typed file name, keeping the directory part
+        let prefix_start = offset - prefix.len();
+        let range = Json::object([
+            ("start", position_json(text, prefix_start)),
+            ("end", position_json(text, offset)),
+        ]);
+        Json::Array(
+            names
+                .into_iter()
+                .map(|(name, is_dir)| {
+                    let (new_text, kind) = if is_dir {
+                        (format!("{name}/"), COMPLETION_KIND_FOLDER)
+                    } else {
+                        (name.clone(), COMPLETION_KIND_FILE)
+                    };
+                    Json::object([
+                        ("label", Json::from(new_text.clone())),
+                        ("kind", Json::from(kind)),
+                        (
+                            "textEdit",
+                            Json::object([("range", range.clone()), ("newText", Json::from(new_text))]),
+                        ),
+                    ])
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Hover text for an included file: size, hash and status against the template's lockfile.
+fn describe_file(fs: &dyn FileSystem, config: &Config, template_path: &Path, file: &Path) -> String {
+    let Ok(content) = fs.read(file) else {
+        return format!("Cannot read `{}`", file.display());
+    };
+    let hash = calculate_hash(&config.hashed_content(&content));
+
+    let lockfile_path = get_lockfile_path(template_path);
+    let status = if !fs.exists(&lockfile_path) {
+        "not locked (no lockfile)"
+    } else {
+        match (Lockfile::load(fs, &lockfile_path), fs.canonicalize(file)) {
+            (Ok(lockfile), Ok(canonical)) => {
+                let current = BTreeMap::from([(canonical.clone(), hash.clone())]);
+                let changes = lockfile.diff_hashes(&current);
+                match changes.get(&normalize_lock_path(&canonical)) {
+                    Some(status) => status.name(),
+                    None => "unchanged",
+                }
+            }
+            _ => "unknown (unreadable lockfile)",
+        }
+    };
+    format!(
+        "**{}**\n\n{} bytes · sha256 `{}`\n\nLockfile: {status}",
+        file.display(),
+        content.len(),
+        &hash[..12]
+    )
+}
+
+fn find_directives(text: &str) -> Vec<DirectiveSpan> {
+    directive_regex()
+        .captures_iter(text)
+        .map(|cap| {
+            let full = cap.get(0).expect("capture 0 is the whole match");
+            DirectiveSpan {
+                kind: DirectiveKind::from_name(&cap[1]).expect("regex only matches known kinds"),
+                start: full.start(),
+                end: full.end(),
+                inner: cap[2].to_string(),
+            }
+        })
+        .collect()
+}
+
+fn diagnostic(text: &str, start: usize, end: usize, message: &str) -> Json {
+    Json::object([
+        (
+            "range",
+            Json::object([
+                ("start", position_json(text, start)),
+                ("end", position_json(text, end)),
+            ]),
+        ),
+        ("severity", Json::from(SEVERITY_ERROR)),
+        ("source", Json::from("clamp")),
+        ("message", Json::from(message)),
+    ])
+}
+
+fn publish_diagnostics(uri: &str, diagnostics: Vec<Json>) -> Json {
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("method", Json::from("textDocument/publishDiagnostics")),
+        (
+            "params",
+            Json::object([("uri", Json::from(uri)), ("diagnostics", Json::Array(diagnostics))]),
+        ),
+    ])
+}
+
+/// The LSP position (line, UTF-16 column) of byte `offset` in `text`.
+fn position_json(text: &str, offset: usize) -> Json {
+    let before = &text[..offset];
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line = before.matches('\n').count();
+    let character: usize = before[line_start..].chars().map(char::len_utf16).sum();
+    Json::object([
+        ("line", Json::from(line)),
+        ("character", Json::from(character)),
+    ])
+}
+
+/// The byte offset of the LSP position (`line`, UTF-16 `character`), clamped to the text.
+fn offset_at(text: &str, line: usize, character: usize) -> usize {
+    let Some(line_start) = (0..line).try_fold(0, |start, _| {
+        text[start..].find('\n').map(|i| start + i + 1)
+    }) else {
+        return text.len();
+    };
+    let mut units = 0;
+    for (index, c) in text[line_start..].char_indices() {
+        if units >= character || c == '\n' {
+            return line_start + index;
+        }
+        units += c.len_utf16();
+    }
+    text.len()
+}
+
+/// Converts a `file://` URI to a path; `None` for other schemes.
+fn uri_to_path(uri: &str) -> Option<PathBuf> {
+    let path = percent_decode(uri.strip_prefix("file://")?)?;
+    // `file:///C:/dir` names `C:/dir` on Windows
+    if cfg!(windows) && path.as_bytes().get(2) == Some(&b':') {
+        return Some(PathBuf::from(&path[1..]));
+    }
+    Some(PathBuf::from(path))
+}
+
+fn path_to_uri(path: &Path) -> String {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let mut uri = String::from(if path.starts_with('/') { "file://" } else { "file:///" });
+    for byte in path.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'/' | b'-' | b'_' | b'.' | b'~' | b':' => {
+                uri.push(byte as char)
+            }
+            _ => uri.push_str(&format!("%{byte:02X}")),
+        }
+    }
+    uri
+}
+
+/// Reads one `Content-Length`-framed message body; `None` at end of input.
+fn read_message(input: &mut impl BufRead) -> Result<Option<String>> {
+    let mut content_length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).context("Failed to read LSP message")? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.eq_ignore_ascii_case("content-length")
+        {
+            content_length = Some(value.trim().parse::<usize>().with_context(|| {
+                format!("Invalid Content-Length header '{}'", value.trim())
+            })?);
+        }
+    }
+
+    let content_length = content_length.context("LSP message without Content-Length header")?;
+    let mut body = vec![0; content_length];
+    input
+        .read_exact(&mut body)
+        .context("Failed to read LSP message body")?;
+    String::from_utf8(body)
+        .map(Some)
+        .context("LSP message is not valid UTF-8")
+}
+
+fn write_message(output: &mut impl Write, message: &Json) -> Result<()> {
+    let body = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n{body}", body.len())
+        .and_then(|()| output.flush())
+        .context("Failed to write LSP message")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{process_template_content, vfs::MemoryFileSystem};
+
+    const URI: &str = "file:///project/t.clamp";
+    const TEXT: &str = "[[include: src/lib.rs]]\n[[include: src/missing.rs]]\n[[include?: maybe.rs]]\n[[include: src/";
+
+    /// A project with `src/lib.rs` locked by `t.clamp.lock`.
+    fn options() -> ProcessOptions {
+        let mut files = MemoryFileSystem::new();
+        files.insert("/project/src/lib.rs", "pub fn f() {}\n");
+        files.insert("/project/src/util/mod.rs", "");
+        files.insert("/project/src/.hidden", "");
+        let options = ProcessOptions {
+            file_system: Some(Arc::new(files)),
+            ..Default::default()
+        };
+        let result = process_template_content("[[include: src/lib.rs]]", Path::new("/project"), "t.clamp", &options).unwrap();
+        let mut lockfile = Lockfile::new("/project/t.clamp.lock");
+        lockfile.update_from(&result);
+        lockfile.save(options.fs()).unwrap();
+        options
+    }
+
+    fn frame(message: &Json) -> String {
+        let body = message.to_string();
+        format!("Content-Length: {}\r\n\r\n{body}", body.len())
+    }
+
+    fn document_request(id: f64, method: &str, line: usize, character: usize) -> Json {
+        Json::object([
+            ("jsonrpc", Json::from("2.0")),
+            ("id", Json::from(id)),
+            ("method", Json::from(method)),
+            (
+                "params",
+                Json::object([
+                    ("textDocument", Json::object([("uri", Json::from(URI))])),
+                    (
+                        "position",
+                        Json::object([("line", Json::from(line)), ("character", Json::from(character))]),
+                    ),
+                ]),
+            ),
+        ])
+    }
+
+    fn notification(method: &str, params: Json) -> Json {
+        Json::object([("jsonrpc", Json::from("2.0")), ("method", Json::from(method)), ("params", params)])
+    }
+
+    /// Runs a session of `messages` and returns every message the server sent.
+    fn session(messages: &[Json]) -> Vec<Json> {
+        let input: String = messages.iter().map(frame).collect();
+        let mut output = Vec::new();
+        serve(&options(), input.as_bytes(), &mut output).unwrap();
+        let mut output = output.as_slice();
+        std::iter::from_fn(|| read_message(&mut output).unwrap())
+            .map(|body| Json::parse(&body).unwrap())
+            .collect()
+    }
+
+    fn response(messages: &[Json], id: f64) -> &Json {
+        messages
+            .iter()
+            .find(|message| message.get("id") == Some(&Json::Number(id)))
+            .unwrap_or_else(|| panic!("no response {id}"))
+    }
+
+    fn diagnostics(message: &Json) -> &[Json] {
+        match message.get("params").and_then(|params| params.get("diagnostics")) {
+            Some(Json::Array(diagnostics)) => diagnostics,
+            _ => panic!("not a diagnostics notification: {message}"),
+        }
+    }
+
+    #[test]
+    fn answers_editor_requests() {
+        let open = notification(
+            "textDocument/didOpen",
+            Json::object([("textDocument", Json::object([("uri", Json::from(URI)), ("text", Json::from(TEXT))]))]),
+        );
+        let messages = session(&[
+            Json::parse(r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#).unwrap(),
+            open,
+            document_request(2.0, "textDocument/completion", 3, 15),
+            document_request(3.0, "textDocument/definition", 0, 12),
+            document_request(4.0, "textDocument/hover", 0, 12),
+            document_request(5.0, "textDocument/rename", 0, 12),
+            Json::parse(r#"{"jsonrpc":"2.0","method":"exit"}"#).unwrap(),
+            document_request(6.0, "textDocument/hover", 0, 12),
+        ]);
+
+        let capabilities = response(&messages, 1.0).get("result").and_then(|result| result.get("capabilities"));
+        assert_eq!(capabilities.and_then(|c| c.get("hoverProvider")), Some(&Json::Bool(true)));
+
+        // Only the required include that does not exist is reported
+        let published = messages.iter().find(|message| message.get("id").is_none()).unwrap();
+        let [missing] = diagnostics(published) else {
+            panic!("{published}");
+        };
+        let message = missing.get("message").and_then(Json::as_str).unwrap();
+        assert!(message.contains("src/missing.rs"), "{message}");
+        let start = missing.get("range").and_then(|range| range.get("start")).unwrap();
+        assert_eq!(start.get("line"), Some(&Json::Number(1.0)));
+
+        let Some(Json::Array(items)) = response(&messages, 2.0).get("result") else {
+            panic!("no completions");
+        };
+        let labels: Vec<&str> = items.iter().filter_map(|item| item.get("label")?.as_str()).collect();
+        assert_eq!(labels, ["lib.rs", "util/"]);
+
+        let Some(Json::Array(locations)) = response(&messages, 3.0).get("result") else {
+            panic!("no definition");
+        };
+        assert_eq!(locations[0].get("uri").and_then(Json::as_str), Some("file:///project/src/lib.rs"));
+
+        let hover = response(&messages, 4.0).get("result").and_then(|result| result.get("contents")).unwrap();
+        let hover = hover.get("value").and_then(Json::as_str).unwrap();
+        assert!(hover.contains("14 bytes") && hover.contains("Lockfile: unchanged"), "{hover}");
+
+        let error = response(&messages, 5.0).get("error").and_then(|error| error.get("code"));
+        assert_eq!(error, Some(&Json::Number(METHOD_NOT_FOUND)));
+        assert!(messages.iter().all(|message| message.get("id") != Some(&Json::Number(6.0))), "answered after exit");
+    }
+
+    #[test]
+    fn updates_diagnostics_as_documents_change() {
+        let document = |text: &str| Json::object([("uri", Json::from(URI)), ("text", Json::from(text))]);
+        let uri_only = Json::object([("textDocument", Json::object([("uri", Json::from(URI))]))]);
+        let messages = session(&[
+            notification("textDocument/didOpen", Json::object([("textDocument", document("[[include: gone.rs]]"))])),
+            notification(
+                "textDocument/didChange",
+                Json::object([
+                    ("textDocument", Json::object([("uri", Json::from(URI))])),
+                    ("contentChanges", Json::Array(vec![Json::object([("text", Json::from("[[include: src/lib.rs]]"))])])),
+                ]),
+            ),
+            notification("textDocument/didClose", uri_only),
+        ]);
+        let counts: Vec<usize> = messages.iter().map(|message| diagnostics(message).len()).collect();
+        assert_eq!(counts, [1, 0, 0]);
+    }
+
+    #[test]
+    fn converts_positions() {
+        let text = "aé\n𝄞x\n";
+        assert_eq!(offset_at(text, 1, 2), "aé\n𝄞".len());
+        assert_eq!(offset_at(text, 9, 0), text.len());
+        assert_eq!(position_json(text, "aé\n𝄞".len()).get("character"), Some(&Json::Number(2.0)));
+        assert_eq!(uri_to_path("file:///a%20b/c.clamp"), Some(PathBuf::from("/a b/c.clamp")));
+        assert_eq!(path_to_uri(Path::new("/a b/c.clamp")), "file:///a%20b/c.clamp");
+    }
+}
@@ -1,14 +1,43 @@
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result, anyhow, bail};
 use clamp_lib::{
-    LockfileData, compare_hashes, get_lockfile_path, process_template, read_lockfile,
-    write_lockfile, init
+    ChangeStatus, IncludeFailure, LockPolicy, Lockfile, ProcessOptions, ProcessResult, SymlinkPolicy,
+    find_repo_root, get_lockfile_path, init, locked_hash, normalize_lock_path, process_template_content,
+    process_template_outputs, relative_path, tracked_hashes,
+    apply::{parse_response, resolve_target},
+    clipboard::copy_to_clipboard,
+    config::{self, Config},
+    diff::unified_diff,
+    formatter::{FormatOptions, format_template},
+    graph::DependencyGraph,
+    hook::{self, HookKind},
+    http,
+    json::Json,
+    lint::{LintOptions, lint_template},
+    log::{self, Level, Logger, Record},
+    lsp,
+    man::render_man_pages,
+    mcp,
+    preview,
+    frontmatter::split_frontmatter,
+    progress::{ProgressListener, TerminalProgress, format_bytes},
+    scan::find_templates,
+    snapshot::SnapshotStore,
+    split::split_output,
+    starters::STARTERS,
+    vfs::OsFileSystem,
+    workspace::Workspace,
+    wrapper::WrapStyle,
 };
-use clap::Parser;
+use clap::{FromArgMatches, Parser, ValueHint};
 use clap_complete::{Shell, generate};
 use std::{
-    io::{self, Write},
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    io::{self, IsTerminal, Read, Write},
     path::{Path, PathBuf},
     process::ExitCode,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
 #[derive(Parser, Debug)]
@@ -23,69 +52,669 @@ struct Cli {
     #[clap(subcommand)]
     command: Option<Commands>,
 
-    /// The .clamp template file to process (default action: build and check)
+    /// The .clamp template file to process (default action: build and check), or `-` for stdin
     /// Only used if no subcommand is provided.
-    #[clap(value_parser)]
+    #[clap(value_parser, value_hint = ValueHint::FilePath)]
     template_path_if_no_command: Option<PathBuf>,
+
+    /// Copy the processed template to the system clipboard instead of printing it
+    #[clap(long)]
+    copy: bool,
+
+    /// Explain how each include was resolved: raw path, base directory, canonical
+    /// path, hash and matching lockfile entry (logged as trace messages, so it
+    /// implies -vv)
+    #[clap(long)]
+    trace: bool,
+
+    /// Write a JSON manifest of the build to this file: every include's canonical
+    /// path, hash, size and byte range in the output, the options used and timing
+    #[clap(long, value_name = "PATH", value_hint = ValueHint::FilePath)]
+    emit: Option<PathBuf>,
+
+    /// Directory to resolve include paths against
+    /// (default: the template's directory, or the current directory for stdin) [env: CLAMP_BASE_DIR]
+    #[clap(long, global = true, value_parser, value_hint = ValueHint::DirPath)]
+    base_dir: Option<PathBuf>,
+
+    /// Lockfile to use instead of the one next to the template
+    /// (required to track changes when the template is read from stdin) [env: CLAMP_LOCKFILE]
+    #[clap(long, global = true, value_parser, value_hint = ValueHint::FilePath)]
+    lockfile: Option<PathBuf>,
+
+    /// Reject includes that resolve outside this directory
+    /// (default when given without a value: the base directory)
+    #[clap(
+        long,
+        global = true,
+        value_parser,
+        value_name = "DIR",
+        value_hint = ValueHint::DirPath,
+        require_equals = true
+    )]
+    restrict_root: Option<Option<PathBuf>>,
+
+    /// Strict mode: unless --restrict-root is given, confine includes to the repository
+    /// root [env: CLAMP_STRICT]
+    #[clap(long, global = true)]
+    strict: bool,
+
+    /// How to treat symlinked includes: follow (the default), deny, or no-follow (record
+    /// the link itself) [env: CLAMP_SYMLINKS]
+    #[clap(long, global = true, value_parser, value_name = "POLICY")]
+    symlinks: Option<SymlinkPolicy>,
+
+    /// Embed each included file once; repeated includes become a short reference
+    /// (overrides the template's `dedup` frontmatter setting)
+    #[clap(long, global = true)]
+    dedup: bool,
+
+    /// Header line above each embedded file; `{path}` and `{lang}` are substituted
+    /// (default: "### `{path}`", or the template's `header` frontmatter setting)
+    #[clap(long, global = true, value_name = "FORMAT")]
+    header: Option<String>,
+
+    /// Omit the header, emitting the plain "Contents of <path>:" line instead
+    #[clap(long, global = true, conflicts_with = "header")]
+    no_header: bool,
+
+    /// Prefix each embedded line with its original line number
+    /// (per-directive `linenos` modifiers take precedence)
+    #[clap(long, global = true)]
+    linenos: bool,
+
+    /// Wrap each embedded file in a collapsible <details> block
+    /// (per-directive `collapsible` modifiers take precedence)
+    #[clap(long, global = true)]
+    collapsible: bool,
+
+    /// Format used to embed included files: markdown, xml, none (plain concatenation),
+    /// or a wrapper defined in clamp.toml (default: frontmatter/clamp.toml `wrap`, else markdown)
+    #[clap(long, global = true, value_parser, value_name = "STYLE")]
+    wrap: Option<WrapStyle>,
+
+    /// Expand include patterns without honoring .gitignore/.clampignore files
+    /// [env: CLAMP_NO_IGNORE]
+    #[clap(long, global = true)]
+    no_ignore: bool,
+
+    /// Replace missing or unreadable includes with a `[clamp: missing <path>]`
+    /// placeholder and keep rendering; exits with code 2 if any include failed
+    /// [env: CLAMP_KEEP_GOING]
+    #[clap(long, global = true)]
+    keep_going: bool,
+
+    /// When to color the status report: auto (the default: if stderr is a terminal and
+    /// NO_COLOR is not set), always or never [env: CLAMP_COLOR]
+    #[clap(long, global = true, value_enum, value_name = "WHEN")]
+    color: Option<ColorChoice>,
+
+    /// Fail if a template's lockfile is missing (instead of treating every include as
+    /// added) or, for `update-lock`, would change. Meant for CI [env: CLAMP_LOCKED]
+    #[clap(long, global = true, visible_alias = "frozen")]
+    locked: bool,
+
+    /// Only print errors, hiding status messages and warnings
+    #[clap(long, short, global = true, conflicts_with = "verbose")]
+    quiet: bool,
+
+    /// Also print debug messages (-v) or trace messages (-vv) about how templates
+    /// are resolved
+    #[clap(long, short, global = true, action = clap::ArgAction::Count)]
+    verbose: u8,
+
+    /// Format of messages on stderr: text (the default), or json (one object per line
+    /// with level, target and message) [env: CLAMP_LOG_FORMAT]
+    #[clap(long, global = true, value_enum, value_name = "FORMAT")]
+    log_format: Option<LogFormat>,
+}
+
+/// Options shared by every command that processes a template.
+struct TemplateOptions {
+    base_dir: Option<PathBuf>,
+    lockfile: Option<PathBuf>,
+    restrict_root: Option<Option<PathBuf>>,
+    strict: bool,
+    symlink_policy: SymlinkPolicy,
+    dedup: bool,
+    header: Option<String>,
+    linenos: bool,
+    collapsible: bool,
+    wrap: Option<WrapStyle>,
+    no_ignore: bool,
+    keep_going: bool,
+    /// Color the status report
+    color: bool,
+    /// Require existing, up-to-date lockfiles (`--locked`)
+    locked: bool,
 }
 
+/// Template path that means "read the template from stdin".
+const STDIN_PATH: &str = "-";
+
 #[derive(clap::Subcommand, Debug)]
 enum Commands {
     /// Update the lock file for a given template with the current state of its includes
     UpdateLock {
+        /// The .clamp template file, or `-` for stdin
+        #[clap(value_parser, required = true, value_hint = ValueHint::FilePath)]
+        template_path: PathBuf,
+
+        /// Print the entries that would be added, removed or re-hashed without writing
+        /// the lockfile; exits with 1 if anything would change
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Ask for each changed entry whether to update it, keeping rejected entries
+        /// as locked
+        #[clap(long, short, conflicts_with = "dry_run")]
+        interactive: bool,
+    },
+
+    /// Re-lock a single include of a template, e.g. a pinned one, leaving every other
+    /// lockfile entry as it is
+    Bump {
         /// The .clamp template file
-        #[clap(value_parser, required = true)]
+        #[clap(value_parser, required = true, value_hint = ValueHint::FilePath)]
+        template_path: PathBuf,
+
+        /// The include to re-lock: its path as embedded (relative to the template's
+        /// directory, as listed by `--trace`) or the path of the file itself
+        #[clap(value_parser, required = true, value_hint = ValueHint::FilePath)]
+        include_path: PathBuf,
+    },
+
+    /// Show line-level diffs of the included files that changed since the lockfile
+    /// was written, using the snapshots stored by `update-lock` (see `snapshots` in
+    /// clamp.toml); exits with 1 if any file changed
+    Diff {
+        /// The .clamp template file, or `-` for stdin
+        #[clap(value_parser, required = true, value_hint = ValueHint::FilePath)]
         template_path: PathBuf,
     },
 
+    /// Remove stored snapshots that no lockfile in the repository references
+    Gc {
+        /// List the snapshots that would be removed without removing them
+        #[clap(long)]
+        dry_run: bool,
+    },
+
     /// Generate shell completion scripts
     Completions {
         /// The shell to generate completions for
         #[clap(value_parser = clap::value_parser!(Shell))]
         shell: Shell,
+
+        /// Generate a script that asks clamp for candidates at each <TAB>, completing
+        /// the templates and lockfiles found below the current directory
+        /// (bash, zsh and fish only)
+        #[clap(long)]
+        dynamic: bool,
+    },
+
+    /// Write man pages for clamp and each of its subcommands to a directory
+    Man {
+        /// Directory to write the pages to (created if missing)
+        #[clap(value_parser, required = true, value_hint = ValueHint::DirPath)]
+        out_dir: PathBuf,
+    },
+
+    /// Print completion candidates for a partial command line (used by the scripts of
+    /// `completions --dynamic`)
+    #[clap(name = "__complete", hide = true)]
+    Complete {
+        /// The words after `clamp`, the last being the one to complete
+        #[clap(trailing_var_arg = true, allow_hyphen_values = true)]
+        words: Vec<String>,
     },
 
     /// Create a sample .clamp file
     Init {
         /// Path to where create sample .clamp
-        #[clap(value_parser)]
+        #[clap(value_parser, value_hint = ValueHint::FilePath)]
         new: Option<PathBuf>,
+
+        /// Starter template to write instead of the minimal sample
+        #[clap(long, value_parser = starter_names())]
+        template: Option<String>,
+
+        /// Scan the project (respecting .gitignore) and include its key files:
+        /// README, manifests, and entry points
+        #[clap(long, conflicts_with = "template")]
+        scan: bool,
+    },
+
+    /// Print the dependency graph of templates and the files they include,
+    /// following nested .clamp templates
+    Graph {
+        /// Templates to graph (default: every .clamp file below the current directory)
+        #[clap(value_parser, value_hint = ValueHint::FilePath)]
+        templates: Vec<PathBuf>,
+
+        /// Output format
+        #[clap(long, value_enum, default_value = "dot")]
+        format: GraphFormat,
+    },
+
+    /// Show how many bytes and lines each include contributes to the output,
+    /// largest first
+    Stats {
+        /// The .clamp template file, or `-` for stdin
+        #[clap(value_parser, required = true, value_hint = ValueHint::FilePath)]
+        template_path: PathBuf,
+
+        /// Output format
+        #[clap(long, value_enum, default_value = "text")]
+        format: ReportFormat,
+    },
+
+    /// Split the processed template into numbered parts that each fit a token budget,
+    /// for contexts larger than a model's window
+    Split {
+        /// The .clamp template file, or `-` for stdin
+        #[clap(value_parser, required = true, value_hint = ValueHint::FilePath)]
+        template_path: PathBuf,
+
+        /// Largest estimated number of tokens per part (about four characters each)
+        #[clap(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+        max_tokens_per_part: u64,
+
+        /// Directory to write the parts to
+        #[clap(long, value_name = "DIR", default_value = ".", value_hint = ValueHint::DirPath)]
+        out_dir: PathBuf,
+
+        /// File name stem of the parts, as in <NAME>.part1.md
+        /// (default: the template's file name without extension)
+        #[clap(long, value_name = "NAME")]
+        name: Option<String>,
+    },
+
+    /// Write the files in a model's response back to the template's includes: code
+    /// blocks labeled with a path, or <file path="..."> tags. Files changed since
+    /// the lockfile was written are left alone
+    Apply {
+        /// The .clamp template the prompt was built from
+        #[clap(value_parser, required = true, value_hint = ValueHint::FilePath)]
+        template_path: PathBuf,
+
+        /// File holding the response, or `-` for stdin
+        #[clap(value_parser, default_value = STDIN_PATH, value_hint = ValueHint::FilePath)]
+        response_path: PathBuf,
+
+        /// Print the changes as diffs without writing any file; exits with 1 if any
+        /// file would change
+        #[clap(long)]
+        dry_run: bool,
+
+        /// Ask for each file whether to write it
+        #[clap(long, short, conflicts_with = "dry_run")]
+        interactive: bool,
+
+        /// Also write included files that have no lockfile entry, so cannot be
+        /// checked for changes since the prompt was built
+        #[clap(long)]
+        force: bool,
+    },
+
+    /// Serve the templates below the current directory to other programs
+    #[clap(group(clap::ArgGroup::new("mode").required(true)))]
+    Serve {
+        /// Run a Model Context Protocol server over stdio, offering the
+        /// render_template, list_templates and check_drift tools
+        #[clap(long, group = "mode")]
+        mcp: bool,
+
+        /// Serve over HTTP on this address: GET /templates, /render/<template>
+        /// and /check/<template>
+        #[clap(long, group = "mode", value_name = "ADDR")]
+        http: Option<String>,
+
+        /// Run a Language Server Protocol server over stdio for editing .clamp files
+        #[clap(long, group = "mode")]
+        lsp: bool,
+    },
+
+    /// Render a template as HTML on a local web server, refreshing open pages
+    /// whenever the template or one of its includes changes
+    Preview {
+        /// The .clamp template file
+        #[clap(value_parser, required = true, value_hint = ValueHint::FilePath)]
+        template_path: PathBuf,
+
+        /// Address to serve the preview on
+        #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:8700")]
+        addr: String,
+    },
+
+    /// Check templates against their lockfiles without printing them; exits with 1
+    /// if any included file changed
+    Check {
+        /// Templates to check (default: every .clamp file below the current directory
+        /// that has a lockfile)
+        #[clap(value_parser, value_hint = ValueHint::FilePath)]
+        templates: Vec<PathBuf>,
+    },
+
+    /// Write every output declared under `[[outputs]]` in the templates' frontmatter,
+    /// then report changes against their lockfiles
+    Build {
+        /// Templates to build (default: every .clamp file below the current directory
+        /// that declares outputs)
+        #[clap(value_parser, value_hint = ValueHint::FilePath)]
+        templates: Vec<PathBuf>,
+    },
+
+    /// Rewrite directives in templates into their canonical spelling
+    Fmt {
+        /// Templates to format (default: every .clamp file below the current directory)
+        #[clap(value_parser, value_hint = ValueHint::FilePath)]
+        templates: Vec<PathBuf>,
+
+        /// Only report templates that are not formatted; exits with 1 if there are any
+        #[clap(long)]
+        check: bool,
+
+        /// Rewrite absolute include paths relative to the template's directory
+        #[clap(long)]
+        relative: bool,
+    },
+
+    /// Report template hygiene problems: duplicate, oversized and out-of-repository
+    /// includes. Exits with 1 if any are found
+    Lint {
+        /// Templates to lint (default: every .clamp file below the current directory)
+        #[clap(value_parser, value_hint = ValueHint::FilePath)]
+        templates: Vec<PathBuf>,
+
+        /// Flag includes larger than this many bytes
+        /// (default: `max_include_size` in clamp.toml's [lint] table, else 102400)
+        #[clap(long, value_name = "BYTES")]
+        max_size: Option<u64>,
+    },
+
+    /// Install or remove a git hook running `clamp check`
+    Hook {
+        #[clap(subcommand)]
+        action: HookAction,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum HookAction {
+    /// Add `clamp check` to a git hook, keeping any existing hook script
+    Install {
+        /// The hook to install into: pre-commit or pre-push
+        #[clap(long, value_parser, default_value = "pre-commit")]
+        hook: HookKind,
     },
+
+    /// Remove `clamp check` from a git hook
+    Uninstall {
+        /// The hook to remove it from: pre-commit or pre-push
+        #[clap(long, value_parser, default_value = "pre-commit")]
+        hook: HookKind,
+    },
+}
+
+/// How `update-lock` and `apply` apply changes.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum LockUpdate {
+    /// Accept every change
+    All,
+    /// Only print the changes
+    DryRun,
+    /// Ask for each change
+    Interactive,
+}
+
+/// Formats of the messages printed to stderr.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+enum LogFormat {
+    /// `Status: ...`, `Warning: ...` lines
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+/// Prints each message to stderr as a JSON object on its own line.
+struct JsonLogger;
+
+impl Logger for JsonLogger {
+    fn log(&self, record: &Record) {
+        let line = Json::object([
+            ("level", Json::from(record.level.name())),
+            ("target", Json::from(record.target)),
+            ("message", Json::from(record.message.to_string())),
+        ]);
+        eprintln!("{line}");
+    }
+}
+
+/// Logs a status message (printed as `Status: ...` in text format).
+macro_rules! status {
+    ($($arg:tt)*) => {
+        clamp_lib::log::log(Level::Info, module_path!(), format_args!($($arg)*))
+    };
+}
+
+/// When to color the status report.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ColorChoice {
+    /// Color if stderr is a terminal and `NO_COLOR` is unset or empty
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Auto => {
+                io::stderr().is_terminal()
+                    && std::env::var_os("NO_COLOR").is_none_or(|value| value.is_empty())
+            }
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+/// Output formats of reporting commands.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum ReportFormat {
+    /// Human-readable table
+    Text,
+    /// JSON for tooling
+    Json,
+}
+
+/// Output formats of the `graph` command.
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+enum GraphFormat {
+    /// Graphviz DOT
+    Dot,
+    /// JSON with each template's includes and each file's dependent templates
+    Json,
+}
+
+/// Fills in the options missing from the command line with their `CLAMP_*`
+/// environment variables (see [`config::ENV_VARS`]).
+fn apply_env(cli: &mut Cli) -> Result<()> {
+    fn text(option: &str) -> Result<Option<String>> {
+        Ok(config::env_option(option)?.and_then(|value| value.as_str().map(str::to_string)))
+    }
+    fn flag(option: &str) -> Result<bool> {
+        Ok(config::env_option(option)?.and_then(|value| value.as_bool()).unwrap_or(false))
+    }
+    fn choice<T: clap::ValueEnum>(option: &str) -> Result<Option<T>> {
+        text(option)?
+            .map(|value| T::from_str(&value, false).map_err(|e| anyhow!("{e}")))
+            .transpose()
+            .with_context(|| format!("Invalid value of CLAMP_{}", option.to_uppercase().replace('-', "_")))
+    }
+
+    if cli.base_dir.is_none() {
+        cli.base_dir = text("base-dir")?.map(PathBuf::from);
+    }
+    if cli.lockfile.is_none() {
+        cli.lockfile = text("lockfile")?.map(PathBuf::from);
+    }
+    if cli.symlinks.is_none() {
+        cli.symlinks = text("symlinks")?
+            .map(|value| value.parse())
+            .transpose()
+            .context("Invalid value of CLAMP_SYMLINKS")?;
+    }
+    if cli.color.is_none() {
+        cli.color = choice("color")?;
+    }
+    if cli.log_format.is_none() {
+        cli.log_format = choice("log-format")?;
+    }
+    cli.strict |= flag("strict")?;
+    cli.no_ignore |= flag("no-ignore")?;
+    cli.keep_going |= flag("keep-going")?;
+    cli.locked |= flag("locked")?;
+    Ok(())
 }
 
 fn main() -> ExitCode {
-    let cli = Cli::parse();
+    let matches = <Cli as clap::CommandFactory>::command().get_matches();
+    let mut cli = Cli::from_arg_matches(&matches).unwrap_or_else(|e| e.exit());
+    if let Err(e) = apply_env(&mut cli) {
+        log::log(Level::Error, module_path!(), format_args!("{e:#}"));
+        return ExitCode::FAILURE;
+    }
+    let log_format = cli.log_format.unwrap_or(LogFormat::Text);
+    log::set_max_level(Some(match (cli.quiet, cli.verbose) {
+        (true, _) => Level::Error,
+        // The resolution trace is logged as trace messages
+        (false, _) if cli.trace => Level::Trace,
+        (false, 0) => Level::Info,
+        (false, 1) => Level::Debug,
+        (false, _) => Level::Trace,
+    }));
+    if log_format == LogFormat::Json {
+        log::set_logger(JsonLogger);
+    }
+    let options = TemplateOptions {
+        base_dir: cli.base_dir,
+        lockfile: cli.lockfile,
+        restrict_root: cli.restrict_root,
+        strict: cli.strict,
+        symlink_policy: cli.symlinks.unwrap_or_default(),
+        dedup: cli.dedup,
+        header: if cli.no_header { Some(String::new()) } else { cli.header },
+        linenos: cli.linenos,
+        collapsible: cli.collapsible,
+        wrap: cli.wrap,
+        no_ignore: cli.no_ignore,
+        keep_going: cli.keep_going,
+        color: log_format == LogFormat::Text && cli.color.unwrap_or(ColorChoice::Auto).enabled(),
+        locked: cli.locked,
+    };
+
+    // `init`, `hook` and the shells' hidden `__complete` callback ignore a stray template path
+    if let Some(name) = matches.subcommand_name()
+        && !matches!(name, "init" | "hook" | "__complete")
+        && cli.template_path_if_no_command.is_some()
+    {
+        log::log(
+            Level::Error,
+            module_path!(),
+            format_args!("Cannot provide both '{name}' subcommand and a default template path."),
+        );
+        return ExitCode::FAILURE;
+    }
 
     let result = match cli.command {
-        Some(Commands::UpdateLock { template_path }) => {
-            if cli.template_path_if_no_command.is_some() {
-                eprintln!(
-                    "Error: Cannot provide both 'update-lock' subcommand and a default template path."
-                );
-                return ExitCode::FAILURE;
-            }
-            run_update_lock(&template_path)
+        Some(Commands::UpdateLock { template_path, dry_run, interactive }) => {
+            let mode = if dry_run {
+                LockUpdate::DryRun
+            } else if interactive {
+                LockUpdate::Interactive
+            } else {
+                LockUpdate::All
+            };
+            run_update_lock(&template_path, mode, &options)
         }
-        Some(Commands::Completions { shell }) => {
-            if cli.template_path_if_no_command.is_some() {
-                eprintln!(
-                    "Error: Cannot provide both 'completions' subcommand and a default template path."
-                );
-                return ExitCode::FAILURE;
+        Some(Commands::Bump { template_path, include_path }) => {
+            run_bump(&template_path, &include_path, &options)
+        }
+        Some(Commands::Diff { template_path }) => {
+            run_diff(&template_path, &options)
+        }
+        Some(Commands::Gc { dry_run }) => {
+            run_gc(dry_run)
+        }
+        Some(Commands::Completions { shell, dynamic }) => {
+            if dynamic {
+                run_generate_dynamic_completions(shell)
+            } else {
+                run_generate_completions(shell)
             }
-            run_generate_completions(shell)
         }
-        Some(Commands::Init { new }) => {
-            init(new)
+        Some(Commands::Man { out_dir }) => run_man(&out_dir),
+        Some(Commands::Complete { words }) => run_complete(&words),
+        Some(Commands::Init { new, template, scan }) => {
+            init(new, template.as_deref(), scan)
+        }
+        Some(Commands::Graph { templates, format }) => {
+            run_graph(templates, format, &options)
+        }
+        Some(Commands::Stats { template_path, format }) => {
+            run_stats(&template_path, format, &options)
+        }
+        Some(Commands::Split { template_path, max_tokens_per_part, out_dir, name }) => {
+            let max_tokens = usize::try_from(max_tokens_per_part).unwrap_or(usize::MAX);
+            run_split(&template_path, max_tokens, &out_dir, name.as_deref(), &options)
+        }
+        Some(Commands::Apply { template_path, response_path, dry_run, interactive, force }) => {
+            let mode = if dry_run {
+                LockUpdate::DryRun
+            } else if interactive {
+                LockUpdate::Interactive
+            } else {
+                LockUpdate::All
+            };
+            run_apply(&template_path, &response_path, mode, force, &options)
+        }
+        Some(Commands::Check { templates }) => {
+            run_check(templates, &options)
+        }
+        Some(Commands::Hook { action }) => run_hook(action),
+        Some(Commands::Lint { templates, max_size }) => {
+            let lint_options = LintOptions {
+                max_include_size: max_size,
+            };
+            run_lint(templates, &lint_options, &options)
+        }
+        Some(Commands::Fmt { templates, check, relative }) => {
+            run_fmt(templates, check, &FormatOptions { relative })
+        }
+        Some(Commands::Serve { mcp: _, http, lsp }) => {
+            run_serve(http.as_deref(), lsp, &options)
+        }
+        Some(Commands::Preview { template_path, addr }) => {
+            run_preview(&template_path, &addr, &options)
+        }
+        Some(Commands::Build { templates }) => {
+            run_build(templates, &options)
         }
-        // Example if you add an explicit Build command:
-        // Some(Commands::Build { template_path }) => { ... }
         None => match cli.template_path_if_no_command {
-            Some(template_path) => run_build_check(&template_path),
+            Some(template_path) => {
+                run_build_check(&template_path, &options, cli.copy, cli.trace, cli.emit.as_deref())
+            }
             None => {
-                eprintln!("Error: No command specified and no template file provided.");
-                eprintln!("\nUsage: clamp <TEMPLATE_PATH>");
-                eprintln!("   or: clamp <COMMAND> --help");
+                log::log(
+                    Level::Error,
+                    module_path!(),
+                    format_args!(
+                        "No command specified and no template file provided.\n\n\
+                         Usage: clamp <TEMPLATE_PATH>\n   or: clamp <COMMAND> --help"
+                    ),
+                );
                 return ExitCode::FAILURE;
             }
         },
@@ -93,22 +722,186 @@ fn main() -> ExitCode {
 
     match result {
         Ok(exit_code) => exit_code,
+        Err(e) if log_format == LogFormat::Json => {
+            log::log(Level::Error, module_path!(), format_args!("{e:#}"));
+            ExitCode::FAILURE
+        }
         Err(e) => {
-            eprintln!("Error: {e}");
-            let mut cause = e.source();
-            while let Some(source) = cause {
-                eprintln!("  Caused by: {source}");
-                cause = source.source();
+            let mut message = e.to_string();
+            for cause in e.chain().skip(1) {
+                message += &format!("\n  Caused by: {cause}");
             }
+            log::log(Level::Error, module_path!(), format_args!("{message}"));
             ExitCode::FAILURE
         }
     }
 }
 
+/// Possible values for `init --template`, with their descriptions as help.
+fn starter_names() -> clap::builder::PossibleValuesParser {
+    STARTERS
+        .iter()
+        .map(|starter| clap::builder::PossibleValue::new(starter.name).help(starter.description))
+        .collect::<Vec<_>>()
+        .into()
+}
+
+/// Processes the template at `template_path`, or from stdin if the path is `-`,
+/// honoring the `--base-dir` override and the include sandbox settings.
+fn load_template(template_path: &Path, options: &TemplateOptions) -> Result<ProcessResult> {
+    let template = read_template(template_path, options)?;
+    render_template(&template, options)
+}
+
+/// A template's content, with the directory its includes resolve against and the
+/// name it goes by in messages.
+struct TemplateSource {
+    content: String,
+    base_dir: PathBuf,
+    origin: String,
+}
+
+/// Reads a template from its file, or from stdin for [`STDIN_PATH`].
+fn read_template(template_path: &Path, options: &TemplateOptions) -> Result<TemplateSource> {
+    let (template_content, origin) = if template_path == Path::new(STDIN_PATH) {
+        let mut template_content = String::new();
+        io::stdin()
+            .read_to_string(&mut template_content)
+            .context("Failed to read template from stdin")?;
+        (template_content, "<stdin>".to_string())
+    } else {
+        let template_content = fs::read_to_string(template_path).with_context(|| {
+            format!("Failed to read template file '{}'", template_path.display())
+        })?;
+        (template_content, template_path.display().to_string())
+    };
+
+    let base_dir = match &options.base_dir {
+        Some(base_dir) => base_dir.clone(),
+        None if template_path == Path::new(STDIN_PATH) => {
+            std::env::current_dir().context("Failed to determine current directory")?
+        }
+        None => match template_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+    };
+
+    Ok(TemplateSource {
+        content: template_content,
+        base_dir,
+        origin,
+    })
+}
+
+/// Renders a template's default variant.
+fn render_template(template: &TemplateSource, options: &TemplateOptions) -> Result<ProcessResult> {
+    render_with_options(template, build_process_options(options, &template.base_dir)?)
+}
+
+/// Like [`render_template`], with the [`ProcessOptions`] already built.
+fn render_with_options(template: &TemplateSource, mut process_options: ProcessOptions) -> Result<ProcessResult> {
+    // Show a progress bar for long builds, unless stderr is redirected
+    let progress = io::stderr().is_terminal().then(|| Arc::new(TerminalProgress::new()));
+    process_options.progress = progress
+        .clone()
+        .map(|progress| progress as Arc<dyn ProgressListener>);
+    let result = process_template_content(
+        &template.content,
+        &template.base_dir,
+        &template.origin,
+        &process_options,
+    );
+    if let Some(progress) = progress {
+        progress.finish();
+    }
+    result
+}
+
+/// Like [`load_template`], but also returns the hashes the template's lockfile tracks,
+/// which include those of its `[[outputs]]` variants.
+fn load_tracked_template(
+    template_path: &Path,
+    options: &TemplateOptions,
+) -> Result<(ProcessResult, BTreeMap<PathBuf, String>)> {
+    let template = read_template(template_path, options)?;
+    let process_options = build_process_options(options, &template.base_dir)?;
+    let result = render_with_options(&template, process_options.clone())?;
+    let hashes = tracked_hashes(
+        &template.content,
+        &template.base_dir,
+        &template.origin,
+        &process_options,
+        &result,
+    )?;
+    Ok((result, hashes))
+}
+
+/// Translates the command-line template options into library [`ProcessOptions`],
+/// resolving the include sandbox and discovering `clamp.toml` from `base_dir`.
+fn build_process_options(options: &TemplateOptions, base_dir: &Path) -> Result<ProcessOptions> {
+    let restrict_root = match &options.restrict_root {
+        Some(Some(root)) => Some(root.clone()),
+        Some(None) => Some(base_dir.to_path_buf()),
+        None if options.strict => {
+            Some(find_repo_root(&OsFileSystem, base_dir).unwrap_or_else(|| base_dir.to_path_buf()))
+        }
+        None => None,
+    };
+
+    Ok(ProcessOptions {
+        restrict_root,
+        symlink_policy: options.symlink_policy,
+        dedup: options.dedup.then_some(true),
+        header: options.header.clone(),
+        linenos: options.linenos.then_some(true),
+        collapsible: options.collapsible.then_some(true),
+        wrap: options.wrap.clone(),
+        custom_wrapper: None,
+        config: Config::discover(&OsFileSystem, base_dir)?,
+        no_ignore: options.no_ignore,
+        progress: None,
+        keep_going: options.keep_going,
+        vars: BTreeMap::new(),
+        events: false,
+        file_system: None,
+    })
+}
+
+/// Determines the lockfile for a template: the `--lockfile` override if given,
+/// otherwise the one next to the template. Stdin templates have none by default.
+fn resolve_lockfile_path(template_path: &Path, options: &TemplateOptions) -> Option<PathBuf> {
+    match &options.lockfile {
+        Some(lockfile) => Some(lockfile.clone()),
+        None if template_path == Path::new(STDIN_PATH) => None,
+        None => Some(get_lockfile_path(template_path)),
+    }
+}
+
+/// Reads the lockfile at `path`. With `--locked`, a missing lockfile is an error
+/// rather than a warning treating every include as added.
+fn load_lockfile(path: &Path, options: &TemplateOptions) -> Result<Lockfile> {
+    if options.locked && !path.exists() {
+        bail!(
+            "Lockfile '{}' not found and --locked was passed; create it with `clamp update-lock`",
+            path.display()
+        );
+    }
+    Lockfile::load(&OsFileSystem, path)
+}
+
 /// Implements the default action: build template, print to stdout, check against lockfile.
-fn run_build_check(template_path: &Path) -> Result<ExitCode> {
+fn run_build_check(
+    template_path: &Path,
+    options: &TemplateOptions,
+    copy: bool,
+    trace: bool,
+    emit: Option<&Path>,
+) -> Result<ExitCode> {
     // 1. Process the template
-    let process_result = process_template(template_path).map_err(|e| {
+    let started = SystemTime::now();
+    let timer = Instant::now();
+    let (process_result, tracked) = load_tracked_template(template_path, options).map_err(|e| {
         anyhow!(e).context(format!(
             "Failed to process template '{}'",
             template_path.display()
@@ -116,83 +909,1545 @@ fn run_build_check(template_path: &Path) -> Result<ExitCode> {
     })?;
 
     // 2. Determine and read the lock file
-    let lockfile_path = get_lockfile_path(template_path);
-    let lockfile_data = read_lockfile(&lockfile_path)?;
+    let lockfile_path = resolve_lockfile_path(template_path, options);
+    if options.locked && lockfile_path.is_none() {
+        bail!("--locked requires a --lockfile path when the template is read from stdin");
+    }
+    let lockfile = lockfile_path
+        .map(|path| load_lockfile(&path, options))
+        .transpose()?;
 
     // 3. Compare current state with lock file state
-    let changes = compare_hashes(&process_result.current_hashes, &lockfile_data.files);
+    let changes = lockfile
+        .as_ref()
+        .map(|lockfile| lockfile.diff_hashes(&tracked))
+        .unwrap_or_default();
+    if let Some(lockfile) = &lockfile {
+        check_pins(template_path, lockfile, &changes, &process_result.pinned)?;
+    }
+    if trace {
+        print_trace(&process_result, lockfile.as_ref());
+    }
+    if let Some(manifest_path) = emit {
+        let manifest = BuildManifest {
+            template_path,
+            lockfile: lockfile.as_ref(),
+            result: &process_result,
+            changes: &changes,
+            options,
+            started,
+            duration: timer.elapsed(),
+        };
+        fs::write(manifest_path, manifest.to_json().to_pretty_string() + "\n")
+            .with_context(|| format!("Failed to write manifest '{}'", manifest_path.display()))?;
+    }
 
-    // 4. Print the processed template content to stdout
-    if let Err(e) = io::stdout().write_all(process_result.output_content.as_bytes()) {
-        eprintln!("Error writing output to stdout: {e}");
-        return Err(anyhow!(e).context("Failed to write processed template to stdout"));
+    // 4. Print the processed template content to stdout, or place it on the clipboard
+    if copy {
+        copy_to_clipboard(&process_result.output_content)
+            .context("Failed to copy processed template to clipboard")?;
+        status!("Processed template copied to clipboard.");
+    } else {
+        io::stdout()
+            .write_all(process_result.output_content.as_bytes())
+            .context("Failed to write processed template to stdout")?;
+        io::stdout().flush().context("Failed to flush stdout")?;
     }
-    io::stdout().flush().context("Failed to flush stdout")?;
 
     // 5. Report status to stderr and determine exit code
-    if changes.is_empty() {
-        eprintln!(
-            "Status: No changes detected relative to lockfile '{}'.",
-            lockfile_path.display()
-        );
+    let complete = report_failures(&process_result.failures);
+    let Some(lockfile) = lockfile else {
+        status!("Template read from stdin; pass --lockfile to track changes.");
+        return Ok(if complete { ExitCode::SUCCESS } else { ExitCode::from(2) });
+    };
+    let unchanged = report_changes(lockfile.path(), &changes, options.color);
+    if !complete {
+        Ok(ExitCode::from(2)) // 2 for includes replaced by placeholders
+    } else if unchanged {
         Ok(ExitCode::SUCCESS) // 0 for no changes
     } else {
-        eprintln!(
-            "Status: Changes detected relative to lockfile '{}':",
-            lockfile_path.display()
-        );
-        for (path, status) in changes {
-            use clamp_lib::ChangeStatus::*;
-            let status_str = match status {
-                Modified => "Modified",
-                Added => "Added",
-                Removed => "Removed",
-                _ => unreachable!(),
-            };
-            eprintln!("  - {}: {}", status_str, path.display());
-        }
         Ok(ExitCode::from(1)) // 1 for changes detected
     }
 }
 
-/// Implements the `update-lock` command.
-fn run_update_lock(template_path: &Path) -> Result<ExitCode> {
-    // 1. Process the template to get current includes and hashes
-    let process_result = process_template(template_path).map_err(|e| {
-        anyhow!(e).context(format!(
-            "Failed to process template '{}' for lock update",
-            template_path.display()
-        ))
-    })?;
+/// Everything `--emit` records about a run of the default action.
+struct BuildManifest<'a> {
+    template_path: &'a Path,
+    lockfile: Option<&'a Lockfile>,
+    result: &'a ProcessResult,
+    changes: &'a BTreeMap<PathBuf, ChangeStatus>,
+    options: &'a TemplateOptions,
+    started: SystemTime,
+    duration: Duration,
+}
 
-    // 2. Prepare lockfile data
-    let new_lockfile_data = LockfileData {
-        files: process_result.current_hashes, // Use the freshly calculated hashes
-    };
+impl BuildManifest<'_> {
+    fn to_json(&self) -> Json {
+        let path = |path: &Path| Json::from(path.display().to_string());
+        let includes = self
+            .result
+            .resolutions
+            .iter()
+            .map(|resolution| {
+                // Lockfile status; `None` without a lockfile
+                let status = self.lockfile.map(|_| {
+                    self.changes
+                        .get(&normalize_lock_path(&resolution.canonical_path))
+                        .map_or("unchanged", ChangeStatus::name)
+                });
+                let range = resolution.output_range.as_ref();
+                Json::object([
+                    ("directive", Json::from(resolution.directive.as_str())),
+                    ("path", Json::from(resolution.path.as_str())),
+                    ("canonical_path", path(&resolution.canonical_path)),
+                    ("hash", Json::from(resolution.hash.as_str())),
+                    ("size", Json::from(resolution.size)),
+                    ("output_start", Json::from(range.map(|range| range.start))),
+                    ("output_end", Json::from(range.map(|range| range.end))),
+                    ("status", Json::from(status)),
+                ])
+            })
+            .collect::<Vec<_>>();
+        let failures = self
+            .result
+            .failures
+            .iter()
+            .map(|failure| {
+                Json::object([
+                    ("path", Json::from(failure.path.as_str())),
+                    ("error", Json::from(failure.error.as_str())),
+                ])
+            })
+            .collect::<Vec<_>>();
 
-    // 3. Determine lockfile path and write it
-    let lockfile_path = get_lockfile_path(template_path);
-    write_lockfile(&lockfile_path, &new_lockfile_data).map_err(|e| {
-        anyhow!(e).context(format!(
-            "Failed to write lockfile '{}'",
+        let options = self.options;
+        let restrict_root = match &options.restrict_root {
+            Some(Some(root)) => Json::from(root.display().to_string()),
+            Some(None) => Json::from(true),
+            None => Json::Null,
+        };
+        let started_at = self
+            .started
+            .duration_since(UNIX_EPOCH)
+            .map_or(0.0, |since| since.as_secs_f64());
+
+        Json::object([
+            ("clamp_version", Json::from(env!("CARGO_PKG_VERSION"))),
+            ("template", path(self.template_path)),
+            ("lockfile", Json::from(self.lockfile.map(|lockfile| lockfile.path().display().to_string()))),
+            (
+                "output",
+                Json::object([
+                    ("bytes", Json::from(self.result.output_content.len())),
+                    ("lines", Json::from(self.result.output_content.lines().count())),
+                ]),
+            ),
+            ("includes", Json::Array(includes)),
+            ("failures", Json::Array(failures)),
+            (
+                "options",
+                Json::object([
+                    ("base_dir", Json::from(options.base_dir.as_ref().map(|dir| dir.display().to_string()))),
+                    ("restrict_root", restrict_root),
+                    ("strict", Json::from(options.strict)),
+                    ("symlinks", Json::from(options.symlink_policy.name())),
+                    ("dedup", Json::from(options.dedup)),
+                    ("header", Json::from(options.header.clone())),
+                    ("linenos", Json::from(options.linenos)),
+                    ("collapsible", Json::from(options.collapsible)),
+                    ("wrap", Json::from(options.wrap.as_ref().map(ToString::to_string))),
+                    ("no_ignore", Json::from(options.no_ignore)),
+                    ("keep_going", Json::from(options.keep_going)),
+                ]),
+            ),
+            (
+                "timing",
+                Json::object([
+                    ("started_at", Json::from(started_at)),
+                    ("duration_ms", Json::from(self.duration.as_secs_f64() * 1000.0)),
+                ]),
+            ),
+        ])
+    }
+}
+
+/// Logs an error for each include that was replaced by a placeholder (`--keep-going`).
+/// Returns whether there were none.
+fn report_failures(failures: &[IncludeFailure]) -> bool {
+    for failure in failures {
+        log::log(
+            Level::Error,
+            module_path!(),
+            format_args!("Include '{}' was replaced by a placeholder: {}", failure.path, failure.error),
+        );
+    }
+    failures.is_empty()
+}
+
+/// Logs how each include was resolved, one trace message per directive.
+fn print_trace(result: &ProcessResult, lockfile: Option<&Lockfile>) {
+    // (directive, message listing its files)
+    let mut messages: Vec<(&str, String)> = Vec::new();
+    for resolution in &result.resolutions {
+        if messages.last().is_none_or(|(directive, _)| *directive != resolution.directive) {
+            messages.push((&resolution.directive, resolution.directive.clone()));
+        }
+        let lock_entry = match lockfile.map(|lockfile| lockfile.hash(&resolution.canonical_path)) {
+            None => "no lockfile".to_string(),
+            Some(None) => "not in lockfile (Added)".to_string(),
+            // Compared with the entry's current hash, which combines several excerpts of a file
+            Some(Some(hash)) if result.current_hashes.get(&resolution.canonical_path) == Some(hash) => {
+                format!("{hash} (Unchanged)")
+            }
+            Some(Some(hash)) => format!("{hash} (Modified)"),
+        };
+        // Linked files have URLs and repositories rather than paths and directories
+        let base_dir = if resolution.canonical_path.is_absolute() {
+            std::path::absolute(&resolution.base_dir).unwrap_or(resolution.base_dir.clone())
+        } else {
+            resolution.base_dir.clone()
+        };
+        let (_, message) = messages.last_mut().expect("a message was pushed for the directive");
+        message.push_str(&format!(
+            "\n  path:      {}\n  base dir:  {}\n  canonical: {}\n  hash:      {}\n  lockfile:  {lock_entry}",
+            resolution.path,
+            base_dir.display(),
+            resolution.canonical_path.display(),
+            resolution.hash
+        ));
+    }
+    for (_, message) in messages {
+        log::log(Level::Trace, module_path!(), format_args!("{message}"));
+    }
+}
+
+/// Prints the status report for `changes` to stderr, grouped by kind of change, with
+/// paths relative to the current directory. Returns whether there were none.
+fn report_changes(lockfile_path: &Path, changes: &BTreeMap<PathBuf, ChangeStatus>, color: bool) -> bool {
+    if changes.is_empty() {
+        status!(
+            "No changes detected relative to lockfile '{}'.",
             lockfile_path.display()
-        ))
-    })?;
+        );
+        return true;
+    }
 
-    eprintln!(
-        "Status: Lockfile '{}' updated successfully.",
-        lockfile_path.display()
+    // (heading, ANSI color, entries) in report order
+    let mut groups: [(&str, &str, Vec<String>); 4] = [
+        ("Modified", "33", Vec::new()),
+        ("Added", "32", Vec::new()),
+        ("Removed", "31", Vec::new()),
+        ("Renamed", "36", Vec::new()),
+    ];
+    let display = relative_display();
+    for (path, status) in changes {
+        let (index, entry) = match status {
+            ChangeStatus::Modified => (0, display(path)),
+            ChangeStatus::Added => (1, display(path)),
+            ChangeStatus::Removed => (2, display(path)),
+            ChangeStatus::Renamed { from } => (3, format!("{} -> {}", display(from), display(path))),
+            ChangeStatus::Unchanged => unreachable!(),
+        };
+        groups[index].2.push(entry);
+    }
+    for (_, _, entries) in &mut groups {
+        entries.sort();
+    }
+
+    let counts: Vec<String> = groups
+        .iter()
+        .filter(|(_, _, entries)| !entries.is_empty())
+        .map(|(heading, _, entries)| format!("{} {}", entries.len(), heading.to_lowercase()))
+        .collect();
+    let mut report = format!(
+        "{} detected relative to lockfile '{}' ({}):",
+        plural(changes.len(), "change"),
+        lockfile_path.display(),
+        counts.join(", ")
     );
-    Ok(ExitCode::SUCCESS) // 0 for success
+    for (heading, code, entries) in &groups {
+        if entries.is_empty() {
+            continue;
+        }
+        let heading = format!("{heading} ({}):", entries.len());
+        if color {
+            report.push_str(&format!("\n  \x1b[1;{code}m{heading}\x1b[0m"));
+        } else {
+            report.push_str(&format!("\n  {heading}"));
+        }
+        for entry in entries {
+            report.push_str(&format!("\n    {entry}"));
+        }
+    }
+    status!("{report}");
+    false
 }
 
-/// Implements the `completions` command.
-fn run_generate_completions(shell: Shell) -> Result<ExitCode> {
-    eprintln!("Generating completions for {shell:?}...");
-    let mut cmd = <Cli as clap::CommandFactory>::command();
-    let bin_name = cmd.get_name().to_string();
+/// Displays canonical paths relative to the current directory when they are below it
+/// or in the same repository, and as-is otherwise.
+fn relative_display() -> impl Fn(&Path) -> String {
+    let cwd = std::env::current_dir().and_then(fs::canonicalize).ok();
+    let repo_root = cwd.as_deref().and_then(|cwd| find_repo_root(&OsFileSystem, cwd));
+    move |path| {
+        let Some(cwd) = &cwd else {
+            return path.display().to_string();
+        };
+        let nearby = path.starts_with(cwd) || repo_root.as_ref().is_some_and(|root| path.starts_with(root));
+        match relative_path(cwd, path) {
+            Ok(relative) if nearby => relative.display().to_string(),
+            _ => path.display().to_string(),
+        }
+    }
+}
 
-    generate(shell, &mut cmd, bin_name, &mut io::stdout());
+/// Describes a reported change, e.g. `Modified: src/main.rs` or
+/// `Renamed: src/old.rs -> src/new.rs`.
+fn describe_change(path: &Path, status: &ChangeStatus) -> String {
+    use clamp_lib::ChangeStatus::*;
+    match status {
+        Modified => format!("Modified: {}", path.display()),
+        Added => format!("Added: {}", path.display()),
+        Removed => format!("Removed: {}", path.display()),
+        Renamed { from } => format!("Renamed: {} -> {}", from.display(), path.display()),
+        Unchanged => unreachable!(),
+    }
+}
 
-    Ok(ExitCode::SUCCESS)
+/// Implements the `check` command.
+fn run_check(templates: Vec<PathBuf>, options: &TemplateOptions) -> Result<ExitCode> {
+    let templates = if templates.is_empty() {
+        // Only templates already under lockfile tracking take part
+        let cwd = std::env::current_dir().context("Failed to determine current directory")?;
+        find_templates(&OsFileSystem, &cwd)?
+            .into_iter()
+            .filter(|template| resolve_lockfile_path(template, options).is_some_and(|lock| lock.exists()))
+            .collect()
+    } else {
+        templates
+    };
+    if templates.is_empty() {
+        status!("No locked .clamp templates found below the current directory.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut up_to_date = true;
+    let mut complete = true;
+    for template_path in &templates {
+        let lockfile_path = resolve_lockfile_path(template_path, options)
+            .context("A --lockfile path is required when the template is read from stdin")?;
+        let (process_result, tracked) =
+            load_tracked_template(template_path, options).map_err(|e| {
+                anyhow!(e).context(format!(
+                    "Failed to process template '{}'",
+                    template_path.display()
+                ))
+            })?;
+        let lockfile = load_lockfile(&lockfile_path, options)?;
+        let changes = lockfile.diff_hashes(&tracked);
+        check_pins(template_path, &lockfile, &changes, &process_result.pinned)?;
+        complete &= report_failures(&process_result.failures);
+        up_to_date &= report_changes(&lockfile_path, &changes, options.color);
+    }
+
+    if !complete {
+        Ok(ExitCode::from(2))
+    } else if up_to_date {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        status!("Run `clamp update-lock <template>` to accept the changes.");
+        Ok(ExitCode::from(1))
+    }
+}
+
+/// Implements the `build` command.
+fn run_build(templates: Vec<PathBuf>, options: &TemplateOptions) -> Result<ExitCode> {
+    let templates = if templates.is_empty() {
+        let cwd = std::env::current_dir().context("Failed to determine current directory")?;
+        find_templates(&OsFileSystem, &cwd)?
+            .into_iter()
+            .filter(|template| {
+                fs::read_to_string(template).is_ok_and(|content| {
+                    split_frontmatter(&content).is_ok_and(|(frontmatter, _)| !frontmatter.outputs.is_empty())
+                })
+            })
+            .collect()
+    } else {
+        templates
+    };
+    if templates.is_empty() {
+        status!("No .clamp templates declaring outputs found below the current directory.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let mut up_to_date = true;
+    let mut complete = true;
+    for template_path in &templates {
+        let template = read_template(template_path, options)?;
+        let process_options = build_process_options(options, &template.base_dir)?;
+        let outputs = process_template_outputs(
+            &template.content,
+            &template.base_dir,
+            &template.origin,
+            &process_options,
+        )
+        .with_context(|| format!("Failed to build template '{}'", template_path.display()))?;
+        if outputs.is_empty() {
+            bail!(
+                "Template '{}' declares no [[outputs]] in its frontmatter",
+                template_path.display()
+            );
+        }
+
+        let template_stem = match template_path.file_stem() {
+            Some(stem) if template_path != Path::new(STDIN_PATH) => stem.to_string_lossy().into_owned(),
+            _ => "stdin".to_string(),
+        };
+        for (output, result) in &outputs {
+            let output_path = output.resolve_path(&template.base_dir, &template_stem)?;
+            if let Some(parent) = output_path.parent() {
+                fs::create_dir_all(parent).with_context(|| {
+                    format!("Failed to create output directory '{}'", parent.display())
+                })?;
+            }
+            fs::write(&output_path, &result.output_content)
+                .with_context(|| format!("Failed to write output '{}'", output_path.display()))?;
+            status!(
+                "Wrote '{}' ({}).",
+                output_path.display(),
+                format_bytes(result.output_content.len())
+            );
+            complete &= report_failures(&result.failures);
+        }
+
+        let Some(lockfile_path) = resolve_lockfile_path(template_path, options) else {
+            if options.locked {
+                bail!("--locked requires a --lockfile path when the template is read from stdin");
+            }
+            continue; // Stdin templates are only tracked with --lockfile
+        };
+        // Track the default variant like `tracked_hashes`; an output without vars already is it
+        let (mut tracked, mut pinned) = if outputs.iter().any(|(output, _)| output.vars.is_empty()) {
+            Default::default()
+        } else {
+            let default = render_with_options(&template, process_options)?;
+            (default.current_hashes, default.pinned)
+        };
+        for (_, result) in outputs {
+            tracked.extend(result.current_hashes);
+            pinned.extend(result.pinned);
+        }
+        let lockfile = load_lockfile(&lockfile_path, options)?;
+        let changes = lockfile.diff_hashes(&tracked);
+        check_pins(template_path, &lockfile, &changes, &pinned)?;
+        up_to_date &= report_changes(&lockfile_path, &changes, options.color);
+    }
+
+    if !complete {
+        Ok(ExitCode::from(2))
+    } else if up_to_date {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::from(1))
+    }
+}
+
+/// Implements the `fmt` command.
+fn run_fmt(templates: Vec<PathBuf>, check: bool, format_options: &FormatOptions) -> Result<ExitCode> {
+    let templates = if templates.is_empty() {
+        find_templates(&OsFileSystem, &std::env::current_dir().context("Failed to determine current directory")?)?
+    } else {
+        templates
+    };
+
+    let mut unformatted = 0;
+    for template_path in &templates {
+        let content = fs::read_to_string(template_path).with_context(|| {
+            format!("Failed to read template file '{}'", template_path.display())
+        })?;
+        let template_dir = match template_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let formatted = format_template(&content, template_dir, format_options)
+            .with_context(|| format!("Failed to format template '{}'", template_path.display()))?;
+        if formatted == content {
+            continue;
+        }
+
+        unformatted += 1;
+        if check {
+            log::log(
+                Level::Warn,
+                module_path!(),
+                format_args!("Not formatted: {}", template_path.display()),
+            );
+        } else {
+            fs::write(template_path, formatted).with_context(|| {
+                format!("Failed to write template file '{}'", template_path.display())
+            })?;
+            status!("Formatted: {}", template_path.display());
+        }
+    }
+
+    match (unformatted, check) {
+        (0, _) => {
+            status!("{} already formatted.", plural(templates.len(), "template"));
+            Ok(ExitCode::SUCCESS)
+        }
+        (count, true) => {
+            status!(
+                "{} not formatted; run `clamp fmt` to fix.",
+                plural(count, "template")
+            );
+            Ok(ExitCode::from(1))
+        }
+        (count, false) => {
+            status!("Formatted {}.", plural(count, "template"));
+            Ok(ExitCode::SUCCESS)
+        }
+    }
+}
+
+/// Implements the `lint` command.
+fn run_lint(
+    templates: Vec<PathBuf>,
+    lint_options: &LintOptions,
+    options: &TemplateOptions,
+) -> Result<ExitCode> {
+    let cwd = std::env::current_dir().context("Failed to determine current directory")?;
+    let templates = if templates.is_empty() {
+        find_templates(&OsFileSystem, &cwd)?
+    } else {
+        templates
+    };
+
+    let mut total = 0;
+    for template_path in &templates {
+        let base_dir = match &options.base_dir {
+            Some(base_dir) => base_dir.clone(),
+            None => match template_path.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+                _ => PathBuf::from("."),
+            },
+        };
+        let process_options = build_process_options(options, &base_dir)?;
+        let lints = lint_template(template_path, &process_options, lint_options)
+            .with_context(|| format!("Failed to lint template '{}'", template_path.display()))?;
+        for lint in &lints {
+            println!(
+                "{}:{}: {}: {}",
+                template_path.display(),
+                lint.line,
+                lint.id,
+                lint.message
+            );
+        }
+        total += lints.len();
+    }
+
+    if total == 0 {
+        status!("No problems found in {}.", plural(templates.len(), "template"));
+        Ok(ExitCode::SUCCESS)
+    } else {
+        status!(
+            "Found {}; suppress one with an `allow=<lint>` modifier on its directive.",
+            plural(total, "problem")
+        );
+        Ok(ExitCode::from(1))
+    }
+}
+
+/// Formats `count` with `noun`, pluralized unless the count is 1 (e.g. `3 templates`).
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{count} {noun}")
+    } else {
+        format!("{count} {noun}s")
+    }
+}
+
+/// Implements the `hook` command.
+fn run_hook(action: HookAction) -> Result<ExitCode> {
+    let cwd = std::env::current_dir().context("Failed to determine current directory")?;
+    match action {
+        HookAction::Install { hook } => {
+            let path = hook::install(&cwd, hook)?;
+            let bypass = match hook {
+                HookKind::PreCommit => "git commit --no-verify",
+                HookKind::PrePush => "git push --no-verify",
+            };
+            status!(
+                "Installed `clamp check` into '{}'. Bypass it with `{bypass}`.",
+                path.display()
+            );
+        }
+        HookAction::Uninstall { hook } => {
+            if hook::uninstall(&cwd, hook)? {
+                status!("Removed `clamp check` from the {} hook.", hook.name());
+            } else {
+                status!("The {} hook does not run `clamp check`.", hook.name());
+            }
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Implements the `update-lock` command.
+fn run_update_lock(template_path: &Path, mode: LockUpdate, options: &TemplateOptions) -> Result<ExitCode> {
+    let lockfile_path = resolve_lockfile_path(template_path, options)
+        .context("A --lockfile path is required when the template is read from stdin")?;
+    if mode == LockUpdate::Interactive && template_path == Path::new(STDIN_PATH) {
+        bail!("--interactive reads answers from stdin, so the template cannot be read from it");
+    }
+
+    // 1. Process the template to get current includes and hashes
+    let (process_result, tracked) = load_tracked_template(template_path, options).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to process template '{}' for lock update",
+            template_path.display()
+        ))
+    })?;
+
+    // A lockfile written from a partial render would silently drop the failed includes
+    if !report_failures(&process_result.failures) {
+        bail!(
+            "Refusing to update lockfile '{}' while includes are missing",
+            lockfile_path.display()
+        );
+    }
+
+    // Like cargo's --locked: the lockfile must exist and already be up to date
+    if options.locked {
+        let changes = load_lockfile(&lockfile_path, options)?.diff_hashes(&tracked);
+        if !changes.is_empty() {
+            report_changes(&lockfile_path, &changes, options.color);
+            bail!(
+                "Lockfile '{}' needs to be updated but --locked was passed",
+                lockfile_path.display()
+            );
+        }
+    }
+
+    // 2. Prepare lockfile data
+    let store = SnapshotStore::locate(lockfile_dir(&lockfile_path));
+    let config = Config::discover(&OsFileSystem, lockfile_dir(&lockfile_path))?;
+    let mut lockfile = match mode {
+        LockUpdate::DryRun => return preview_lock_update(&load_lockfile(&lockfile_path, options)?, &tracked),
+        LockUpdate::Interactive => {
+            match select_lock_updates(load_lockfile(&lockfile_path, options)?, &tracked, &store, &config)? {
+                Some(lockfile) => lockfile,
+                None => return Ok(ExitCode::SUCCESS),
+            }
+        }
+        LockUpdate::All => {
+            let mut lockfile = Lockfile::new(&lockfile_path);
+            lockfile.set_entries(tracked); // Use the freshly calculated hashes
+            lockfile
+        }
+    };
+    // Policies follow the template and clamp.toml, also for entries kept as locked
+    lockfile.set_pinned(process_result.pinned.clone());
+
+    // 3. Write the lockfile
+    lockfile.save(&OsFileSystem).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to write lockfile '{}'",
+            lockfile_path.display()
+        ))
+    })?;
+
+    status!(
+        "Lockfile '{}' updated successfully.",
+        lockfile_path.display()
+    );
+
+    // 4. Snapshot the locked content for `clamp diff`
+    if config.snapshots {
+        let stored = store.store_files(lockfile.entries(), &config)?;
+        if stored > 0 {
+            status!(
+                "Stored {} in '{}'.",
+                plural(stored, "new snapshot"),
+                store.dir().display()
+            );
+        }
+    }
+    Ok(ExitCode::SUCCESS) // 0 for success
+}
+
+/// Fails if any of `changes` modifies a pinned include (see
+/// [`Lockfile::pinned_changes`]), naming the `clamp bump` commands that accept them.
+fn check_pins(
+    template_path: &Path,
+    lockfile: &Lockfile,
+    changes: &BTreeMap<PathBuf, ChangeStatus>,
+    pinned: &BTreeSet<PathBuf>,
+) -> Result<()> {
+    let pinned_changes = lockfile.pinned_changes(changes, pinned);
+    if pinned_changes.is_empty() {
+        return Ok(());
+    }
+    let display = relative_display();
+    for path in &pinned_changes {
+        log::log(Level::Error, module_path!(), format_args!("Modified (pinned): {}", display(path)));
+    }
+    bail!(
+        "{} changed since lockfile '{}' was written; run `clamp bump {} <path>` to accept a change",
+        plural(pinned_changes.len(), "pinned include"),
+        lockfile.path().display(),
+        template_path.display()
+    );
+}
+
+/// Implements the `bump` command.
+fn run_bump(template_path: &Path, include_path: &Path, options: &TemplateOptions) -> Result<ExitCode> {
+    let lockfile_path = resolve_lockfile_path(template_path, options)
+        .context("A --lockfile path is required when the template is read from stdin")?;
+    if !lockfile_path.exists() {
+        bail!(
+            "Lockfile '{}' not found; create it with `clamp update-lock`",
+            lockfile_path.display()
+        );
+    }
+    let (process_result, tracked) = load_tracked_template(template_path, options).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to process template '{}'",
+            template_path.display()
+        ))
+    })?;
+
+    // The path as embedded, or the file itself
+    let written = include_path.to_string_lossy();
+    let canonical = process_result
+        .resolutions
+        .iter()
+        .find(|resolution| resolution.path == written)
+        .map(|resolution| resolution.canonical_path.clone())
+        .or_else(|| fs::canonicalize(include_path).ok());
+    let Some((canonical, hash)) =
+        canonical.and_then(|canonical| tracked.get(&canonical).cloned().map(|hash| (canonical, hash)))
+    else {
+        bail!(
+            "'{}' is not included by template '{}'",
+            include_path.display(),
+            template_path.display()
+        );
+    };
+
+    let mut lockfile = Lockfile::load(&OsFileSystem, &lockfile_path)?;
+    let policy = if process_result.pinned.contains(&canonical) {
+        LockPolicy::Pin
+    } else {
+        LockPolicy::Track
+    };
+    if lockfile.hash(&canonical) == Some(&hash) && (policy == LockPolicy::Pin) == lockfile.is_pinned(&canonical) {
+        status!(
+            "'{}' is already up to date in lockfile '{}'.",
+            include_path.display(),
+            lockfile_path.display()
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+    lockfile.update_entry(&canonical, Some(hash.clone()));
+    lockfile.set_policy(&canonical, policy);
+    lockfile.save(&OsFileSystem).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to write lockfile '{}'",
+            lockfile_path.display()
+        ))
+    })?;
+    status!(
+        "Bumped '{}' in lockfile '{}'.",
+        include_path.display(),
+        lockfile_path.display()
+    );
+
+    let config = Config::discover(&OsFileSystem, lockfile_dir(&lockfile_path))?;
+    if config.snapshots {
+        let store = SnapshotStore::locate(lockfile_dir(&lockfile_path));
+        store.store_files(&BTreeMap::from([(canonical, hash)]), &config)?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The directory containing `lockfile_path`, which locates its `clamp.toml` and
+/// snapshot store.
+fn lockfile_dir(lockfile_path: &Path) -> &Path {
+    match lockfile_path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    }
+}
+
+/// Diffs the snapshot of `path` stored under `locked` against its current content,
+/// hashed as `config` directs. Returns `None` if there is no such snapshot.
+fn snapshot_diff(store: &SnapshotStore, config: &Config, path: &Path, locked: &str) -> Result<Option<String>> {
+    let Some(old) = store.load(locked)? else {
+        return Ok(None);
+    };
+    let new = fs::read(path).with_context(|| format!("Failed to read file '{}'", path.display()))?;
+    let new = config.hashed_content(&new);
+    let label = path.display();
+    Ok(Some(unified_diff(
+        &String::from_utf8_lossy(&old),
+        &String::from_utf8_lossy(&new),
+        &format!("{label} (locked)"),
+        &format!("{label} (current)"),
+    )))
+}
+
+/// Implements the `diff` command.
+fn run_diff(template_path: &Path, options: &TemplateOptions) -> Result<ExitCode> {
+    let lockfile_path = resolve_lockfile_path(template_path, options)
+        .context("A --lockfile path is required when the template is read from stdin")?;
+    let (process_result, tracked) = load_tracked_template(template_path, options).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to process template '{}'",
+            template_path.display()
+        ))
+    })?;
+    let complete = report_failures(&process_result.failures);
+    let lockfile = load_lockfile(&lockfile_path, options)?;
+    let changes = lockfile.diff_hashes(&tracked);
+    if changes.is_empty() {
+        report_changes(&lockfile_path, &changes, options.color);
+        return Ok(if complete { ExitCode::SUCCESS } else { ExitCode::from(2) });
+    }
+
+    let store = SnapshotStore::locate(lockfile_dir(&lockfile_path));
+    let config = Config::discover(&OsFileSystem, lockfile_dir(&lockfile_path))?;
+    let mut missing = 0;
+    let mut stdout = io::stdout().lock();
+    for (path, status) in &changes {
+        let diff = match (status, lockfile.hash(path)) {
+            (ChangeStatus::Modified, Some(locked)) => snapshot_diff(&store, &config, path, locked)?,
+            _ => None,
+        };
+        let text = match diff {
+            Some(diff) => diff,
+            None => {
+                if *status == ChangeStatus::Modified {
+                    missing += 1;
+                }
+                format!("{}\n", describe_change(path, status))
+            }
+        };
+        write!(stdout, "{text}").context("Failed to write to stdout")?;
+    }
+    if missing > 0 {
+        status!(
+            "No snapshot of the locked content of {}; set `snapshots = true` in clamp.toml \
+             to store them at `update-lock`.",
+            plural(missing, "modified file")
+        );
+    }
+    Ok(if complete { ExitCode::from(1) } else { ExitCode::from(2) })
+}
+
+/// Implements the `gc` command: removes snapshots not referenced by the lockfile of
+/// any template in the repository containing the current directory.
+fn run_gc(dry_run: bool) -> Result<ExitCode> {
+    let cwd = std::env::current_dir().context("Failed to determine current directory")?;
+    let root = find_repo_root(&OsFileSystem, &cwd).unwrap_or_else(|| cwd.clone());
+    let store = SnapshotStore::locate(&root);
+
+    let mut referenced = BTreeSet::new();
+    for template in find_templates(&OsFileSystem, &root)? {
+        let lockfile_path = get_lockfile_path(&root.join(template));
+        if lockfile_path.exists() {
+            referenced.extend(Lockfile::load(&OsFileSystem, lockfile_path)?.into_data().files.into_values());
+        }
+    }
+
+    let summary = store.gc(&referenced, dry_run)?;
+    if summary.removed.is_empty() {
+        status!("No unreferenced snapshots in '{}'.", store.dir().display());
+    } else if dry_run {
+        let mut stdout = io::stdout().lock();
+        for hash in &summary.removed {
+            writeln!(stdout, "{hash}").context("Failed to write to stdout")?;
+        }
+        status!(
+            "Dry run: would remove {} ({}) from '{}'.",
+            plural(summary.removed.len(), "unreferenced snapshot"),
+            format_bytes(summary.freed_bytes as usize),
+            store.dir().display()
+        );
+    } else {
+        status!(
+            "Removed {} ({}) from '{}'.",
+            plural(summary.removed.len(), "unreferenced snapshot"),
+            format_bytes(summary.freed_bytes as usize),
+            store.dir().display()
+        );
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Asks on stderr whether to accept one of several changes, reading the answer from
+/// stdin: `y` or `n`, or `a` or `q` to accept or reject this and all remaining ones,
+/// which is remembered in `accept_rest`. End of input rejects the rest, like `q`.
+fn confirm(
+    question: &str,
+    help: &str,
+    answers: &mut io::Lines<io::StdinLock<'static>>,
+    accept_rest: &mut Option<bool>,
+) -> Result<bool> {
+    loop {
+        if let Some(accept) = *accept_rest {
+            return Ok(accept);
+        }
+        eprint!("{question} [y,n,a,q,?] ");
+        io::stderr().flush().context("Failed to flush stderr")?;
+        let answer = match answers.next() {
+            Some(line) => line.context("Failed to read answer from stdin")?,
+            None => {
+                eprintln!();
+                "q".to_string()
+            }
+        };
+        match answer.trim() {
+            "y" => return Ok(true),
+            "n" => return Ok(false),
+            "a" => *accept_rest = Some(true),
+            "q" => *accept_rest = Some(false),
+            _ => eprintln!("{help}"),
+        }
+    }
+}
+
+/// Implements `update-lock --interactive`: asks on stderr whether to apply each change
+/// and returns the lockfile with the accepted ones, or `None` if none was accepted.
+fn select_lock_updates(
+    mut lockfile: Lockfile,
+    tracked: &BTreeMap<PathBuf, String>,
+    store: &SnapshotStore,
+    config: &Config,
+) -> Result<Option<Lockfile>> {
+    let changes = lockfile.diff_hashes(tracked);
+    if changes.is_empty() {
+        status!(
+            "No changes detected relative to lockfile '{}'.",
+            lockfile.path().display()
+        );
+        return Ok(None);
+    }
+
+    let mut answers = io::stdin().lines();
+    let mut accept_rest = None;
+    let mut accepted = 0;
+    for (index, (path, status)) in changes.iter().enumerate() {
+        // A renamed entry's locked hash is recorded under its old path
+        let locked_path = match status {
+            ChangeStatus::Renamed { from } => from,
+            _ => path,
+        };
+        let old = lockfile.hash(locked_path).cloned();
+        let new = locked_hash(tracked, path).cloned();
+        eprintln!("{}", describe_change(path, status));
+        if let Some(old) = &old {
+            eprintln!("  locked:  {old}");
+        }
+        if let Some(new) = &new {
+            let size = fs::metadata(path).map_or(String::new(), |meta| {
+                format!(" ({})", format_bytes(meta.len() as usize))
+            });
+            eprintln!("  current: {new}{size}");
+        }
+        if let (ChangeStatus::Modified, Some(old)) = (status, &old)
+            && let Some(diff) = snapshot_diff(store, config, path, old)?
+        {
+            eprint!("{diff}");
+        }
+
+        let accept = confirm(
+            &format!("Update this entry ({}/{})?", index + 1, changes.len()),
+            "y - update this entry\nn - keep this entry as locked\na - update this and all remaining entries\nq - keep this and all remaining entries",
+            &mut answers,
+            &mut accept_rest,
+        )?;
+        if accept {
+            if let ChangeStatus::Renamed { from } = status {
+                lockfile.update_entry(from, None);
+            }
+            lockfile.update_entry(path, new);
+            accepted += 1;
+        }
+    }
+
+    if accepted == 0 {
+        status!(
+            "No changes accepted; lockfile '{}' left unchanged.",
+            lockfile.path().display()
+        );
+        return Ok(None);
+    }
+    status!("Accepted {accepted} of {}.", plural(changes.len(), "change"));
+    Ok(Some(lockfile))
+}
+
+/// Implements `update-lock --dry-run`: prints how the lockfile would change to stdout,
+/// one `+ path hash` (added), `- path hash` (removed), `~ path old -> new`
+/// (re-hashed) or `> old -> new hash` (moved) line per entry.
+fn preview_lock_update(lockfile: &Lockfile, tracked: &BTreeMap<PathBuf, String>) -> Result<ExitCode> {
+    let changes = lockfile.diff_hashes(tracked);
+    if changes.is_empty() {
+        status!(
+            "Dry run: lockfile '{}' is already up to date.",
+            lockfile.path().display()
+        );
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let short = |hash: Option<&String>| {
+        hash.map_or("?", |hash| &hash[..hash.len().min(12)]).to_string()
+    };
+    let mut stdout = io::stdout().lock();
+    for (path, status) in &changes {
+        let old = short(lockfile.hash(path));
+        let new = short(locked_hash(tracked, path));
+        let line = match status {
+            ChangeStatus::Added => format!("+ {} {new}", path.display()),
+            ChangeStatus::Removed => format!("- {} {old}", path.display()),
+            ChangeStatus::Renamed { from } => format!("> {} -> {} {new}", from.display(), path.display()),
+            _ => format!("~ {} {old} -> {new}", path.display()),
+        };
+        writeln!(stdout, "{line}").context("Failed to write to stdout")?;
+    }
+    status!(
+        "Dry run: {} in lockfile '{}' would change; nothing was written.",
+        plural(changes.len(), "file"),
+        lockfile.path().display()
+    );
+    Ok(ExitCode::from(1))
+}
+
+/// Implements the `graph` command.
+fn run_graph(
+    templates: Vec<PathBuf>,
+    format: GraphFormat,
+    options: &TemplateOptions,
+) -> Result<ExitCode> {
+    let cwd = std::env::current_dir().context("Failed to determine current directory")?;
+    let templates = if templates.is_empty() {
+        find_templates(&OsFileSystem, &cwd)?
+    } else {
+        templates
+    };
+    if templates.is_empty() {
+        status!("No .clamp templates found below the current directory.");
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    let base_dir = options.base_dir.clone().unwrap_or_else(|| cwd.clone());
+    let process_options = build_process_options(options, &base_dir)?;
+    let graph = DependencyGraph::build(&templates, &process_options)?;
+
+    // Canonical paths are shown relative to the (canonical) current directory
+    let display_root = fs::canonicalize(&cwd).unwrap_or(cwd);
+    match format {
+        GraphFormat::Dot => print!("{}", graph.to_dot(&display_root)),
+        GraphFormat::Json => println!("{}", graph.to_json(&display_root).to_pretty_string()),
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Implements the `stats` command.
+fn run_stats(
+    template_path: &Path,
+    format: ReportFormat,
+    options: &TemplateOptions,
+) -> Result<ExitCode> {
+    let process_result = load_template(template_path, options).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to process template '{}'",
+            template_path.display()
+        ))
+    })?;
+
+    let output = &process_result.output_content;
+    let (total_bytes, total_lines) = (output.len(), output.lines().count());
+    let mut includes = process_result.includes;
+    includes.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.path.cmp(&b.path)));
+    // Whatever the includes did not produce comes from the template itself
+    let template_bytes = total_bytes - includes.iter().map(|block| block.bytes).sum::<usize>();
+    let template_lines = total_lines.saturating_sub(includes.iter().map(|block| block.lines).sum());
+    let percent = |bytes: usize| {
+        if total_bytes == 0 {
+            0.0
+        } else {
+            bytes as f64 * 100.0 / total_bytes as f64
+        }
+    };
+
+    match format {
+        ReportFormat::Text => {
+            println!("{:>10} {:>8} {:>7}  INCLUDE", "BYTES", "LINES", "%");
+            for block in &includes {
+                println!(
+                    "{:>10} {:>8} {:>6.1}%  {}",
+                    block.bytes,
+                    block.lines,
+                    percent(block.bytes),
+                    block.path
+                );
+            }
+            println!(
+                "{:>10} {:>8} {:>6.1}%  (template text)",
+                template_bytes,
+                template_lines,
+                percent(template_bytes)
+            );
+            println!("{total_bytes:>10} {total_lines:>8} {:>6.1}%  total", 100.0);
+        }
+        ReportFormat::Json => {
+            let entry = |path: Option<&str>, bytes: usize, lines: usize| {
+                let mut fields = vec![
+                    ("bytes", Json::from(bytes)),
+                    ("lines", Json::from(lines)),
+                    ("percent", Json::from((percent(bytes) * 100.0).round() / 100.0)),
+                ];
+                if let Some(path) = path {
+                    fields.insert(0, ("path", Json::from(path)));
+                }
+                Json::object(fields)
+            };
+            let report = Json::object([
+                ("template", Json::from(template_path.display().to_string())),
+                ("total", entry(None, total_bytes, total_lines)),
+                ("template_text", entry(None, template_bytes, template_lines)),
+                (
+                    "includes",
+                    Json::Array(
+                        includes
+                            .iter()
+                            .map(|block| entry(Some(&block.path), block.bytes, block.lines))
+                            .collect(),
+                    ),
+                ),
+            ]);
+            println!("{}", report.to_pretty_string());
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Implements the `serve` command.
+fn run_serve(http_addr: Option<&str>, lsp: bool, options: &TemplateOptions) -> Result<ExitCode> {
+    let root = match &options.base_dir {
+        Some(base_dir) => base_dir.clone(),
+        None => std::env::current_dir().context("Failed to determine current directory")?,
+    };
+    if lsp {
+        // Documents are resolved relative to their own location, not the root
+        lsp::serve(&build_process_options(options, &root)?, io::stdin().lock(), io::stdout().lock())?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    let workspace = Workspace::new(&root, build_process_options(options, &root)?)?;
+
+    if let Some(addr) = http_addr {
+        http::serve(workspace, addr)?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    status!(
+        "Serving templates below '{}' over MCP (stdio).",
+        workspace.root().display()
+    );
+    mcp::serve(&workspace, io::stdin().lock(), io::stdout().lock())?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Implements the `split` command.
+fn run_split(
+    template_path: &Path,
+    max_tokens: usize,
+    out_dir: &Path,
+    name: Option<&str>,
+    options: &TemplateOptions,
+) -> Result<ExitCode> {
+    let process_result = load_template(template_path, options).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to process template '{}'",
+            template_path.display()
+        ))
+    })?;
+    let parts = split_output(&process_result.output_content, &process_result.includes, max_tokens)?;
+
+    let name = match (name, template_path.file_stem()) {
+        (Some(name), _) => name.to_string(),
+        (None, Some(stem)) if template_path != Path::new(STDIN_PATH) => stem.to_string_lossy().into_owned(),
+        _ => "stdin".to_string(),
+    };
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create output directory '{}'", out_dir.display()))?;
+    let part_path = |number: usize| out_dir.join(format!("{name}.part{number}.md"));
+    for (index, part) in parts.iter().enumerate() {
+        let path = part_path(index + 1);
+        fs::write(&path, &part.content)
+            .with_context(|| format!("Failed to write part '{}'", path.display()))?;
+        status!("Wrote '{}' (~{} tokens).", path.display(), part.tokens);
+        if part.oversized {
+            log::log(
+                Level::Warn,
+                module_path!(),
+                format_args!(
+                    "'{}' exceeds {max_tokens} tokens: a single include or paragraph is larger than a part may be",
+                    path.display()
+                ),
+            );
+        }
+    }
+    // Parts left over from an earlier, longer split would otherwise look current
+    for number in parts.len() + 1.. {
+        let stale = part_path(number);
+        if !stale.is_file() {
+            break;
+        }
+        fs::remove_file(&stale)
+            .with_context(|| format!("Failed to remove stale part '{}'", stale.display()))?;
+        status!("Removed stale part '{}'.", stale.display());
+    }
+
+    if !report_failures(&process_result.failures) {
+        return Ok(ExitCode::from(2));
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Implements the `apply` command.
+fn run_apply(
+    template_path: &Path,
+    response_path: &Path,
+    mode: LockUpdate,
+    force: bool,
+    options: &TemplateOptions,
+) -> Result<ExitCode> {
+    if template_path == Path::new(STDIN_PATH) {
+        bail!("Cannot apply a response to a template read from stdin; pass its path");
+    }
+    if mode == LockUpdate::Interactive && response_path == Path::new(STDIN_PATH) {
+        bail!("--interactive reads answers from stdin, so the response must be read from a file");
+    }
+    let response = if response_path == Path::new(STDIN_PATH) {
+        let mut response = String::new();
+        io::stdin()
+            .read_to_string(&mut response)
+            .context("Failed to read response from stdin")?;
+        response
+    } else {
+        fs::read_to_string(response_path).with_context(|| {
+            format!("Failed to read response file '{}'", response_path.display())
+        })?
+    };
+    let blocks = parse_response(&response);
+    if blocks.is_empty() {
+        bail!(
+            "No files found in the response (expected code blocks labeled with a path, or <file path=\"...\"> tags)"
+        );
+    }
+
+    let template = read_template(template_path, options)?;
+    let process_options = build_process_options(options, &template.base_dir)?;
+    let result = render_with_options(&template, process_options.clone()).map_err(|e| {
+        anyhow!(e).context(format!(
+            "Failed to process template '{}'",
+            template_path.display()
+        ))
+    })?;
+    let lockfile_path = resolve_lockfile_path(template_path, options)
+        .context("A --lockfile path is required to check the includes for changes")?;
+    let lockfile = load_lockfile(&lockfile_path, options)?;
+
+    let display = relative_display();
+    let mut answers = io::stdin().lines();
+    let mut accept_rest = None;
+    let (mut changed, mut skipped) = (0, 0);
+    for (index, block) in blocks.iter().enumerate() {
+        let target = resolve_target(
+            &OsFileSystem,
+            block,
+            &template.base_dir,
+            &result,
+            &lockfile,
+            &process_options.config,
+            force,
+        );
+        let (target, current) = match target {
+            Ok(target) => target,
+            Err(e) => {
+                log::log(
+                    Level::Warn,
+                    module_path!(),
+                    format_args!("Skipping '{}': {e:#}", block.path),
+                );
+                skipped += 1;
+                continue;
+            }
+        };
+        if current.as_deref() == Some(block.content.as_bytes()) {
+            status!("'{}' is unchanged.", display(&target));
+            continue;
+        }
+        changed += 1;
+
+        let label = display(&target);
+        let old = current.as_deref().map(String::from_utf8_lossy).unwrap_or_default();
+        let diff = unified_diff(
+            &old,
+            &block.content,
+            &format!("{label} (current)"),
+            &format!("{label} (response)"),
+        );
+        match mode {
+            LockUpdate::DryRun => {
+                print!("{diff}");
+                continue;
+            }
+            LockUpdate::Interactive => {
+                eprint!("{diff}");
+                let accept = confirm(
+                    &format!("Write this file ({}/{})?", index + 1, blocks.len()),
+                    "y - write this file\nn - leave this file alone\na - write this and all remaining files\nq - leave this and all remaining files alone",
+                    &mut answers,
+                    &mut accept_rest,
+                )?;
+                if !accept {
+                    continue;
+                }
+            }
+            LockUpdate::All => {}
+        }
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory '{}'", parent.display()))?;
+        }
+        fs::write(&target, &block.content)
+            .with_context(|| format!("Failed to write file '{}'", target.display()))?;
+        let verb = if current.is_some() { "Updated" } else { "Created" };
+        status!("{verb} '{label}'.");
+    }
+
+    if mode == LockUpdate::DryRun {
+        status!("{} would change.", plural(changed, "file"));
+    }
+    Ok(if skipped > 0 {
+        ExitCode::from(2)
+    } else if mode == LockUpdate::DryRun && changed > 0 {
+        ExitCode::from(1)
+    } else {
+        ExitCode::SUCCESS
+    })
+}
+
+/// Implements the `preview` command.
+fn run_preview(template_path: &Path, addr: &str, options: &TemplateOptions) -> Result<ExitCode> {
+    if template_path == Path::new(STDIN_PATH) {
+        bail!("Cannot preview a template read from stdin; pass its path so changes can be watched");
+    }
+    let base_dir = match &options.base_dir {
+        Some(base_dir) => base_dir.clone(),
+        None => match template_path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+    };
+    let process_options = build_process_options(options, &base_dir)?;
+    preview::serve(template_path, &base_dir, process_options, addr)?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Implements the `completions` command.
+fn run_generate_completions(shell: Shell) -> Result<ExitCode> {
+    status!("Generating completions for {shell:?}...");
+    let mut cmd = <Cli as clap::CommandFactory>::command();
+    let bin_name = cmd.get_name().to_string();
+
+    generate(shell, &mut cmd, bin_name, &mut io::stdout());
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Implements the `man` command.
+fn run_man(out_dir: &Path) -> Result<ExitCode> {
+    let mut cmd = <Cli as clap::CommandFactory>::command();
+    cmd.build();
+    fs::create_dir_all(out_dir)
+        .with_context(|| format!("Failed to create directory '{}'", out_dir.display()))?;
+    let pages = render_man_pages(&cmd);
+    for page in &pages {
+        let path = out_dir.join(&page.file_name);
+        fs::write(&path, &page.content)
+            .with_context(|| format!("Failed to write man page '{}'", path.display()))?;
+    }
+    status!("Wrote {} man pages to '{}'.", pages.len(), out_dir.display());
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Implements `completions --dynamic`: a script that completes each word by running
+/// the hidden `__complete` command, falling back to file names when it has no
+/// candidates.
+fn run_generate_dynamic_completions(shell: Shell) -> Result<ExitCode> {
+    let bin_name = <Cli as clap::CommandFactory>::command().get_name().to_string();
+    let template = match shell {
+        Shell::Bash => BASH_DYNAMIC_COMPLETION,
+        Shell::Zsh => ZSH_DYNAMIC_COMPLETION,
+        Shell::Fish => FISH_DYNAMIC_COMPLETION,
+        _ => bail!("Dynamic completions are not supported for {shell}; omit --dynamic for a static script"),
+    };
+    status!("Generating dynamic completions for {shell:?}...");
+    print!("{}", template.replace("{bin}", &bin_name));
+    Ok(ExitCode::SUCCESS)
+}
+
+const BASH_DYNAMIC_COMPLETION: &str = r#"_{bin}() {
+    local IFS=$'\n'
+    COMPREPLY=($({bin} __complete -- "${COMP_WORDS[@]:1:COMP_CWORD}" 2>/dev/null))
+}
+complete -o default -F _{bin} {bin}
+"#;
+
+const ZSH_DYNAMIC_COMPLETION: &str = r#"#compdef {bin}
+_{bin}() {
+    local -a candidates
+    candidates=("${(@f)$({bin} __complete -- "${(@)words[2,CURRENT]}" 2>/dev/null)}")
+    if [[ -n ${candidates[1]} ]]; then
+        compadd -a candidates
+    else
+        _files
+    fi
+}
+if [ "$funcstack[1]" = "_{bin}" ]; then
+    _{bin} "$@"
+else
+    compdef _{bin} {bin}
+fi
+"#;
+
+const FISH_DYNAMIC_COMPLETION: &str = r#"function __{bin}_complete
+    set -l words (commandline -opc) (commandline -ct)
+    set -l candidates ({bin} __complete -- $words[2..-1] 2>/dev/null)
+    if test (count $candidates) -gt 0
+        printf '%s\n' $candidates
+    else
+        __fish_complete_path (commandline -ct)
+    end
+end
+complete -c {bin} -f -a '(__{bin}_complete)'
+"#;
+
+/// Implements the hidden `__complete` command: prints the candidates for the last of
+/// `words`, one per line.
+fn run_complete(words: &[String]) -> Result<ExitCode> {
+    let mut cmd = <Cli as clap::CommandFactory>::command();
+    cmd.build();
+    let cwd = std::env::current_dir().context("Failed to determine current directory")?;
+    let (current, preceding) = match words.split_last() {
+        Some((current, preceding)) => (current.as_str(), preceding),
+        None => ("", words),
+    };
+    // Bash splits `--opt=value` into `--opt`, `=` and `value`
+    let current = if current == "=" { "" } else { current };
+
+    let mut command = &cmd;
+    let mut pending_value: Option<&clap::Arg> = None;
+    let mut positionals = 0;
+    for word in preceding {
+        if word == "=" {
+            continue;
+        }
+        if pending_value.take().is_some() {
+            continue;
+        }
+        if let Some(name) = word.strip_prefix("--") {
+            pending_value = find_option(command, |arg| arg.get_long() == Some(name));
+        } else if let Some(name) = word.strip_prefix('-').filter(|name| !name.is_empty()) {
+            // The last of several combined short flags may take a value
+            let short = name.chars().last();
+            pending_value = find_option(command, |arg| arg.get_short() == short);
+        } else if let Some(subcommand) = command.find_subcommand(word).filter(|_| positionals == 0) {
+            command = subcommand;
+        } else {
+            positionals += 1;
+        }
+    }
+
+    let candidates = if let Some(arg) = pending_value {
+        value_candidates(arg, &cwd)?
+    } else if let Some((name, _)) = current.strip_prefix("--").and_then(|rest| rest.split_once('=')) {
+        let arg = command.get_arguments().find(|arg| arg.get_long() == Some(name));
+        let values = match arg {
+            Some(arg) => value_candidates(arg, &cwd)?,
+            None => Vec::new(),
+        };
+        values.into_iter().map(|value| format!("--{name}={value}")).collect()
+    } else if current.starts_with('-') {
+        command
+            .get_arguments()
+            .filter(|arg| !arg.is_hide_set())
+            .filter_map(|arg| arg.get_long())
+            .map(|long| format!("--{long}"))
+            .collect()
+    } else {
+        let mut candidates = Vec::new();
+        if positionals == 0 {
+            candidates.extend(
+                command
+                    .get_subcommands()
+                    .filter(|subcommand| !subcommand.is_hide_set())
+                    .map(|subcommand| subcommand.get_name().to_string()),
+            );
+        }
+        let positional_args: Vec<&clap::Arg> = command.get_positionals().collect();
+        let positional = positional_args.get(positionals).copied().or_else(|| {
+            positional_args
+                .last()
+                .copied()
+                .filter(|arg| matches!(arg.get_action(), clap::ArgAction::Append))
+        });
+        if let Some(arg) = positional {
+            candidates.extend(value_candidates(arg, &cwd)?);
+        }
+        candidates
+    };
+
+    let mut stdout = io::stdout().lock();
+    for candidate in candidates.iter().filter(|candidate| candidate.starts_with(current)) {
+        writeln!(stdout, "{candidate}").context("Failed to write completions")?;
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// The option of `command` matching `predicate`, if it takes a separate value.
+fn find_option(command: &clap::Command, predicate: impl Fn(&clap::Arg) -> bool) -> Option<&clap::Arg> {
+    command
+        .get_arguments()
+        .find(|arg| predicate(arg))
+        .filter(|arg| arg.get_action().takes_values() && !arg.is_require_equals_set())
+}
+
+/// Completion candidates for a value of `arg`: its possible values, the templates
+/// (or their lockfiles) below `cwd` for template (or lockfile) arguments, and nothing
+/// otherwise, leaving file names to the shell.
+fn value_candidates(arg: &clap::Arg, cwd: &Path) -> Result<Vec<String>> {
+    let possible_values = arg.get_possible_values();
+    if !possible_values.is_empty() {
+        return Ok(possible_values
+            .iter()
+            .filter(|value| !value.is_hide_set())
+            .map(|value| value.get_name().to_string())
+            .collect());
+    }
+    let id = arg.get_id().as_str();
+    let paths: Vec<PathBuf> = if id.starts_with("template") {
+        find_templates(&OsFileSystem, cwd)?
+    } else if id == "lockfile" {
+        find_templates(&OsFileSystem, cwd)?
+            .iter()
+            .map(|template| get_lockfile_path(template))
+            .filter(|lockfile| cwd.join(lockfile).is_file())
+            .collect()
+    } else {
+        return Ok(Vec::new());
+    };
+    Ok(paths.iter().map(|path| path.to_string_lossy().replace('\\', "/")).collect())
 }
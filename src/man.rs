@@ -0,0 +1,303 @@
+use clap::{Arg, ArgAction, Command};
+use std::fmt::Write;
+
+/// A rendered man page: its file name (e.g. `clamp-update-lock.1`) and roff source.
+#[derive(Debug, Clone)]
+pub struct ManPage {
+    pub file_name: String,
+    pub content: String,
+}
+
+/// Renders section 1 man pages for `command` and each of its visible subcommands,
+/// recursively, named like `git`'s: `clamp.1`, `clamp-update-lock.1`,
+/// `clamp-hook-install.1`. Every section is read off the command's definition: its
+/// about text, arguments (with their help, aliases, defaults and possible values)
+/// and subcommands; only the roff markup is written here.
+///
+/// `command` should be built (see [`Command::build`]) so that global options show
+/// up on every page.
+pub fn render_man_pages(command: &Command) -> Vec<ManPage> {
+    let mut pages = Vec::new();
+    render_recursive(command, &[], &mut pages);
+    pages
+}
+
+fn render_recursive(command: &Command, parents: &[&str], pages: &mut Vec<ManPage>) {
+    let mut names = parents.to_vec();
+    names.push(command.get_name());
+    pages.push(ManPage {
+        file_name: format!("{}.1", names.join("-")),
+        content: render_page(command, &names),
+    });
+    for subcommand in visible_subcommands(command) {
+        render_recursive(subcommand, &names, pages);
+    }
+}
+
+fn visible_subcommands(command: &Command) -> impl Iterator<Item = &Command> {
+    command
+        .get_subcommands()
+        .filter(|subcommand| !subcommand.is_hide_set() && subcommand.get_name() != "help")
+}
+
+fn visible_arguments(command: &Command) -> impl Iterator<Item = &Arg> {
+    command.get_arguments().filter(|arg| !arg.is_hide_set())
+}
+
+fn render_page(command: &Command, names: &[&str]) -> String {
+    let page_name = names.join("-");
+    let mut page = String::new();
+    let mut line = |text: &str| {
+        page.push_str(text);
+        page.push('\n');
+    };
+
+    line(&format!(
+        ".TH {} 1 \"\" \"{} {}\" \"User Commands\"",
+        escape(&page_name.to_uppercase()),
+        names[0],
+        env!("CARGO_PKG_VERSION")
+    ));
+
+    line(".SH NAME");
+    let about = command.get_about().map(ToString::to_string).unwrap_or_default();
+    if about.is_empty() {
+        line(&escape(&page_name));
+    } else {
+        line(&format!("{} \\- {}", escape(&page_name), escape(first_line(&about))));
+    }
+
+    line(".SH SYNOPSIS");
+    line(&synopsis(command, names));
+
+    // The first line of `about` is already the NAME section
+    let description = command
+        .get_long_about()
+        .or(command.get_about())
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    if description.trim() != first_line(&about).trim() {
+        line(".SH DESCRIPTION");
+        line(&paragraphs(&description, ".PP"));
+    }
+
+    // `Command::build` copies global options to every subcommand; their pages list
+    // them below the subcommand's own
+    let is_inherited = |arg: &Arg| names.len() > 1 && arg.is_global_set();
+    for (heading, inherited) in [("OPTIONS", false), ("GLOBAL OPTIONS", true)] {
+        let options: Vec<&Arg> = visible_arguments(command)
+            .filter(|arg| !arg.is_positional() && is_inherited(arg) == inherited)
+            .collect();
+        if options.is_empty() {
+            continue;
+        }
+        line(&format!(".SH {heading}"));
+        for arg in options {
+            line(".TP");
+            line(&option_label(arg));
+            line(&arg_help(arg));
+        }
+    }
+
+    let positionals: Vec<&Arg> = visible_arguments(command).filter(|arg| arg.is_positional()).collect();
+    if !positionals.is_empty() {
+        line(".SH ARGUMENTS");
+        for arg in positionals {
+            line(".TP");
+            line(&positional_label(arg));
+            line(&arg_help(arg));
+        }
+    }
+
+    let subcommands: Vec<&Command> = visible_subcommands(command).collect();
+    if !subcommands.is_empty() {
+        line(".SH SUBCOMMANDS");
+        for subcommand in subcommands {
+            line(".TP");
+            let mut label = format!("\\fB{}\\-{}\\fR(1)", escape(&page_name), escape(subcommand.get_name()));
+            let aliases: Vec<String> = subcommand.get_visible_aliases().map(escape).collect();
+            if !aliases.is_empty() {
+                write!(label, " (alias: {})", aliases.join(", ")).expect("Writing to String buffer failed unexpectedly");
+            }
+            line(&label);
+            let about = subcommand.get_about().map(ToString::to_string).unwrap_or_default();
+            line(&escape(first_line(&about)));
+        }
+    }
+
+    line(".SH VERSION");
+    line(&format!("v{}", env!("CARGO_PKG_VERSION")));
+
+    if let Some(author) = command.get_author() {
+        line(".SH AUTHORS");
+        line(&escape(author));
+    }
+    page
+}
+
+fn synopsis(command: &Command, names: &[&str]) -> String {
+    let mut synopsis = format!("\\fB{}\\fR", escape(&names.join(" ")));
+    if visible_arguments(command).any(|arg| !arg.is_positional()) {
+        synopsis.push_str(" [\\fIOPTIONS\\fR]");
+    }
+    for arg in visible_arguments(command).filter(|arg| arg.is_positional()) {
+        synopsis.push(' ');
+        synopsis.push_str(&positional_label(arg));
+    }
+    if visible_subcommands(command).next().is_some() {
+        let subcommand = if command.is_subcommand_required_set() { " <\\fICOMMAND\\fR>" } else { " [\\fICOMMAND\\fR]" };
+        synopsis.push_str(subcommand);
+    }
+    synopsis
+}
+
+/// `\fB-i\fR, \fB--interactive\fR` or `\fB--symlinks\fR=\fIPOLICY\fR`.
+fn option_label(arg: &Arg) -> String {
+    let mut names = Vec::new();
+    if let Some(short) = arg.get_short() {
+        names.push(format!("\\fB\\-{}\\fR", escape(&short.to_string())));
+    }
+    for short in arg.get_visible_short_aliases().into_iter().flatten() {
+        names.push(format!("\\fB\\-{}\\fR", escape(&short.to_string())));
+    }
+    if let Some(long) = arg.get_long() {
+        names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    for long in arg.get_visible_aliases().into_iter().flatten() {
+        names.push(format!("\\fB\\-\\-{}\\fR", escape(long)));
+    }
+    let mut label = names.join(", ");
+    if arg.get_action().takes_values() {
+        let value = format!("\\fI{}\\fR", escape(&value_name(arg)));
+        if arg.get_num_args().is_some_and(|range| range.min_values() == 0) {
+            write!(label, "[={value}]").expect("Writing to String buffer failed unexpectedly");
+        } else {
+            write!(label, "={value}").expect("Writing to String buffer failed unexpectedly");
+        }
+    }
+    label
+}
+
+/// `<\fITEMPLATE_PATH\fR>` for a required argument, `[\fITEMPLATES\fR]...` for an
+/// optional repeated one.
+fn positional_label(arg: &Arg) -> String {
+    let name = format!("\\fI{}\\fR", escape(&value_name(arg)));
+    let mut label = if arg.is_required_set() { format!("<{name}>") } else { format!("[{name}]") };
+    if matches!(arg.get_action(), ArgAction::Append) {
+        label.push_str("...");
+    }
+    label
+}
+
+fn value_name(arg: &Arg) -> String {
+    match arg.get_value_names() {
+        Some([name, ..]) => name.to_string(),
+        _ => arg.get_id().as_str().to_uppercase(),
+    }
+}
+
+/// The help text of `arg`, followed by its default and possible values.
+fn arg_help(arg: &Arg) -> String {
+    let mut help = arg
+        .get_long_help()
+        .or(arg.get_help())
+        .map(ToString::to_string)
+        .unwrap_or_default();
+    let defaults: Vec<String> = arg
+        .get_default_values()
+        .iter()
+        .map(|value| value.to_string_lossy().into_owned())
+        .collect();
+    if !defaults.is_empty() && arg.get_action().takes_values() {
+        write!(help, "\n\n[default: {}]", defaults.join(", ")).expect("Writing to String buffer failed unexpectedly");
+    }
+    let possible_values: Vec<String> = arg
+        .get_possible_values()
+        .iter()
+        .filter(|value| !value.is_hide_set())
+        .map(|value| value.get_name().to_string())
+        .collect();
+    if !possible_values.is_empty() && arg.get_action().takes_values() {
+        write!(help, "\n\n[possible values: {}]", possible_values.join(", "))
+            .expect("Writing to String buffer failed unexpectedly");
+    }
+    // `.IP` keeps later paragraphs indented under the option
+    paragraphs(&help, ".IP")
+}
+
+fn first_line(text: &str) -> &str {
+    text.lines().next().unwrap_or("")
+}
+
+/// Escapes `text` and separates its blank-line-delimited paragraphs with the `separator`
+/// request.
+fn paragraphs(text: &str, separator: &str) -> String {
+    text.split("\n\n")
+        .map(|paragraph| escape(paragraph.trim()))
+        .filter(|paragraph| !paragraph.is_empty())
+        .collect::<Vec<_>>()
+        .join(&format!("\n{separator}\n"))
+}
+
+/// Escapes roff metacharacters: backslashes, hyphens (which would otherwise be
+/// rendered as typographic dashes) and control characters at the start of a line.
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\e")
+        .replace('-', "\\-")
+        .lines()
+        .map(|line| {
+            if line.starts_with(['.', '\'']) {
+                format!("\\&{line}")
+            } else {
+                line.to_string()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pages() -> Vec<ManPage> {
+        let mut command = Command::new("tool")
+            .about("Does things")
+            .author("A. Author")
+            .arg(Arg::new("verbose").long("verbose").short('v').global(true).action(ArgAction::SetTrue).help("Be loud"))
+            .arg(Arg::new("locked").long("locked").visible_alias("frozen").action(ArgAction::SetTrue))
+            .subcommand(
+                Command::new("run-it")
+                    .about("Runs it")
+                    .visible_alias("r")
+                    .arg(Arg::new("path").required(true).help("The path to run")),
+            );
+        command.build();
+        render_man_pages(&command)
+    }
+
+    #[test]
+    fn pages_cover_every_subcommand() {
+        let names: Vec<String> = pages().into_iter().map(|page| page.file_name).collect();
+        assert_eq!(names, ["tool.1", "tool-run-it.1"]);
+    }
+
+    #[test]
+    fn options_and_subcommands_are_listed_with_their_aliases() {
+        let page = &pages()[0].content;
+        assert!(page.contains(".SH NAME\ntool \\- Does things\n"), "{page}");
+        assert!(page.contains("\\fB\\-\\-locked\\fR, \\fB\\-\\-frozen\\fR"), "{page}");
+        assert!(page.contains("\\fB\\-v\\fR, \\fB\\-\\-verbose\\fR\nBe loud"), "{page}");
+        assert!(page.contains("\\fBtool\\-run\\-it\\fR(1) (alias: r)\nRuns it"), "{page}");
+        assert!(!page.contains(".SH DESCRIPTION"), "{page}");
+    }
+
+    #[test]
+    fn subcommand_pages_list_inherited_options_separately() {
+        let page = &pages()[1].content;
+        assert!(page.contains(".SH SYNOPSIS\n\\fBtool run\\-it\\fR [\\fIOPTIONS\\fR] <\\fIPATH\\fR>\n"), "{page}");
+        let global = page.find(".SH GLOBAL OPTIONS").expect("inherited options have a section");
+        assert!(page[global..].contains("\\-\\-verbose"), "{page}");
+        assert!(page.contains(".SH ARGUMENTS\n.TP\n<\\fIPATH\\fR>\nThe path to run"), "{page}");
+    }
+}
@@ -0,0 +1,278 @@
+//! Renders generated Markdown as HTML for previews.
+
+/// Renders the Markdown clamp produces as HTML, for previews: ATX headings, fenced
+/// code blocks, lists, block quotes, rules and paragraphs, with inline code, emphasis
+/// and links. The `<details>`/`<summary>` lines of collapsible includes are kept as
+/// HTML; any other markup is escaped and shown as text.
+pub(crate) fn to_html(markdown: &str) -> String {
+    let lines: Vec<&str> = markdown.lines().collect();
+    let mut html = String::with_capacity(markdown.len() + markdown.len() / 4);
+    render_blocks(&lines, &mut html);
+    html
+}
+
+fn render_blocks(lines: &[&str], html: &mut String) {
+    let mut index = 0;
+    while index < lines.len() {
+        let line = lines[index];
+        let trimmed = line.trim_start();
+
+        if trimmed.is_empty() {
+            index += 1;
+        } else if let Some((fence, info)) = opening_fence(trimmed) {
+            let end = lines[index + 1..]
+                .iter()
+                .position(|line| is_closing_fence(line.trim_start(), fence))
+                .map_or(lines.len(), |offset| index + 1 + offset);
+            let lang = info.split_whitespace().next().unwrap_or_default();
+            if lang.is_empty() {
+                html.push_str("<pre><code>");
+            } else {
+                html.push_str(&format!("<pre><code class=\"language-{}\">", escape(lang)));
+            }
+            for line in &lines[index + 1..end] {
+                html.push_str(&escape(line));
+                html.push('\n');
+            }
+            html.push_str("</code></pre>\n");
+            index = end + 1;
+        } else if let Some((level, text)) = heading(trimmed) {
+            html.push_str(&format!("<h{level}>{}</h{level}>\n", inline(text)));
+            index += 1;
+        } else if is_rule(trimmed) {
+            html.push_str("<hr>\n");
+            index += 1;
+        } else if is_details_tag(trimmed) {
+            html.push_str(&details_tag(trimmed));
+            html.push('\n');
+            index += 1;
+        } else if trimmed.starts_with('>') {
+            let end = block_end(lines, index, |line| line.trim_start().starts_with('>'));
+            let quoted: Vec<&str> = lines[index..end]
+                .iter()
+                .map(|line| {
+                    let line = line.trim_start().trim_start_matches('>');
+                    line.strip_prefix(' ').unwrap_or(line)
+                })
+                .collect();
+            html.push_str("<blockquote>\n");
+            render_blocks(&quoted, html);
+            html.push_str("</blockquote>\n");
+            index = end;
+        } else if let Some((ordered, _)) = list_item(trimmed) {
+            let tag = if ordered { "ol" } else { "ul" };
+            html.push_str(&format!("<{tag}>\n"));
+            while index < lines.len() {
+                let Some((item_ordered, text)) = list_item(lines[index].trim_start()) else {
+                    break;
+                };
+                if item_ordered != ordered {
+                    break;
+                }
+                // Indented lines continue the item
+                let mut item = text.to_string();
+                index += 1;
+                while index < lines.len() && lines[index].starts_with([' ', '\t']) && list_item(lines[index].trim_start()).is_none() {
+                    item.push(' ');
+                    item.push_str(lines[index].trim());
+                    index += 1;
+                }
+                html.push_str(&format!("<li>{}</li>\n", inline(&item)));
+            }
+            html.push_str(&format!("</{tag}>\n"));
+        } else {
+            let end = block_end(lines, index, |line| {
+                let trimmed = line.trim_start();
+                !trimmed.is_empty()
+                    && opening_fence(trimmed).is_none()
+                    && heading(trimmed).is_none()
+                    && !is_rule(trimmed)
+                    && !is_details_tag(trimmed)
+                    && !trimmed.starts_with('>')
+                    && list_item(trimmed).is_none()
+            });
+            let end = end.max(index + 1);
+            let text: Vec<&str> = lines[index..end].iter().map(|line| line.trim()).collect();
+            html.push_str(&format!("<p>{}</p>\n", inline(&text.join("\n"))));
+            index = end;
+        }
+    }
+}
+
+/// The index after the run of lines starting at `start` that satisfy `continues`.
+fn block_end(lines: &[&str], start: usize, continues: impl Fn(&str) -> bool) -> usize {
+    lines[start..]
+        .iter()
+        .position(|line| !continues(line))
+        .map_or(lines.len(), |offset| start + offset)
+}
+
+/// The fence (e.g. "```") and info string of a line opening a code block.
+fn opening_fence(line: &str) -> Option<(&str, &str)> {
+    let marker = line.chars().next().filter(|c| matches!(c, '`' | '~'))?;
+    let length = line.len() - line.trim_start_matches(marker).len();
+    if length < 3 {
+        return None;
+    }
+    let info = &line[length..];
+    if marker == '`' && info.contains('`') {
+        return None;
+    }
+    Some((&line[..length], info.trim()))
+}
+
+fn is_closing_fence(line: &str, fence: &str) -> bool {
+    let marker = fence.chars().next().expect("fences are not empty");
+    let rest = line.trim_start_matches(marker);
+    line.len() - rest.len() >= fence.len() && rest.trim().is_empty()
+}
+
+/// The level and text of an ATX heading, e.g. `### Title`.
+fn heading(line: &str) -> Option<(usize, &str)> {
+    let level = line.len() - line.trim_start_matches('#').len();
+    let rest = &line[level..];
+    if !(1..=6).contains(&level) || !(rest.is_empty() || rest.starts_with([' ', '\t'])) {
+        return None;
+    }
+    Some((level, rest.trim().trim_end_matches('#').trim_end()))
+}
+
+/// `---`, `***` or `___`, optionally spaced.
+fn is_rule(line: &str) -> bool {
+    let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.len() >= 3
+        && ['-', '*', '_']
+            .iter()
+            .any(|marker| compact.chars().all(|c| c == *marker))
+}
+
+/// Whether the item is ordered (`1.` or `1)`), and its text.
+fn list_item(line: &str) -> Option<(bool, &str)> {
+    for marker in ["- ", "* ", "+ "] {
+        if let Some(text) = line.strip_prefix(marker) {
+            return Some((false, text));
+        }
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let rest = &line[digits..];
+    if (1..=9).contains(&digits) && (rest.starts_with(". ") || rest.starts_with(") ")) {
+        return Some((true, &rest[2..]));
+    }
+    None
+}
+
+/// The lines clamp writes around collapsible includes.
+fn is_details_tag(line: &str) -> bool {
+    line == "<details>"
+        || line == "</details>"
+        || (line.starts_with("<summary>") && line.ends_with("</summary>"))
+}
+
+fn details_tag(line: &str) -> String {
+    match line
+        .strip_prefix("<summary>")
+        .and_then(|rest| rest.strip_suffix("</summary>"))
+    {
+        Some(summary) => format!("<summary>{}</summary>", escape(summary)),
+        None => line.to_string(),
+    }
+}
+
+/// Renders inline code, `**strong**`, `*emphasis*`/`_emphasis_` and `[links](url)`.
+fn inline(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(c) = rest.chars().next() {
+        if c == '`' {
+            let ticks = rest.len() - rest.trim_start_matches('`').len();
+            let fence = &rest[..ticks];
+            if let Some(end) = rest[ticks..].find(fence) {
+                let code = &rest[ticks..ticks + end];
+                html.push_str(&format!("<code>{}</code>", escape(code.trim())));
+                rest = &rest[ticks + end + ticks..];
+                continue;
+            }
+            html.push_str(fence);
+            rest = &rest[ticks..];
+            continue;
+        }
+        if let Some(inner) = rest.strip_prefix("**")
+            && let Some(end) = inner.find("**").filter(|end| *end > 0)
+        {
+            html.push_str(&format!("<strong>{}</strong>", inline(&inner[..end])));
+            rest = &inner[end + 2..];
+            continue;
+        }
+        if (c == '*' || c == '_')
+            && let Some(end) = emphasis_end(text, rest, c)
+        {
+            html.push_str(&format!("<em>{}</em>", inline(&rest[1..end])));
+            rest = &rest[end + 1..];
+            continue;
+        }
+        if c == '['
+            && let Some((label, url, length)) = link(rest)
+        {
+            html.push_str(&format!("<a href=\"{}\">{}</a>", escape(url), inline(label)));
+            rest = &rest[length..];
+            continue;
+        }
+        html.push_str(&escape(&c.to_string()));
+        rest = &rest[c.len_utf8()..];
+    }
+    html
+}
+
+/// The offset in `rest` of the marker closing an emphasis opened by its first
+/// character. `_` only counts at word boundaries, so `snake_case_names` stay intact.
+fn emphasis_end(text: &str, rest: &str, marker: char) -> Option<usize> {
+    let after_open = rest[1..].chars().next()?;
+    if after_open.is_whitespace() || after_open == marker {
+        return None;
+    }
+    let before = text[..text.len() - rest.len()].chars().next_back();
+    if marker == '_' && before.is_some_and(char::is_alphanumeric) {
+        return None;
+    }
+    let end = rest[1..].find(marker)? + 1;
+    if rest[..end].ends_with(char::is_whitespace) {
+        return None;
+    }
+    let after = rest[end + 1..].chars().next();
+    if marker == '_' && after.is_some_and(char::is_alphanumeric) {
+        return None;
+    }
+    Some(end)
+}
+
+/// The label, URL and length of a `[label](url)` link at the start of `text`.
+/// Only web, `mailto:` and relative URLs are linked.
+fn link(text: &str) -> Option<(&str, &str, usize)> {
+    let label_end = text.find("](")?;
+    let label = &text[1..label_end];
+    let url_start = label_end + 2;
+    let url_end = url_start + text[url_start..].find(')')?;
+    let url = text[url_start..url_end].trim();
+    let scheme = url.split_once(':').map(|(scheme, _)| scheme.to_ascii_lowercase());
+    let safe = match scheme.as_deref() {
+        None => true,
+        Some(scheme) => matches!(scheme, "http" | "https" | "mailto") || url.starts_with(['/', '#', '.']),
+    };
+    (safe && !label.is_empty() && !label.contains('[')).then_some((label, url, url_end + 1))
+}
+
+/// Escapes the characters with a meaning in HTML text and attributes.
+pub(crate) fn escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,288 @@
+use anyhow::{Context, Result};
+use std::io::{BufRead, Write};
+
+use crate::{json::Json, workspace::Workspace};
+
+/// MCP protocol revision implemented by [`serve`].
+pub const PROTOCOL_VERSION: &str = "2024-11-05";
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: f64 = -32700.0;
+const INVALID_REQUEST: f64 = -32600.0;
+const METHOD_NOT_FOUND: f64 = -32601.0;
+const INVALID_PARAMS: f64 = -32602.0;
+
+/// A tool offered to MCP clients.
+struct Tool {
+    name: &'static str,
+    description: &'static str,
+    /// Whether the tool takes a `template` argument.
+    takes_template: bool,
+}
+
+const TOOLS: &[Tool] = &[
+    Tool {
+        name: "render_template",
+        description: "Render a .clamp template, embedding the current contents of its includes.",
+        takes_template: true,
+    },
+    Tool {
+        name: "list_templates",
+        description: "List the .clamp templates in the project.",
+        takes_template: false,
+    },
+    Tool {
+        name: "check_drift",
+        description: "Report which files included by a template changed since its lockfile was last updated.",
+        takes_template: true,
+    },
+];
+
+/// Runs a Model Context Protocol server for `workspace`, reading newline-delimited
+/// JSON-RPC messages from `input` and writing responses to `output` until `input` ends.
+pub fn serve(workspace: &Workspace, input: impl BufRead, mut output: impl Write) -> Result<()> {
+    for line in input.lines() {
+        let line = line.context("Failed to read MCP message")?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        if let Some(response) = handle_message(workspace, &line) {
+            writeln!(output, "{response}").context("Failed to write MCP response")?;
+            output.flush().context("Failed to write MCP response")?;
+        }
+    }
+    Ok(())
+}
+
+/// Handles one JSON-RPC message, returning the response (none for notifications).
+fn handle_message(workspace: &Workspace, line: &str) -> Option<Json> {
+    let message = match Json::parse(line) {
+        Ok(message) => message,
+        Err(e) => return Some(error_response(Json::Null, PARSE_ERROR, &e.to_string())),
+    };
+    let id = message.get("id").cloned();
+    let Some(method) = message.get("method").and_then(Json::as_str) else {
+        return Some(error_response(id.unwrap_or(Json::Null), INVALID_REQUEST, "Missing method"));
+    };
+    // Notifications (no id) never get a response
+    let id = id?;
+    let params = message.get("params").cloned().unwrap_or(Json::Null);
+
+    let result = match method {
+        "initialize" => Ok(Json::object([
+            ("protocolVersion", Json::from(PROTOCOL_VERSION)),
+            ("capabilities", Json::object([("tools", Json::Object(Default::default()))])),
+            (
+                "serverInfo",
+                Json::object([
+                    ("name", Json::from("clamp")),
+                    ("version", Json::from(env!("CARGO_PKG_VERSION"))),
+                ]),
+            ),
+        ])),
+        "ping" => Ok(Json::Object(Default::default())),
+        "tools/list" => Ok(Json::object([(
+            "tools",
+            Json::Array(TOOLS.iter().map(tool_description).collect()),
+        )])),
+        "tools/call" => call_tool(workspace, &params),
+        _ => Err((METHOD_NOT_FOUND, format!("Unknown method '{method}'"))),
+    };
+
+    Some(match result {
+        Ok(result) => success_response(id, result),
+        Err((code, message)) => error_response(id, code, &message),
+    })
+}
+
+/// Runs a `tools/call` request. Tool failures are reported in the result
+/// (`isError`), so the model can see them; malformed calls are protocol errors.
+fn call_tool(workspace: &Workspace, params: &Json) -> Result<Json, (f64, String)> {
+    let name = params
+        .get("name")
+        .and_then(Json::as_str)
+        .ok_or((INVALID_PARAMS, "Missing tool name".to_string()))?;
+    let tool = TOOLS
+        .iter()
+        .find(|tool| tool.name == name)
+        .ok_or_else(|| (INVALID_PARAMS, format!("Unknown tool '{name}'")))?;
+    let template = params
+        .get("arguments")
+        .and_then(|arguments| arguments.get("template"))
+        .and_then(Json::as_str);
+    if tool.takes_template && template.is_none() {
+        return Err((INVALID_PARAMS, format!("Tool '{name}' requires a 'template' argument")));
+    }
+    let template = template.unwrap_or_default();
+
+    let outcome = match tool.name {
+        "render_template" => workspace.render(template).map(|result| result.output_content),
+        "list_templates" => workspace.list_templates().map(|templates| templates.join("\n")),
+        "check_drift" => workspace.check(template).map(|report| report.to_pretty_string()),
+        _ => unreachable!("every tool in TOOLS is handled"),
+    };
+    let (text, is_error) = match outcome {
+        Ok(text) => (text, false),
+        Err(e) => (format!("{e:#}"), true),
+    };
+    Ok(Json::object([
+        (
+            "content",
+            Json::Array(vec![Json::object([
+                ("type", Json::from("text")),
+                ("text", Json::from(text)),
+            ])]),
+        ),
+        ("isError", Json::from(is_error)),
+    ]))
+}
+
+fn tool_description(tool: &Tool) -> Json {
+    let (properties, required) = if tool.takes_template {
+        (
+            Json::object([(
+                "template",
+                Json::object([
+                    ("type", Json::from("string")),
+                    (
+                        "description",
+                        Json::from("Path of the .clamp template, relative to the project root"),
+                    ),
+                ]),
+            )]),
+            vec!["template"],
+        )
+    } else {
+        (Json::Object(Default::default()), Vec::new())
+    };
+    Json::object([
+        ("name", Json::from(tool.name)),
+        ("description", Json::from(tool.description)),
+        (
+            "inputSchema",
+            Json::object([
+                ("type", Json::from("object")),
+                ("properties", properties),
+                ("required", Json::from(required)),
+            ]),
+        ),
+    ])
+}
+
+/// A JSON-RPC 2.0 response carrying `result`.
+pub(crate) fn success_response(id: Json, result: Json) -> Json {
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("id", id),
+        ("result", result),
+    ])
+}
+
+/// A JSON-RPC 2.0 error response.
+pub(crate) fn error_response(id: Json, code: f64, message: &str) -> Json {
+    Json::object([
+        ("jsonrpc", Json::from("2.0")),
+        ("id", id),
+        (
+            "error",
+            Json::object([("code", Json::from(code)), ("message", Json::from(message))]),
+        ),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::workspace::tests::workspace;
+
+    fn request(workspace: &Workspace, message: &str) -> Json {
+        handle_message(workspace, message).expect("requests get a response")
+    }
+
+    fn error_code(response: &Json) -> Option<f64> {
+        response.get("error")?.get("code")?.as_f64()
+    }
+
+    /// The text and `isError` flag of a `tools/call` result.
+    fn tool_output(response: &Json) -> (String, bool) {
+        let result = response.get("result").expect("a result");
+        let Some(Json::Array(content)) = result.get("content") else {
+            panic!("no content in {response}");
+        };
+        let text = content[0].get("text").and_then(Json::as_str).unwrap_or_default();
+        (text.to_string(), result.get("isError") == Some(&Json::Bool(true)))
+    }
+
+    fn call(workspace: &Workspace, tool: &str, template: &str) -> Json {
+        let message = format!(
+            r#"{{"jsonrpc":"2.0","id":7,"method":"tools/call","params":{{"name":"{tool}","arguments":{{"template":"{template}"}}}}}}"#
+        );
+        request(workspace, &message)
+    }
+
+    #[test]
+    fn answers_protocol_requests() {
+        let workspace = workspace();
+        let response = request(&workspace, r#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{}}"#);
+        assert_eq!(response.get("id"), Some(&Json::Number(1.0)));
+        let result = response.get("result").unwrap();
+        assert_eq!(result.get("protocolVersion").and_then(Json::as_str), Some(PROTOCOL_VERSION));
+
+        let response = request(&workspace, r#"{"jsonrpc":"2.0","id":"a","method":"tools/list"}"#);
+        let Some(Json::Array(tools)) = response.get("result").and_then(|result| result.get("tools")) else {
+            panic!("no tools in {response}");
+        };
+        let names: Vec<&str> = tools.iter().filter_map(|tool| tool.get("name")?.as_str()).collect();
+        assert_eq!(names, ["render_template", "list_templates", "check_drift"]);
+
+        assert!(handle_message(&workspace, r#"{"jsonrpc":"2.0","method":"notifications/initialized"}"#).is_none());
+    }
+
+    #[test]
+    fn reports_protocol_errors() {
+        let workspace = workspace();
+        let response = request(&workspace, "{not json");
+        assert_eq!((response.get("id"), error_code(&response)), (Some(&Json::Null), Some(PARSE_ERROR)));
+        let response = request(&workspace, r#"{"jsonrpc":"2.0","id":2}"#);
+        assert_eq!(error_code(&response), Some(INVALID_REQUEST));
+        let response = request(&workspace, r#"{"jsonrpc":"2.0","id":3,"method":"resources/list"}"#);
+        assert_eq!(error_code(&response), Some(METHOD_NOT_FOUND));
+
+        let response = call(&workspace, "delete_everything", "review.clamp");
+        assert_eq!(error_code(&response), Some(INVALID_PARAMS));
+        let response = request(&workspace, r#"{"jsonrpc":"2.0","id":4,"method":"tools/call","params":{"name":"check_drift"}}"#);
+        assert_eq!(error_code(&response), Some(INVALID_PARAMS));
+    }
+
+    #[test]
+    fn runs_tools() {
+        let workspace = workspace();
+        let (text, is_error) = tool_output(&call(&workspace, "render_template", "review.clamp"));
+        assert!(!is_error && text.contains("pub fn g() {}"), "{text}");
+
+        let (text, is_error) = tool_output(&call(&workspace, "list_templates", ""));
+        assert_eq!((text.as_str(), is_error), ("docs/notes.clamp\nleak.clamp\nreview.clamp", false));
+
+        let (text, is_error) = tool_output(&call(&workspace, "check_drift", "review.clamp"));
+        let report = Json::parse(&text).unwrap();
+        assert!(!is_error);
+        assert_eq!(report.get("up_to_date"), Some(&Json::Bool(false)));
+
+        // Failures are shown to the model rather than failing the request
+        let (text, is_error) = tool_output(&call(&workspace, "render_template", "../secret.txt"));
+        assert!(is_error && text.contains("is not a file inside the workspace"), "{text}");
+    }
+
+    #[test]
+    fn serves_one_response_per_request_line() {
+        let input = "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"ping\"}\n\n\
+                     {\"jsonrpc\":\"2.0\",\"method\":\"notifications/initialized\"}\n\
+                     {\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"ping\"}\n";
+        let mut output = Vec::new();
+        serve(&workspace(), input.as_bytes(), &mut output).unwrap();
+        let lines: Vec<Json> = String::from_utf8(output).unwrap().lines().map(|line| Json::parse(line).unwrap()).collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[1].get("id"), Some(&Json::Number(2.0)));
+        assert_eq!(lines[1].get("result"), Some(&Json::Object(Default::default())));
+    }
+}
@@ -0,0 +1,506 @@
+//! Runtime-agnostic async variants of the processing functions. Rendering runs on
+//! a shared pool of background threads, so awaiting it never blocks an executor's
+//! worker threads, and the template's includes are read and downloaded concurrently
+//! beforehand.
+
+use anyhow::{Result, anyhow};
+use std::{
+    collections::{BTreeMap, VecDeque},
+    future::Future,
+    io::{self, Read},
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    pin::Pin,
+    sync::{
+        Arc, Condvar, Mutex, OnceLock,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll, Waker},
+    thread,
+};
+
+use crate::{
+    ProcessOptions, ProcessResult, SymlinkPolicy,
+    directive::{DirectiveKind, IncludeDirective, directive_regex},
+    permalink::Permalink,
+    process_template, process_template_content,
+    render::find_symlink_component,
+    resolve_include_paths,
+    vfs::{DirEntry, FileKind, FileSystem, Metadata, OsFileSystem},
+};
+
+/// Most includes read or downloaded at the same time.
+const MAX_READERS: usize = 8;
+
+/// Files larger than this are left to the render, which streams them instead of
+/// holding them in memory.
+const MAX_PREFETCH_SIZE: u64 = 4 * 1024 * 1024;
+
+/// Renderers started when the number of CPUs is unknown.
+const DEFAULT_RENDERERS: usize = 4;
+
+type RenderJob = Box<dyn FnOnce() + Send>;
+
+/// The threads rendering templates for every [`ProcessFuture`]. They are started as
+/// futures are created, up to one per CPU, and then kept for later ones; futures
+/// beyond that wait for a free renderer.
+#[derive(Default)]
+struct RenderPool {
+    state: Mutex<PoolState>,
+    job_added: Condvar,
+}
+
+#[derive(Default)]
+struct PoolState {
+    jobs: VecDeque<RenderJob>,
+    threads: usize,
+    /// Threads waiting for a job.
+    idle: usize,
+}
+
+impl RenderPool {
+    fn get() -> &'static Self {
+        static POOL: OnceLock<RenderPool> = OnceLock::new();
+        POOL.get_or_init(Self::default)
+    }
+
+    /// Queues `job`, starting another renderer if every running one is busy.
+    fn submit(&'static self, job: RenderJob) -> io::Result<()> {
+        let max_threads = thread::available_parallelism().map_or(DEFAULT_RENDERERS, usize::from);
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.jobs.push_back(job);
+        if state.jobs.len() > state.idle && state.threads < max_threads {
+            let spawned = thread::Builder::new()
+                .name("clamp-render".to_string())
+                .spawn(|| self.work());
+            match spawned {
+                Ok(_) => state.threads += 1,
+                // Running renderers get to the job eventually
+                Err(_) if state.threads > 0 => {}
+                Err(e) => {
+                    state.jobs.pop_back();
+                    return Err(e);
+                }
+            }
+        }
+        self.job_added.notify_one();
+        Ok(())
+    }
+
+    fn work(&self) {
+        loop {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            let job = loop {
+                match state.jobs.pop_front() {
+                    Some(job) => break job,
+                    None => {
+                        state.idle += 1;
+                        state = self.job_added.wait(state).unwrap_or_else(|e| e.into_inner());
+                        state.idle -= 1;
+                    }
+                }
+            };
+            drop(state);
+            job();
+        }
+    }
+}
+
+/// A template being processed in the background. Resolves to the same
+/// result as the corresponding blocking function.
+#[derive(Debug)]
+pub struct ProcessFuture {
+    shared: Arc<Mutex<Shared>>,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    result: Option<Result<ProcessResult>>,
+    waker: Option<Waker>,
+}
+
+impl ProcessFuture {
+    /// Runs `job` on the [`RenderPool`], completing the future with its result.
+    fn spawn(job: impl FnOnce() -> Result<ProcessResult> + Send + 'static) -> Self {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let worker_shared = shared.clone();
+        let submitted = RenderPool::get().submit(Box::new(move || {
+            let result = panic::catch_unwind(AssertUnwindSafe(job))
+                .unwrap_or_else(|_| Err(anyhow!("Rendering the template panicked")));
+            let mut shared = worker_shared.lock().unwrap_or_else(|e| e.into_inner());
+            shared.result = Some(result);
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+        }));
+        if let Err(e) = submitted {
+            let mut state = shared.lock().unwrap_or_else(|e| e.into_inner());
+            state.result = Some(Err(anyhow!(e).context("Failed to spawn render thread")));
+        }
+        Self { shared }
+    }
+}
+
+impl Future for ProcessFuture {
+    type Output = Result<ProcessResult>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut shared = self.shared.lock().unwrap_or_else(|e| e.into_inner());
+        match shared.result.take() {
+            Some(result) => Poll::Ready(result),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+/// Async variant of [`process_template`]. The template's includes are read (and
+/// pinned links downloaded) concurrently before it is rendered.
+pub fn process_template_async(
+    template_path: impl Into<PathBuf>,
+    options: ProcessOptions,
+) -> ProcessFuture {
+    let template_path = template_path.into();
+    ProcessFuture::spawn(move || {
+        let base_dir = template_path.parent().unwrap_or(Path::new("."));
+        let options = match options.fs().read_to_string(&template_path) {
+            Ok(content) => prefetch(&content, base_dir, options),
+            Err(_) => options, // Reported by `process_template`
+        };
+        process_template(&template_path, &options)
+    })
+}
+
+/// Async variant of [`process_template_content`], prefetching includes like
+/// [`process_template_async`].
+pub fn process_template_content_async(
+    template_content: String,
+    base_dir: impl Into<PathBuf>,
+    origin: String,
+    options: ProcessOptions,
+) -> ProcessFuture {
+    let base_dir = base_dir.into();
+    ProcessFuture::spawn(move || {
+        let options = prefetch(&template_content, &base_dir, options);
+        process_template_content(&template_content, &base_dir, &origin, &options)
+    })
+}
+
+/// Something the template's includes need, fetched ahead of rendering.
+enum Job {
+    /// A file, by canonical path.
+    Read(PathBuf),
+    /// A linked file pinned to a commit, by raw URL.
+    Download(String),
+}
+
+/// Reads the files named by the `include` directives of `template_content`, and
+/// downloads the links among them that are pinned to a commit, on up to
+/// [`MAX_READERS`] threads at once. Returns `options` with a file system serving
+/// what was fetched, so rendering then waits on none of it.
+///
+/// Only directives written in the template itself are prefetched, with each path's
+/// first existing alternative; everything else (base templates, `include-dir`, include
+/// roots, unpinned links) is read as the render reaches it. Files the render would
+/// refuse (see [`prefetch_jobs`]) are not touched. Failures are left for the render
+/// to report.
+fn prefetch(template_content: &str, base_dir: &Path, mut options: ProcessOptions) -> ProcessOptions {
+    let inner: Arc<dyn FileSystem> = options.file_system.clone().unwrap_or_else(|| Arc::new(OsFileSystem));
+    let jobs = prefetch_jobs(template_content, base_dir, &options);
+    if jobs.is_empty() {
+        return options;
+    }
+
+    let next = AtomicUsize::new(0);
+    let fetched = Mutex::new(Prefetched {
+        inner: inner.clone(),
+        files: BTreeMap::new(),
+        downloads: BTreeMap::new(),
+    });
+    thread::scope(|scope| {
+        for _ in 0..jobs.len().min(MAX_READERS) {
+            scope.spawn(|| {
+                while let Some(job) = jobs.get(next.fetch_add(1, Ordering::Relaxed)) {
+                    match job {
+                        Job::Read(path) => {
+                            if let Ok(content) = read_bounded(&*inner, path) {
+                                let mut fetched = fetched.lock().unwrap_or_else(|e| e.into_inner());
+                                fetched.files.insert(path.clone(), content);
+                            }
+                        }
+                        Job::Download(url) => {
+                            if let Ok(content) = inner.download(url) {
+                                let mut fetched = fetched.lock().unwrap_or_else(|e| e.into_inner());
+                                fetched.downloads.insert(url.clone(), content);
+                            }
+                        }
+                    }
+                }
+            });
+        }
+    });
+
+    let fetched = fetched.into_inner().unwrap_or_else(|e| e.into_inner());
+    crate::log::debug!(
+        "Prefetched {} files and {} links",
+        fetched.files.len(),
+        fetched.downloads.len()
+    );
+    options.file_system = Some(Arc::new(fetched));
+    options
+}
+
+/// The [`Job`]s for the include directives of `template_content`.
+///
+/// The sandbox is applied as the render applies it: with a restrict root nothing is
+/// downloaded and only files inside it are read, and symlinks are left alone unless
+/// the symlink policy follows them. Empty files are skipped too, since devices and
+/// FIFOs look like them.
+fn prefetch_jobs(template_content: &str, base_dir: &Path, options: &ProcessOptions) -> Vec<Job> {
+    let fs = options.fs();
+    let restrict_root = match &options.restrict_root {
+        Some(root) => match fs.canonicalize(root) {
+            Ok(root) => Some(root),
+            Err(_) => return Vec::new(), // Reported by the render
+        },
+        None => None,
+    };
+    let readable = |file: &Path| {
+        let follows_symlinks = match options.symlink_policy {
+            SymlinkPolicy::Follow => true,
+            SymlinkPolicy::Deny => {
+                let relative = file.strip_prefix(base_dir).unwrap_or(file);
+                find_symlink_component(fs, base_dir, relative).is_none()
+            }
+            SymlinkPolicy::NoFollow => fs.symlink_metadata(file).is_ok_and(|meta| meta.kind != FileKind::Symlink),
+        };
+        let canonical = fs.canonicalize(file).ok()?;
+        let inside_root = restrict_root.as_ref().is_none_or(|root| canonical.starts_with(root));
+        let size = fs.metadata(&canonical).ok()?.len;
+        (follows_symlinks && inside_root && (1..=MAX_PREFETCH_SIZE).contains(&size)).then_some(canonical)
+    };
+
+    let mut jobs = Vec::new();
+    for cap in directive_regex().captures_iter(template_content) {
+        if !DirectiveKind::from_name(&cap[1]).is_some_and(DirectiveKind::is_include) {
+            continue;
+        }
+        let Ok(directive) = IncludeDirective::parse(&cap[2]) else {
+            continue;
+        };
+        if let Some(link) = Permalink::parse(&directive.path) {
+            if let Ok(link) = link
+                && link.is_pinned()
+                && restrict_root.is_none()
+            {
+                jobs.push(Job::Download(link.raw_url()));
+            }
+            continue;
+        }
+        let files = directive
+            .alternatives()
+            .find_map(|path| resolve_include_paths(fs, base_dir, path, !options.no_ignore).ok())
+            .unwrap_or_default();
+        jobs.extend(files.iter().filter_map(|file| readable(file)).map(Job::Read));
+    }
+    jobs
+}
+
+/// Reads the file at `path` if it has at most [`MAX_PREFETCH_SIZE`] bytes, however
+/// large its metadata claimed it to be.
+fn read_bounded(fs: &dyn FileSystem, path: &Path) -> io::Result<Vec<u8>> {
+    let mut content = Vec::new();
+    fs.open(path)?.take(MAX_PREFETCH_SIZE + 1).read_to_end(&mut content)?;
+    if content.len() as u64 > MAX_PREFETCH_SIZE {
+        return Err(io::Error::other("file grew past the prefetch limit"));
+    }
+    Ok(content)
+}
+
+/// A file system serving prefetched contents, and everything else from `inner`.
+#[derive(Debug)]
+struct Prefetched {
+    inner: Arc<dyn FileSystem>,
+    /// Canonical path -> content
+    files: BTreeMap<PathBuf, Vec<u8>>,
+    /// Raw URL -> content, `None` for files the server does not have
+    downloads: BTreeMap<String, Option<Vec<u8>>>,
+}
+
+impl FileSystem for Prefetched {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        match self.files.get(path) {
+            Some(content) => Ok(content.clone()),
+            None => self.inner.read(path),
+        }
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        match self.files.get(path) {
+            Some(content) => Ok(Box::new(content.as_slice())),
+            None => self.inner.open(path),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.inner.metadata(path)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.inner.symlink_metadata(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        self.inner.canonicalize(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        self.inner.read_dir(path)
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        self.inner.read_link(path)
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        self.inner.write(path, content)
+    }
+
+    fn download(&self, url: &str) -> Result<Option<Vec<u8>>> {
+        match self.downloads.get(url) {
+            Some(content) => Ok(content.clone()),
+            None => self.inner.download(url),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::task::Wake;
+
+    use super::*;
+    use crate::vfs::MemoryFileSystem;
+
+    struct ThreadWaker(thread::Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Polls `future` to completion on the current thread.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = std::pin::pin!(future);
+        loop {
+            match future.as_mut().poll(&mut cx) {
+                Poll::Ready(output) => return output,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    fn options() -> ProcessOptions {
+        let mut files = MemoryFileSystem::new();
+        files.insert("/project/prompt.clamp", "[[include: src/*.rs]]\n[[include?: missing.rs]]\n[[include: README.md | head 1]]\n");
+        for index in 0..20 {
+            files.insert(format!("/project/src/file{index:02}.rs"), format!("fn f{index}() {{}}\n"));
+        }
+        files.insert("/project/README.md", "# Title\nBody\n");
+        ProcessOptions {
+            file_system: Some(Arc::new(files)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_like_the_blocking_functions() {
+        let path = Path::new("/project/prompt.clamp");
+        let expected = process_template(path, &options()).unwrap();
+        let result = block_on(process_template_async(path, options())).unwrap();
+        assert_eq!(result.output_content, expected.output_content);
+        assert_eq!(result.current_hashes, expected.current_hashes);
+
+        let content = options().fs().read_to_string(path).unwrap();
+        let result = block_on(process_template_content_async(
+            content,
+            "/project",
+            "prompt.clamp".to_string(),
+            options(),
+        ))
+        .unwrap();
+        assert_eq!(result.output_content, expected.output_content);
+    }
+
+    #[test]
+    fn prefetches_every_included_file() {
+        let options = options();
+        let content = options.fs().read_to_string(Path::new("/project/prompt.clamp")).unwrap();
+        let jobs = prefetch_jobs(&content, Path::new("/project"), &options);
+        let paths = read_paths(&jobs);
+        assert_eq!(paths.len(), 21);
+        assert!(paths.contains(&Path::new("/project/README.md")));
+    }
+
+    fn read_paths(jobs: &[Job]) -> Vec<&Path> {
+        jobs.iter()
+            .filter_map(|job| match job {
+                Job::Read(path) => Some(path.as_path()),
+                Job::Download(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn prefetches_only_what_the_render_may_read() {
+        let mut files = MemoryFileSystem::new();
+        let template = "[[include: src/lib.rs]]\n[[include: ../secret.txt]]\n[[include: src/empty.rs]]\n\
+                        [[include: https://github.com/org/repo/blob/3f2a9c1e0b7d4a5f6e8c9b0a1d2e3f4a5b6c7d8e/a.rs]]\n";
+        files.insert("/project/src/lib.rs", "fn f() {}\n");
+        files.insert("/project/src/empty.rs", "");
+        files.insert("/secret.txt", "token\n");
+        let mut options = ProcessOptions {
+            file_system: Some(Arc::new(files)),
+            ..Default::default()
+        };
+
+        let jobs = prefetch_jobs(template, Path::new("/project"), &options);
+        assert_eq!(read_paths(&jobs), [Path::new("/project/src/lib.rs"), Path::new("/secret.txt")]);
+        assert_eq!(jobs.len(), 3, "the pinned link is downloaded");
+
+        options.restrict_root = Some(PathBuf::from("/project"));
+        let jobs = prefetch_jobs(template, Path::new("/project"), &options);
+        assert_eq!(read_paths(&jobs), [Path::new("/project/src/lib.rs")]);
+        assert_eq!(jobs.len(), 1, "links are refused with a restrict root");
+    }
+
+    #[test]
+    fn reads_at_most_the_prefetch_limit() {
+        let mut files = MemoryFileSystem::new();
+        files.insert("/big.txt", vec![b'x'; MAX_PREFETCH_SIZE as usize + 1]);
+        files.insert("/small.txt", "small\n");
+        assert!(read_bounded(&files, Path::new("/big.txt")).is_err());
+        assert_eq!(read_bounded(&files, Path::new("/small.txt")).unwrap(), b"small\n");
+    }
+
+    #[test]
+    fn renders_many_templates_on_the_pool() {
+        let futures: Vec<_> = (0..32)
+            .map(|_| process_template_async("/project/prompt.clamp", options()))
+            .collect();
+        let expected = process_template(Path::new("/project/prompt.clamp"), &options()).unwrap();
+        for future in futures {
+            assert_eq!(block_on(future).unwrap().output_content, expected.output_content);
+        }
+    }
+
+    #[test]
+    fn reports_errors_like_the_blocking_functions() {
+        let error = block_on(process_template_async("/project/missing.clamp", options())).unwrap_err();
+        assert!(error.to_string().contains("Failed to read template file"), "{error}");
+    }
+}
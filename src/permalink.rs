@@ -0,0 +1,174 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    fmt,
+    path::PathBuf,
+    process::Command,
+};
+
+/// A code host whose file links clamp understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Forge {
+    GitHub,
+    /// gitlab.com or any self-hosted instance
+    GitLab,
+}
+
+/// A link to a file in a GitHub or GitLab repository, as copied from the browser,
+/// e.g. `https://github.com/org/repo/blob/<sha>/src/lib.rs#L10-L42`. Such links can
+/// be included directly; a `#L` fragment selects lines.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Permalink {
+    pub forge: Forge,
+    /// Host of the link, e.g. `github.com`
+    pub host: String,
+    /// `owner/repo`, or a GitLab project path with its groups
+    pub project: String,
+    /// The commit SHA, or the branch or tag the link was copied from
+    pub git_ref: String,
+    /// Path of the file in the repository, as written in the link
+    pub path: String,
+    /// Lines `from` to `to` (counted from 1, inclusive) selected by the fragment
+    pub lines: Option<(usize, usize)>,
+}
+
+impl Permalink {
+    /// Parses an include path as a file link. Returns `None` for anything not written
+    /// as an `https://` URL, and an error for URLs that are not GitHub or GitLab file
+    /// links.
+    pub fn parse(text: &str) -> Option<Result<Self>> {
+        let rest = text.strip_prefix("https://")?;
+        Some(Self::parse_url(rest).with_context(|| format!("Unsupported include URL '{text}'")))
+    }
+
+    fn parse_url(rest: &str) -> Result<Self> {
+        let (location, fragment) = match rest.split_once('#') {
+            Some((location, fragment)) => (location, Some(fragment)),
+            None => (rest, None),
+        };
+        let location = location.split_once('?').map_or(location, |(location, _)| location);
+        let Some((host, route)) = location.split_once('/') else {
+            bail!("Expected a link to a file on GitHub or GitLab");
+        };
+
+        let (forge, project, blob) = if let Some((project, blob)) = route.split_once("/-/blob/") {
+            (Forge::GitLab, project, blob)
+        } else if matches!(host, "github.com" | "www.github.com") {
+            let mut segments = route.splitn(4, '/');
+            let (Some(owner), Some(repo), Some("blob"), Some(blob)) =
+                (segments.next(), segments.next(), segments.next(), segments.next())
+            else {
+                bail!("Expected a link to a file (https://github.com/<owner>/<repo>/blob/<commit>/<path>)");
+            };
+            (Forge::GitHub, &route[..owner.len() + 1 + repo.len()], blob)
+        } else {
+            bail!("Only links to files on GitHub (.../blob/...) and GitLab (.../-/blob/...) can be included");
+        };
+        let Some((git_ref, path)) = blob.split_once('/').filter(|(git_ref, path)| {
+            !git_ref.is_empty() && !path.is_empty() && !path.ends_with('/')
+        }) else {
+            bail!("Expected a commit and a file path after 'blob/'");
+        };
+        if project.is_empty() || project.split('/').any(str::is_empty) {
+            bail!("Expected a project path before 'blob/'");
+        }
+
+        let lines = fragment.filter(|fragment| !fragment.is_empty()).map(parse_lines).transpose()?;
+        Ok(Self {
+            forge,
+            host: host.to_ascii_lowercase(),
+            project: project.to_string(),
+            git_ref: git_ref.to_string(),
+            path: path.to_string(),
+            lines,
+        })
+    }
+
+    /// Whether the link names a full commit SHA rather than a branch or tag.
+    pub fn is_pinned(&self) -> bool {
+        matches!(self.git_ref.len(), 40 | 64) && self.git_ref.chars().all(|c| c.is_ascii_hexdigit())
+    }
+
+    /// The same link at `commit`.
+    pub fn at_commit(&self, commit: &str) -> Self {
+        Self {
+            git_ref: commit.to_string(),
+            ..self.clone()
+        }
+    }
+
+    /// The repository's clone URL.
+    pub fn repository_url(&self) -> String {
+        format!("https://{}/{}.git", self.host, self.project)
+    }
+
+    /// The URL serving the file's raw content.
+    pub fn raw_url(&self) -> String {
+        match self.forge {
+            Forge::GitHub => format!(
+                "https://raw.githubusercontent.com/{}/{}/{}",
+                self.project, self.git_ref, self.path
+            ),
+            Forge::GitLab => format!(
+                "https://{}/{}/-/raw/{}/{}",
+                self.host, self.project, self.git_ref, self.path
+            ),
+        }
+    }
+
+    /// The key of the file in the lockfile: the link without its fragment. Recorded
+    /// for the resolved commit, it pins the content the lockfile hash belongs to.
+    pub fn lock_path(&self) -> PathBuf {
+        PathBuf::from(self.to_string())
+    }
+}
+
+/// The link to the file, without the fragment.
+impl fmt::Display for Permalink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let blob = match self.forge {
+            Forge::GitHub => "blob",
+            Forge::GitLab => "-/blob",
+        };
+        write!(f, "https://{}/{}/{blob}/{}/{}", self.host, self.project, self.git_ref, self.path)
+    }
+}
+
+/// Parses a line fragment: `L10` or `L10-L42` (GitHub, which may also add columns as
+/// in `L10C5-L42C9`) or `L10-42` (GitLab).
+fn parse_lines(fragment: &str) -> Result<(usize, usize)> {
+    let line = |part: &str| -> Option<usize> {
+        let part = part.strip_prefix('L').unwrap_or(part);
+        let digits = part.split_once('C').map_or(part, |(digits, _)| digits);
+        digits.parse().ok().filter(|line| *line > 0)
+    };
+    let range = fragment.strip_prefix('L').and_then(|_| match fragment.split_once('-') {
+        Some((from, to)) => Some((line(from)?, line(to)?)),
+        None => line(fragment).map(|line| (line, line)),
+    });
+    match range {
+        Some((from, to)) if from <= to => Ok((from, to)),
+        _ => bail!("Invalid line fragment '#{fragment}' (expected e.g. #L10 or #L10-L42)"),
+    }
+}
+
+/// Downloads `url` with `curl`. Returns `None` if the server has no such file.
+///
+/// Returns an error if curl cannot be started or the download fails otherwise,
+/// including curl's own error message.
+pub fn fetch(url: &str) -> Result<Option<Vec<u8>>> {
+    let output = Command::new("curl")
+        .args(["--silent", "--show-error", "--fail", "--location", "--proto", "=https"])
+        .args(["--max-time", "60", "--", url])
+        .output()
+        .context("Failed to run curl (is it installed?)")?;
+
+    if !output.status.success() {
+        let message = String::from_utf8_lossy(&output.stderr);
+        // curl reports HTTP errors with exit code 22
+        if output.status.code() == Some(22) && message.contains("404") {
+            return Ok(None);
+        }
+        bail!("Failed to download '{url}': {}", message.trim());
+    }
+    Ok(Some(output.stdout))
+}
@@ -0,0 +1,261 @@
+use anyhow::{Context, Result};
+use std::{
+    collections::BTreeMap,
+    fs,
+    net::{TcpListener, TcpStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Condvar, Mutex, MutexGuard},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use crate::{
+    ProcessOptions,
+    config::find_config_file,
+    http::{Response, read_get_target, write_response},
+    json::Json,
+    log, markdown, process_template_content,
+    vfs::OsFileSystem,
+};
+
+/// How often the watched files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(300);
+
+/// How long a browser's `/wait` request is held open when nothing changes.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(25);
+
+/// The preview page. `{title}`, `{generation}` and `{body}` are filled in.
+const PAGE: &str = r#"<!DOCTYPE html>
+<html lang="en">
+<head>
+<meta charset="utf-8">
+<title>{title} - clamp preview</title>
+<style>
+body { font-family: system-ui, sans-serif; line-height: 1.5; max-width: 60rem; margin: 2rem auto; padding: 0 1rem; color: #1f2328; }
+pre { background: #f6f8fa; padding: 0.75rem 1rem; overflow-x: auto; border-radius: 6px; }
+code { font-family: ui-monospace, monospace; font-size: 0.9em; }
+:not(pre) > code { background: #eff1f3; padding: 0.1em 0.3em; border-radius: 4px; }
+blockquote { margin-left: 0; padding-left: 1rem; border-left: 4px solid #d0d7de; color: #59636e; }
+details { border: 1px solid #d0d7de; border-radius: 6px; padding: 0.5rem 1rem; margin: 1rem 0; }
+#status { position: fixed; top: 0.5rem; right: 0.75rem; font-size: 0.8rem; color: #59636e; }
+.error { background: #ffebe9; color: #82071e; }
+</style>
+</head>
+<body>
+<div id="status"></div>
+<main id="content">
+{body}</main>
+<script>
+let generation = {generation};
+const status = document.getElementById("status");
+async function poll() {
+  for (;;) {
+    try {
+      const response = await fetch("/wait?after=" + generation);
+      const update = await response.json();
+      status.textContent = "";
+      if (update.generation !== generation) {
+        generation = update.generation;
+        document.getElementById("content").innerHTML = update.html;
+        status.textContent = "Updated " + new Date().toLocaleTimeString();
+      }
+    } catch (error) {
+      status.textContent = "Disconnected, retrying...";
+      await new Promise((resolve) => setTimeout(resolve, 1000));
+    }
+  }
+}
+poll();
+</script>
+</body>
+</html>
+"#;
+
+/// A file's modification time and size, or `None` if it does not exist.
+type Stamp = Option<(SystemTime, u64)>;
+
+/// A template being previewed and its latest rendering.
+struct Preview {
+    template_path: PathBuf,
+    base_dir: PathBuf,
+    options: ProcessOptions,
+    state: Mutex<State>,
+    /// Notified whenever the rendering changes
+    changed: Condvar,
+}
+
+struct State {
+    /// Incremented on every re-render
+    generation: usize,
+    /// The rendered template (or its error) as HTML
+    html: String,
+    /// The files the rendering depends on, with their stamps at the time
+    watched: BTreeMap<PathBuf, Stamp>,
+}
+
+/// Serves a live HTML preview of the template at `template_path`, with includes
+/// resolved against `base_dir`, over HTTP on `addr` (e.g. `127.0.0.1:8700`) until
+/// the process is stopped.
+///
+/// The template, its base templates, its includes (and their directories, to notice
+/// new glob matches) and `clamp.toml` are watched; whenever one of them changes, the
+/// template is rendered again and open pages update in place.
+pub fn serve(template_path: &Path, base_dir: &Path, options: ProcessOptions, addr: &str) -> Result<()> {
+    let listener =
+        TcpListener::bind(addr).with_context(|| format!("Failed to listen on '{addr}'"))?;
+    let local_addr = listener.local_addr().context("Failed to determine listen address")?;
+
+    let mut preview = Preview {
+        template_path: template_path.to_path_buf(),
+        base_dir: base_dir.to_path_buf(),
+        options,
+        state: Mutex::new(State {
+            generation: 0,
+            html: String::new(),
+            watched: BTreeMap::new(),
+        }),
+        changed: Condvar::new(),
+    };
+    let (html, watched) = preview.render(&BTreeMap::new());
+    *preview.state.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner()) = State {
+        generation: 1,
+        html,
+        watched,
+    };
+    log::info!(
+        "Previewing '{}' on http://{local_addr}/ (watching for changes)",
+        template_path.display()
+    );
+
+    let preview = Arc::new(preview);
+    let watcher = preview.clone();
+    thread::spawn(move || watcher.watch());
+    for stream in listener.incoming() {
+        let Ok(stream) = stream else {
+            continue; // The client went away before the connection was accepted
+        };
+        let preview = preview.clone();
+        thread::spawn(move || {
+            if let Err(e) = preview.handle_connection(&stream) {
+                log::warning!("HTTP connection failed: {e:#}");
+            }
+        });
+    }
+    Ok(())
+}
+
+impl Preview {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Renders the template as HTML, returning it with the files it depends on. If
+    /// rendering fails, the error is shown instead and the `previous` files stay
+    /// watched, so fixing a broken include triggers a new rendering.
+    fn render(&self, previous: &BTreeMap<PathBuf, Stamp>) -> (String, BTreeMap<PathBuf, Stamp>) {
+        let mut paths = vec![self.template_path.clone()];
+        paths.extend(find_config_file(&OsFileSystem, &self.base_dir));
+        let html = match self.process() {
+            Ok(result) => {
+                for path in result.current_hashes.keys() {
+                    paths.push(path.clone());
+                    paths.extend(path.parent().map(Path::to_path_buf));
+                }
+                markdown::to_html(&result.output_content)
+            }
+            Err(e) => {
+                log::warning!("{e:#}");
+                paths.extend(previous.keys().cloned());
+                format!("<pre class=\"error\">{}</pre>\n", markdown::escape(&format!("Error: {e:#}")))
+            }
+        };
+        let watched = paths.into_iter().map(|path| {
+            let stamp = stamp(&path);
+            (path, stamp)
+        });
+        (html, watched.collect())
+    }
+
+    fn process(&self) -> Result<crate::ProcessResult> {
+        let content = fs::read_to_string(&self.template_path).with_context(|| {
+            format!("Failed to read template file '{}'", self.template_path.display())
+        })?;
+        let origin = self.template_path.display().to_string();
+        process_template_content(&content, &self.base_dir, &origin, &self.options)
+            .with_context(|| format!("Failed to process template '{origin}'"))
+    }
+
+    /// Polls the watched files, rendering the template again when one changes.
+    fn watch(&self) {
+        loop {
+            thread::sleep(POLL_INTERVAL);
+            let watched = self.lock().watched.clone();
+            if watched.iter().all(|(path, seen)| stamp(path) == *seen) {
+                continue;
+            }
+            let (html, watched) = self.render(&watched);
+            let mut state = self.lock();
+            state.generation += 1;
+            state.html = html;
+            state.watched = watched;
+            log::debug!("Rendered '{}' again (generation {})", self.template_path.display(), state.generation);
+            self.changed.notify_all();
+        }
+    }
+
+    fn handle_connection(&self, stream: &TcpStream) -> Result<()> {
+        let response = match read_get_target(stream)? {
+            Ok(target) => self.route(&target),
+            Err(response) => response,
+        };
+        write_response(stream, &response)
+    }
+
+    fn route(&self, target: &str) -> Response {
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        match path {
+            "/" => {
+                let state = self.lock();
+                let title = self
+                    .template_path
+                    .file_name()
+                    .map_or_else(|| self.template_path.display().to_string(), |name| name.to_string_lossy().into_owned());
+                Response {
+                    status: 200,
+                    content_type: "text/html; charset=utf-8",
+                    body: PAGE
+                        .replace("{title}", &markdown::escape(&title))
+                        .replace("{generation}", &state.generation.to_string())
+                        .replace("{body}", &state.html),
+                }
+            }
+            // Long polling: answers once the rendering is newer than `after`, or
+            // after a timeout with the current one
+            "/wait" => {
+                let after = query
+                    .split('&')
+                    .find_map(|pair| pair.strip_prefix("after="))
+                    .and_then(|value| value.parse().ok())
+                    .unwrap_or(0);
+                let state = self
+                    .changed
+                    .wait_timeout_while(self.lock(), WAIT_TIMEOUT, |state| state.generation <= after)
+                    .unwrap_or_else(|poisoned| poisoned.into_inner())
+                    .0;
+                Response::json(
+                    200,
+                    &Json::object([
+                        ("generation", Json::from(state.generation)),
+                        ("html", Json::from(state.html.as_str())),
+                    ]),
+                )
+            }
+            _ => Response::error(404, &format!("No such endpoint '{path}'")),
+        }
+    }
+}
+
+fn stamp(path: &Path) -> Stamp {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
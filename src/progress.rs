@@ -0,0 +1,157 @@
+use std::{
+    fmt::Debug,
+    io::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Receives progress notifications while a template is rendered.
+///
+/// Set through [`ProcessOptions::progress`](crate::ProcessOptions::progress). All methods
+/// default to doing nothing, so implementors only override what they need.
+pub trait ProgressListener: Debug + Send + Sync {
+    /// A directive resolved to `count` files that are about to be embedded
+    /// (1 for a plain include, the number of matches for a glob).
+    fn on_files_discovered(&self, count: usize) {
+        let _ = count;
+    }
+
+    /// Embedding of the file at `path` (as written in the directive) starts.
+    fn on_include_start(&self, path: &str) {
+        let _ = path;
+    }
+
+    /// Embedding of the file at `path` finished; `bytes` is the size of its content.
+    fn on_include_finish(&self, path: &str, bytes: usize) {
+        let _ = (path, bytes);
+    }
+}
+
+/// Width of the bar itself, in characters.
+const BAR_WIDTH: usize = 30;
+
+/// Longest file name shown next to the bar; longer paths keep their tail.
+const MAX_PATH_WIDTH: usize = 40;
+
+/// Delay before the bar first appears, so quick builds do not flicker.
+const SHOW_DELAY: Duration = Duration::from_millis(200);
+
+/// Minimum time between redraws.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(50);
+
+/// A single-line progress bar drawn on stderr, meant for interactive terminals.
+///
+/// Call [`TerminalProgress::finish`] once rendering is done to erase the bar.
+#[derive(Debug)]
+pub struct TerminalProgress {
+    started: Instant,
+    state: Mutex<BarState>,
+}
+
+#[derive(Debug, Default)]
+struct BarState {
+    total: usize,
+    done: usize,
+    bytes: usize,
+    current: String,
+    last_draw: Option<Instant>,
+}
+
+impl TerminalProgress {
+    pub fn new() -> Self {
+        Self {
+            started: Instant::now(),
+            state: Mutex::new(BarState::default()),
+        }
+    }
+
+    /// Erases the bar, if it was drawn.
+    pub fn finish(&self) {
+        let state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.last_draw.is_some() {
+            let mut stderr = io::stderr().lock();
+            let _ = write!(stderr, "\r\x1b[K");
+            let _ = stderr.flush();
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut BarState)) {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        change(&mut state);
+
+        let now = Instant::now();
+        let due = match state.last_draw {
+            Some(last) => now.duration_since(last) >= REDRAW_INTERVAL,
+            None => now.duration_since(self.started) >= SHOW_DELAY,
+        };
+        if due {
+            state.last_draw = Some(now);
+            draw(&state);
+        }
+    }
+}
+
+impl Default for TerminalProgress {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressListener for TerminalProgress {
+    fn on_files_discovered(&self, count: usize) {
+        self.update(|state| state.total += count);
+    }
+
+    fn on_include_start(&self, path: &str) {
+        self.update(|state| state.current = path.to_string());
+    }
+
+    fn on_include_finish(&self, _path: &str, bytes: usize) {
+        self.update(|state| {
+            state.done += 1;
+            state.bytes += bytes;
+        });
+    }
+}
+
+fn draw(state: &BarState) {
+    let total = state.total.max(state.done);
+    let filled = (state.done * BAR_WIDTH).checked_div(total).unwrap_or(0);
+    let bar = format!("{}{}", "=".repeat(filled), " ".repeat(BAR_WIDTH - filled));
+
+    let mut stderr = io::stderr().lock();
+    let _ = write!(
+        stderr,
+        "\r\x1b[K[{bar}] {}/{} files, {}  {}",
+        state.done,
+        total,
+        format_bytes(state.bytes),
+        truncate_path(&state.current)
+    );
+    let _ = stderr.flush();
+}
+
+fn truncate_path(path: &str) -> String {
+    let count = path.chars().count();
+    if count <= MAX_PATH_WIDTH {
+        return path.to_string();
+    }
+    let tail: String = path.chars().skip(count - (MAX_PATH_WIDTH - 3)).collect();
+    format!("...{tail}")
+}
+
+/// Formats a byte count with a binary unit, e.g. `1.5 MiB`.
+pub fn format_bytes(bytes: usize) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", UNITS[unit])
+    }
+}
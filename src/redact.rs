@@ -0,0 +1,122 @@
+use anyhow::{Context, Result, bail};
+use regex::{Captures, Regex};
+use std::{borrow::Cow, collections::BTreeMap};
+
+use crate::config::RedactConfig;
+
+/// The built-in redaction rules: name and pattern. A `secret` group, if present,
+/// marks the part of the match that is replaced, so that e.g. the `Bearer` keyword
+/// stays in the output.
+pub const BUILTIN_RULES: &[(&str, &str)] = &[
+    ("aws-access-key", r"\b(?:AKIA|ASIA|AGPA|AIDA|AROA|ANPA)[0-9A-Z]{16}\b"),
+    (
+        "aws-secret-key",
+        r#"(?i)aws_?secret_?(?:access_?)?key["']?\s*[:=]\s*["']?(?P<secret>[A-Za-z0-9/+=]{40})\b"#,
+    ),
+    ("bearer-token", r"(?i)\bbearer\s+(?P<secret>[A-Za-z0-9\-._~+/]{16,}=*)"),
+    (
+        "private-key",
+        r"-----BEGIN (?:[A-Z0-9]+ )*PRIVATE KEY(?: BLOCK)?-----[\s\S]*?-----END (?:[A-Z0-9]+ )*PRIVATE KEY(?: BLOCK)?-----",
+    ),
+];
+
+/// The start of the marker a redacted secret is replaced with, `[REDACTED:<rule>]`.
+pub const MARKER_PREFIX: &str = "[REDACTED:";
+
+/// Replaces secrets in included content with `[REDACTED:<rule>]` before it reaches
+/// the output, using the [built-in rules](BUILTIN_RULES) and those of `clamp.toml`.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    rules: Vec<(String, Regex)>,
+}
+
+/// The result of [`Redactor::redact`].
+#[derive(Debug, Clone)]
+pub struct Redaction<'a> {
+    /// The content with every match replaced.
+    pub content: Cow<'a, str>,
+    /// Number of replacements per rule name, for the rules that matched.
+    pub counts: BTreeMap<String, usize>,
+}
+
+impl Redactor {
+    /// Compiles the rules `config` enables: the built-in ones not disabled, then the
+    /// user-defined ones (which replace a built-in rule of the same name).
+    pub fn new(config: &RedactConfig) -> Result<Self> {
+        if !config.enabled {
+            return Ok(Self::default());
+        }
+        for name in &config.disable {
+            if !BUILTIN_RULES.iter().any(|(builtin, _)| builtin == name) {
+                bail!("Unknown built-in redaction rule '{name}' in 'disable'");
+            }
+        }
+        let builtin = BUILTIN_RULES
+            .iter()
+            .filter(|(name, _)| !config.disable.iter().any(|disabled| disabled == name))
+            .filter(|(name, _)| !config.rules.contains_key(*name))
+            .map(|(name, pattern)| (name.to_string(), pattern.to_string()));
+        let custom = config
+            .rules
+            .iter()
+            .map(|(name, pattern)| (name.clone(), pattern.clone()));
+        let rules = builtin
+            .chain(custom)
+            .map(|(name, pattern)| {
+                let regex = Regex::new(&pattern)
+                    .with_context(|| format!("Invalid pattern for redaction rule '{name}'"))?;
+                Ok((name, regex))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { rules })
+    }
+
+    /// Whether no rules are enabled, so that [`redact`](Self::redact) never changes anything.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Applies every rule to `content` in turn.
+    pub fn redact<'a>(&self, content: &'a str) -> Redaction<'a> {
+        let mut redacted = Cow::Borrowed(content);
+        let mut counts = BTreeMap::new();
+        for (name, regex) in &self.rules {
+            let mut count = 0;
+            let replaced = regex.replace_all(&redacted, |captures: &Captures| {
+                count += 1;
+                let whole = captures.get(0).expect("capture 0 is the whole match");
+                match captures.name("secret") {
+                    Some(secret) => format!(
+                        "{}{MARKER_PREFIX}{name}]{}",
+                        &whole.as_str()[..secret.start() - whole.start()],
+                        &whole.as_str()[secret.end() - whole.start()..]
+                    ),
+                    None => format!("{MARKER_PREFIX}{name}]"),
+                }
+            });
+            if let Cow::Owned(replaced) = replaced {
+                redacted = Cow::Owned(replaced);
+                counts.insert(name.clone(), count);
+            }
+        }
+        Redaction {
+            content: redacted,
+            counts,
+        }
+    }
+}
+
+impl Redaction<'_> {
+    /// Total number of replacements.
+    pub fn total(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    /// e.g. `2 secrets (aws-access-key, bearer-token)`.
+    pub fn summary(&self) -> String {
+        let total = self.total();
+        let noun = if total == 1 { "secret" } else { "secrets" };
+        let rules: Vec<&str> = self.counts.keys().map(String::as_str).collect();
+        format!("{total} {noun} ({})", rules.join(", "))
+    }
+}
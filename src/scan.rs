@@ -0,0 +1,118 @@
+use anyhow::Result;
+use std::{
+    fmt::Write,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    graph::is_template,
+    ignore::{IgnoreRules, walk_files},
+    vfs::{FileSystem, OsFileSystem},
+};
+
+/// How many directory levels below the project root are scanned for key files.
+const SCAN_DEPTH: usize = 3;
+
+/// Maximum number of includes written by a scan.
+const MAX_SCANNED_FILES: usize = 20;
+
+/// Documentation and manifest file names, in priority order.
+const KEY_FILE_NAMES: &[&str] = &[
+    "README.md",
+    "README.rst",
+    "README.txt",
+    "README",
+    "Cargo.toml",
+    "package.json",
+    "pyproject.toml",
+    "setup.py",
+    "go.mod",
+    "pom.xml",
+    "build.gradle",
+    "build.gradle.kts",
+    "Gemfile",
+    "composer.json",
+    "CMakeLists.txt",
+    "Makefile",
+];
+
+/// Library and binary entry point file names, in priority order.
+const ENTRY_POINTS: &[&str] = &[
+    "lib.rs",
+    "main.rs",
+    "__main__.py",
+    "main.py",
+    "app.py",
+    "main.go",
+    "index.ts",
+    "index.js",
+    "main.ts",
+    "main.js",
+];
+
+/// Entry point names that only count inside a source directory (e.g. `src/main.rs`),
+/// so stray copies elsewhere in the tree are not picked up.
+const ENTRY_POINT_DIRS: &[&str] = &["", "src", "cmd", "lib", "app"];
+
+/// Walks the project at `root` (respecting `.gitignore`) and picks its key files:
+/// the README, package manifests, and library/binary entry points.
+///
+/// Returns paths relative to `root`, shallowest and most important first.
+pub fn scan_project(root: &Path) -> Result<Vec<PathBuf>> {
+    let files = walk_files(&OsFileSystem, root, &IgnoreRules::new(), true, Some(SCAN_DEPTH))?;
+
+    let mut key_files: Vec<(usize, usize, PathBuf)> = files
+        .into_iter()
+        .filter_map(|path| {
+            let name = path.file_name()?.to_str()?;
+            let priority = match KEY_FILE_NAMES.iter().position(|key| *key == name) {
+                Some(priority) => priority,
+                None if in_entry_point_dir(&path) => {
+                    KEY_FILE_NAMES.len() + ENTRY_POINTS.iter().position(|key| *key == name)?
+                }
+                None => return None,
+            };
+            Some((path.components().count(), priority, path))
+        })
+        .collect();
+
+    key_files.sort();
+    key_files.truncate(MAX_SCANNED_FILES);
+    Ok(key_files.into_iter().map(|(_, _, path)| path).collect())
+}
+
+/// Renders a template including each of `files`.
+pub fn scanned_template(files: &[PathBuf]) -> String {
+    let mut template = String::from("Here is the context of my project:\n");
+    for file in files {
+        let path = file
+            .components()
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/");
+        write!(template, "\n[[include: {path}]]\n")
+            .expect("Writing to String buffer failed unexpectedly");
+    }
+    template.push_str("\n<describe your question here>\n");
+    template
+}
+
+/// Whether the file's parent directory is a conventional source directory,
+/// possibly nested in a workspace member (e.g. `crates/foo/src/lib.rs`).
+fn in_entry_point_dir(path: &Path) -> bool {
+    let parent_name = path
+        .parent()
+        .and_then(|parent| parent.file_name())
+        .and_then(|name| name.to_str())
+        .unwrap_or("");
+    ENTRY_POINT_DIRS.contains(&parent_name)
+}
+
+/// Finds every `.clamp` template below `root` (respecting ignore files),
+/// as sorted paths relative to `root`.
+pub fn find_templates(fs: &dyn FileSystem, root: &Path) -> Result<Vec<PathBuf>> {
+    Ok(walk_files(fs, root, &IgnoreRules::new(), true, None)?
+        .into_iter()
+        .filter(|path| is_template(path))
+        .collect())
+}
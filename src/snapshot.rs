@@ -0,0 +1,411 @@
+use anyhow::{Context, Result, bail};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{calculate_hash, config::Config, find_repo_root, vfs::OsFileSystem};
+
+/// Directory of the snapshot store, relative to the repository root.
+pub const STORE_DIR: &str = ".clamp/objects";
+
+/// First bytes of every stored object.
+const MAGIC: &[u8] = b"CLZ1";
+
+/// Shortest back-reference the compressor emits.
+const MIN_MATCH: usize = 4;
+
+/// Furthest back a reference may point (offsets are stored as two bytes).
+const WINDOW: usize = u16::MAX as usize;
+
+/// Most bytes of output a byte of compressed data can produce: each continuation
+/// byte of a match length adds up to 255.
+const MAX_EXPANSION: usize = 255;
+
+/// Number of bits of the compressor's match-finder hash.
+const HASH_BITS: u32 = 16;
+
+/// A content-addressed store of compressed include snapshots, named by the same
+/// SHA256 hash the lockfile records, so that `clamp diff` can show what changed
+/// in a file since it was locked.
+///
+/// Objects live at `<root>/.clamp/objects/<first two hex digits>/<remaining digits>`.
+#[derive(Debug, Clone)]
+pub struct SnapshotStore {
+    dir: PathBuf,
+}
+
+/// What [`SnapshotStore::gc`] removed (or would remove, in a dry run).
+#[derive(Debug, Default)]
+pub struct GcSummary {
+    /// Hashes of the unreferenced objects.
+    pub removed: Vec<String>,
+    /// Total size of the unreferenced objects in bytes.
+    pub freed_bytes: u64,
+}
+
+impl SnapshotStore {
+    /// The store of the repository containing `start`, or of `start` itself outside
+    /// a repository. The store directory is created on first write.
+    pub fn locate(start: &Path) -> Self {
+        let root = find_repo_root(&OsFileSystem, start)
+            .unwrap_or_else(|| fs::canonicalize(start).unwrap_or_else(|_| start.to_path_buf()));
+        Self {
+            dir: root.join(STORE_DIR),
+        }
+    }
+
+    /// The store's directory.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn object_path(&self, hash: &str) -> PathBuf {
+        let (prefix, rest) = hash.split_at(2.min(hash.len()));
+        self.dir.join(prefix).join(rest)
+    }
+
+    /// Whether a snapshot with this hash is stored.
+    pub fn contains(&self, hash: &str) -> bool {
+        self.object_path(hash).is_file()
+    }
+
+    /// Returns the content stored under `hash`, or `None` if there is no such snapshot.
+    pub fn load(&self, hash: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.object_path(hash);
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let data = fs::read(&path)
+            .with_context(|| format!("Failed to read snapshot '{}'", path.display()))?;
+        let content = decompress(&data)
+            .with_context(|| format!("Failed to decompress snapshot '{}'", path.display()))?;
+        if calculate_hash(&content) != hash {
+            bail!("Snapshot '{}' is corrupt (hash mismatch)", path.display());
+        }
+        Ok(Some(content))
+    }
+
+    /// Stores `content` under its hash. Returns whether a new object was written.
+    pub fn store(&self, content: &[u8]) -> Result<bool> {
+        let hash = calculate_hash(content);
+        if self.contains(&hash) {
+            return Ok(false);
+        }
+        let path = self.object_path(&hash);
+        let parent = path.parent().expect("Object paths have a parent directory");
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create snapshot directory '{}'", parent.display()))?;
+        // Write under a temporary name first so an interrupted write never leaves a
+        // truncated object behind
+        let partial = path.with_extension("partial");
+        fs::write(&partial, compress(content))
+            .with_context(|| format!("Failed to write snapshot '{}'", partial.display()))?;
+        fs::rename(&partial, &path)
+            .with_context(|| format!("Failed to write snapshot '{}'", path.display()))?;
+        Ok(true)
+    }
+
+    /// Stores a snapshot of each file in `hashes` (path -> locked hash) whose current
+    /// content has that hash. Files embedded only in part (e.g. with `head=`) hash
+    /// differently from their content and are skipped. `config` determines what is
+    /// hashed (see [`Config::hashed_content`]). Returns the number of new objects.
+    pub fn store_files(&self, hashes: &BTreeMap<PathBuf, String>, config: &Config) -> Result<usize> {
+        let mut stored = 0;
+        for (path, hash) in hashes {
+            if self.contains(hash) || !path.is_file() {
+                continue;
+            }
+            let content = fs::read(path)
+                .with_context(|| format!("Failed to read file '{}'", path.display()))?;
+            let content = config.hashed_content(&content);
+            if calculate_hash(&content) == *hash && self.store(&content)? {
+                stored += 1;
+            }
+        }
+        Ok(stored)
+    }
+
+    /// Removes every object whose hash is not in `referenced`. With `dry_run`,
+    /// only reports what would be removed.
+    pub fn gc(&self, referenced: &BTreeSet<String>, dry_run: bool) -> Result<GcSummary> {
+        let mut summary = GcSummary::default();
+        if !self.dir.is_dir() {
+            return Ok(summary);
+        }
+        for prefix_entry in read_dir(&self.dir)? {
+            let prefix_dir = prefix_entry.path();
+            if !prefix_dir.is_dir() {
+                continue;
+            }
+            let prefix = prefix_entry.file_name().to_string_lossy().into_owned();
+            for entry in read_dir(&prefix_dir)? {
+                let hash = format!("{prefix}{}", entry.file_name().to_string_lossy());
+                if referenced.contains(&hash) {
+                    continue;
+                }
+                let path = entry.path();
+                summary.freed_bytes += entry.metadata().map_or(0, |metadata| metadata.len());
+                if !dry_run {
+                    fs::remove_file(&path)
+                        .with_context(|| format!("Failed to remove snapshot '{}'", path.display()))?;
+                }
+                summary.removed.push(hash);
+            }
+            if !dry_run {
+                // Only succeeds once the directory is empty
+                let _ = fs::remove_dir(&prefix_dir);
+            }
+        }
+        summary.removed.sort();
+        Ok(summary)
+    }
+}
+
+fn read_dir(dir: &Path) -> Result<Vec<fs::DirEntry>> {
+    fs::read_dir(dir)
+        .and_then(|entries| entries.collect())
+        .with_context(|| format!("Failed to read snapshot directory '{}'", dir.display()))
+}
+
+/// Compresses `input` with a small LZ77 scheme in the style of LZ4: a sequence of
+/// literal runs, each followed by a back-reference into the previous 64 KiB.
+///
+/// Each sequence starts with a token byte holding the literal length (high nibble)
+/// and the match length minus [`MIN_MATCH`] (low nibble); a nibble of 15 continues
+/// in following bytes, each adding up to 255. The literals come next, then the match
+/// offset as two little-endian bytes. The final sequence has literals only.
+fn compress(input: &[u8]) -> Vec<u8> {
+    let mut output = MAGIC.to_vec();
+    write_varint(&mut output, input.len());
+
+    let mut table = vec![usize::MAX; 1 << HASH_BITS];
+    let (mut position, mut anchor) = (0, 0);
+    while position + MIN_MATCH <= input.len() {
+        let slot = hash_slot(&input[position..position + MIN_MATCH]);
+        let candidate = table[slot];
+        table[slot] = position;
+        if candidate != usize::MAX
+            && position - candidate <= WINDOW
+            && input[candidate..candidate + MIN_MATCH] == input[position..position + MIN_MATCH]
+        {
+            let mut length = MIN_MATCH;
+            while position + length < input.len() && input[candidate + length] == input[position + length] {
+                length += 1;
+            }
+            write_sequence(&mut output, &input[anchor..position], Some((position - candidate, length)));
+            position += length;
+            anchor = position;
+        } else {
+            position += 1;
+        }
+    }
+    write_sequence(&mut output, &input[anchor..], None);
+    output
+}
+
+/// Reverses [`compress`].
+fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let Some(data) = data.strip_prefix(MAGIC) else {
+        bail!("Not a snapshot object");
+    };
+    let mut reader = Reader { data, position: 0 };
+    let length = reader.varint()?;
+    // A corrupt length must not be allocated up front
+    if length > data.len().saturating_mul(MAX_EXPANSION) {
+        bail!("Snapshot object declares a length of {length} bytes, more than its data can hold");
+    }
+    let mut output = Vec::with_capacity(length);
+    let check_length = |size: usize| -> Result<()> {
+        if size > length {
+            bail!("Snapshot object is longer than its declared length of {length} bytes");
+        }
+        Ok(())
+    };
+
+    while !reader.at_end() {
+        let token = reader.byte()?;
+        let literals = reader.length(usize::from(token >> 4))?;
+        check_length(output.len().saturating_add(literals))?;
+        output.extend_from_slice(reader.take(literals)?);
+        if reader.at_end() {
+            break;
+        }
+        let offset = usize::from(u16::from_le_bytes([reader.byte()?, reader.byte()?]));
+        if offset == 0 || offset > output.len() {
+            bail!("Snapshot object has an invalid back-reference");
+        }
+        let matched = reader.length(usize::from(token & 0x0f))? + MIN_MATCH;
+        check_length(output.len() + matched)?;
+        // Byte by byte: a match may overlap the bytes it produces
+        for _ in 0..matched {
+            output.push(output[output.len() - offset]);
+        }
+    }
+
+    if output.len() != length {
+        bail!("Snapshot object is shorter than its declared length of {length} bytes");
+    }
+    Ok(output)
+}
+
+fn hash_slot(bytes: &[u8]) -> usize {
+    let value = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    (value.wrapping_mul(2_654_435_761) >> (32 - HASH_BITS)) as usize
+}
+
+fn write_sequence(output: &mut Vec<u8>, literals: &[u8], matched: Option<(usize, usize)>) {
+    let match_nibble = matched.map_or(0, |(_, length)| (length - MIN_MATCH).min(15));
+    output.push(((literals.len().min(15) << 4) | match_nibble) as u8);
+    write_length(output, literals.len());
+    output.extend_from_slice(literals);
+    if let Some((offset, length)) = matched {
+        output.extend_from_slice(&(offset as u16).to_le_bytes());
+        write_length(output, length - MIN_MATCH);
+    }
+}
+
+/// Writes the continuation bytes of a length whose nibble is 15.
+fn write_length(output: &mut Vec<u8>, length: usize) {
+    if length < 15 {
+        return;
+    }
+    let mut rest = length - 15;
+    while rest >= 255 {
+        output.push(255);
+        rest -= 255;
+    }
+    output.push(rest as u8);
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn at_end(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self.data.get(self.position).context("Snapshot object is truncated")?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + count)
+            .context("Snapshot object is truncated")?;
+        self.position += count;
+        Ok(bytes)
+    }
+
+    /// Reads the continuation bytes of a length starting with `nibble`.
+    fn length(&mut self, nibble: usize) -> Result<usize> {
+        let mut length = nibble;
+        if nibble == 15 {
+            loop {
+                let byte = self.byte()?;
+                length += usize::from(byte);
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    }
+
+    fn varint(&mut self) -> Result<usize> {
+        let mut value = 0usize;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= usize::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        bail!("Snapshot object has an invalid length")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(input: &[u8]) -> Vec<u8> {
+        let compressed = compress(input);
+        assert_eq!(decompress(&compressed).unwrap(), input);
+        compressed
+    }
+
+    #[test]
+    fn round_trips_empty_input() {
+        round_trip(b"");
+    }
+
+    #[test]
+    fn round_trips_incompressible_input() {
+        // A xorshift sequence has no repeats for the compressor to find
+        let mut state = 0x2545_f491_u32;
+        let input: Vec<u8> = (0..100_000)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        let compressed = round_trip(&input);
+        assert!(compressed.len() < input.len() + input.len() / 100 + 16);
+    }
+
+    #[test]
+    fn round_trips_long_runs() {
+        let compressed = round_trip(&vec![b'a'; 1_000_000]);
+        assert!(compressed.len() < 5_000);
+        round_trip(&b"abc".repeat(100_000));
+
+        let text: String = (0..5_000).map(|index| format!("line {}\n", index % 300)).collect();
+        round_trip(text.as_bytes());
+    }
+
+    #[test]
+    fn round_trips_short_inputs() {
+        for input in [&b"a"[..], b"abcd", b"abcdabcd", b"aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa"] {
+            round_trip(input);
+        }
+    }
+
+    #[test]
+    fn rejects_corrupt_lengths() {
+        let mut huge = MAGIC.to_vec();
+        write_varint(&mut huge, usize::MAX >> 1);
+        huge.push(0);
+        let error = decompress(&huge).unwrap_err();
+        assert!(error.to_string().contains("more than its data can hold"), "{error}");
+
+        let mut compressed = compress(b"hello hello hello hello");
+        compressed[MAGIC.len()] -= 1; // Declared length one byte short
+        let error = decompress(&compressed).unwrap_err();
+        assert!(error.to_string().contains("longer than its declared length"), "{error}");
+
+        let mut compressed = compress(b"hello hello hello hello");
+        compressed[MAGIC.len()] += 1;
+        let error = decompress(&compressed).unwrap_err();
+        assert!(error.to_string().contains("shorter than its declared length"), "{error}");
+
+        assert!(decompress(b"not an object").is_err());
+    }
+}
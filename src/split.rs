@@ -0,0 +1,124 @@
+use anyhow::{Result, bail};
+use std::ops::Range;
+
+use crate::EmbeddedBlock;
+
+/// One part of a split output (see [`split_output`]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Part {
+    /// The continuation header followed by the part's share of the output.
+    pub content: String,
+    /// Estimated tokens of `content`.
+    pub tokens: usize,
+    /// Whether the part exceeds the budget because a single block does.
+    pub oversized: bool,
+}
+
+/// Estimates the number of tokens a model reads for `text`, at about four characters
+/// per token. Real tokenizers differ by model, so budgets should leave some headroom.
+pub fn estimate_tokens(text: &str) -> usize {
+    text.chars().count().div_ceil(4)
+}
+
+/// Splits `output` into parts of at most `max_tokens` estimated tokens each, header
+/// included, so that a context too large for a model's window can be pasted in turns.
+///
+/// Parts are cut only at the boundaries of the embedded `blocks` (see
+/// [`ProcessResult::includes`](crate::ProcessResult::includes)) and, within the
+/// template's own text, after blank lines, so no file is torn apart. A block larger
+/// than the budget gets a part of its own, marked as [`oversized`](Part::oversized).
+/// Without the headers, the parts concatenate to `output`. Output that fits the budget
+/// is returned as a single part without a header.
+pub fn split_output(output: &str, blocks: &[EmbeddedBlock], max_tokens: usize) -> Result<Vec<Part>> {
+    // Room for the longest header this could produce
+    let reserved = estimate_tokens(&header(usize::MAX, usize::MAX));
+    if max_tokens <= reserved {
+        bail!("A part must hold more than {reserved} tokens to fit its header");
+    }
+    if estimate_tokens(output) <= max_tokens {
+        return Ok(vec![Part {
+            content: output.to_string(),
+            tokens: estimate_tokens(output),
+            oversized: false,
+        }]);
+    }
+
+    let budget = max_tokens - reserved;
+    let mut ranges: Vec<Range<usize>> = Vec::new();
+    let mut current: Option<(Range<usize>, usize)> = None;
+    for unit in units(output, blocks) {
+        let tokens = estimate_tokens(&output[unit.clone()]);
+        current = match current {
+            Some((range, used)) if used + tokens <= budget || used == 0 => {
+                Some((range.start..unit.end, used + tokens))
+            }
+            Some((range, _)) => {
+                ranges.push(range);
+                Some((unit, tokens))
+            }
+            None => Some((unit, tokens)),
+        };
+    }
+    ranges.extend(current.map(|(range, _)| range));
+
+    let count = ranges.len();
+    Ok(ranges
+        .into_iter()
+        .enumerate()
+        .map(|(index, range)| {
+            let text = &output[range];
+            let content = format!("{}\n\n{text}", header(index + 1, count));
+            Part {
+                tokens: estimate_tokens(&content),
+                oversized: estimate_tokens(text) > budget,
+                content,
+            }
+        })
+        .collect())
+}
+
+/// The line heading part `number` of `count`.
+fn header(number: usize, count: usize) -> String {
+    let position = match number {
+        1 => format!("> Part 1 of {count}."),
+        _ => format!("> Part {number} of {count}, continued from part {}.", number - 1),
+    };
+    if number == count {
+        format!("{position} This is the last part.")
+    } else {
+        format!("{position} The next part continues this context.")
+    }
+}
+
+/// The pieces of `output` that parts are made of: each block, and the paragraphs of
+/// the text around them.
+fn units(output: &str, blocks: &[EmbeddedBlock]) -> Vec<Range<usize>> {
+    let mut units = Vec::new();
+    let text_units = |start: usize, end: usize, units: &mut Vec<Range<usize>>| {
+        let mut unit_start = start;
+        let mut offset = start;
+        for line in output[start..end].split_inclusive('\n') {
+            offset += line.len();
+            if line.trim().is_empty() {
+                units.push(unit_start..offset);
+                unit_start = offset;
+            }
+        }
+        if unit_start < end {
+            units.push(unit_start..end);
+        }
+    };
+
+    let mut position = 0;
+    for block in blocks {
+        let range = &block.output_range;
+        if range.start < position || range.is_empty() {
+            continue; // Not expected: blocks are recorded in output order
+        }
+        text_units(position, range.start, &mut units);
+        units.push(range.clone());
+        position = range.end;
+    }
+    text_units(position, output.len(), &mut units);
+    units
+}
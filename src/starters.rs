@@ -0,0 +1,112 @@
+/// A built-in starter template for `clamp init --template <name>`.
+#[derive(Debug, Clone, Copy)]
+pub struct Starter {
+    /// Name used to select the starter.
+    pub name: &'static str,
+    /// One-line description shown in help output.
+    pub description: &'static str,
+    /// The template content written by `init`.
+    pub content: &'static str,
+}
+
+/// The trivial sample written by `clamp init` without `--template`.
+pub const DEFAULT_STARTER: Starter = Starter {
+    name: "sample",
+    description: "Minimal sample including the README",
+    content: "
+TL;DR how to use this?
+
+[[include: README.md]]",
+};
+
+/// Built-in starter templates, in the order they are listed.
+pub const STARTERS: &[Starter] = &[
+    DEFAULT_STARTER,
+    Starter {
+        name: "bug-report",
+        description: "Describe a bug and ask for a diagnosis",
+        content: r#"+++
+linenos = true
++++
+I am seeing a bug and need help finding its cause.
+
+## Expected behavior
+
+<describe what should happen>
+
+## Actual behavior
+
+<describe what happens instead, including error messages>
+
+## Steps to reproduce
+
+1. <first step>
+
+## Relevant code
+
+[[include: src/main.rs]]
+
+Please identify the most likely cause, referring to line numbers, and suggest a minimal fix.
+"#,
+    },
+    Starter {
+        name: "code-review",
+        description: "Ask for a review of a set of files",
+        content: r#"+++
+linenos = true
+dedup = true
++++
+Please review the following code as an experienced maintainer would.
+
+Focus on correctness, error handling, naming, and anything that would surprise a future reader.
+For each finding, name the file and line, explain the problem, and propose a concrete change.
+
+[[include: src/main.rs]]
+"#,
+    },
+    Starter {
+        name: "refactor-request",
+        description: "Request a behavior-preserving refactoring",
+        content: r#"+++
+dedup = true
++++
+I want to refactor the code below without changing its behavior.
+
+## Goal
+
+<describe the desired structure, e.g. "split parsing from rendering">
+
+## Constraints
+
+- Keep the public API unchanged.
+- Keep changes minimal and reviewable.
+
+## Code
+
+[[include: src/main.rs]]
+
+Please propose the refactoring as a sequence of small steps, showing the changed code for each.
+"#,
+    },
+    Starter {
+        name: "architecture-overview",
+        description: "Ask for a high-level explanation of a project",
+        content: r#"+++
+collapsible = true
++++
+Please give me an architecture overview of this project: its main components,
+how data flows between them, and where a newcomer should start reading.
+
+[[include: README.md]]
+
+[[include: Cargo.toml]]
+
+[[include: src/main.rs]]
+"#,
+    },
+];
+
+/// Looks up a starter template by name.
+pub fn find_starter(name: &str) -> Option<&'static Starter> {
+    STARTERS.iter().find(|starter| starter.name == name)
+}
@@ -0,0 +1,338 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Debug,
+    fs,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::RwLock,
+};
+
+use crate::permalink;
+
+/// The kind of a file system entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Dir,
+    Symlink,
+}
+
+/// The metadata clamp needs about an entry.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metadata {
+    pub kind: FileKind,
+    /// Size in bytes.
+    pub len: u64,
+}
+
+/// An entry of a directory listing.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The directory's path joined with the entry's name.
+    pub path: PathBuf,
+    /// The entry's own kind, without following symlinks.
+    pub kind: FileKind,
+}
+
+/// Where templates, base templates, includes, ignore files, lockfiles and
+/// `clamp.toml` are read from, and lockfiles are written to.
+///
+/// Set through [`ProcessOptions::file_system`](crate::ProcessOptions::file_system);
+/// the default, [`OsFileSystem`], is the real file system. Supplying file contents
+/// with [`MemoryFileSystem`] instead keeps the library off the disk: functions that
+/// take a `FileSystem`, such as [`Lockfile::load`](crate::Lockfile::load) and
+/// [`Config::discover`](crate::config::Config::discover), use only it.
+///
+/// Linked remote files are downloaded through [`download`](Self::download), which runs
+/// `curl` unless overridden. Git refs (`include-changed`, `gitdiff`, and the branch of
+/// a link that is not pinned to a commit) are resolved by running `git`, and are not
+/// available through this abstraction.
+pub trait FileSystem: Debug + Send + Sync {
+    /// Reads the whole file at `path`.
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    /// Opens the file at `path` for reading in chunks. Defaults to reading it whole.
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(io::Cursor::new(self.read(path)?)))
+    }
+
+    /// The metadata of the entry at `path`, following symlinks.
+    fn metadata(&self, path: &Path) -> io::Result<Metadata>;
+
+    /// The metadata of the entry at `path` itself. Defaults to [`metadata`](Self::metadata),
+    /// for file systems without symlinks.
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        self.metadata(path)
+    }
+
+    /// The absolute path of `path` with every `.` and `..` component and symlink
+    /// resolved. Fails if nothing exists there.
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf>;
+
+    /// The entries of the directory at `path`, in any order.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    /// The target of the symlink at `path`.
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a symlink", path.display()),
+        ))
+    }
+
+    /// Reads the file at `path` as UTF-8.
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        String::from_utf8(self.read(path)?).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Replaces the contents of the file at `path` with `content`, creating it if needed.
+    /// Defaults to failing, for read-only file systems.
+    fn write(&self, path: &Path, _content: &[u8]) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            format!("Cannot write '{}': the file system is read-only", path.display()),
+        ))
+    }
+
+    /// Whether anything exists at `path` (following symlinks).
+    fn exists(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok()
+    }
+
+    /// Whether `path` is a file (following symlinks).
+    fn is_file(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|meta| meta.kind == FileKind::File)
+    }
+
+    /// Whether `path` is a directory (following symlinks).
+    fn is_dir(&self, path: &Path) -> bool {
+        self.metadata(path).is_ok_and(|meta| meta.kind == FileKind::Dir)
+    }
+
+    /// Downloads the linked file at `url` (see [`permalink::fetch`]). Returns `None`
+    /// if the server has no such file.
+    fn download(&self, url: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        permalink::fetch(url)
+    }
+}
+
+/// The real file system, through [`std::fs`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct OsFileSystem;
+
+fn os_metadata(meta: fs::Metadata) -> Metadata {
+    let file_type = meta.file_type();
+    let kind = if file_type.is_symlink() {
+        FileKind::Symlink
+    } else if file_type.is_dir() {
+        FileKind::Dir
+    } else {
+        FileKind::File
+    };
+    Metadata { kind, len: meta.len() }
+}
+
+impl FileSystem for OsFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn open(&self, path: &Path) -> io::Result<Box<dyn Read + '_>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::metadata(path).map(os_metadata)
+    }
+
+    fn symlink_metadata(&self, path: &Path) -> io::Result<Metadata> {
+        fs::symlink_metadata(path).map(os_metadata)
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::canonicalize(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        fs::read_dir(path)?
+            .map(|entry| {
+                let entry = entry?;
+                let file_type = entry.file_type()?;
+                let kind = if file_type.is_symlink() {
+                    FileKind::Symlink
+                } else if file_type.is_dir() {
+                    FileKind::Dir
+                } else {
+                    FileKind::File
+                };
+                Ok(DirEntry { path: entry.path(), kind })
+            })
+            .collect()
+    }
+
+    fn read_link(&self, path: &Path) -> io::Result<PathBuf> {
+        fs::read_link(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> io::Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        fs::write(path, content)
+    }
+}
+
+/// A file system held in memory: a set of files with their contents. Directories
+/// exist implicitly, as the ancestors of the files. Relative paths are taken relative
+/// to the root `/`, and `.` and `..` components are resolved lexically.
+/// [`write`](FileSystem::write) adds or replaces files, e.g. lockfiles.
+///
+/// ```text
+/// let mut files = MemoryFileSystem::new();
+/// files.insert("/project/prompt.clamp", "[[include: src/lib.rs]]");
+/// files.insert("/project/src/lib.rs", "pub fn answer() -> u32 { 42 }\n");
+/// let options = ProcessOptions { file_system: Some(Arc::new(files)), ..Default::default() };
+/// let result = process_template(Path::new("/project/prompt.clamp"), &options)?;
+/// ```
+#[derive(Debug, Default)]
+pub struct MemoryFileSystem {
+    files: RwLock<BTreeMap<PathBuf, Vec<u8>>>,
+}
+
+impl Clone for MemoryFileSystem {
+    fn clone(&self) -> Self {
+        Self {
+            files: RwLock::new(self.files().clone()),
+        }
+    }
+}
+
+impl MemoryFileSystem {
+    /// An empty file system.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds the file at `path` with `content`, replacing any file already there.
+    pub fn insert(&mut self, path: impl AsRef<Path>, content: impl Into<Vec<u8>>) {
+        self.files_mut().insert(normalize(path.as_ref()), content.into());
+    }
+
+    /// Removes the file at `path`, returning its content.
+    pub fn remove(&mut self, path: impl AsRef<Path>) -> Option<Vec<u8>> {
+        self.files_mut().remove(&normalize(path.as_ref()))
+    }
+
+    /// The files' paths, sorted.
+    pub fn paths(&self) -> Vec<PathBuf> {
+        self.files().keys().cloned().collect()
+    }
+
+    fn files(&self) -> std::sync::RwLockReadGuard<'_, BTreeMap<PathBuf, Vec<u8>>> {
+        self.files.read().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn files_mut(&mut self) -> &mut BTreeMap<PathBuf, Vec<u8>> {
+        self.files.get_mut().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    fn kind(&self, path: &Path) -> Option<FileKind> {
+        let files = self.files();
+        if files.contains_key(path) {
+            return Some(FileKind::File);
+        }
+        let is_dir = path.parent().is_none()
+            || files
+                .range(path.to_path_buf()..)
+                .next()
+                .is_some_and(|(file, _)| file.starts_with(path));
+        is_dir.then_some(FileKind::Dir)
+    }
+}
+
+/// `path` as an absolute path without `.` and `..` components.
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(name) => normalized.push(name),
+            Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+        }
+    }
+    normalized
+}
+
+fn not_found(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::NotFound,
+        format!("No such file or directory '{}'", path.display()),
+    )
+}
+
+impl FileSystem for MemoryFileSystem {
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        let path = normalize(path);
+        let content = self.files().get(&path).cloned();
+        match content {
+            Some(content) => Ok(content),
+            None if self.kind(&path).is_some() => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is a directory", path.display()),
+            )),
+            None => Err(not_found(&path)),
+        }
+    }
+
+    fn metadata(&self, path: &Path) -> io::Result<Metadata> {
+        let path = normalize(path);
+        let kind = self.kind(&path).ok_or_else(|| not_found(&path))?;
+        let len = self.files().get(&path).map_or(0, |content| content.len() as u64);
+        Ok(Metadata { kind, len })
+    }
+
+    fn canonicalize(&self, path: &Path) -> io::Result<PathBuf> {
+        let path = normalize(path);
+        self.kind(&path).map(|_| path.clone()).ok_or_else(|| not_found(&path))
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let dir = normalize(path);
+        if self.kind(&dir) != Some(FileKind::Dir) {
+            return Err(not_found(&dir));
+        }
+        let mut entries: BTreeMap<PathBuf, FileKind> = BTreeMap::new();
+        for file in self.files().keys().filter(|file| file.starts_with(&dir)) {
+            let Ok(relative) = file.strip_prefix(&dir) else {
+                continue;
+            };
+            let mut components = relative.components();
+            let Some(name) = components.next() else {
+                continue;
+            };
+            let kind = if components.next().is_some() {
+                FileKind::Dir
+            } else {
+                FileKind::File
+            };
+            entries.insert(dir.join(name), kind);
+        }
+        Ok(entries.into_iter().map(|(path, kind)| DirEntry { path, kind }).collect())
+    }
+
+    fn write(&self, path: &Path, content: &[u8]) -> io::Result<()> {
+        let path = normalize(path);
+        if self.kind(&path) == Some(FileKind::Dir) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("'{}' is a directory", path.display()),
+            ));
+        }
+        let mut files = self.files.write().unwrap_or_else(|poisoned| poisoned.into_inner());
+        files.insert(path, content.to_vec());
+        Ok(())
+    }
+}
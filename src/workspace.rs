@@ -0,0 +1,163 @@
+use anyhow::{Context, Result, bail};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    ChangeStatus, Lockfile, ProcessOptions, ProcessResult, graph::display_path, json::Json,
+    process_template, process_template_content,
+    scan::find_templates, tracked_hashes,
+};
+
+/// A project directory whose templates are served to other programs
+/// (MCP clients, HTTP bots, editors).
+///
+/// Templates are addressed by their path relative to the root; paths that
+/// resolve outside the root are rejected. So are their includes, unless
+/// [`ProcessOptions::restrict_root`] names another sandbox. Everything, lockfiles
+/// included, is read through the options' [file system](ProcessOptions::file_system).
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    root: PathBuf,
+    options: ProcessOptions,
+}
+
+impl Workspace {
+    /// Creates a workspace rooted at `root`, processing templates with `options`.
+    /// Includes are confined to the root if `options` sets no `restrict_root`.
+    pub fn new(root: &Path, mut options: ProcessOptions) -> Result<Self> {
+        let root = options
+            .fs()
+            .canonicalize(root)
+            .with_context(|| format!("Failed to canonicalize workspace root '{}'", root.display()))?;
+        options.restrict_root.get_or_insert_with(|| root.clone());
+        Ok(Self { root, options })
+    }
+
+    /// The canonical root directory.
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Lists the templates below the root, as `/`-separated relative paths.
+    pub fn list_templates(&self) -> Result<Vec<String>> {
+        Ok(find_templates(self.options.fs(), &self.root)?
+            .iter()
+            .map(|path| path.to_string_lossy().replace('\\', "/"))
+            .collect())
+    }
+
+    /// Resolves `name` (a path relative to the root) to a template file inside the root.
+    pub fn resolve_template(&self, name: &str) -> Result<PathBuf> {
+        let fs = self.options.fs();
+        let path = fs
+            .canonicalize(&self.root.join(name))
+            .with_context(|| format!("Template '{name}' not found"))?;
+        if !path.starts_with(&self.root) || !fs.is_file(&path) {
+            bail!("Template '{name}' is not a file inside the workspace");
+        }
+        Ok(path)
+    }
+
+    /// Renders the template `name`.
+    pub fn render(&self, name: &str) -> Result<ProcessResult> {
+        let path = self.resolve_template(name)?;
+        process_template(&path, &self.options)
+            .with_context(|| format!("Failed to process template '{name}'"))
+    }
+
+    /// Compares the template's includes against its lockfile, returning a report like
+    /// `{"template": ..., "lockfile": ..., "up_to_date": false, "changes": [{"path": ..., "status": "modified"}]}`.
+    pub fn check(&self, name: &str) -> Result<Json> {
+        let fs = self.options.fs();
+        let path = self.resolve_template(name)?;
+        let content = fs
+            .read_to_string(&path)
+            .with_context(|| format!("Failed to read template file '{}'", path.display()))?;
+        let base_dir = path.parent().context("Template path must have a parent directory")?;
+        let origin = path.display().to_string();
+        let hashes = process_template_content(&content, base_dir, &origin, &self.options)
+            .and_then(|result| tracked_hashes(&content, base_dir, &origin, &self.options, &result))
+            .with_context(|| format!("Failed to process template '{name}'"))?;
+        let lockfile = Lockfile::for_template(fs, &path)?;
+        let changes = lockfile.diff_hashes(&hashes);
+
+        let changes: Vec<Json> = changes
+            .into_iter()
+            .map(|(file, status)| {
+                let mut fields = vec![
+                    ("path", Json::from(display_path(&file, &self.root))),
+                    ("status", Json::from(status.name())),
+                ];
+                if let ChangeStatus::Renamed { from } = &status {
+                    fields.push(("from", Json::from(display_path(from, &self.root))));
+                }
+                Json::object(fields)
+            })
+            .collect();
+        Ok(Json::object([
+            ("template", Json::from(name)),
+            ("lockfile", Json::from(display_path(lockfile.path(), &self.root))),
+            ("up_to_date", Json::from(changes.is_empty())),
+            ("changes", Json::Array(changes)),
+        ]))
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::vfs::MemoryFileSystem;
+
+    /// A workspace at `/project` with the templates `review.clamp` (including
+    /// `src/lib.rs`), `docs/notes.clamp` and `leak.clamp` (including a file outside
+    /// the root). `review.clamp` is locked, with `src/lib.rs` modified since.
+    pub(crate) fn workspace() -> Workspace {
+        let mut files = MemoryFileSystem::new();
+        files.insert("/project/review.clamp", "[[include: src/lib.rs]]\n");
+        files.insert("/project/docs/notes.clamp", "Notes\n");
+        files.insert("/project/leak.clamp", "[[include: ../secret.txt]]\n");
+        files.insert("/project/src/lib.rs", "pub fn f() {}\n");
+        files.insert("/secret.txt", "token\n");
+        let options = ProcessOptions {
+            file_system: Some(Arc::new(files)),
+            ..Default::default()
+        };
+        let workspace = Workspace::new(Path::new("/project"), options).unwrap();
+
+        let fs = workspace.options.fs();
+        let mut lockfile = Lockfile::new("/project/review.clamp.lock");
+        lockfile.update_from(&workspace.render("review.clamp").unwrap());
+        lockfile.save(fs).unwrap();
+        fs.write(Path::new("/project/src/lib.rs"), b"pub fn g() {}\n").unwrap();
+        workspace
+    }
+
+    #[test]
+    fn serves_templates_inside_the_root() {
+        let workspace = workspace();
+        assert_eq!(workspace.list_templates().unwrap(), ["docs/notes.clamp", "leak.clamp", "review.clamp"]);
+        assert!(workspace.render("review.clamp").unwrap().output_content.contains("pub fn g() {}"));
+
+        for name in ["../secret.txt", "missing.clamp", "src"] {
+            assert!(workspace.resolve_template(name).is_err(), "{name}");
+        }
+        let error = workspace.render("leak.clamp").unwrap_err();
+        assert!(format!("{error:#}").contains("outside the restrict root"), "{error:#}");
+    }
+
+    #[test]
+    fn reports_drift_against_the_lockfile() {
+        let report = workspace().check("review.clamp").unwrap();
+        assert_eq!(report.get("up_to_date"), Some(&Json::Bool(false)));
+        assert_eq!(report.get("lockfile").and_then(Json::as_str), Some("review.clamp.lock"));
+        let Some(Json::Array(changes)) = report.get("changes") else {
+            panic!("no changes in {report}");
+        };
+        assert_eq!(changes[0].get("path").and_then(Json::as_str), Some("src/lib.rs"));
+        assert_eq!(changes[0].get("status").and_then(Json::as_str), Some("modified"));
+
+        let report = workspace().check("docs/notes.clamp").unwrap();
+        assert_eq!(report.get("up_to_date"), Some(&Json::Bool(true)));
+    }
+}
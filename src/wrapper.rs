@@ -0,0 +1,236 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    fmt::{self, Write},
+    str::FromStr,
+};
+
+use crate::expand_placeholders;
+
+/// An included file about to be embedded in the output.
+#[derive(Debug, Clone, Copy)]
+pub struct EmbeddedFile<'a> {
+    /// The include path as written in the directive.
+    pub path: &'a str,
+    /// Language hint for the file (e.g. `rs`), possibly empty.
+    pub lang: &'a str,
+    /// The (possibly transformed) content to embed.
+    pub content: &'a str,
+    /// Size of the included file in bytes.
+    pub size: usize,
+    /// SHA256 hash (hex) of the included file, as recorded in the lockfile.
+    pub hash: &'a str,
+    /// The file is rendered inside a collapsible `<details>` block whose summary
+    /// already names it, so wrappers may omit their own file label.
+    pub in_details: bool,
+}
+
+impl EmbeddedFile<'_> {
+    /// Expands the `{path}`, `{lang}`, `{size}` and `{hash}` placeholders in `format`.
+    pub fn expand(&self, format: &str) -> String {
+        expand_placeholders(
+            format,
+            &[
+                ("path", self.path),
+                ("lang", self.lang),
+                ("size", &self.size.to_string()),
+                ("hash", self.hash),
+            ],
+        )
+    }
+}
+
+/// Renders an included file into the output. Implement this to add embedding formats.
+pub trait Wrapper: fmt::Debug + Send + Sync {
+    /// Appends the wrapped `file` to `out`.
+    fn wrap(&self, file: &EmbeddedFile<'_>, out: &mut String);
+
+    /// The text emitted before and after `file.content`, if the wrapper only surrounds
+    /// the content. Large files are then streamed into the output once instead of being
+    /// buffered and copied by [`wrap`](Self::wrap). Defaults to `None`.
+    fn surround(&self, _file: &EmbeddedFile<'_>) -> Option<(String, String)> {
+        None
+    }
+}
+
+/// Implements [`Wrapper::wrap`] for wrappers that implement [`Wrapper::surround`].
+fn wrap_surrounded(wrapper: &impl Wrapper, file: &EmbeddedFile<'_>, out: &mut String) {
+    let (prefix, suffix) = wrapper
+        .surround(file)
+        .expect("Built-in wrappers only surround the content");
+    out.push_str(&prefix);
+    out.push_str(file.content);
+    out.push_str(&suffix);
+}
+
+/// Embedding formats, selected with `--wrap`, the `wrap` frontmatter/`clamp.toml`
+/// setting, or a per-directive `wrap=` modifier.
+#[derive(Debug, PartialEq, Eq, Clone, Default, Deserialize)]
+#[serde(try_from = "String")]
+pub enum WrapStyle {
+    /// A header line followed by a fenced markdown code block.
+    #[default]
+    Markdown,
+    /// `<file path="...">…</file>` tags.
+    Xml,
+    /// Plain concatenation with a minimal separator line, without code fences.
+    None,
+    /// A user-defined wrapper template, looked up by name (see [`WrapperTemplate`]).
+    Custom(String),
+}
+
+impl FromStr for WrapStyle {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "markdown" => Ok(Self::Markdown),
+            "xml" => Ok(Self::Xml),
+            "none" => Ok(Self::None),
+            "" => bail!("Wrap style must not be empty"),
+            name => Ok(Self::Custom(name.to_string())),
+        }
+    }
+}
+
+/// The name accepted by [`FromStr`], e.g. `xml`.
+impl fmt::Display for WrapStyle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Markdown => f.write_str("markdown"),
+            Self::Xml => f.write_str("xml"),
+            Self::None => f.write_str("none"),
+            Self::Custom(name) => f.write_str(name),
+        }
+    }
+}
+
+impl TryFrom<String> for WrapStyle {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self> {
+        s.parse()
+    }
+}
+
+impl WrapStyle {
+    /// Creates the wrapper for this style. `header` is the markdown header format
+    /// (see [`crate::DEFAULT_HEADER`]); other styles ignore it. Custom styles are
+    /// looked up in `templates`.
+    ///
+    /// Returns an error if a custom style names an undefined template.
+    pub fn wrapper(
+        &self,
+        header: &str,
+        templates: &BTreeMap<String, WrapperTemplate>,
+    ) -> Result<Box<dyn Wrapper>> {
+        Ok(match self {
+            Self::Markdown => Box::new(MarkdownWrapper {
+                header: header.to_string(),
+            }),
+            Self::Xml => Box::new(XmlWrapper),
+            Self::None => Box::new(PlainWrapper),
+            Self::Custom(name) => match templates.get(name) {
+                Some(template) => Box::new(template.clone()),
+                None => bail!(
+                    "Unknown wrap style '{name}' (expected markdown, xml, none, or a [wrappers.{name}] table in clamp.toml)"
+                ),
+            },
+        })
+    }
+}
+
+/// A user-defined wrapper: `prefix` and `suffix` are emitted around the content,
+/// with `{path}`, `{lang}`, `{size}` and `{hash}` placeholders substituted.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WrapperTemplate {
+    #[serde(default)]
+    pub prefix: String,
+    #[serde(default)]
+    pub suffix: String,
+}
+
+impl Wrapper for WrapperTemplate {
+    fn wrap(&self, file: &EmbeddedFile<'_>, out: &mut String) {
+        wrap_surrounded(self, file, out);
+    }
+
+    fn surround(&self, file: &EmbeddedFile<'_>) -> Option<(String, String)> {
+        Some((file.expand(&self.prefix), file.expand(&self.suffix)))
+    }
+}
+
+/// Embeds files as a header line and a fenced code block with a language hint.
+#[derive(Debug, Clone)]
+pub struct MarkdownWrapper {
+    /// Header format with [`EmbeddedFile::expand`] placeholders; empty for `Contents of <path>:`.
+    pub header: String,
+}
+
+impl Wrapper for MarkdownWrapper {
+    fn wrap(&self, file: &EmbeddedFile<'_>, out: &mut String) {
+        wrap_surrounded(self, file, out);
+    }
+
+    fn surround(&self, file: &EmbeddedFile<'_>) -> Option<(String, String)> {
+        let mut prefix = String::new();
+        if !file.in_details {
+            if self.header.is_empty() {
+                writeln!(prefix, "Contents of {}:", file.path)
+            } else {
+                writeln!(prefix, "{}", file.expand(&self.header))
+            }
+            .expect("Writing to String buffer failed unexpectedly");
+        }
+        writeln!(prefix, "```{}", file.lang).expect("Writing to String buffer failed unexpectedly");
+        Some((prefix, "\n```\n".to_string()))
+    }
+}
+
+/// Embeds files as `<file path="...">` tags, as recommended by several LLM providers.
+#[derive(Debug, Clone, Copy)]
+pub struct XmlWrapper;
+
+impl Wrapper for XmlWrapper {
+    fn wrap(&self, file: &EmbeddedFile<'_>, out: &mut String) {
+        wrap_surrounded(self, file, out);
+    }
+
+    fn surround(&self, file: &EmbeddedFile<'_>) -> Option<(String, String)> {
+        let prefix = format!("<file path=\"{}\">\n", escape_xml_attribute(file.path));
+        Some((prefix, "\n</file>\n".to_string()))
+    }
+}
+
+/// Concatenates files with a `==> path <==` separator line, for tools that choke on markdown.
+#[derive(Debug, Clone, Copy)]
+pub struct PlainWrapper;
+
+impl Wrapper for PlainWrapper {
+    fn wrap(&self, file: &EmbeddedFile<'_>, out: &mut String) {
+        wrap_surrounded(self, file, out);
+    }
+
+    fn surround(&self, file: &EmbeddedFile<'_>) -> Option<(String, String)> {
+        let prefix = if file.in_details { String::new() } else { format!("==> {} <==\n", file.path) };
+        let suffix = if file.content.ends_with('\n') { "" } else { "\n" };
+        Some((prefix, suffix.to_string()))
+    }
+}
+
+/// Escapes characters that are not allowed verbatim in a double-quoted XML attribute.
+pub fn escape_xml_attribute(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}